@@ -54,6 +54,7 @@ pub fn api_function(_attr: TokenStream, input: TokenStream) -> TokenStream {
     };
 
     let fn_self = fn_item.sig.inputs.iter().next().unwrap();
+    let fn_vis = &fn_item.vis;
     let fn_name = &fn_item.sig.ident;
     let fn_return = &fn_item.sig.output;
 
@@ -113,7 +114,7 @@ pub fn api_function(_attr: TokenStream, input: TokenStream) -> TokenStream {
     let fn_name_str = fn_name.to_string().replace('_', " ").to_uppercase();
     let glue_name = format_ident!("__api_{}", fn_name);
     streams.push(quote! {
-        fn #glue_name(#fn_self, mut accessor: EmulatorAccessor) -> Result<ReturnValue, EmulatorError> {
+        #fn_vis fn #glue_name(#fn_self, mut accessor: EmulatorAccessor) -> Result<ReturnValue, EmulatorError> {
             debug!("ENTER {}", #fn_name_str);
             #(#param_reading_code)*
             self.#fn_name(#(#params),*)
@@ -0,0 +1,71 @@
+//! Accelerator tables, which turn keys into commands.
+
+use crate::constants::{AcceleratorFlags, MessageType};
+use crate::util::u16_from_slice;
+
+/// The size of an entry of an accelerator table: its flags, its key and its command.
+const ENTRY_SIZE: usize = 5;
+
+/// The bit of lParam of a key message that tells whether Alt is down.
+const ALT_DOWN: u32 = 0x2000_0000;
+/// lParam of the WM_COMMAND or WM_SYSCOMMAND of an accelerator, whose high word says that it
+/// came from one.
+pub const FROM_ACCELERATOR: u32 = 0x1_0000;
+
+pub struct Accelerator {
+    pub flags: AcceleratorFlags,
+    /// A virtual key, or a character for the entries that aren't VIRTKEY.
+    pub key: u16,
+    pub command: u16,
+}
+
+/// Reads the entries of an accelerator table, as found in an RT_ACCELERATOR resource. The
+/// table ends at the entry with the END flag, or where the data ends.
+pub fn parse_table(data: &[u8]) -> Vec<Accelerator> {
+    let mut accelerators = Vec::new();
+    for entry in data.chunks_exact(ENTRY_SIZE) {
+        let flags = AcceleratorFlags::from_bits_truncate(entry[0]);
+        accelerators.push(Accelerator {
+            flags,
+            key: u16_from_slice(entry, 1).expect("entry is big enough"),
+            command: u16_from_slice(entry, 3).expect("entry is big enough"),
+        });
+        if flags.contains(AcceleratorFlags::END) {
+            break;
+        }
+    }
+    accelerators
+}
+
+/// The accelerator for a key message, if any. Virtual keys match WM_KEYDOWN and
+/// WM_SYSKEYDOWN with exactly the Shift, Ctrl and Alt keys of the entry down, characters
+/// match WM_CHAR and WM_SYSCHAR with Alt down only if the entry wants it.
+pub fn find(
+    table: &[Accelerator],
+    message: u16,
+    w_param: u16,
+    l_param: u32,
+    shift: bool,
+    control: bool,
+) -> Option<&Accelerator> {
+    let alt = l_param & ALT_DOWN != 0;
+    let virtual_key =
+        if message == MessageType::KeyDown.into() || message == MessageType::SysKeyDown.into() {
+            true
+        } else if message == MessageType::Char.into() || message == MessageType::SysChar.into() {
+            false
+        } else {
+            return None;
+        };
+    table.iter().find(|accelerator| {
+        if accelerator.key != w_param
+            || accelerator.flags.contains(AcceleratorFlags::VIRTKEY) != virtual_key
+            || accelerator.flags.contains(AcceleratorFlags::ALT) != alt
+        {
+            return false;
+        }
+        !virtual_key
+            || (accelerator.flags.contains(AcceleratorFlags::SHIFT) == shift
+                && accelerator.flags.contains(AcceleratorFlags::CONTROL) == control)
+    })
+}
@@ -3,7 +3,6 @@ pub struct Pointer(pub u32);
 pub enum ReturnValue {
     U16(u16),
     U32(u32),
-    DelayedU16(u16),
     None,
 }
 
@@ -14,6 +13,11 @@ impl From<u32> for Pointer {
 }
 
 impl Pointer {
+    /// Converts a far pointer, as passed in a lParam, to a flat address.
+    pub fn from_far(far_pointer: u32) -> Self {
+        Self(((far_pointer >> 16) << 4) + (far_pointer & 0xFFFF))
+    }
+
     pub fn advanced(&self, amount: u32) -> Self {
         Self(self.0 + amount)
     }
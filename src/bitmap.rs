@@ -26,12 +26,15 @@ impl Color {
 pub struct BitmapView<'a> {
     bitmap: &'a mut Bitmap,
     translation: Point,
+    clip: Option<Rect>,
 }
 
 pub struct Bitmap {
     // TODO: should probably not use a vec?
     pixels: Vec<Color>,
     translation: Point,
+    /// Drawing outside of this rectangle, in pixels of the bitmap, is discarded.
+    clip: Option<Rect>,
     moved_to: Point,
     raster_op: RasterOp,
     width: i16,
@@ -41,11 +44,20 @@ pub struct Bitmap {
 impl<'a> BitmapView<'a> {
     pub fn new(bitmap: &'a mut Bitmap, translation: Point) -> Self {
         bitmap.translation += translation;
+        let clip = bitmap.clip;
         Self {
             bitmap,
             translation,
+            clip,
         }
     }
+
+    /// Discards drawing outside of the given rectangle, which is in the coordinates of this
+    /// view, until the view is dropped.
+    pub fn clip_to(&mut self, rect: Rect) {
+        let rect = rect.offset(self.bitmap.translation.x, self.bitmap.translation.y);
+        self.bitmap.clip = Some(self.bitmap.bounds().intersect(&rect));
+    }
 }
 
 impl<'a> Deref for BitmapView<'a> {
@@ -65,6 +77,7 @@ impl<'a> DerefMut for BitmapView<'a> {
 impl<'a> Drop for BitmapView<'a> {
     fn drop(&mut self) {
         self.bitmap.translation -= self.translation;
+        self.bitmap.clip = self.clip;
     }
 }
 
@@ -73,6 +86,7 @@ impl Bitmap {
         Self {
             pixels: vec![Color(192, 192, 192); (width as usize) * (height as usize)],
             translation: Point::origin(),
+            clip: None,
             moved_to: Point::origin(),
             raster_op: RasterOp::CopyPen,
             width,
@@ -84,23 +98,23 @@ impl Bitmap {
         self.moved_to = point;
     }
 
-    fn clip_and_translate_x(&self, x: i16) -> i16 {
-        let x = x.wrapping_add(self.translation.x);
-        x.clamp(0, self.width - 1)
-    }
-
-    fn clip_and_translate_y(&self, y: i16) -> i16 {
-        let y = y.wrapping_add(self.translation.y);
-        y.clamp(0, self.height - 1)
+    /// The part of the bitmap that can be drawn on.
+    fn bounds(&self) -> Rect {
+        let bitmap = Rect {
+            left: 0,
+            top: 0,
+            right: self.width,
+            bottom: self.height,
+        };
+        match self.clip {
+            Some(clip) => bitmap.intersect(&clip),
+            None => bitmap,
+        }
     }
 
     pub fn clip_and_translate_rect(&self, rect: Rect) -> Rect {
-        Rect {
-            left: self.clip_and_translate_x(rect.left),
-            top: self.clip_and_translate_y(rect.top),
-            right: self.clip_and_translate_x(rect.right),
-            bottom: self.clip_and_translate_y(rect.bottom),
-        }
+        rect.offset(self.translation.x, self.translation.y)
+            .intersect(&self.bounds())
     }
 
     #[inline]
@@ -123,8 +137,11 @@ impl Bitmap {
     }
 
     pub fn set_pixel(&mut self, x: i16, y: i16, color: Color) {
-        let x = self.clip_and_translate_x(x);
-        let y = self.clip_and_translate_y(y);
+        let x = x.wrapping_add(self.translation.x);
+        let y = y.wrapping_add(self.translation.y);
+        if !self.bounds().contains(Point::new(x, y)) {
+            return;
+        }
         let index = self.index_for(x, y);
         let color = match self.raster_op {
             RasterOp::Black => Color(0, 0, 0),
@@ -163,26 +180,32 @@ impl Bitmap {
     }
 
     pub fn draw_vertical_line(&mut self, x: i16, y_start: i16, y_to: i16, color: Color) {
-        self.draw_vertical_line_unclipped_untranslated(
-            self.clip_and_translate_x(x),
-            self.clip_and_translate_y(y_start),
-            self.clip_and_translate_y(y_to),
-            color,
-        )
+        let line = self.clip_and_translate_rect(Rect {
+            left: x,
+            top: y_start,
+            right: x.wrapping_add(1),
+            bottom: y_to,
+        });
+        if !line.is_empty() {
+            self.draw_vertical_line_unclipped_untranslated(line.left, line.top, line.bottom, color)
+        }
     }
 
     pub fn draw_horizontal_line(&mut self, x_start: i16, y: i16, x_to: i16, color: Color) {
-        self.draw_horizontal_line_unclipped_untranslated(
-            self.clip_and_translate_x(x_start),
-            self.clip_and_translate_y(y),
-            self.clip_and_translate_x(x_to),
-            color,
-        )
+        let line = self.clip_and_translate_rect(Rect {
+            left: x_start,
+            top: y,
+            right: x_to,
+            bottom: y.wrapping_add(1),
+        });
+        if !line.is_empty() {
+            self.draw_horizontal_line_unclipped_untranslated(line.left, line.top, line.right, color)
+        }
     }
 
     pub fn fill_rectangle(&mut self, rect: Rect, color: Color) {
         let rect = self.clip_and_translate_rect(rect);
-        if rect.left < rect.right && rect.top < rect.bottom {
+        if !rect.is_empty() {
             for y in rect.top..rect.bottom {
                 self.draw_horizontal_line_unclipped_untranslated(rect.left, y, rect.right, color);
             }
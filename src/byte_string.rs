@@ -1,11 +1,11 @@
 use std::fmt::{Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Clone, Eq, Hash, PartialEq)]
 pub struct HeapByteString {
-    data: Rc<[u8]>,
+    data: Arc<[u8]>,
 }
 
 #[derive(Clone, Eq, Hash, PartialEq)]
@@ -20,7 +20,7 @@ pub enum ByteString<'a> {
 }
 
 impl HeapByteString {
-    pub fn from(data: Rc<[u8]>) -> Self {
+    pub fn from(data: Arc<[u8]>) -> Self {
         Self { data }
     }
 
@@ -0,0 +1,124 @@
+use crate::bitmap::{Bitmap, BitmapView, Color};
+use crate::constants::RasterOp;
+use crate::two_d::{Point, Rect};
+use crate::window_manager::WindowIdentifier;
+use std::time::{Duration, Instant};
+
+/// How long the caret stays on and off, in milliseconds, unless SetCaretBlinkTime changes it.
+pub const DEFAULT_BLINK_TIME: u16 = 500;
+
+/// The caret of the system, which blinks in the client area of one window at a time. It is
+/// drawn by inverting the pixels under it, so drawing it twice takes it away again.
+pub struct Caret {
+    /// The window that created the caret.
+    pub owner: WindowIdentifier,
+    /// The window whose bitmap the caret is drawn on, and where the client area of the owner
+    /// lies on that bitmap.
+    bitmap_window_identifier: WindowIdentifier,
+    bitmap_translation: Point,
+    /// The client area of the owner, which the caret doesn't draw outside of.
+    client_rect: Rect,
+    /// The top left corner, in client coordinates of the owner.
+    position: Point,
+    width: i16,
+    height: i16,
+    /// Gray carets only invert every other pixel.
+    gray: bool,
+    /// How many more times the caret got hidden than shown. It only shows at zero.
+    hide_count: u16,
+    /// Whether the caret is on the bitmap right now.
+    drawn: bool,
+    /// When the caret last went on or off.
+    last_blink: Instant,
+}
+
+impl Caret {
+    /// A caret that is hidden until it gets shown.
+    pub fn new(owner: WindowIdentifier, width: i16, height: i16, gray: bool) -> Self {
+        Self {
+            owner,
+            bitmap_window_identifier: owner,
+            bitmap_translation: Point::origin(),
+            client_rect: Rect::zero(),
+            position: Point::origin(),
+            width,
+            height,
+            gray,
+            hide_count: 1,
+            drawn: false,
+            last_blink: Instant::now(),
+        }
+    }
+
+    pub fn bitmap_window_identifier(&self) -> WindowIdentifier {
+        self.bitmap_window_identifier
+    }
+
+    pub fn is_drawn(&self) -> bool {
+        self.drawn
+    }
+
+    pub fn position(&self) -> Point {
+        self.position
+    }
+
+    /// Moves the caret, and sets where it is drawn. It must not be drawn right now.
+    pub fn place(
+        &mut self,
+        position: Point,
+        bitmap_window_identifier: WindowIdentifier,
+        bitmap_translation: Point,
+        client_rect: Rect,
+    ) {
+        debug_assert!(!self.drawn);
+        self.position = position;
+        self.bitmap_window_identifier = bitmap_window_identifier;
+        self.bitmap_translation = bitmap_translation;
+        self.client_rect = client_rect;
+    }
+
+    pub fn hide(&mut self) {
+        self.hide_count = self.hide_count.saturating_add(1);
+    }
+
+    /// Shows the caret if it got shown as often as it got hidden. Returns whether it should be
+    /// drawn now.
+    pub fn show(&mut self) -> bool {
+        if self.hide_count == 0 {
+            return false;
+        }
+        self.hide_count -= 1;
+        self.last_blink = Instant::now();
+        self.hide_count == 0 && !self.drawn
+    }
+
+    /// Whether the caret should go on or off by now.
+    pub fn should_blink(&self, now: Instant, blink_time: u16) -> bool {
+        if self.hide_count > 0 {
+            return self.drawn;
+        }
+        now.duration_since(self.last_blink) >= Duration::from_millis(blink_time.into())
+    }
+
+    /// Draws the caret if it is off, and takes it away if it is on.
+    pub fn invert(&mut self, bitmap: &mut Bitmap) {
+        let mut bitmap = BitmapView::new(bitmap, self.bitmap_translation);
+        bitmap.clip_to(self.client_rect);
+        bitmap.set_raster_op(RasterOp::Not);
+        for y in self.position.y..self.position.y.saturating_add(self.height) {
+            for x in self.position.x..self.position.x.saturating_add(self.width) {
+                if !self.gray || (x ^ y) & 1 == 0 {
+                    bitmap.set_pixel(x, y, Color(0, 0, 0));
+                }
+            }
+        }
+        bitmap.set_raster_op(RasterOp::CopyPen);
+        self.drawn = !self.drawn;
+        self.last_blink = Instant::now();
+    }
+
+    /// Forgets that the caret was drawn, for when the bitmap it was drawn on got replaced.
+    pub fn forget_drawing(&mut self) {
+        self.drawn = false;
+    }
+}
@@ -0,0 +1,261 @@
+use crate::byte_string::HeapByteString;
+use crate::constants::{CF_BITMAP, CF_OEMTEXT, CF_PALETTE, CF_TEXT};
+use crate::handle_table::Handle;
+use crate::keyboard::{ansi_to_oem, oem_to_ansi};
+use crate::window_manager::{ProcessId, WindowIdentifier};
+use std::borrow::Cow;
+
+/// RegisterClipboardFormat hands out the formats from here on, like the string atoms.
+const FIRST_REGISTERED_FORMAT: u16 = 0xC000;
+
+/// What the clipboard holds for a format.
+#[derive(Clone)]
+pub enum ClipboardData {
+    /// The contents of a global memory block, copied out of the task that put it there. Every
+    /// task that asks for it gets a copy in its own memory.
+    Memory(Box<[u8]>),
+    /// A GDI object, such as the bitmap of CF_BITMAP. GDI objects are shared by all tasks.
+    Object(Handle),
+    /// Nothing yet: the owner renders it once a task asks for it, with WM_RENDERFORMAT.
+    Delayed,
+}
+
+/// The clipboard of the whole system. Text goes to and comes from the clipboard of the host, the
+/// other formats stay within the emulator.
+pub struct Clipboard {
+    /// The window that has the clipboard open, which may be no window, with its task.
+    opened_by: Option<WindowIdentifier>,
+    /// The window that emptied the clipboard last, which renders the delayed formats.
+    owner: Option<WindowIdentifier>,
+    /// The formats in the order they were put on the clipboard.
+    formats: Vec<(u16, ClipboardData)>,
+    /// Counts the changes, so that the copies that tasks got of the data can be told apart.
+    sequence_number: u32,
+    registered_formats: Vec<HeapByteString>,
+    /// Text for the host, as soon as the screen gets to it.
+    text_for_host: Option<String>,
+    /// Text from the host that waits for the clipboard to be closed.
+    pending_host_text: Option<String>,
+    /// The text that was last exchanged with the host, so that it doesn't come back as new.
+    host_text: Option<String>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Self {
+            opened_by: None,
+            owner: None,
+            formats: Vec::new(),
+            sequence_number: 0,
+            registered_formats: Vec::new(),
+            text_for_host: None,
+            pending_host_text: None,
+            host_text: None,
+        }
+    }
+
+    /// Opens the clipboard for a window of a task. Fails if another task has it open.
+    pub fn open(&mut self, identifier: WindowIdentifier) -> bool {
+        match self.opened_by {
+            Some(opened_by) if opened_by.process_id != identifier.process_id => false,
+            _ => {
+                self.opened_by = Some(identifier);
+                true
+            }
+        }
+    }
+
+    pub fn is_open_by(&self, process_id: ProcessId) -> bool {
+        self.opened_by
+            .is_some_and(|opened_by| opened_by.process_id == process_id)
+    }
+
+    /// Closes the clipboard if the task has it open. Text that the host copied in the meantime
+    /// takes its place. Returns whether it was open, and the GDI objects that the clipboard
+    /// let go of.
+    pub fn close(&mut self, process_id: ProcessId) -> (bool, Vec<Handle>) {
+        if !self.is_open_by(process_id) {
+            return (false, Vec::new());
+        }
+        self.opened_by = None;
+        let released = match self.pending_host_text.take() {
+            Some(text) => self.take_host_text(text),
+            None => Vec::new(),
+        };
+        (true, released)
+    }
+
+    /// Empties the clipboard, and makes the window that has it open its owner. Returns the GDI
+    /// objects that the clipboard let go of.
+    pub fn empty(&mut self) -> Vec<Handle> {
+        self.owner = self
+            .opened_by
+            .filter(|opened_by| opened_by.window_handle != Handle::null());
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.formats
+            .drain(..)
+            .filter_map(|(_, data)| match data {
+                ClipboardData::Object(handle) => Some(handle),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn owner(&self) -> Option<WindowIdentifier> {
+        self.owner
+    }
+
+    /// Puts data on the clipboard, in place of the data it had in that format. Returns the GDI
+    /// object that the clipboard let go of, if any.
+    pub fn set(&mut self, format: u16, data: ClipboardData) -> Option<Handle> {
+        if format == CF_TEXT || format == CF_OEMTEXT {
+            if let ClipboardData::Memory(bytes) = &data {
+                let text = text_for_host(bytes, format == CF_OEMTEXT);
+                self.host_text = Some(text.clone());
+                self.text_for_host = Some(text);
+            }
+        }
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        match self.formats.iter_mut().find(|(other, _)| *other == format) {
+            Some((_, old_data)) => match std::mem::replace(old_data, data) {
+                ClipboardData::Object(handle) => Some(handle),
+                _ => None,
+            },
+            None => {
+                self.formats.push((format, data));
+                None
+            }
+        }
+    }
+
+    /// The data in a format. CF_TEXT and CF_OEMTEXT stand in for each other, with the
+    /// characters converted between the ANSI and the OEM character set.
+    pub fn get(&self, format: u16) -> Option<Cow<'_, ClipboardData>> {
+        let find = |format: u16| {
+            self.formats
+                .iter()
+                .find(|(other, _)| *other == format)
+                .map(|(_, data)| data)
+        };
+        if let Some(data) = find(format) {
+            return Some(Cow::Borrowed(data));
+        }
+        let (data, convert): (_, fn(u8) -> u8) = match format {
+            CF_TEXT => (find(CF_OEMTEXT)?, oem_to_ansi),
+            CF_OEMTEXT => (find(CF_TEXT)?, ansi_to_oem),
+            _ => return None,
+        };
+        Some(match data {
+            ClipboardData::Memory(bytes) => Cow::Owned(ClipboardData::Memory(
+                bytes.iter().map(|&b| convert(b)).collect(),
+            )),
+            _ => Cow::Borrowed(data),
+        })
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        self.sequence_number
+    }
+
+    /// The formats that the clipboard has data in, with the text format that stands in for
+    /// the other one at the end.
+    pub fn formats(&self) -> Vec<u16> {
+        let mut formats = self
+            .formats
+            .iter()
+            .map(|(format, _)| *format)
+            .collect::<Vec<_>>();
+        for (format, other) in [(CF_TEXT, CF_OEMTEXT), (CF_OEMTEXT, CF_TEXT)] {
+            if formats.contains(&format) && !formats.contains(&other) {
+                formats.push(other);
+            }
+        }
+        formats
+    }
+
+    /// Forgets a window that got destroyed. The formats it didn't render are gone with it.
+    pub fn forget_window(&mut self, identifier: WindowIdentifier) {
+        if self.owner == Some(identifier) {
+            self.owner = None;
+            self.formats
+                .retain(|(_, data)| !matches!(data, ClipboardData::Delayed));
+        }
+        if self.opened_by == Some(identifier) {
+            self.opened_by = Some(identifier.other_handle(Handle::null()));
+        }
+    }
+
+    /// Registers a format by its name, or finds the one registered before with that name.
+    pub fn register_format(&mut self, name: HeapByteString) -> Option<u16> {
+        let index = match self
+            .registered_formats
+            .iter()
+            .position(|other| other.as_slice().eq_ignore_ascii_case(name.as_slice()))
+        {
+            Some(index) => index,
+            None => {
+                self.registered_formats.push(name);
+                self.registered_formats.len() - 1
+            }
+        };
+        FIRST_REGISTERED_FORMAT.checked_add(u16::try_from(index).ok()?)
+    }
+
+    pub fn format_name(&self, format: u16) -> Option<&HeapByteString> {
+        let index = format.checked_sub(FIRST_REGISTERED_FORMAT)?;
+        self.registered_formats.get(index as usize)
+    }
+
+    /// The text that a task put on the clipboard since the host last got it.
+    pub fn take_text_for_host(&mut self) -> Option<String> {
+        self.text_for_host.take()
+    }
+
+    /// Takes the text that got copied on the host, unless it is the text that came from here.
+    /// Returns the GDI objects that the clipboard let go of.
+    pub fn receive_host_text(&mut self, text: String) -> Vec<Handle> {
+        if self.host_text.as_ref() == Some(&text) {
+            return Vec::new();
+        }
+        self.host_text = Some(text.clone());
+        if self.opened_by.is_some() {
+            self.pending_host_text = Some(text);
+            return Vec::new();
+        }
+        self.take_host_text(text)
+    }
+
+    fn take_host_text(&mut self, text: String) -> Vec<Handle> {
+        let released = self.empty();
+        self.formats
+            .push((CF_TEXT, ClipboardData::Memory(text_from_host(&text))));
+        released
+    }
+}
+
+/// Whether the data of a format is a GDI object instead of a global memory block.
+pub fn is_object_format(format: u16) -> bool {
+    format == CF_BITMAP || format == CF_PALETTE
+}
+
+/// Turns the text of a task, which ends with a null byte and has CR LF line breaks, into text
+/// for the host. The characters are taken to be Latin-1, after OEM text is converted to ANSI.
+fn text_for_host(bytes: &[u8], oem: bool) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    bytes[..end]
+        .iter()
+        .map(|&b| if oem { oem_to_ansi(b) } else { b } as char)
+        .collect::<String>()
+        .replace("\r\n", "\n")
+}
+
+/// Turns text from the host into null-terminated text with CR LF line breaks. Characters
+/// outside of Latin-1 become question marks.
+fn text_from_host(text: &str) -> Box<[u8]> {
+    text.replace("\r\n", "\n")
+        .replace('\n', "\r\n")
+        .chars()
+        .map(|c| u8::try_from(c as u32).unwrap_or(b'?'))
+        .chain(std::iter::once(0))
+        .collect()
+}
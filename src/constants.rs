@@ -6,6 +6,72 @@ pub const USER_INT_VECTOR: u8 = 0xfe;
 pub const GDI_INT_VECTOR: u8 = 0xfd;
 pub const KEYBOARD_INT_VECTOR: u8 = 0xfc;
 pub const LOWEST_SYSCALL_INT_VECTOR: u8 = 0xfc;
+/// The segment right after conventional memory, which DOS programs get to use up to.
+pub const CONVENTIONAL_MEMORY_TOP: u16 = 0xA000;
+
+/// The type ids of the predefined resource types, without the high bit that marks them
+/// as numbers.
+pub const RT_MENU: u16 = 4;
+pub const RT_DIALOG: u16 = 5;
+pub const RT_ACCELERATOR: u16 = 9;
+
+pub const CF_TEXT: u16 = 1;
+pub const CF_BITMAP: u16 = 2;
+pub const CF_OEMTEXT: u16 = 7;
+pub const CF_PALETTE: u16 = 9;
+
+/// The commands that OK and Cancel buttons send, and that Enter and Escape stand for in
+/// dialog boxes.
+pub const IDOK: u16 = 1;
+pub const IDCANCEL: u16 = 2;
+/// The commands of the other buttons that message boxes can have.
+pub const IDABORT: u16 = 3;
+pub const IDRETRY: u16 = 4;
+pub const IDIGNORE: u16 = 5;
+pub const IDYES: u16 = 6;
+pub const IDNO: u16 = 7;
+
+/// The window that PostMessage and SendMessage take to mean all top-level windows.
+pub const HWND_BROADCAST: u16 = 0xffff;
+
+/// The window that GetDesktopWindow gives. There is no window behind it, the functions that
+/// take it treat it as the screen.
+pub const DESKTOP_WINDOW: u16 = 0xfffe;
+
+/// What CreateWindow takes as a position or a size to mean that Windows chooses it.
+pub const CW_USEDEFAULT: i16 = -32768;
+
+/// What GetWindowWord and GetWindowLong read at negative offsets, instead of the extra bytes.
+pub const GWL_WNDPROC: i16 = -4;
+pub const GWW_HINSTANCE: i16 = -6;
+pub const GWW_HWNDPARENT: i16 = -8;
+pub const GWW_ID: i16 = -12;
+pub const GWL_STYLE: i16 = -16;
+pub const GWL_EXSTYLE: i16 = -20;
+
+/// What GetClassWord and GetClassLong read at negative offsets, instead of the extra bytes.
+pub const GCW_HBRBACKGROUND: i16 = -10;
+pub const GCW_HCURSOR: i16 = -12;
+pub const GCW_HICON: i16 = -14;
+pub const GCW_HMODULE: i16 = -16;
+pub const GCW_CBWNDEXTRA: i16 = -18;
+pub const GCW_CBCLSEXTRA: i16 = -20;
+pub const GCL_WNDPROC: i16 = -24;
+pub const GCW_STYLE: i16 = -26;
+
+/// The kinds of hooks, for SetWindowsHook and SetWindowsHookEx.
+pub const WH_MSGFILTER: i16 = -1;
+pub const WH_KEYBOARD: i16 = 2;
+pub const WH_GETMESSAGE: i16 = 3;
+pub const WH_CALLWNDPROC: i16 = 4;
+pub const WH_CBT: i16 = 5;
+pub const WH_SYSMSGFILTER: i16 = 6;
+pub const WH_MOUSE: i16 = 7;
+
+/// The code that hooks get for a message that gets removed from the queue.
+pub const HC_ACTION: u16 = 0;
+/// The code that hooks get for a message that PeekMessage leaves in the queue.
+pub const HC_NOREMOVE: u16 = 3;
 
 bitflags! {
     #[allow(dead_code)]
@@ -26,23 +92,223 @@ bitflags! {
     pub struct ClassStyles: u16 {
         const VREDRAW = 0x001;
         const HREDRAW = 0x002;
+        const DBLCLKS = 0x008;
         const PARENT_DC = 0x080;
     }
 }
 
+bitflags! {
+    #[allow(dead_code)]
+    pub struct WindowStyles: u32 {
+        const POPUP = 0x80000000;
+        const CHILD = 0x40000000;
+        const MINIMIZE = 0x20000000;
+        const VISIBLE = 0x10000000;
+        const DISABLED = 0x08000000;
+        const CLIPSIBLINGS = 0x04000000;
+        const CLIPCHILDREN = 0x02000000;
+        const MAXIMIZE = 0x01000000;
+        const CAPTION = 0x00C00000;
+        const BORDER = 0x00800000;
+        const DLGFRAME = 0x00400000;
+        const VSCROLL = 0x00200000;
+        const HSCROLL = 0x00100000;
+        const SYSMENU = 0x00080000;
+        const THICKFRAME = 0x00040000;
+        const MINIMIZEBOX = 0x00020000;
+        const MAXIMIZEBOX = 0x00010000;
+        /// Controls use the bits of the caption buttons for the keyboard interface of
+        /// dialog boxes.
+        const GROUP = 0x00020000;
+        const TABSTOP = 0x00010000;
+    }
+}
+
+bitflags! {
+    #[allow(dead_code)]
+    pub struct DialogStyles: u32 {
+        const ABSALIGN = 0x01;
+        const SYSMODAL = 0x02;
+        const LOCALEDIT = 0x20;
+        const SETFONT = 0x40;
+        const MODALFRAME = 0x80;
+        const NOIDLEMSG = 0x100;
+    }
+}
+
+bitflags! {
+    #[allow(dead_code)]
+    pub struct SetWindowPosFlags: u16 {
+        const NOSIZE = 0x0001;
+        const NOMOVE = 0x0002;
+        const NOZORDER = 0x0004;
+        const NOREDRAW = 0x0008;
+        const NOACTIVATE = 0x0010;
+        const FRAMECHANGED = 0x0020;
+        const SHOWWINDOW = 0x0040;
+        const HIDEWINDOW = 0x0080;
+        const NOCOPYBITS = 0x0100;
+        const NOOWNERZORDER = 0x0200;
+    }
+}
+
+bitflags! {
+    #[allow(dead_code)]
+    pub struct AcceleratorFlags: u8 {
+        /// The key is a virtual key instead of a character.
+        const VIRTKEY = 0x01;
+        const NOINVERT = 0x02;
+        const SHIFT = 0x04;
+        const CONTROL = 0x08;
+        const ALT = 0x10;
+        /// Marks the last entry of an accelerator table.
+        const END = 0x80;
+    }
+}
+
+bitflags! {
+    #[allow(dead_code)]
+    pub struct MenuFlags: u16 {
+        const GRAYED = 0x0001;
+        const DISABLED = 0x0002;
+        const BITMAP = 0x0004;
+        const CHECKED = 0x0008;
+        const POPUP = 0x0010;
+        const MENUBARBREAK = 0x0020;
+        const MENUBREAK = 0x0040;
+        /// Marks the last item of a menu in a menu template.
+        const END = 0x0080;
+        const HILITE = 0x0080;
+        const OWNERDRAW = 0x0100;
+        const BYPOSITION = 0x0400;
+        const SEPARATOR = 0x0800;
+        const SYSMENU = 0x2000;
+        const MOUSESELECT = 0x8000;
+    }
+}
+
+bitflags! {
+    pub struct MouseKeyState: u16 {
+        const LBUTTON = 0x0001;
+        const RBUTTON = 0x0002;
+        const SHIFT = 0x0004;
+        const CONTROL = 0x0008;
+        const MBUTTON = 0x0010;
+    }
+}
+
 #[derive(Eq, PartialEq, Copy, Clone)]
 pub enum MessageType {
     Create = 0x01,
+    Destroy = 0x02,
+    Move = 0x03,
     Size = 0x05,
+    Activate = 0x06,
+    SetFocus = 0x07,
+    KillFocus = 0x08,
+    Enable = 0x0a,
+    SetText = 0x0c,
+    GetText = 0x0d,
+    GetTextLength = 0x0e,
     Paint = 0x0f,
+    Close = 0x10,
     Quit = 0x12,
     EraseBkGnd = 0x14,
     ShowWindow = 0x18,
+    ActivateApp = 0x1c,
+    SetCursor = 0x20,
+    MouseActivate = 0x21,
     GetMinMaxInfo = 0x24,
     WindowPosChanging = 0x46,
+    WindowPosChanged = 0x47,
     NcCreate = 0x81,
+    NcDestroy = 0x82,
     NcCalcSize = 0x83,
+    NcHitTest = 0x84,
+    NcPaint = 0x85,
+    NcActivate = 0x86,
+    NcMouseMove = 0xa0,
+    NcLButtonDown = 0xa1,
+    NcLButtonUp = 0xa2,
+    NcLButtonDblClk = 0xa3,
+    NcRButtonDown = 0xa4,
+    NcRButtonUp = 0xa5,
+    NcRButtonDblClk = 0xa6,
+    NcMButtonDown = 0xa7,
+    NcMButtonUp = 0xa8,
+    NcMButtonDblClk = 0xa9,
+    KeyDown = 0x100,
+    KeyUp = 0x101,
+    Char = 0x102,
+    DeadChar = 0x103,
+    SysKeyDown = 0x104,
+    SysKeyUp = 0x105,
+    SysChar = 0x106,
+    SysDeadChar = 0x107,
+    InitDialog = 0x110,
+    Command = 0x111,
+    SysCommand = 0x112,
     Timer = 0x113,
+    HScroll = 0x114,
+    VScroll = 0x115,
+    InitMenu = 0x116,
+    InitMenuPopup = 0x117,
+    MenuSelect = 0x11f,
+    MouseMove = 0x200,
+    LButtonDown = 0x201,
+    LButtonUp = 0x202,
+    LButtonDblClk = 0x203,
+    RButtonDown = 0x204,
+    RButtonUp = 0x205,
+    RButtonDblClk = 0x206,
+    MButtonDown = 0x207,
+    MButtonUp = 0x208,
+    MButtonDblClk = 0x209,
+    RenderFormat = 0x305,
+    RenderAllFormats = 0x306,
+    DestroyClipboard = 0x307,
+}
+
+impl MessageType {
+    pub fn is_key_message(self) -> bool {
+        matches!(
+            self,
+            MessageType::KeyDown
+                | MessageType::KeyUp
+                | MessageType::SysKeyDown
+                | MessageType::SysKeyUp
+        )
+    }
+
+    /// The double click message for a button down message.
+    pub fn double_click(self) -> Option<Self> {
+        match self {
+            MessageType::LButtonDown => Some(MessageType::LButtonDblClk),
+            MessageType::RButtonDown => Some(MessageType::RButtonDblClk),
+            MessageType::MButtonDown => Some(MessageType::MButtonDblClk),
+            MessageType::NcLButtonDown => Some(MessageType::NcLButtonDblClk),
+            MessageType::NcRButtonDown => Some(MessageType::NcRButtonDblClk),
+            MessageType::NcMButtonDown => Some(MessageType::NcMButtonDblClk),
+            _ => None,
+        }
+    }
+
+    /// The non-client area counterpart of a mouse message.
+    pub fn non_client(self) -> Option<Self> {
+        match self {
+            MessageType::MouseMove => Some(MessageType::NcMouseMove),
+            MessageType::LButtonDown => Some(MessageType::NcLButtonDown),
+            MessageType::LButtonUp => Some(MessageType::NcLButtonUp),
+            MessageType::LButtonDblClk => Some(MessageType::NcLButtonDblClk),
+            MessageType::RButtonDown => Some(MessageType::NcRButtonDown),
+            MessageType::RButtonUp => Some(MessageType::NcRButtonUp),
+            MessageType::RButtonDblClk => Some(MessageType::NcRButtonDblClk),
+            MessageType::MButtonDown => Some(MessageType::NcMButtonDown),
+            MessageType::MButtonUp => Some(MessageType::NcMButtonUp),
+            MessageType::MButtonDblClk => Some(MessageType::NcMButtonDblClk),
+            _ => None,
+        }
+    }
 }
 
 impl From<MessageType> for u16 {
@@ -51,6 +317,602 @@ impl From<MessageType> for u16 {
     }
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HitTest {
+    Error = -2,
+    Transparent = -1,
+    Nowhere = 0,
+    Client = 1,
+    Caption = 2,
+    SysMenu = 3,
+    Size = 4,
+    Menu = 5,
+    HScroll = 6,
+    VScroll = 7,
+    MinButton = 8,
+    MaxButton = 9,
+    Left = 10,
+    Right = 11,
+    Top = 12,
+    TopLeft = 13,
+    TopRight = 14,
+    Bottom = 15,
+    BottomLeft = 16,
+    BottomRight = 17,
+    Border = 18,
+}
+
+impl From<HitTest> for u16 {
+    fn from(h: HitTest) -> Self {
+        h as i16 as u16
+    }
+}
+
+/// The messages of button controls, which start at WM_USER.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ButtonMessage {
+    GetCheck = 0x400,
+    SetCheck = 0x401,
+    GetState = 0x402,
+    SetState = 0x403,
+    SetStyle = 0x404,
+}
+
+impl From<ButtonMessage> for u16 {
+    fn from(m: ButtonMessage) -> Self {
+        m as u16
+    }
+}
+
+/// The kinds of buttons, in the lowest four bits of the style of a button.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ButtonType {
+    PushButton = 0x0,
+    DefPushButton = 0x1,
+    CheckBox = 0x2,
+    AutoCheckBox = 0x3,
+    RadioButton = 0x4,
+    ThreeState = 0x5,
+    AutoThreeState = 0x6,
+    GroupBox = 0x7,
+    UserButton = 0x8,
+    AutoRadioButton = 0x9,
+    OwnerDraw = 0xb,
+}
+
+impl From<ButtonType> for u16 {
+    fn from(t: ButtonType) -> Self {
+        t as u16
+    }
+}
+
+bitflags! {
+    pub struct ButtonStyles: u16 {
+        /// Puts the text of a check box or radio button on the left of it.
+        const LEFTTEXT = 0x20;
+    }
+}
+
+/// BN_CLICKED, what a button notifies its parent of when it gets clicked.
+pub const BN_CLICKED: u16 = 0;
+
+/// The kinds of static controls, in the lowest four bits of the style of a static control.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StaticType {
+    Left = 0x0,
+    Center = 0x1,
+    Right = 0x2,
+    Icon = 0x3,
+    BlackRect = 0x4,
+    GrayRect = 0x5,
+    WhiteRect = 0x6,
+    BlackFrame = 0x7,
+    GrayFrame = 0x8,
+    WhiteFrame = 0x9,
+    Simple = 0xb,
+    LeftNoWordWrap = 0xc,
+}
+
+impl From<StaticType> for u16 {
+    fn from(t: StaticType) -> Self {
+        t as u16
+    }
+}
+
+/// The icons that Windows provides, by the name LoadIcon and static controls know them by.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SystemIcon {
+    Application = 32512,
+    Hand = 32513,
+    Question = 32514,
+    Exclamation = 32515,
+    Asterisk = 32516,
+}
+
+impl From<SystemIcon> for u16 {
+    fn from(icon: SystemIcon) -> Self {
+        icon as u16
+    }
+}
+
+/// The buttons of a message box, in the lowest four bits of its style.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MessageBoxType {
+    Ok = 0x0,
+    OkCancel = 0x1,
+    AbortRetryIgnore = 0x2,
+    YesNoCancel = 0x3,
+    YesNo = 0x4,
+    RetryCancel = 0x5,
+}
+
+/// The icon of a message box, in bits 4 to 7 of its style.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MessageBoxIcon {
+    Hand = 0x10,
+    Question = 0x20,
+    Exclamation = 0x30,
+    Asterisk = 0x40,
+}
+
+bitflags! {
+    pub struct StaticStyles: u16 {
+        /// Shows ampersands instead of underlining the character after them.
+        const NOPREFIX = 0x80;
+    }
+}
+
+/// The messages of static controls, which start at WM_USER.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StaticMessage {
+    SetIcon = 0x400,
+    GetIcon = 0x401,
+}
+
+impl From<StaticMessage> for u16 {
+    fn from(m: StaticMessage) -> Self {
+        m as u16
+    }
+}
+
+bitflags! {
+    #[allow(dead_code)]
+    pub struct EditStyles: u16 {
+        const CENTER = 0x0001;
+        const RIGHT = 0x0002;
+        const MULTILINE = 0x0004;
+        const UPPERCASE = 0x0008;
+        const LOWERCASE = 0x0010;
+        const PASSWORD = 0x0020;
+        const AUTOVSCROLL = 0x0040;
+        const AUTOHSCROLL = 0x0080;
+        const NOHIDESEL = 0x0100;
+        const OEMCONVERT = 0x0400;
+        const READONLY = 0x0800;
+        const WANTRETURN = 0x1000;
+    }
+}
+
+/// The messages of edit controls, which start at WM_USER.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EditMessage {
+    GetSel = 0x400,
+    SetSel = 0x401,
+    GetRect = 0x402,
+    SetRect = 0x403,
+    SetRectNp = 0x404,
+    Scroll = 0x405,
+    LineScroll = 0x406,
+    GetModify = 0x408,
+    SetModify = 0x409,
+    GetLineCount = 0x40a,
+    LineIndex = 0x40b,
+    SetHandle = 0x40c,
+    GetHandle = 0x40d,
+    GetThumb = 0x40e,
+    LineLength = 0x411,
+    ReplaceSel = 0x412,
+    SetFont = 0x413,
+    GetLine = 0x414,
+    LimitText = 0x415,
+    CanUndo = 0x416,
+    Undo = 0x417,
+    FmtLines = 0x418,
+    LineFromChar = 0x419,
+    SetWordBreak = 0x41a,
+    SetTabStops = 0x41b,
+    SetPasswordChar = 0x41c,
+    EmptyUndoBuffer = 0x41d,
+    GetFirstVisibleLine = 0x41e,
+    SetReadOnly = 0x41f,
+}
+
+impl From<EditMessage> for u16 {
+    fn from(m: EditMessage) -> Self {
+        m as u16
+    }
+}
+
+/// What an edit control notifies its parent of with WM_COMMAND.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EditNotification {
+    SetFocus = 0x100,
+    KillFocus = 0x200,
+    Change = 0x300,
+    Update = 0x400,
+    ErrSpace = 0x500,
+    MaxText = 0x501,
+    HScroll = 0x601,
+    VScroll = 0x602,
+}
+
+impl From<EditNotification> for u16 {
+    fn from(n: EditNotification) -> Self {
+        n as u16
+    }
+}
+
+bitflags! {
+    #[allow(dead_code)]
+    pub struct ListBoxStyles: u16 {
+        const NOTIFY = 0x0001;
+        const SORT = 0x0002;
+        const NOREDRAW = 0x0004;
+        const MULTIPLESEL = 0x0008;
+        const OWNERDRAWFIXED = 0x0010;
+        const OWNERDRAWVARIABLE = 0x0020;
+        const HASSTRINGS = 0x0040;
+        const USETABSTOPS = 0x0080;
+        const NOINTEGRALHEIGHT = 0x0100;
+        const MULTICOLUMN = 0x0200;
+        const WANTKEYBOARDINPUT = 0x0400;
+        const EXTENDEDSEL = 0x0800;
+        const DISABLENOSCROLL = 0x1000;
+    }
+}
+
+/// The messages of list boxes, which start at WM_USER + 1.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ListBoxMessage {
+    AddString = 0x401,
+    InsertString = 0x402,
+    DeleteString = 0x403,
+    ResetContent = 0x405,
+    SetSel = 0x406,
+    SetCurSel = 0x407,
+    GetSel = 0x408,
+    GetCurSel = 0x409,
+    GetText = 0x40a,
+    GetTextLen = 0x40b,
+    GetCount = 0x40c,
+    SelectString = 0x40d,
+    Dir = 0x40e,
+    GetTopIndex = 0x40f,
+    FindString = 0x410,
+    GetSelCount = 0x411,
+    GetSelItems = 0x412,
+    SetTabStops = 0x413,
+    GetHorizontalExtent = 0x414,
+    SetHorizontalExtent = 0x415,
+    SetColumnWidth = 0x416,
+    SetTopIndex = 0x418,
+    GetItemRect = 0x419,
+    GetItemData = 0x41a,
+    SetItemData = 0x41b,
+    SelItemRange = 0x41c,
+    SetCaretIndex = 0x41f,
+    GetCaretIndex = 0x420,
+    SetItemHeight = 0x421,
+    GetItemHeight = 0x422,
+    FindStringExact = 0x423,
+}
+
+impl From<ListBoxMessage> for u16 {
+    fn from(m: ListBoxMessage) -> Self {
+        m as u16
+    }
+}
+
+/// What a list box with LBS_NOTIFY notifies its parent of with WM_COMMAND.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ListBoxNotification {
+    SelChange = 1,
+    DblClk = 2,
+    SelCancel = 3,
+    SetFocus = 4,
+    KillFocus = 5,
+}
+
+impl From<ListBoxNotification> for u16 {
+    fn from(n: ListBoxNotification) -> Self {
+        n as u16
+    }
+}
+
+/// The kinds of combo boxes, in the lowest two bits of the style of a combo box.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ComboBoxType {
+    Simple = 1,
+    DropDown = 2,
+    DropDownList = 3,
+}
+
+bitflags! {
+    #[allow(dead_code)]
+    pub struct ComboBoxStyles: u16 {
+        const OWNERDRAWFIXED = 0x0010;
+        const OWNERDRAWVARIABLE = 0x0020;
+        const AUTOHSCROLL = 0x0040;
+        const OEMCONVERT = 0x0080;
+        const SORT = 0x0100;
+        const HASSTRINGS = 0x0200;
+        const NOINTEGRALHEIGHT = 0x0400;
+    }
+}
+
+/// The messages of combo boxes, which start at WM_USER.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ComboBoxMessage {
+    GetEditSel = 0x400,
+    LimitText = 0x401,
+    SetEditSel = 0x402,
+    AddString = 0x403,
+    DeleteString = 0x404,
+    Dir = 0x405,
+    GetCount = 0x406,
+    GetCurSel = 0x407,
+    GetLbText = 0x408,
+    GetLbTextLen = 0x409,
+    InsertString = 0x40a,
+    ResetContent = 0x40b,
+    FindString = 0x40c,
+    SelectString = 0x40d,
+    SetCurSel = 0x40e,
+    ShowDropDown = 0x40f,
+    GetItemData = 0x410,
+    SetItemData = 0x411,
+    GetDroppedControlRect = 0x412,
+    SetItemHeight = 0x413,
+    GetItemHeight = 0x414,
+    SetExtendedUi = 0x415,
+    GetExtendedUi = 0x416,
+    GetDroppedState = 0x417,
+    FindStringExact = 0x418,
+}
+
+impl From<ComboBoxMessage> for u16 {
+    fn from(m: ComboBoxMessage) -> Self {
+        m as u16
+    }
+}
+
+/// What a combo box notifies its parent of with WM_COMMAND.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ComboBoxNotification {
+    SelChange = 1,
+    DblClk = 2,
+    SetFocus = 3,
+    KillFocus = 4,
+    EditChange = 5,
+    EditUpdate = 6,
+    DropDown = 7,
+    CloseUp = 8,
+}
+
+impl From<ComboBoxNotification> for u16 {
+    fn from(n: ComboBoxNotification) -> Self {
+        n as u16
+    }
+}
+
+bitflags! {
+    pub struct ScrollBarStyles: u16 {
+        const VERT = 0x0001;
+    }
+}
+
+/// The messages of scroll bar controls, which start at WM_USER.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScrollBarMessage {
+    SetPos = 0x400,
+    GetPos = 0x401,
+    SetRange = 0x402,
+    GetRange = 0x403,
+    EnableArrows = 0x404,
+}
+
+impl From<ScrollBarMessage> for u16 {
+    fn from(m: ScrollBarMessage) -> Self {
+        m as u16
+    }
+}
+
+/// SB_CTL, the scroll bar functions work on a scroll bar control instead of the scroll bars
+/// of a window.
+pub const SB_CTL: u16 = 2;
+
+/// What a scroll bar asks its parent to do with WM_HSCROLL or WM_VSCROLL.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScrollCode {
+    LineUp = 0,
+    LineDown = 1,
+    PageUp = 2,
+    PageDown = 3,
+    ThumbPosition = 4,
+    ThumbTrack = 5,
+    Top = 6,
+    Bottom = 7,
+    EndScroll = 8,
+}
+
+impl From<ScrollCode> for u16 {
+    fn from(c: ScrollCode) -> Self {
+        c as u16
+    }
+}
+
+/// How a window got activated or deactivated, as WM_ACTIVATE tells it in wParam.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ActivateState {
+    Inactive = 0,
+    Active = 1,
+    ClickActive = 2,
+}
+
+impl From<ActivateState> for u16 {
+    fn from(s: ActivateState) -> Self {
+        s as u16
+    }
+}
+
+/// What a window answers to WM_MOUSEACTIVATE: whether a click activates its top-level window,
+/// and whether the click itself gets thrown away.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MouseActivate {
+    Activate = 1,
+    ActivateAndEat = 2,
+    NoActivate = 3,
+    NoActivateAndEat = 4,
+}
+
+impl From<MouseActivate> for u16 {
+    fn from(m: MouseActivate) -> Self {
+        m as u16
+    }
+}
+
+/// The kind of size change a WM_SIZE message reports.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SizeType {
+    Restored = 0,
+    Minimized = 1,
+    Maximized = 2,
+    MaxShow = 3,
+    MaxHide = 4,
+}
+
+impl From<SizeType> for u16 {
+    fn from(s: SizeType) -> Self {
+        s as u16
+    }
+}
+
+/// What a WH_CBT hook gets told about.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CbtCode {
+    MoveSize = 0,
+    MinMax = 1,
+    Qs = 2,
+    CreateWnd = 3,
+    DestroyWnd = 4,
+    Activate = 5,
+    ClickSkipped = 6,
+    KeySkipped = 7,
+    SysCommand = 8,
+    SetFocus = 9,
+}
+
+impl From<CbtCode> for u16 {
+    fn from(c: CbtCode) -> Self {
+        c as u16
+    }
+}
+
+/// The modal loops that call WH_MSGFILTER hooks.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MessageFilterCode {
+    DialogBox = 0,
+    MessageBox = 1,
+    Menu = 2,
+    Move = 3,
+    Size = 4,
+    ScrollBar = 5,
+    NextWindow = 6,
+}
+
+impl From<MessageFilterCode> for u16 {
+    fn from(c: MessageFilterCode) -> Self {
+        c as u16
+    }
+}
+
+/// The commands of WM_SYSCOMMAND. Windows uses the lowest four bits internally.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SystemCommand {
+    Size = 0xf000,
+    Move = 0xf010,
+    Minimize = 0xf020,
+    Maximize = 0xf030,
+    NextWindow = 0xf040,
+    PrevWindow = 0xf050,
+    Close = 0xf060,
+    VScroll = 0xf070,
+    HScroll = 0xf080,
+    MouseMenu = 0xf090,
+    KeyMenu = 0xf100,
+    Arrange = 0xf110,
+    Restore = 0xf120,
+    TaskList = 0xf130,
+}
+
+impl From<SystemCommand> for u16 {
+    fn from(c: SystemCommand) -> Self {
+        c as u16
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ShowWindowCommand {
+    Hide = 0,
+    ShowNormal = 1,
+    ShowMinimized = 2,
+    ShowMaximized = 3,
+    ShowNoActivate = 4,
+    Show = 5,
+    Minimize = 6,
+    ShowMinNoActive = 7,
+    ShowNA = 8,
+    Restore = 9,
+}
+
+impl From<ShowWindowCommand> for u16 {
+    fn from(c: ShowWindowCommand) -> Self {
+        c as u16
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GetWindowCommand {
+    HwndFirst = 0,
+    HwndLast = 1,
+    HwndNext = 2,
+    HwndPrev = 3,
+    Owner = 4,
+    Child = 5,
+}
+
+impl From<GetWindowCommand> for u16 {
+    fn from(c: GetWindowCommand) -> Self {
+        c as u16
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Eq, PartialEq, FromPrimitive)]
 pub enum SystemColors {
@@ -0,0 +1,3362 @@
+//! The predefined control classes: what USER keeps about buttons, static controls, edit
+//! controls, list boxes, combo boxes and scroll bars, how they are laid out and drawn, and the
+//! window procedures of their classes.
+
+use crate::api_helpers::{Pointer, ReturnValue};
+use crate::bitmap::{Bitmap, Color};
+use crate::constants::{
+    ButtonMessage, ButtonStyles, ButtonType, ComboBoxMessage, ComboBoxNotification, ComboBoxStyles,
+    ComboBoxType, EditMessage, EditNotification, EditStyles, ListBoxMessage, ListBoxNotification,
+    ListBoxStyles, MessageType, ScrollBarMessage, ScrollBarStyles, ScrollCode, SetWindowPosFlags,
+    StaticMessage, StaticStyles, StaticType, SystemColors, SystemIcon, WindowStyles, BN_CLICKED,
+};
+use crate::emulated_user::{get_text, read_string_parameter, write_string, EmulatedUser};
+use crate::emulator_accessor::EmulatorAccessor;
+use crate::font;
+use crate::handle_table::{GenericHandle, Handle};
+use crate::keyboard::{
+    VK_CONTROL, VK_DELETE, VK_DOWN, VK_END, VK_ESCAPE, VK_F4, VK_HOME, VK_LEFT, VK_NEXT, VK_PRIOR,
+    VK_RETURN, VK_RIGHT, VK_SHIFT, VK_SPACE, VK_UP,
+};
+use crate::menu;
+use crate::non_client::ICON_SIZE;
+use crate::object_environment::UserObject;
+use crate::two_d::{Point, Rect};
+use crate::util::encode_u16_u16_to_u32;
+use crate::{debug, EmulatorError};
+use syscall::api_function;
+
+/// LB_ERR and CB_ERR, what list boxes and combo boxes return when something goes wrong.
+pub const LIST_ERROR: u32 = 0xffff_ffff;
+/// The most text an edit control takes unless EM_LIMITTEXT says otherwise.
+const DEFAULT_EDIT_LIMIT: usize = 30000;
+/// The space between the edge of an edit control or a list box and its text.
+const TEXT_MARGIN: i16 = 2;
+/// The height of a line of an edit control, and of an item of a list box.
+pub const LINE_HEIGHT: i16 = font::GLYPH_HEIGHT + 2;
+/// The size of the box of a check box, and of the circle of a radio button.
+const CHECK_BOX_SIZE: i16 = 12;
+/// The space between the box of a check box or radio button and its text.
+const CHECK_TEXT_GAP: i16 = 4;
+/// Where the text of a group box starts, from the left edge.
+const GROUP_TEXT_INDENT: i16 = 8;
+/// The height of the selection field of a combo box, which is also the width of its button.
+pub const COMBO_FIELD_HEIGHT: i16 = font::GLYPH_HEIGHT + 8;
+
+/// The outline of a radio button, with the leftmost pixel in the highest of twelve bits.
+const RADIO_OUTLINE: [u16; 12] = [
+    0x0f0, 0x30c, 0x402, 0x402, 0x801, 0x801, 0x801, 0x801, 0x402, 0x402, 0x30c, 0x0f0,
+];
+/// The inside of a radio button.
+const RADIO_INSIDE: [u16; 12] = [
+    0x000, 0x0f0, 0x3fc, 0x3fc, 0x7fe, 0x7fe, 0x7fe, 0x7fe, 0x3fc, 0x3fc, 0x0f0, 0x000,
+];
+/// The dot of a checked radio button.
+const RADIO_DOT: [u16; 12] = [
+    0x000, 0x000, 0x000, 0x000, 0x060, 0x0f0, 0x0f0, 0x060, 0x000, 0x000, 0x000, 0x000,
+];
+
+/// What USER keeps about a window of one of the predefined control classes.
+pub enum Control {
+    Button(Button),
+    Static(Static),
+    Edit(Edit),
+    ListBox(ListBox),
+    ComboBox(ComboBox),
+    ScrollBar(ScrollBar),
+}
+
+pub struct Button {
+    /// 0 if unchecked, 1 if checked, and 2 if a three-state button is indeterminate.
+    pub check: u16,
+    /// Whether the button is shown pushed in, such as while the mouse button is held on it.
+    pub pushed: bool,
+    pub focused: bool,
+}
+
+impl Button {
+    pub fn new() -> Self {
+        Self {
+            check: 0,
+            pushed: false,
+            focused: false,
+        }
+    }
+
+    /// The state as BM_GETSTATE gives it: the check state, with 4 for pushed and 8 for focused.
+    pub fn state(&self) -> u16 {
+        self.check | if self.pushed { 4 } else { 0 } | if self.focused { 8 } else { 0 }
+    }
+}
+
+/// The kind of button that a button style asks for. Unknown kinds are push buttons.
+pub fn button_type(style: u16) -> ButtonType {
+    match style & 0xf {
+        0x1 => ButtonType::DefPushButton,
+        0x2 => ButtonType::CheckBox,
+        0x3 => ButtonType::AutoCheckBox,
+        0x4 => ButtonType::RadioButton,
+        0x5 => ButtonType::ThreeState,
+        0x6 => ButtonType::AutoThreeState,
+        0x7 => ButtonType::GroupBox,
+        0x8 => ButtonType::UserButton,
+        0x9 => ButtonType::AutoRadioButton,
+        0xb => ButtonType::OwnerDraw,
+        _ => ButtonType::PushButton,
+    }
+}
+
+/// Whether a kind of button is a radio button, which unchecks the others of its group.
+pub fn is_radio_button(button_type: ButtonType) -> bool {
+    matches!(
+        button_type,
+        ButtonType::RadioButton | ButtonType::AutoRadioButton
+    )
+}
+
+pub struct Static {
+    /// The icon that a static control with SS_ICON shows.
+    pub icon: Handle,
+    /// The icon of Windows that the text of a static control with SS_ICON names, if any.
+    pub system_icon: Option<SystemIcon>,
+}
+
+/// The icon of Windows that a resource name such as "#32513" stands for.
+pub fn system_icon(name: &[u8]) -> Option<SystemIcon> {
+    let number: u16 = std::str::from_utf8(name.strip_prefix(b"#")?)
+        .ok()?
+        .parse()
+        .ok()?;
+    [
+        SystemIcon::Application,
+        SystemIcon::Hand,
+        SystemIcon::Question,
+        SystemIcon::Exclamation,
+        SystemIcon::Asterisk,
+    ]
+    .into_iter()
+    .find(|&icon| u16::from(icon) == number)
+}
+
+/// The kind of static control that a static style asks for. Unknown kinds show text on the
+/// left.
+pub fn static_type(style: u16) -> StaticType {
+    match style & 0xf {
+        0x1 => StaticType::Center,
+        0x2 => StaticType::Right,
+        0x3 => StaticType::Icon,
+        0x4 => StaticType::BlackRect,
+        0x5 => StaticType::GrayRect,
+        0x6 => StaticType::WhiteRect,
+        0x7 => StaticType::BlackFrame,
+        0x8 => StaticType::GrayFrame,
+        0x9 => StaticType::WhiteFrame,
+        0xb => StaticType::Simple,
+        0xc => StaticType::LeftNoWordWrap,
+        _ => StaticType::Left,
+    }
+}
+
+pub struct Edit {
+    pub text: Vec<u8>,
+    /// Where the selection started, which stays put while the selection gets extended.
+    pub anchor: usize,
+    /// Where the caret is, which is the other end of the selection.
+    pub caret: usize,
+    pub limit: usize,
+    pub modified: bool,
+    /// The text and the selection before the last change, which EM_UNDO goes back to.
+    pub undo: Option<(Vec<u8>, usize, usize)>,
+    /// The line at the top of a multiline edit control.
+    pub first_line: usize,
+    /// The column at the left edge, for text that is wider than the edit control.
+    pub first_column: usize,
+    /// The character that a password edit control shows instead of the text.
+    pub password_char: u8,
+    pub focused: bool,
+}
+
+impl Edit {
+    pub fn new(text: &[u8]) -> Self {
+        Self {
+            text: text.to_vec(),
+            anchor: 0,
+            caret: 0,
+            limit: DEFAULT_EDIT_LIMIT,
+            modified: false,
+            undo: None,
+            first_line: 0,
+            first_column: 0,
+            password_char: 0,
+            focused: false,
+        }
+    }
+
+    /// The start and the end of the selection.
+    pub fn selection(&self) -> (usize, usize) {
+        (self.anchor.min(self.caret), self.anchor.max(self.caret))
+    }
+
+    /// Selects from start to end, which may be past the end of the text.
+    pub fn set_selection(&mut self, start: usize, end: usize) {
+        self.anchor = self.snap(start);
+        self.caret = self.snap(end);
+    }
+
+    /// Keeps an index within the text, and out of the middle of a CR LF line break, where it
+    /// goes to the end of the line instead.
+    fn snap(&self, index: usize) -> usize {
+        let index = index.min(self.text.len());
+        if index > 0 && self.text[index - 1] == b'\r' && self.text.get(index) == Some(&b'\n') {
+            index - 1
+        } else {
+            index
+        }
+    }
+
+    /// Replaces all the text, which can't be undone.
+    pub fn set_text(&mut self, text: &[u8]) {
+        self.text = text.to_vec();
+        self.anchor = 0;
+        self.caret = 0;
+        self.first_line = 0;
+        self.first_column = 0;
+        self.modified = false;
+        self.undo = None;
+    }
+
+    /// Replaces the selection with the given text, or with as much of it as the limit allows.
+    /// The caret ends up after the new text. Returns false if not all of the text fit.
+    pub fn replace_selection(&mut self, text: &[u8]) -> bool {
+        let (start, end) = self.selection();
+        let room = self.limit.saturating_sub(self.text.len() - (end - start));
+        let fitting = &text[..text.len().min(room)];
+        if start != end || !fitting.is_empty() {
+            self.undo = Some((self.text.clone(), self.anchor, self.caret));
+            self.text.splice(start..end, fitting.iter().copied());
+            self.modified = true;
+        }
+        self.anchor = start + fitting.len();
+        self.caret = self.anchor;
+        fitting.len() == text.len()
+    }
+
+    /// Goes back to the text before the last change. Undoing twice redoes the change.
+    pub fn undo(&mut self) -> bool {
+        match self.undo.take() {
+            Some((text, anchor, caret)) => {
+                self.undo = Some((
+                    std::mem::replace(&mut self.text, text),
+                    self.anchor,
+                    self.caret,
+                ));
+                self.anchor = anchor;
+                self.caret = caret;
+                self.modified = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Where the lines start and end, without the line breaks. A line break is CR LF, or LF.
+    pub fn lines(&self) -> Vec<(usize, usize)> {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for (index, &byte) in self.text.iter().enumerate() {
+            if byte == b'\n' {
+                let end = if index > start && self.text[index - 1] == b'\r' {
+                    index - 1
+                } else {
+                    index
+                };
+                lines.push((start, end));
+                start = index + 1;
+            }
+        }
+        lines.push((start, self.text.len()));
+        lines
+    }
+
+    /// The line that the character at the given index is on.
+    pub fn line_from_char(&self, index: usize) -> usize {
+        let lines = self.lines();
+        lines
+            .iter()
+            .position(|&(_, end)| index <= end)
+            .unwrap_or(lines.len() - 1)
+    }
+
+    /// Moves the caret, and extends the selection up to it or drops the selection.
+    pub fn move_caret(&mut self, to: usize, extend: bool) {
+        self.caret = self.snap(to);
+        if !extend {
+            self.anchor = self.caret;
+        }
+    }
+
+    /// Where the caret goes when Left or Right is pressed. Line breaks are skipped at once.
+    pub fn neighbour(&self, forward: bool) -> usize {
+        let caret = self.caret;
+        if forward {
+            match self.text.get(caret..caret + 2) {
+                Some(b"\r\n") => caret + 2,
+                _ => (caret + 1).min(self.text.len()),
+            }
+        } else if caret >= 2 && &self.text[caret - 2..caret] == b"\r\n" {
+            caret - 2
+        } else {
+            caret.saturating_sub(1)
+        }
+    }
+
+    /// Where the caret goes on the line the given number of lines away, in the same column if
+    /// that line is long enough.
+    pub fn vertical_neighbour(&self, lines_away: isize) -> usize {
+        let lines = self.lines();
+        let line = self.line_from_char(self.caret);
+        let column = self.caret.saturating_sub(lines[line].0);
+        let target = (line as isize + lines_away).clamp(0, lines.len() as isize - 1) as usize;
+        let (start, end) = lines[target];
+        (start + column).min(end)
+    }
+
+    /// Where the line with the caret starts and ends.
+    pub fn caret_line(&self) -> (usize, usize) {
+        self.lines()[self.line_from_char(self.caret)]
+    }
+
+    /// Deletes the selection, or else the character before or after the caret. Returns whether
+    /// anything got deleted.
+    pub fn delete(&mut self, forward: bool) -> bool {
+        if self.anchor == self.caret {
+            self.anchor = self.neighbour(forward);
+        }
+        if self.anchor == self.caret {
+            return false;
+        }
+        self.replace_selection(&[]);
+        true
+    }
+
+    /// Handles a key that moves the caret, which extends the selection while Shift is held, or
+    /// Delete. Page Up and Page Down move by the given number of lines. Returns None for other
+    /// keys, and otherwise whether the text changed.
+    pub fn key_down(
+        &mut self,
+        vk: u8,
+        shift: bool,
+        control: bool,
+        page: usize,
+        read_only: bool,
+    ) -> Option<bool> {
+        let (start, end) = self.selection();
+        let to = match vk {
+            VK_LEFT if start != end && !shift => start,
+            VK_RIGHT if start != end && !shift => end,
+            VK_LEFT => self.neighbour(false),
+            VK_RIGHT => self.neighbour(true),
+            VK_UP => self.vertical_neighbour(-1),
+            VK_DOWN => self.vertical_neighbour(1),
+            VK_PRIOR => self.vertical_neighbour(-(page as isize)),
+            VK_NEXT => self.vertical_neighbour(page as isize),
+            VK_HOME if control => 0,
+            VK_END if control => self.text.len(),
+            VK_HOME => self.caret_line().0,
+            VK_END => self.caret_line().1,
+            VK_DELETE if !read_only => return Some(self.delete(true)),
+            _ => return None,
+        };
+        self.move_caret(to, shift);
+        Some(false)
+    }
+
+    /// Types a character over the selection. Backspace deletes, Enter starts a new line in a
+    /// multiline edit control, and other control characters are ignored. Returns None if the
+    /// text didn't change, and otherwise whether all of the typed text fit.
+    pub fn type_character(&mut self, character: u8, styles: EditStyles) -> Option<bool> {
+        if styles.contains(EditStyles::READONLY) {
+            return None;
+        }
+        match character {
+            0x08 => self.delete(false).then_some(true),
+            b'\r' if styles.contains(EditStyles::MULTILINE) => {
+                Some(self.replace_selection(b"\r\n"))
+            }
+            0x00..=0x1f => None,
+            _ => {
+                let character = if styles.contains(EditStyles::UPPERCASE) {
+                    character.to_ascii_uppercase()
+                } else if styles.contains(EditStyles::LOWERCASE) {
+                    character.to_ascii_lowercase()
+                } else {
+                    character
+                };
+                Some(self.replace_selection(&[character]))
+            }
+        }
+    }
+
+    /// Scrolls so that the caret can be seen, in an edit control of the given size.
+    pub fn scroll_to_caret(&mut self, size: (i16, i16)) {
+        let (visible_lines, visible_columns) = edit_capacity(size);
+        let line = self.line_from_char(self.caret);
+        let column = self.caret.saturating_sub(self.lines()[line].0);
+        if line < self.first_line {
+            self.first_line = line;
+        } else if line >= self.first_line + visible_lines {
+            self.first_line = line + 1 - visible_lines;
+        }
+        if column < self.first_column {
+            self.first_column = column;
+        } else if column >= self.first_column + visible_columns {
+            self.first_column = column + 1 - visible_columns;
+        }
+    }
+
+    /// The character that is closest to a point in an edit control of the given size.
+    pub fn index_at(&self, size: (i16, i16), multiline: bool, point: Point) -> usize {
+        let origin = edit_text_origin(size, multiline);
+        let lines = self.lines();
+        let line = if point.y < origin.y {
+            self.first_line.saturating_sub(1)
+        } else {
+            self.first_line + ((point.y - origin.y) / LINE_HEIGHT) as usize
+        };
+        let (start, end) = lines[line.min(lines.len() - 1)];
+        let column = if point.x < origin.x {
+            self.first_column.saturating_sub(1)
+        } else {
+            self.first_column
+                + ((point.x - origin.x + font::GLYPH_ADVANCE / 2) / font::GLYPH_ADVANCE) as usize
+        };
+        (start + column).min(end)
+    }
+}
+
+/// How many lines and columns of text an edit control of the given size shows. There is always
+/// room for at least one.
+pub fn edit_capacity((width, height): (i16, i16)) -> (usize, usize) {
+    let lines = (height - 2 * TEXT_MARGIN) / LINE_HEIGHT;
+    let columns = (width - 2 * TEXT_MARGIN) / font::GLYPH_ADVANCE;
+    (lines.max(1) as usize, columns.max(1) as usize)
+}
+
+/// Where the first visible line of an edit control starts. A single line is centred
+/// vertically.
+fn edit_text_origin((_, height): (i16, i16), multiline: bool) -> Point {
+    if multiline {
+        Point::new(TEXT_MARGIN, TEXT_MARGIN)
+    } else {
+        Point::new(TEXT_MARGIN, ((height - font::GLYPH_HEIGHT) / 2).max(0))
+    }
+}
+
+pub struct ListItem {
+    pub text: Vec<u8>,
+    /// The value that LB_SETITEMDATA stored with the item.
+    pub data: u32,
+    pub selected: bool,
+}
+
+pub struct ListBox {
+    pub items: Vec<ListItem>,
+    /// The item that the keyboard works on, which is the selected item in a list box where
+    /// only a single item can be selected.
+    pub caret: Option<usize>,
+    /// The item at the top.
+    pub top: usize,
+    pub sorted: bool,
+    /// Whether several items can be selected at once.
+    pub multiple: bool,
+    pub focused: bool,
+}
+
+impl ListBox {
+    pub fn new(sorted: bool, multiple: bool) -> Self {
+        Self {
+            items: Vec::new(),
+            caret: None,
+            top: 0,
+            sorted,
+            multiple,
+            focused: false,
+        }
+    }
+
+    /// Inserts an item at the given index, or adds it if there is no index: at its place in
+    /// a sorted list box, and at the end otherwise. Returns the index the item ended up at.
+    pub fn insert(&mut self, index: Option<usize>, text: Vec<u8>) -> usize {
+        let index = match index {
+            Some(index) => index.min(self.items.len()),
+            None if self.sorted => self.items.partition_point(|item| {
+                compare_ignoring_case(&item.text, &text) != std::cmp::Ordering::Greater
+            }),
+            None => self.items.len(),
+        };
+        self.items.insert(
+            index,
+            ListItem {
+                text,
+                data: 0,
+                selected: false,
+            },
+        );
+        if let Some(caret) = self.caret.as_mut() {
+            if *caret >= index {
+                *caret += 1;
+            }
+        }
+        index
+    }
+
+    pub fn delete(&mut self, index: usize) -> bool {
+        if index >= self.items.len() {
+            return false;
+        }
+        self.items.remove(index);
+        self.caret = match self.caret {
+            Some(caret) if caret == index => None,
+            Some(caret) if caret > index => Some(caret - 1),
+            caret => caret,
+        };
+        self.top = self.top.min(self.items.len().saturating_sub(1));
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.caret = None;
+        self.top = 0;
+    }
+
+    /// The first item after the given one that starts with the given text, or is the given
+    /// text if it has to be exact. Case doesn't matter. The search wraps around to the start.
+    pub fn find(&self, after: Option<usize>, text: &[u8], exact: bool) -> Option<usize> {
+        let count = self.items.len();
+        let start = after.map_or(0, |after| after + 1);
+        (0..count)
+            .map(|step| (start + step) % count)
+            .find(|&index| {
+                let item = &self.items[index].text;
+                if exact {
+                    item.eq_ignore_ascii_case(text)
+                } else {
+                    item.len() >= text.len() && item[..text.len()].eq_ignore_ascii_case(text)
+                }
+            })
+    }
+
+    /// The selected item of a list box where only a single item can be selected.
+    pub fn current(&self) -> Option<usize> {
+        self.items.iter().position(|item| item.selected)
+    }
+
+    /// Selects a single item, or nothing, and puts the caret on it.
+    pub fn select(&mut self, index: Option<usize>) {
+        let index = index.filter(|&index| index < self.items.len());
+        for (item_index, item) in self.items.iter_mut().enumerate() {
+            item.selected = Some(item_index) == index;
+        }
+        if index.is_some() {
+            self.caret = index;
+        }
+    }
+
+    /// Selects or deselects an item, or every item if there is no index, in a list box where
+    /// several items can be selected.
+    pub fn set_selected(&mut self, index: Option<usize>, selected: bool) -> bool {
+        match index {
+            Some(index) => match self.items.get_mut(index) {
+                Some(item) => {
+                    item.selected = selected;
+                    true
+                }
+                None => false,
+            },
+            None => {
+                for item in &mut self.items {
+                    item.selected = selected;
+                }
+                true
+            }
+        }
+    }
+
+    /// Scrolls so that the given item can be seen in a list box of the given height.
+    pub fn scroll_to(&mut self, index: usize, height: i16) {
+        let visible = list_capacity(height);
+        if index < self.top {
+            self.top = index;
+        } else if index >= self.top + visible {
+            self.top = index + 1 - visible;
+        }
+    }
+
+    /// The item that a key moves the caret to, in a list box that shows the given number of
+    /// items at once. Returns None for keys that don't move the caret.
+    pub fn key_target(&self, vk: u8, page: usize) -> Option<usize> {
+        let last = self.items.len().checked_sub(1)?;
+        let target = match (vk, self.caret) {
+            (VK_UP | VK_DOWN | VK_PRIOR | VK_NEXT, None) | (VK_HOME, _) => 0,
+            (VK_UP, Some(caret)) => caret.saturating_sub(1),
+            (VK_DOWN, Some(caret)) => caret + 1,
+            (VK_PRIOR, Some(caret)) => caret.saturating_sub(page),
+            (VK_NEXT, Some(caret)) => caret + page,
+            (VK_END, _) => last,
+            _ => return None,
+        };
+        Some(target.min(last))
+    }
+
+    /// Chooses an item the way clicking it or moving to it with the keyboard does. A list box
+    /// where only a single item can be selected selects it. Otherwise the caret moves to it,
+    /// and toggling selects or deselects it. Returns whether the selection changed.
+    pub fn choose(&mut self, index: usize, toggle: bool) -> bool {
+        if !self.multiple {
+            let changed = self.current() != Some(index);
+            self.select(Some(index));
+            return changed;
+        }
+        self.caret = Some(index);
+        match self.items.get_mut(index) {
+            Some(item) if toggle => {
+                item.selected = !item.selected;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The item at the given height in the list box, if there is one there.
+    pub fn item_at(&self, y: i16) -> Option<usize> {
+        if y < 0 {
+            return None;
+        }
+        let index = self.top + (y / LINE_HEIGHT) as usize;
+        (index < self.items.len()).then_some(index)
+    }
+
+    /// Where an item is in the list box, relative to the top left corner of the list box.
+    pub fn item_rect(&self, index: usize, width: i16) -> Rect {
+        let top = (index as i16 - self.top as i16).saturating_mul(LINE_HEIGHT);
+        Rect {
+            left: 0,
+            top,
+            right: width,
+            bottom: top.saturating_add(LINE_HEIGHT),
+        }
+    }
+}
+
+/// How many items a list box of the given height shows. There is always room for one.
+pub fn list_capacity(height: i16) -> usize {
+    (height / LINE_HEIGHT).max(1) as usize
+}
+
+fn compare_ignoring_case(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+    a.iter()
+        .map(u8::to_ascii_lowercase)
+        .cmp(b.iter().map(u8::to_ascii_lowercase))
+}
+
+pub struct ComboBox {
+    pub combo_box_type: ComboBoxType,
+    pub list: ListBox,
+    /// The text in the selection field, which can be edited unless this is a drop-down list.
+    pub edit: Edit,
+    pub dropped: bool,
+    /// The height of the combo box with its list dropped down, as it was created.
+    pub dropped_height: i16,
+}
+
+impl ComboBox {
+    pub fn new(style: u16, dropped_height: i16, sorted: bool) -> Self {
+        Self {
+            combo_box_type: combo_box_type(style),
+            list: ListBox::new(sorted, false),
+            edit: Edit::new(&[]),
+            dropped: false,
+            dropped_height,
+        }
+    }
+
+    /// Selects an item, or nothing, and shows its text in the selection field.
+    pub fn select(&mut self, index: Option<usize>) {
+        self.list.select(index);
+        let text = self
+            .list
+            .current()
+            .map_or(Vec::new(), |index| self.list.items[index].text.clone());
+        self.edit.set_text(&text);
+        self.edit.set_selection(0, text.len());
+    }
+
+    /// Whether the list shows below the selection field, which it always does in a simple
+    /// combo box.
+    pub fn list_shown(&self) -> bool {
+        self.dropped || self.combo_box_type == ComboBoxType::Simple
+    }
+
+    /// Where the selection field is in a combo box of the given width.
+    pub fn field_rect(&self, width: i16) -> Rect {
+        let right = if self.combo_box_type == ComboBoxType::Simple {
+            width
+        } else {
+            width - COMBO_FIELD_HEIGHT
+        };
+        Rect {
+            left: 0,
+            top: 0,
+            right,
+            bottom: COMBO_FIELD_HEIGHT,
+        }
+    }
+
+    /// Where the button that drops the list down is, if the combo box has one.
+    pub fn button_rect(&self, width: i16) -> Option<Rect> {
+        (self.combo_box_type != ComboBoxType::Simple).then(|| Rect {
+            left: width - COMBO_FIELD_HEIGHT,
+            top: 0,
+            right: width,
+            bottom: COMBO_FIELD_HEIGHT,
+        })
+    }
+
+    /// Where the list is, inside its frame, in a combo box of the given size.
+    pub fn list_rect(&self, (width, height): (i16, i16)) -> Rect {
+        Rect {
+            left: 1,
+            top: COMBO_FIELD_HEIGHT + 1,
+            right: width - 1,
+            bottom: height - 1,
+        }
+    }
+}
+
+/// The list box message that does the same as a combo box message, for the messages that
+/// combo boxes pass on to their list.
+pub fn combo_box_list_message(msg: u16) -> Option<u16> {
+    [
+        (ComboBoxMessage::AddString, ListBoxMessage::AddString),
+        (ComboBoxMessage::DeleteString, ListBoxMessage::DeleteString),
+        (ComboBoxMessage::GetCount, ListBoxMessage::GetCount),
+        (ComboBoxMessage::GetLbText, ListBoxMessage::GetText),
+        (ComboBoxMessage::GetLbTextLen, ListBoxMessage::GetTextLen),
+        (ComboBoxMessage::InsertString, ListBoxMessage::InsertString),
+        (ComboBoxMessage::ResetContent, ListBoxMessage::ResetContent),
+        (ComboBoxMessage::FindString, ListBoxMessage::FindString),
+        (
+            ComboBoxMessage::FindStringExact,
+            ListBoxMessage::FindStringExact,
+        ),
+        (ComboBoxMessage::GetItemData, ListBoxMessage::GetItemData),
+        (ComboBoxMessage::SetItemData, ListBoxMessage::SetItemData),
+        (
+            ComboBoxMessage::GetItemHeight,
+            ListBoxMessage::GetItemHeight,
+        ),
+        (
+            ComboBoxMessage::SetItemHeight,
+            ListBoxMessage::SetItemHeight,
+        ),
+    ]
+    .into_iter()
+    .find(|&(combo_box_message, _)| msg == u16::from(combo_box_message))
+    .map(|(_, list_box_message)| list_box_message.into())
+}
+
+/// The kind of combo box that a combo box style asks for.
+pub fn combo_box_type(style: u16) -> ComboBoxType {
+    match style & 0x3 {
+        0x2 => ComboBoxType::DropDown,
+        0x3 => ComboBoxType::DropDownList,
+        _ => ComboBoxType::Simple,
+    }
+}
+
+/// The parts of a scroll bar that can be clicked.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ScrollBarPart {
+    LineUp,
+    PageUp,
+    Thumb,
+    PageDown,
+    LineDown,
+}
+
+pub struct ScrollBar {
+    pub min: i16,
+    pub max: i16,
+    pub pos: i16,
+    pub vertical: bool,
+}
+
+impl ScrollBar {
+    pub fn new(vertical: bool) -> Self {
+        Self {
+            min: 0,
+            max: 100,
+            pos: 0,
+            vertical,
+        }
+    }
+
+    /// Moves the thumb, as far as the range allows. Returns where it was.
+    pub fn set_pos(&mut self, pos: i16) -> i16 {
+        let old = self.pos;
+        self.pos = pos.clamp(self.min, self.max.max(self.min));
+        old
+    }
+
+    pub fn set_range(&mut self, min: i16, max: i16) {
+        self.min = min;
+        self.max = max;
+        self.set_pos(self.pos);
+    }
+
+    /// How long and how thick a scroll bar of the given size is.
+    fn extent(&self, (width, height): (i16, i16)) -> (i16, i16) {
+        if self.vertical {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// How long the arrows at the ends are. They are square unless the scroll bar is too short.
+    fn arrow_length(&self, size: (i16, i16)) -> i16 {
+        let (length, thickness) = self.extent(size);
+        thickness.min(length / 2)
+    }
+
+    /// Where the thumb starts and ends along a scroll bar of the given size. An empty range
+    /// has no thumb.
+    fn thumb_span(&self, size: (i16, i16)) -> Option<(i16, i16)> {
+        let (length, thickness) = self.extent(size);
+        let arrow = self.arrow_length(size);
+        let track = length - 2 * arrow - thickness;
+        if self.max <= self.min || track < 0 {
+            return None;
+        }
+        let offset = (self.pos - self.min) as i32 * track as i32 / (self.max - self.min) as i32;
+        let start = arrow + offset as i16;
+        Some((start, start + thickness))
+    }
+
+    /// The part at a point in a scroll bar of the given size.
+    pub fn part_at(&self, size: (i16, i16), point: Point) -> Option<ScrollBarPart> {
+        let (length, _) = self.extent(size);
+        let along = if self.vertical { point.y } else { point.x };
+        let arrow = self.arrow_length(size);
+        if along < 0 || along >= length {
+            None
+        } else if along < arrow {
+            Some(ScrollBarPart::LineUp)
+        } else if along >= length - arrow {
+            Some(ScrollBarPart::LineDown)
+        } else {
+            match self.thumb_span(size) {
+                Some((start, _)) if along < start => Some(ScrollBarPart::PageUp),
+                Some((_, end)) if along >= end => Some(ScrollBarPart::PageDown),
+                Some(_) => Some(ScrollBarPart::Thumb),
+                None => None,
+            }
+        }
+    }
+
+    /// The position that puts the thumb at a point, when the thumb was grabbed the given
+    /// distance from its start.
+    pub fn pos_at(&self, size: (i16, i16), point: Point, grab: i16) -> i16 {
+        let (length, thickness) = self.extent(size);
+        let along = if self.vertical { point.y } else { point.x };
+        let arrow = self.arrow_length(size);
+        let track = length - 2 * arrow - thickness;
+        if track <= 0 {
+            return self.pos;
+        }
+        let offset = (along - grab - arrow).clamp(0, track);
+        let range = (self.max - self.min) as i32;
+        self.min + ((offset as i32 * range + track as i32 / 2) / track as i32) as i16
+    }
+
+    /// How far from the start of the thumb a point is, along the scroll bar.
+    pub fn grab_offset(&self, size: (i16, i16), point: Point) -> i16 {
+        let along = if self.vertical { point.y } else { point.x };
+        self.thumb_span(size).map_or(0, |(start, _)| along - start)
+    }
+}
+
+/// Draws a control in the given rectangle, which is its client area. Buttons and static
+/// controls show the given text.
+pub fn paint(
+    bitmap: &mut Bitmap,
+    control: &Control,
+    rect: Rect,
+    style: u16,
+    text: &[u8],
+    enabled: bool,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let text_color = if enabled {
+        system_color(SystemColors::WindowText)
+    } else {
+        system_color(SystemColors::GrayText)
+    };
+    match control {
+        Control::Button(button) => {
+            paint_button(bitmap, button, rect, style, text, enabled, system_color)
+        }
+        Control::Static(static_control) => paint_static(
+            bitmap,
+            static_control,
+            rect,
+            style,
+            text,
+            text_color,
+            system_color,
+        ),
+        Control::Edit(edit) => {
+            paint_edit(bitmap, edit, rect, style, text_color, system_color);
+        }
+        Control::ListBox(list) => paint_list(bitmap, list, rect, text_color, system_color),
+        Control::ComboBox(combo_box) => {
+            paint_combo_box(bitmap, combo_box, rect, text_color, system_color)
+        }
+        Control::ScrollBar(scroll_bar) => paint_scroll_bar(bitmap, scroll_bar, rect, system_color),
+    }
+}
+
+fn paint_button(
+    bitmap: &mut Bitmap,
+    button: &Button,
+    rect: Rect,
+    style: u16,
+    text: &[u8],
+    enabled: bool,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let text_color = if enabled {
+        system_color(SystemColors::ButtonText)
+    } else {
+        system_color(SystemColors::GrayText)
+    };
+    let (label, _, _) = menu::split_text(text);
+    let label_width = font::text_width(&label);
+    let text_top = (rect.top + rect.bottom - font::GLYPH_HEIGHT) / 2;
+    let button_type = button_type(style);
+    match button_type {
+        ButtonType::CheckBox
+        | ButtonType::AutoCheckBox
+        | ButtonType::ThreeState
+        | ButtonType::AutoThreeState
+        | ButtonType::RadioButton
+        | ButtonType::AutoRadioButton => {
+            bitmap.fill_rectangle(rect, system_color(SystemColors::Window));
+            let left_text =
+                ButtonStyles::from_bits_truncate(style).contains(ButtonStyles::LEFTTEXT);
+            let (box_left, text_left) = if left_text {
+                (rect.right - CHECK_BOX_SIZE, rect.left)
+            } else {
+                (rect.left, rect.left + CHECK_BOX_SIZE + CHECK_TEXT_GAP)
+            };
+            let box_top = (rect.top + rect.bottom - CHECK_BOX_SIZE) / 2;
+            let check_box = Rect {
+                left: box_left,
+                top: box_top,
+                right: box_left + CHECK_BOX_SIZE,
+                bottom: box_top + CHECK_BOX_SIZE,
+            };
+            if is_radio_button(button_type) {
+                draw_radio_button(bitmap, check_box, button, system_color);
+            } else {
+                draw_check_box(bitmap, check_box, button, system_color);
+            }
+            menu::draw_label(bitmap, Point::new(text_left, text_top), text, text_color);
+            if button.focused {
+                let text_rect = Rect {
+                    left: text_left - 1,
+                    top: text_top - 1,
+                    right: text_left + label_width + 1,
+                    bottom: text_top + font::GLYPH_HEIGHT + 2,
+                };
+                draw_focus_rect(bitmap, text_rect, text_color);
+            }
+        }
+        ButtonType::GroupBox => {
+            let frame = system_color(SystemColors::WindowFrame);
+            let top = rect.top + font::GLYPH_HEIGHT / 2;
+            draw_frame(bitmap, Rect { top, ..rect }, frame);
+            if !label.is_empty() {
+                let text_left = rect.left + GROUP_TEXT_INDENT;
+                bitmap.fill_rectangle(
+                    Rect {
+                        left: text_left - 2,
+                        top: rect.top,
+                        right: text_left + label_width + 2,
+                        bottom: rect.top + font::GLYPH_HEIGHT + 1,
+                    },
+                    system_color(SystemColors::Window),
+                );
+                menu::draw_label(bitmap, Point::new(text_left, rect.top), text, text_color);
+            }
+        }
+        // TODO: owner-drawn buttons should get WM_DRAWITEM instead
+        _ => {
+            let default = button_type == ButtonType::DefPushButton;
+            draw_push_button(bitmap, rect, button.pushed, default, system_color);
+            let shift = i16::from(button.pushed);
+            let text_left = (rect.left + rect.right - label_width) / 2 + shift;
+            menu::draw_label(
+                bitmap,
+                Point::new(text_left, text_top + shift),
+                text,
+                text_color,
+            );
+            if button.focused {
+                let text_rect = Rect {
+                    left: text_left - 2,
+                    top: text_top + shift - 2,
+                    right: text_left + label_width + 2,
+                    bottom: text_top + shift + font::GLYPH_HEIGHT + 2,
+                };
+                draw_focus_rect(bitmap, text_rect, text_color);
+            }
+        }
+    }
+}
+
+/// Draws a push button with a rounded black frame. Pushed buttons show their shadow on the
+/// top left instead of the bottom right. The default push button has a thicker frame.
+fn draw_push_button(
+    bitmap: &mut Bitmap,
+    rect: Rect,
+    pushed: bool,
+    default: bool,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let frame = system_color(SystemColors::WindowFrame);
+    bitmap.draw_horizontal_line(rect.left + 1, rect.top, rect.right - 1, frame);
+    bitmap.draw_horizontal_line(rect.left + 1, rect.bottom - 1, rect.right - 1, frame);
+    bitmap.draw_vertical_line(rect.left, rect.top + 1, rect.bottom - 1, frame);
+    bitmap.draw_vertical_line(rect.right - 1, rect.top + 1, rect.bottom - 1, frame);
+    let inside = if default {
+        draw_frame(bitmap, rect.shrink(1), frame);
+        rect.shrink(2)
+    } else {
+        rect.shrink(1)
+    };
+    if pushed {
+        let shadow = system_color(SystemColors::ButtonShadow);
+        bitmap.fill_rectangle(inside, system_color(SystemColors::ButtonFace));
+        bitmap.draw_horizontal_line(inside.left, inside.top, inside.right, shadow);
+        bitmap.draw_vertical_line(inside.left, inside.top, inside.bottom, shadow);
+    } else {
+        draw_raised(bitmap, inside, system_color);
+    }
+}
+
+/// Draws a raised area with a highlight of two pixels on the top left and a shadow of two
+/// pixels on the bottom right.
+fn draw_raised(bitmap: &mut Bitmap, rect: Rect, system_color: &dyn Fn(SystemColors) -> Color) {
+    let highlight = system_color(SystemColors::ButtonHighlight);
+    let shadow = system_color(SystemColors::ButtonShadow);
+    bitmap.fill_rectangle(rect, system_color(SystemColors::ButtonFace));
+    for inset in 0..2 {
+        let rect = rect.shrink(inset);
+        bitmap.draw_horizontal_line(rect.left, rect.top, rect.right - 1, highlight);
+        bitmap.draw_vertical_line(rect.left, rect.top, rect.bottom - 1, highlight);
+        bitmap.draw_horizontal_line(rect.left, rect.bottom - 1, rect.right, shadow);
+        bitmap.draw_vertical_line(rect.right - 1, rect.top, rect.bottom, shadow);
+    }
+}
+
+fn draw_frame(bitmap: &mut Bitmap, rect: Rect, color: Color) {
+    bitmap.draw_horizontal_line(rect.left, rect.top, rect.right, color);
+    bitmap.draw_horizontal_line(rect.left, rect.bottom - 1, rect.right, color);
+    bitmap.draw_vertical_line(rect.left, rect.top, rect.bottom, color);
+    bitmap.draw_vertical_line(rect.right - 1, rect.top, rect.bottom, color);
+}
+
+/// Draws the dotted rectangle that shows which control has the focus.
+fn draw_focus_rect(bitmap: &mut Bitmap, rect: Rect, color: Color) {
+    for x in (rect.left..rect.right).step_by(2) {
+        bitmap.set_pixel(x, rect.top, color);
+        bitmap.set_pixel(x, rect.bottom - 1, color);
+    }
+    for y in (rect.top..rect.bottom).step_by(2) {
+        bitmap.set_pixel(rect.left, y, color);
+        bitmap.set_pixel(rect.right - 1, y, color);
+    }
+}
+
+/// Draws the box of a check box, with a cross if it is checked, or grayed if it is
+/// indeterminate. A pushed box has a thicker frame.
+fn draw_check_box(
+    bitmap: &mut Bitmap,
+    rect: Rect,
+    button: &Button,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let frame = system_color(SystemColors::WindowFrame);
+    let inside = if button.check == 2 {
+        system_color(SystemColors::ButtonShadow)
+    } else {
+        system_color(SystemColors::Window)
+    };
+    bitmap.fill_rectangle(rect, inside);
+    draw_frame(bitmap, rect, frame);
+    if button.pushed {
+        draw_frame(bitmap, rect.shrink(1), frame);
+    }
+    if button.check == 1 {
+        let size = rect.right - rect.left;
+        for step in 2..size - 2 {
+            bitmap.set_pixel(rect.left + step, rect.top + step, frame);
+            bitmap.set_pixel(rect.right - 1 - step, rect.top + step, frame);
+        }
+    }
+}
+
+/// Draws the circle of a radio button, with a dot if it is checked.
+fn draw_radio_button(
+    bitmap: &mut Bitmap,
+    rect: Rect,
+    button: &Button,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let frame = system_color(SystemColors::WindowFrame);
+    let inside = if button.pushed {
+        system_color(SystemColors::ButtonFace)
+    } else {
+        system_color(SystemColors::Window)
+    };
+    let origin = Point::new(rect.left, rect.top);
+    draw_mask(bitmap, origin, &RADIO_INSIDE, inside);
+    draw_mask(bitmap, origin, &RADIO_OUTLINE, frame);
+    if button.check != 0 {
+        draw_mask(bitmap, origin, &RADIO_DOT, frame);
+    }
+}
+
+/// Draws the pixels of a mask of twelve bits wide, with the leftmost pixel in the highest bit.
+fn draw_mask(bitmap: &mut Bitmap, origin: Point, mask: &[u16; 12], color: Color) {
+    for (row, &bits) in mask.iter().enumerate() {
+        for column in 0..12 {
+            if bits & (0x800 >> column) != 0 {
+                bitmap.set_pixel(origin.x + column, origin.y + row as i16, color);
+            }
+        }
+    }
+}
+
+fn paint_static(
+    bitmap: &mut Bitmap,
+    static_control: &Static,
+    rect: Rect,
+    style: u16,
+    text: &[u8],
+    text_color: Color,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let black = system_color(SystemColors::WindowFrame);
+    let gray = system_color(SystemColors::Background);
+    let white = system_color(SystemColors::Window);
+    match static_type(style) {
+        StaticType::BlackRect => bitmap.fill_rectangle(rect, black),
+        StaticType::GrayRect => bitmap.fill_rectangle(rect, gray),
+        StaticType::WhiteRect => bitmap.fill_rectangle(rect, white),
+        StaticType::BlackFrame => draw_frame(bitmap, rect, black),
+        StaticType::GrayFrame => draw_frame(bitmap, rect, gray),
+        StaticType::WhiteFrame => draw_frame(bitmap, rect, white),
+        // TODO: draw the icons of programs once icons can be loaded
+        StaticType::Icon => {
+            if let Some(icon) = static_control.system_icon {
+                draw_system_icon(bitmap, Point::new(rect.left, rect.top), icon, system_color);
+            }
+        }
+        static_type => {
+            bitmap.fill_rectangle(rect, white);
+            let (label, mnemonic) =
+                if StaticStyles::from_bits_truncate(style).contains(StaticStyles::NOPREFIX) {
+                    (text.to_vec(), None)
+                } else {
+                    let (label, mnemonic, _) = menu::split_text(text);
+                    (label, mnemonic)
+                };
+            let width = rect.right - rect.left;
+            let wrap = !matches!(static_type, StaticType::Simple | StaticType::LeftNoWordWrap);
+            let lines = if wrap {
+                wrap_text(&label, width)
+            } else {
+                vec![(0, label.len())]
+            };
+            for (row, (start, end)) in lines.into_iter().enumerate() {
+                let line = &label[start..end];
+                let line_width = font::text_width(line);
+                let left = match static_type {
+                    StaticType::Center => rect.left + (width - line_width) / 2,
+                    StaticType::Right => rect.right - line_width,
+                    _ => rect.left,
+                };
+                let top = rect.top + row as i16 * LINE_HEIGHT;
+                font::draw_text(bitmap, Point::new(left, top), line, text_color);
+                if let Some(index) = mnemonic.filter(|&index| index >= start && index < end) {
+                    let x = left + font::character_offset(index - start);
+                    let y = top + font::GLYPH_HEIGHT;
+                    bitmap.draw_horizontal_line(x, y, x + font::GLYPH_WIDTH, text_color);
+                }
+            }
+        }
+    }
+}
+
+/// Draws one of the icons of Windows with its top left corner at the given point.
+pub fn draw_system_icon(
+    bitmap: &mut Bitmap,
+    origin: Point,
+    icon: SystemIcon,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let black = system_color(SystemColors::WindowFrame);
+    let white = system_color(SystemColors::Window);
+    let center = Point::new(origin.x + ICON_SIZE / 2, origin.y + ICON_SIZE / 2);
+    // The icons are signs: a disc with a character on it, or a bar for the stop sign
+    let (color, mark) = match icon {
+        SystemIcon::Application => {
+            let rect = Rect {
+                left: origin.x,
+                top: origin.y,
+                right: origin.x + ICON_SIZE,
+                bottom: origin.y + ICON_SIZE,
+            }
+            .shrink(2);
+            bitmap.fill_rectangle(rect, white);
+            draw_frame(bitmap, rect, black);
+            return;
+        }
+        SystemIcon::Hand => (Color(0xc0, 0, 0), None),
+        SystemIcon::Question => (white, Some(b'?')),
+        SystemIcon::Exclamation => (Color(0xff, 0xff, 0), Some(b'!')),
+        SystemIcon::Asterisk => (Color(0, 0, 0xc0), Some(b'i')),
+    };
+    let radius = ICON_SIZE / 2 - 1;
+    draw_disc(bitmap, center, radius, black);
+    draw_disc(bitmap, center, radius - 1, color);
+    match mark {
+        Some(character) => {
+            let mark_color = if icon == SystemIcon::Asterisk {
+                white
+            } else {
+                black
+            };
+            // Twice, a pixel apart, to make it stand out
+            let top_left = Point::new(
+                center.x - font::GLYPH_WIDTH / 2,
+                center.y - font::GLYPH_HEIGHT / 2,
+            );
+            font::draw_text(bitmap, top_left, &[character], mark_color);
+            font::draw_text(
+                bitmap,
+                Point::new(top_left.x + 1, top_left.y),
+                &[character],
+                mark_color,
+            );
+        }
+        None => bitmap.fill_rectangle(
+            Rect {
+                left: center.x - radius + 5,
+                top: center.y - 3,
+                right: center.x + radius - 4,
+                bottom: center.y + 3,
+            },
+            white,
+        ),
+    }
+}
+
+/// Fills a circle around a point.
+fn draw_disc(bitmap: &mut Bitmap, center: Point, radius: i16, color: Color) {
+    for dy in -radius..=radius {
+        let squared = (radius * radius - dy * dy) as f32;
+        let half_width = squared.sqrt() as i16;
+        bitmap.draw_horizontal_line(
+            center.x - half_width,
+            center.y + dy,
+            center.x + half_width + 1,
+            color,
+        );
+    }
+}
+
+/// Breaks text into lines that fit in the given width, at spaces where possible and at line
+/// breaks. Returns where each line starts and ends.
+pub fn wrap_text(text: &[u8], width: i16) -> Vec<(usize, usize)> {
+    let columns = ((width + 1) / font::GLYPH_ADVANCE).max(1) as usize;
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let rest = &text[start..];
+        let line_end = rest
+            .iter()
+            .position(|&byte| byte == b'\r' || byte == b'\n')
+            .unwrap_or(rest.len());
+        let end = if line_end <= columns {
+            line_end
+        } else {
+            // Break at the last space that fits, or in the middle of a word that doesn't fit
+            rest[..=columns]
+                .iter()
+                .rposition(|&byte| byte == b' ')
+                .filter(|&space| space > 0)
+                .unwrap_or(columns)
+        };
+        lines.push((start, start + end));
+        start += end;
+        // The space or line break that ended the line isn't on the next line
+        match text.get(start) {
+            Some(b' ') => start += 1,
+            Some(b'\r') if text.get(start + 1) == Some(&b'\n') => start += 2,
+            Some(b'\r') | Some(b'\n') => start += 1,
+            _ => {}
+        }
+    }
+    lines
+}
+
+fn paint_edit(
+    bitmap: &mut Bitmap,
+    edit: &Edit,
+    rect: Rect,
+    style: u16,
+    text_color: Color,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let styles = EditStyles::from_bits_truncate(style);
+    let multiline = styles.contains(EditStyles::MULTILINE);
+    bitmap.fill_rectangle(rect, system_color(SystemColors::Window));
+    let size = (rect.right - rect.left, rect.bottom - rect.top);
+    let origin = edit_text_origin(size, multiline);
+    let (visible_lines, visible_columns) = edit_capacity(size);
+    let (selection_start, selection_end) = if edit.focused || styles.contains(EditStyles::NOHIDESEL)
+    {
+        edit.selection()
+    } else {
+        (0, 0)
+    };
+    let hidden = (edit.password_char != 0).then(|| vec![edit.password_char; edit.text.len()]);
+    let text = hidden.as_deref().unwrap_or(&edit.text);
+    let highlight = system_color(SystemColors::Highlight);
+    let highlight_text = system_color(SystemColors::HighlightText);
+
+    for (row, &(line_start, line_end)) in edit
+        .lines()
+        .iter()
+        .enumerate()
+        .skip(edit.first_line)
+        .take(visible_lines)
+    {
+        let top = rect.top + origin.y + (row - edit.first_line) as i16 * LINE_HEIGHT;
+        let start = (line_start + edit.first_column).min(line_end);
+        let end = (start + visible_columns).min(line_end);
+        let x_of = |index: usize| rect.left + origin.x + font::character_offset(index - start);
+        font::draw_text(
+            bitmap,
+            Point::new(x_of(start), top),
+            &text[start..end],
+            text_color,
+        );
+        let selected_start = selection_start.clamp(start, end);
+        let selected_end = selection_end.clamp(start, end);
+        if selected_start < selected_end {
+            bitmap.fill_rectangle(
+                Rect {
+                    left: x_of(selected_start),
+                    top,
+                    right: x_of(selected_end),
+                    bottom: top + font::GLYPH_HEIGHT,
+                },
+                highlight,
+            );
+            font::draw_text(
+                bitmap,
+                Point::new(x_of(selected_start), top),
+                &text[selected_start..selected_end],
+                highlight_text,
+            );
+        }
+        if edit.focused && (start..=end).contains(&edit.caret) {
+            let x = x_of(edit.caret) - 1;
+            bitmap.draw_vertical_line(x, top, top + font::GLYPH_HEIGHT, text_color);
+        }
+    }
+}
+
+fn paint_list(
+    bitmap: &mut Bitmap,
+    list: &ListBox,
+    rect: Rect,
+    text_color: Color,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    bitmap.fill_rectangle(rect, system_color(SystemColors::Window));
+    let width = rect.right - rect.left;
+    let visible = list_capacity(rect.bottom - rect.top);
+    for (index, item) in list.items.iter().enumerate().skip(list.top).take(visible) {
+        let item_rect = list.item_rect(index, width).offset(rect.left, rect.top);
+        let color = if item.selected {
+            bitmap.fill_rectangle(item_rect, system_color(SystemColors::Highlight));
+            system_color(SystemColors::HighlightText)
+        } else {
+            text_color
+        };
+        font::draw_text(
+            bitmap,
+            Point::new(item_rect.left + TEXT_MARGIN, item_rect.top + 1),
+            &item.text,
+            color,
+        );
+        if list.focused && list.caret == Some(index) {
+            draw_focus_rect(bitmap, item_rect, color);
+        }
+    }
+}
+
+fn paint_combo_box(
+    bitmap: &mut Bitmap,
+    combo_box: &ComboBox,
+    rect: Rect,
+    text_color: Color,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let frame = system_color(SystemColors::WindowFrame);
+    let width = rect.right - rect.left;
+    let field = combo_box.field_rect(width).offset(rect.left, rect.top);
+    if combo_box.combo_box_type == ComboBoxType::DropDownList {
+        bitmap.fill_rectangle(field, system_color(SystemColors::Window));
+        let color = if combo_box.edit.focused {
+            let text_rect = Rect {
+                left: field.left + TEXT_MARGIN,
+                top: field.top + TEXT_MARGIN,
+                right: field.right - TEXT_MARGIN,
+                bottom: field.bottom - TEXT_MARGIN,
+            };
+            bitmap.fill_rectangle(text_rect, system_color(SystemColors::Highlight));
+            system_color(SystemColors::HighlightText)
+        } else {
+            text_color
+        };
+        let text_top = (field.top + field.bottom - font::GLYPH_HEIGHT) / 2;
+        font::draw_text(
+            bitmap,
+            Point::new(field.left + 2 * TEXT_MARGIN, text_top),
+            &combo_box.edit.text,
+            color,
+        );
+    } else {
+        paint_edit(
+            bitmap,
+            &combo_box.edit,
+            field.shrink(1),
+            EditStyles::AUTOHSCROLL.bits(),
+            text_color,
+            system_color,
+        );
+    }
+    draw_frame(bitmap, field, frame);
+
+    if let Some(button) = combo_box.button_rect(width) {
+        let button = button.offset(rect.left, rect.top);
+        draw_frame(bitmap, button, frame);
+        draw_raised(bitmap, button.shrink(1), system_color);
+        let center = Point::new(
+            (button.left + button.right) / 2,
+            (button.top + button.bottom) / 2,
+        );
+        draw_arrow(
+            bitmap,
+            center,
+            ScrollBarPart::LineDown,
+            true,
+            system_color(SystemColors::ButtonText),
+        );
+    }
+
+    if combo_box.list_shown() {
+        let size = (width, rect.bottom - rect.top);
+        let list_rect = combo_box.list_rect(size).offset(rect.left, rect.top);
+        draw_frame(bitmap, list_rect.shrink(-1), frame);
+        paint_list(bitmap, &combo_box.list, list_rect, text_color, system_color);
+    }
+}
+
+/// Draws a small triangle that points away from the middle of a scroll bar, for the arrow at
+/// the given end.
+fn draw_arrow(
+    bitmap: &mut Bitmap,
+    center: Point,
+    end: ScrollBarPart,
+    vertical: bool,
+    color: Color,
+) {
+    for step in 0..4 {
+        // The row of the triangle that is the given number of steps from its tip
+        let along = if end == ScrollBarPart::LineUp {
+            -2 + step
+        } else {
+            1 - step
+        };
+        if vertical {
+            bitmap.draw_horizontal_line(
+                center.x - step,
+                center.y + along,
+                center.x + step + 1,
+                color,
+            );
+        } else {
+            bitmap.draw_vertical_line(
+                center.x + along,
+                center.y - step,
+                center.y + step + 1,
+                color,
+            );
+        }
+    }
+}
+
+fn paint_scroll_bar(
+    bitmap: &mut Bitmap,
+    scroll_bar: &ScrollBar,
+    rect: Rect,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let frame = system_color(SystemColors::WindowFrame);
+    let arrow_color = system_color(SystemColors::ButtonText);
+    let size = (rect.right - rect.left, rect.bottom - rect.top);
+    bitmap.fill_rectangle(rect, system_color(SystemColors::Scrollbar));
+    draw_frame(bitmap, rect, frame);
+    let arrow = scroll_bar.arrow_length(size);
+    // The parts along the scroll bar, as the span that each covers
+    let span_rect = |start: i16, end: i16| {
+        if scroll_bar.vertical {
+            Rect {
+                left: rect.left,
+                top: rect.top + start,
+                right: rect.right,
+                bottom: rect.top + end,
+            }
+        } else {
+            Rect {
+                left: rect.left + start,
+                top: rect.top,
+                right: rect.left + end,
+                bottom: rect.bottom,
+            }
+        }
+    };
+    let (length, _) = scroll_bar.extent(size);
+    for (end, span) in [
+        (ScrollBarPart::LineUp, span_rect(0, arrow)),
+        (ScrollBarPart::LineDown, span_rect(length - arrow, length)),
+    ] {
+        draw_frame(bitmap, span, frame);
+        draw_raised(bitmap, span.shrink(1), system_color);
+        let center = Point::new((span.left + span.right) / 2, (span.top + span.bottom) / 2);
+        draw_arrow(bitmap, center, end, scroll_bar.vertical, arrow_color);
+    }
+    if let Some((start, end)) = scroll_bar.thumb_span(size) {
+        let thumb = span_rect(start, end);
+        draw_frame(bitmap, thumb, frame);
+        draw_raised(bitmap, thumb.shrink(1), system_color);
+    }
+}
+
+impl EmulatedUser<'_> {
+    /// Runs a closure on what a control keeps about itself, with the style of its class, if the
+    /// window is one of the predefined controls.
+    pub fn with_control<R>(
+        &self,
+        h_wnd: Handle,
+        f: impl FnOnce(&mut Control, u16) -> R,
+    ) -> Option<R> {
+        match self.write_objects().user.get_mut(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                let style = user_window.class_specific_style;
+                user_window
+                    .control
+                    .as_mut()
+                    .map(|control| f(control, style))
+            }
+            _ => None,
+        }
+    }
+
+    fn set_control(&self, h_wnd: Handle, control: Control) {
+        if let Some(UserObject::Window(user_window)) = self.write_objects().user.get_mut(h_wnd) {
+            user_window.control = Some(Box::new(control));
+        }
+    }
+
+    fn with_button<R>(&self, h_wnd: Handle, f: impl FnOnce(&mut Button, u16) -> R) -> Option<R> {
+        self.with_control(h_wnd, |control, style| match control {
+            Control::Button(button) => Some(f(button, style)),
+            _ => None,
+        })
+        .flatten()
+    }
+
+    fn with_edit<R>(&self, h_wnd: Handle, f: impl FnOnce(&mut Edit, EditStyles) -> R) -> Option<R> {
+        self.with_control(h_wnd, |control, style| match control {
+            Control::Edit(edit) => Some(f(edit, EditStyles::from_bits_truncate(style))),
+            _ => None,
+        })
+        .flatten()
+    }
+
+    /// Runs a closure on the list of a list box, or on the list of a combo box.
+    fn with_list<R>(&self, h_wnd: Handle, f: impl FnOnce(&mut ListBox, u16) -> R) -> Option<R> {
+        self.with_control(h_wnd, |control, style| match control {
+            Control::ListBox(list) => Some(f(list, style)),
+            Control::ComboBox(combo_box) => Some(f(&mut combo_box.list, style)),
+            _ => None,
+        })
+        .flatten()
+    }
+
+    fn with_combo_box<R>(&self, h_wnd: Handle, f: impl FnOnce(&mut ComboBox) -> R) -> Option<R> {
+        self.with_control(h_wnd, |control, _| match control {
+            Control::ComboBox(combo_box) => Some(f(combo_box)),
+            _ => None,
+        })
+        .flatten()
+    }
+
+    fn with_scroll_bar<R>(&self, h_wnd: Handle, f: impl FnOnce(&mut ScrollBar) -> R) -> Option<R> {
+        self.with_control(h_wnd, |control, _| match control {
+            Control::ScrollBar(scroll_bar) => Some(f(scroll_bar)),
+            _ => None,
+        })
+        .flatten()
+    }
+
+    /// Changes the low word of the style of a window, which BM_SETSTYLE and EM_SETREADONLY do.
+    fn update_class_specific_style(&self, h_wnd: Handle, f: impl FnOnce(u16) -> u16) {
+        if let Some(UserObject::Window(user_window)) = self.write_objects().user.get_mut(h_wnd) {
+            user_window.class_specific_style = f(user_window.class_specific_style);
+        }
+    }
+
+    /// Tells the parent of a control about something that happened to it with WM_COMMAND.
+    /// The parent gets the id of the control, and the control with the notification code.
+    fn notify_parent(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        code: u16,
+    ) -> Result<(), EmulatorError> {
+        let (parent, id) = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => (user_window.parent_handle, user_window.menu),
+            _ => return Ok(()),
+        };
+        self.send_message(
+            accessor,
+            parent,
+            MessageType::Command.into(),
+            id.as_u16(),
+            encode_u16_u16_to_u32(h_wnd.as_u16(), code),
+        )?;
+        Ok(())
+    }
+
+    /// Gives the focus to a control that got clicked.
+    fn focus_control(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<(), EmulatorError> {
+        self.set_focus(accessor, h_wnd)?;
+        Ok(())
+    }
+
+    /// Draws a control with a device context for it.
+    fn paint_control(&self, h_wnd: Handle, hdc: Handle) {
+        let objects = self.read_objects();
+        let user_window = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window,
+            _ => return,
+        };
+        let control = match &user_window.control {
+            Some(control) => control,
+            None => return,
+        };
+        let rect = self
+            .get_client_rect(h_wnd, &objects)
+            .unwrap_or_else(Rect::zero);
+        let enabled = !user_window.style.contains(WindowStyles::DISABLED);
+        objects.with_paint_bitmap_for(hdc, &|mut bitmap, _| {
+            paint(
+                &mut bitmap,
+                control,
+                rect,
+                user_window.class_specific_style,
+                user_window.title.as_slice(),
+                enabled,
+                &|color| self.get_system_color(color),
+            );
+        });
+    }
+
+    /// Draws a control for WM_PAINT.
+    fn paint_control_window(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<(), EmulatorError> {
+        if let Some(paint) = self.begin_paint(accessor, h_wnd)? {
+            self.paint_control(h_wnd, paint.hdc);
+            self.end_paint(h_wnd, paint.hdc);
+        }
+        Ok(())
+    }
+
+    /// Draws a control again after it changed.
+    pub fn repaint_control(&self, h_wnd: Handle) {
+        if let Some(hdc) = self.get_dc(h_wnd) {
+            self.paint_control(h_wnd, hdc);
+            self.release_dc(h_wnd, hdc);
+        }
+    }
+
+    /// Lets the window procedure of a control set the text of the control, and draws the
+    /// control with it.
+    fn set_control_text(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        let result =
+            self.default_window_proc(accessor, h_wnd, MessageType::SetText.into(), 0, l_param)?;
+        self.repaint_control(h_wnd);
+        Ok(result)
+    }
+
+    #[api_function]
+    pub fn button_window_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] BUTTON WINDOW PROC {:?} {:x} {:x} {:x}",
+            h_wnd, msg, w_param, l_param
+        );
+        let result = if msg == MessageType::Create.into() {
+            self.set_control(h_wnd, Control::Button(Button::new()));
+            0
+        } else if msg == MessageType::Paint.into() {
+            self.paint_control_window(&mut accessor, h_wnd)?;
+            0
+        } else if msg == MessageType::SetFocus.into() || msg == MessageType::KillFocus.into() {
+            let focused = msg == MessageType::SetFocus.into();
+            self.with_button(h_wnd, |button, _| {
+                button.focused = focused;
+                button.pushed &= focused;
+            });
+            self.repaint_control(h_wnd);
+            0
+        } else if msg == MessageType::LButtonDown.into() || msg == MessageType::LButtonDblClk.into()
+        {
+            let group_box = self
+                .with_button(h_wnd, |_, style| button_type(style) == ButtonType::GroupBox)
+                .unwrap_or(true);
+            if !group_box {
+                self.focus_control(&mut accessor, h_wnd)?;
+                if self.track_button(h_wnd) {
+                    self.click_button(&mut accessor, h_wnd)?;
+                }
+            }
+            0
+        } else if msg == MessageType::KeyDown.into() && w_param as u8 == VK_SPACE {
+            self.with_button(h_wnd, |button, _| button.pushed = true);
+            self.repaint_control(h_wnd);
+            0
+        } else if msg == MessageType::KeyUp.into() && w_param as u8 == VK_SPACE {
+            let was_pushed = self
+                .with_button(h_wnd, |button, _| {
+                    std::mem::replace(&mut button.pushed, false)
+                })
+                .unwrap_or(false);
+            if was_pushed {
+                self.repaint_control(h_wnd);
+                self.click_button(&mut accessor, h_wnd)?;
+            }
+            0
+        } else if msg == ButtonMessage::GetCheck.into() {
+            self.with_button(h_wnd, |button, _| button.check.into())
+                .unwrap_or(0)
+        } else if msg == ButtonMessage::SetCheck.into() {
+            let changed = self
+                .with_button(h_wnd, |button, style| {
+                    let check = match button_type(style) {
+                        ButtonType::ThreeState | ButtonType::AutoThreeState => w_param.min(2),
+                        ButtonType::CheckBox
+                        | ButtonType::AutoCheckBox
+                        | ButtonType::RadioButton
+                        | ButtonType::AutoRadioButton => (w_param != 0).into(),
+                        _ => return false,
+                    };
+                    std::mem::replace(&mut button.check, check) != check
+                })
+                .unwrap_or(false);
+            if changed {
+                self.repaint_control(h_wnd);
+            }
+            0
+        } else if msg == ButtonMessage::GetState.into() {
+            self.with_button(h_wnd, |button, _| button.state().into())
+                .unwrap_or(0)
+        } else if msg == ButtonMessage::SetState.into() {
+            self.with_button(h_wnd, |button, _| button.pushed = w_param != 0);
+            self.repaint_control(h_wnd);
+            0
+        } else if msg == ButtonMessage::SetStyle.into() {
+            self.update_class_specific_style(h_wnd, |_| w_param);
+            // lParam tells whether to redraw
+            if l_param as u16 != 0 {
+                self.repaint_control(h_wnd);
+            }
+            0
+        } else if msg == MessageType::SetText.into() {
+            self.set_control_text(&mut accessor, h_wnd, l_param)?
+        } else {
+            self.default_window_proc(&mut accessor, h_wnd, msg, w_param, l_param)?
+        };
+        Ok(ReturnValue::U32(result))
+    }
+
+    /// Shows a button pushed for as long as the mouse button is held down on it. Returns
+    /// whether the mouse button was released on the button.
+    fn track_button(&self, h_wnd: Handle) -> bool {
+        let window_identifier = match self.read_objects().window_identifier(h_wnd) {
+            Some(window_identifier) => window_identifier,
+            None => return false,
+        };
+        self.read_objects()
+            .write_window_manager()
+            .set_capture(Some(window_identifier));
+        let (width, height) = self.client_size_of(h_wnd);
+        let rect = Rect {
+            left: 0,
+            top: 0,
+            right: width,
+            bottom: height,
+        };
+        let mut pushed = true;
+        self.with_button(h_wnd, |button, _| button.pushed = true);
+        self.repaint_control(h_wnd);
+        loop {
+            let input = self.wait_for_input();
+            match input.message {
+                MessageType::MouseMove => {
+                    let on_button = rect.contains(self.screen_to_client(h_wnd, input.point));
+                    if on_button != pushed {
+                        pushed = on_button;
+                        self.with_button(h_wnd, |button, _| button.pushed = pushed);
+                        self.repaint_control(h_wnd);
+                    }
+                }
+                MessageType::LButtonUp => break,
+                _ => {}
+            }
+        }
+        self.read_objects().write_window_manager().set_capture(None);
+        self.with_button(h_wnd, |button, _| button.pushed = false);
+        self.repaint_control(h_wnd);
+        pushed
+    }
+
+    /// Clicks a button: automatic check boxes and radio buttons change their check state, and
+    /// the parent gets BN_CLICKED.
+    pub fn click_button(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<(), EmulatorError> {
+        let kind = self.with_button(h_wnd, |button, style| {
+            let button_type = button_type(style);
+            match button_type {
+                ButtonType::AutoCheckBox => button.check = (button.check == 0).into(),
+                ButtonType::AutoThreeState => button.check = (button.check + 1) % 3,
+                ButtonType::AutoRadioButton => button.check = 1,
+                _ => {}
+            }
+            button_type
+        });
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return Ok(()),
+        };
+        // Checking an automatic radio button unchecks the others of its group
+        if kind == ButtonType::AutoRadioButton {
+            for sibling in self.group_of(h_wnd) {
+                let unchecked = sibling != h_wnd
+                    && self
+                        .with_button(sibling, |button, style| {
+                            button_type(style) == ButtonType::AutoRadioButton
+                                && std::mem::replace(&mut button.check, 0) != 0
+                        })
+                        .unwrap_or(false);
+                if unchecked {
+                    self.repaint_control(sibling);
+                }
+            }
+        }
+        self.repaint_control(h_wnd);
+        self.notify_parent(accessor, h_wnd, BN_CLICKED)
+    }
+
+    /// The siblings in the same group as a window: from the last one with WS_GROUP up to the
+    /// window, until the next one with WS_GROUP.
+    fn group_of(&self, h_wnd: Handle) -> Vec<Handle> {
+        let objects = self.read_objects();
+        let siblings = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                match objects.user.get(user_window.parent_handle) {
+                    Some(UserObject::Window(parent_window)) => &parent_window.children,
+                    _ => return vec![h_wnd],
+                }
+            }
+            _ => return Vec::new(),
+        };
+        let index = match siblings.iter().position(|&sibling| sibling == h_wnd) {
+            Some(index) => index,
+            None => return vec![h_wnd],
+        };
+        let starts_group = |index: &usize| {
+            matches!(
+                objects.user.get(siblings[*index]),
+                Some(UserObject::Window(sibling_window))
+                    if sibling_window.style.contains(WindowStyles::GROUP)
+            )
+        };
+        let start = (0..=index).rev().find(starts_group).unwrap_or(0);
+        let end = (index + 1..siblings.len())
+            .find(starts_group)
+            .unwrap_or(siblings.len());
+        siblings[start..end].to_vec()
+    }
+
+    #[api_function]
+    pub fn static_window_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] STATIC WINDOW PROC {:?} {:x} {:x} {:x}",
+            h_wnd, msg, w_param, l_param
+        );
+        let result = if msg == MessageType::Create.into() {
+            let system_icon = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window))
+                    if static_type(user_window.class_specific_style) == StaticType::Icon =>
+                {
+                    // TODO: load the icons of programs too
+                    system_icon(user_window.title.as_slice())
+                }
+                _ => None,
+            };
+            self.set_control(
+                h_wnd,
+                Control::Static(Static {
+                    icon: Handle::null(),
+                    system_icon,
+                }),
+            );
+            0
+        } else if msg == MessageType::Paint.into() {
+            self.paint_control_window(&mut accessor, h_wnd)?;
+            0
+        } else if msg == MessageType::SetText.into() {
+            self.set_control_text(&mut accessor, h_wnd, l_param)?
+        } else if msg == StaticMessage::SetIcon.into() || msg == StaticMessage::GetIcon.into() {
+            let set = msg == StaticMessage::SetIcon.into();
+            let icon = self
+                .with_control(h_wnd, |control, _| match control {
+                    Control::Static(static_control) if set => {
+                        std::mem::replace(&mut static_control.icon, w_param.into())
+                    }
+                    Control::Static(static_control) => static_control.icon,
+                    _ => Handle::null(),
+                })
+                .unwrap_or(Handle::null());
+            if set {
+                self.repaint_control(h_wnd);
+            }
+            icon.as_u16().into()
+        } else {
+            self.default_window_proc(&mut accessor, h_wnd, msg, w_param, l_param)?
+        };
+        Ok(ReturnValue::U32(result))
+    }
+
+    #[api_function]
+    pub fn edit_window_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] EDIT WINDOW PROC {:?} {:x} {:x} {:x}",
+            h_wnd, msg, w_param, l_param
+        );
+        let result = self.edit_proc(&mut accessor, h_wnd, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+
+    fn edit_proc(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        if msg == MessageType::Create.into() {
+            let (title, style) = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => (
+                    user_window.title.clone(),
+                    EditStyles::from_bits_truncate(user_window.class_specific_style),
+                ),
+                _ => return Ok(0),
+            };
+            let mut edit = Edit::new(title.as_slice());
+            if style.contains(EditStyles::PASSWORD) {
+                edit.password_char = b'*';
+            }
+            self.set_control(h_wnd, Control::Edit(edit));
+            Ok(0)
+        } else if msg == MessageType::Paint.into() {
+            self.paint_control_window(accessor, h_wnd)?;
+            Ok(0)
+        } else if msg == MessageType::SetFocus.into() || msg == MessageType::KillFocus.into() {
+            let focused = msg == MessageType::SetFocus.into();
+            self.with_edit(h_wnd, |edit, _| edit.focused = focused);
+            self.repaint_control(h_wnd);
+            let code = if focused {
+                EditNotification::SetFocus
+            } else {
+                EditNotification::KillFocus
+            };
+            self.notify_parent(accessor, h_wnd, code.into())?;
+            Ok(0)
+        } else if msg == MessageType::Char.into() {
+            let fit = self
+                .with_edit(h_wnd, |edit, style| {
+                    edit.type_character(w_param as u8, style)
+                })
+                .flatten();
+            if let Some(fit) = fit {
+                self.edit_changed(accessor, h_wnd, fit)?;
+            }
+            Ok(0)
+        } else if msg == MessageType::KeyDown.into() {
+            let (shift, control) = {
+                let objects = self.read_objects();
+                (
+                    objects.key_states.is_down(VK_SHIFT),
+                    objects.key_states.is_down(VK_CONTROL),
+                )
+            };
+            let (page, _) = edit_capacity(self.client_size_of(h_wnd));
+            let changed = self
+                .with_edit(h_wnd, |edit, style| {
+                    edit.key_down(
+                        w_param as u8,
+                        shift,
+                        control,
+                        page,
+                        style.contains(EditStyles::READONLY),
+                    )
+                })
+                .flatten();
+            match changed {
+                Some(true) => self.edit_changed(accessor, h_wnd, true)?,
+                Some(false) => self.edit_caret_moved(h_wnd),
+                None => {}
+            }
+            Ok(0)
+        } else if msg == MessageType::LButtonDown.into() || msg == MessageType::LButtonDblClk.into()
+        {
+            self.focus_control(accessor, h_wnd)?;
+            let point = Point::new(l_param as i16, (l_param >> 16) as i16);
+            self.track_edit_selection(h_wnd, point);
+            Ok(0)
+        } else if msg == MessageType::SetText.into() {
+            let text = read_string_parameter(accessor, l_param)?;
+            self.with_edit(h_wnd, |edit, _| edit.set_text(&text));
+            let result = self.default_window_proc(accessor, h_wnd, msg, w_param, l_param)?;
+            self.edit_changed(accessor, h_wnd, true)?;
+            Ok(result)
+        } else if msg == MessageType::GetText.into() {
+            let text = self
+                .with_edit(h_wnd, |edit, _| edit.text.clone())
+                .unwrap_or_default();
+            get_text(accessor, &text, w_param, l_param)
+        } else if msg == MessageType::GetTextLength.into() {
+            Ok(self
+                .with_edit(h_wnd, |edit, _| edit.text.len() as u32)
+                .unwrap_or(0))
+        } else if msg == EditMessage::GetSel.into() {
+            Ok(self
+                .with_edit(h_wnd, |edit, _| {
+                    let (start, end) = edit.selection();
+                    encode_u16_u16_to_u32(start as u16, end as u16)
+                })
+                .unwrap_or(0))
+        } else if msg == EditMessage::SetSel.into() {
+            // A start of -1 drops the selection, and an end of -1 is the end of the text
+            let (start, end) = (l_param as u16, (l_param >> 16) as u16);
+            self.with_edit(h_wnd, |edit, _| {
+                if start == 0xffff {
+                    edit.move_caret(edit.caret, false);
+                } else {
+                    edit.set_selection(start.into(), end.into());
+                }
+            });
+            self.edit_caret_moved(h_wnd);
+            Ok(1)
+        } else if msg == EditMessage::GetRect.into() {
+            // The text goes in the whole client area
+            let (width, height) = self.client_size_of(h_wnd);
+            accessor.write_rect(
+                Pointer::from_far(l_param).0,
+                &Rect {
+                    left: 0,
+                    top: 0,
+                    right: width,
+                    bottom: height,
+                },
+            )?;
+            Ok(0)
+        } else if msg == EditMessage::Scroll.into() || msg == EditMessage::LineScroll.into() {
+            let (page, _) = edit_capacity(self.client_size_of(h_wnd));
+            let (lines, columns) = if msg == EditMessage::LineScroll.into() {
+                (l_param as i16 as isize, (l_param >> 16) as i16 as isize)
+            } else if w_param == ScrollCode::LineUp.into() {
+                (-1, 0)
+            } else if w_param == ScrollCode::LineDown.into() {
+                (1, 0)
+            } else if w_param == ScrollCode::PageUp.into() {
+                (-(page as isize), 0)
+            } else if w_param == ScrollCode::PageDown.into() {
+                (page as isize, 0)
+            } else {
+                (0, 0)
+            };
+            let multiline = self
+                .with_edit(h_wnd, |edit, style| {
+                    if style.contains(EditStyles::MULTILINE) {
+                        let last = edit.lines().len() as isize - 1;
+                        edit.first_line =
+                            (edit.first_line as isize + lines).clamp(0, last) as usize;
+                        edit.first_column = (edit.first_column as isize + columns).max(0) as usize;
+                    }
+                    style.contains(EditStyles::MULTILINE)
+                })
+                .unwrap_or(false);
+            self.repaint_control(h_wnd);
+            Ok(multiline.into())
+        } else if msg == EditMessage::GetModify.into() {
+            Ok(self
+                .with_edit(h_wnd, |edit, _| edit.modified.into())
+                .unwrap_or(0))
+        } else if msg == EditMessage::SetModify.into() {
+            self.with_edit(h_wnd, |edit, _| edit.modified = w_param != 0);
+            Ok(0)
+        } else if msg == EditMessage::GetLineCount.into() {
+            Ok(self
+                .with_edit(h_wnd, |edit, _| edit.lines().len() as u32)
+                .unwrap_or(1))
+        } else if msg == EditMessage::LineIndex.into()
+            || msg == EditMessage::LineLength.into()
+            || msg == EditMessage::LineFromChar.into()
+        {
+            // -1 stands for the line with the caret
+            Ok(self
+                .with_edit(h_wnd, |edit, _| {
+                    let lines = edit.lines();
+                    if msg == EditMessage::LineIndex.into() {
+                        let line = if w_param == 0xffff {
+                            edit.line_from_char(edit.caret)
+                        } else {
+                            w_param as usize
+                        };
+                        lines.get(line).map_or(u32::MAX, |&(start, _)| start as u32)
+                    } else {
+                        let index = if w_param == 0xffff {
+                            edit.caret
+                        } else {
+                            w_param as usize
+                        };
+                        let line = edit.line_from_char(index);
+                        if msg == EditMessage::LineLength.into() {
+                            (lines[line].1 - lines[line].0) as u32
+                        } else {
+                            line as u32
+                        }
+                    }
+                })
+                .unwrap_or(0))
+        } else if msg == EditMessage::GetLine.into() {
+            // The first word of the buffer tells how much fits in it, and no null byte follows
+            let buffer = Pointer::from_far(l_param).0;
+            let size = accessor.memory().read_u16(buffer)? as usize;
+            let line = self
+                .with_edit(h_wnd, |edit, style| {
+                    let index = if style.contains(EditStyles::MULTILINE) {
+                        w_param as usize
+                    } else {
+                        0
+                    };
+                    edit.lines()
+                        .get(index)
+                        .map(|&(start, end)| edit.text[start..end].to_vec())
+                })
+                .flatten()
+                .unwrap_or_default();
+            let length = line.len().min(size);
+            accessor
+                .memory_mut()
+                .copy_from(&line[..length], buffer as usize)?;
+            Ok(length as u32)
+        } else if msg == EditMessage::ReplaceSel.into() {
+            let text = read_string_parameter(accessor, l_param)?;
+            let fit = self
+                .with_edit(h_wnd, |edit, _| edit.replace_selection(&text))
+                .unwrap_or(true);
+            self.edit_changed(accessor, h_wnd, fit)?;
+            Ok(0)
+        } else if msg == EditMessage::LimitText.into() {
+            // 0 takes away the limit
+            let limit = if w_param == 0 {
+                u16::MAX.into()
+            } else {
+                w_param.into()
+            };
+            self.with_edit(h_wnd, |edit, _| edit.limit = limit);
+            Ok(0)
+        } else if msg == EditMessage::CanUndo.into() {
+            Ok(self
+                .with_edit(h_wnd, |edit, _| edit.undo.is_some().into())
+                .unwrap_or(0))
+        } else if msg == EditMessage::Undo.into() {
+            let undone = self
+                .with_edit(h_wnd, |edit, _| edit.undo())
+                .unwrap_or(false);
+            if undone {
+                self.edit_changed(accessor, h_wnd, true)?;
+            }
+            Ok(undone.into())
+        } else if msg == EditMessage::EmptyUndoBuffer.into() {
+            self.with_edit(h_wnd, |edit, _| edit.undo = None);
+            Ok(0)
+        } else if msg == EditMessage::SetPasswordChar.into() {
+            self.with_edit(h_wnd, |edit, _| edit.password_char = w_param as u8);
+            self.repaint_control(h_wnd);
+            Ok(0)
+        } else if msg == EditMessage::GetFirstVisibleLine.into() {
+            Ok(self
+                .with_edit(h_wnd, |edit, style| {
+                    if style.contains(EditStyles::MULTILINE) {
+                        edit.first_line as u32
+                    } else {
+                        edit.first_column as u32
+                    }
+                })
+                .unwrap_or(0))
+        } else if msg == EditMessage::SetReadOnly.into() {
+            let read_only = EditStyles::READONLY.bits();
+            self.update_class_specific_style(h_wnd, |style| {
+                if w_param != 0 {
+                    style | read_only
+                } else {
+                    style & !read_only
+                }
+            });
+            self.repaint_control(h_wnd);
+            Ok(1)
+        } else if msg == EditMessage::GetHandle.into() || msg == EditMessage::SetHandle.into() {
+            // TODO: the text lives outside of the local heap of the task, so it has no handle
+            Ok(0)
+        } else {
+            self.default_window_proc(accessor, h_wnd, msg, w_param, l_param)
+        }
+    }
+
+    /// Scrolls an edit control to its caret and draws it again.
+    fn edit_caret_moved(&self, h_wnd: Handle) {
+        let size = self.client_size_of(h_wnd);
+        self.with_edit(h_wnd, |edit, _| edit.scroll_to_caret(size));
+        self.repaint_control(h_wnd);
+    }
+
+    /// Shows the changed text of an edit control. Its parent hears about the change with
+    /// EN_UPDATE before it shows and EN_CHANGE after, and with EN_MAXTEXT if not all of the
+    /// text fit.
+    fn edit_changed(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        fit: bool,
+    ) -> Result<(), EmulatorError> {
+        if !fit {
+            self.notify_parent(accessor, h_wnd, EditNotification::MaxText.into())?;
+        }
+        self.notify_parent(accessor, h_wnd, EditNotification::Update.into())?;
+        self.edit_caret_moved(h_wnd);
+        self.notify_parent(accessor, h_wnd, EditNotification::Change.into())
+    }
+
+    /// Puts the caret of an edit control where it got clicked, and selects the text that the
+    /// mouse moves over while the mouse button is held down. Shift extends the selection.
+    fn track_edit_selection(&self, h_wnd: Handle, point: Point) {
+        let window_identifier = match self.read_objects().window_identifier(h_wnd) {
+            Some(window_identifier) => window_identifier,
+            None => return,
+        };
+        let size = self.client_size_of(h_wnd);
+        let place_caret = |point: Point, extend: bool| {
+            self.with_edit(h_wnd, |edit, style| {
+                let index = edit.index_at(size, style.contains(EditStyles::MULTILINE), point);
+                edit.move_caret(index, extend);
+            });
+            self.edit_caret_moved(h_wnd);
+        };
+        place_caret(point, self.read_objects().key_states.is_down(VK_SHIFT));
+        self.read_objects()
+            .write_window_manager()
+            .set_capture(Some(window_identifier));
+        loop {
+            let input = self.wait_for_input();
+            match input.message {
+                MessageType::MouseMove => {
+                    place_caret(self.screen_to_client(h_wnd, input.point), true)
+                }
+                MessageType::LButtonUp => break,
+                _ => {}
+            }
+        }
+        self.read_objects().write_window_manager().set_capture(None);
+    }
+
+    /// Handles the messages that list boxes and the lists of combo boxes have in common, given
+    /// as list box messages. Returns None for the other messages.
+    fn list_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<Option<u32>, EmulatorError> {
+        let index = w_param as usize;
+        // -1 searches from the start and inserts at the end
+        let start = (w_param != 0xffff).then_some(index);
+        if msg == ListBoxMessage::GetText.into() {
+            let text = self
+                .with_list(h_wnd, |list, _| {
+                    list.items.get(index).map(|item| item.text.clone())
+                })
+                .flatten();
+            return match text {
+                Some(text) => {
+                    write_string(accessor, Pointer::from_far(l_param).0, &text)?;
+                    Ok(Some(text.len() as u32))
+                }
+                None => Ok(Some(LIST_ERROR)),
+            };
+        }
+        let text = if msg == ListBoxMessage::AddString.into()
+            || msg == ListBoxMessage::InsertString.into()
+            || msg == ListBoxMessage::FindString.into()
+            || msg == ListBoxMessage::FindStringExact.into()
+        {
+            read_string_parameter(accessor, l_param)?
+        } else {
+            Vec::new()
+        };
+        let changes = msg == ListBoxMessage::AddString.into()
+            || msg == ListBoxMessage::InsertString.into()
+            || msg == ListBoxMessage::DeleteString.into()
+            || msg == ListBoxMessage::ResetContent.into();
+        let result = self
+            .with_list(h_wnd, |list, _| {
+                if msg == ListBoxMessage::AddString.into() {
+                    Some(list.insert(None, text) as u32)
+                } else if msg == ListBoxMessage::InsertString.into() {
+                    Some(list.insert(Some(start.unwrap_or(usize::MAX)), text) as u32)
+                } else if msg == ListBoxMessage::DeleteString.into() {
+                    Some(if list.delete(index) {
+                        list.items.len() as u32
+                    } else {
+                        LIST_ERROR
+                    })
+                } else if msg == ListBoxMessage::ResetContent.into() {
+                    list.clear();
+                    Some(0)
+                } else if msg == ListBoxMessage::GetTextLen.into() {
+                    Some(
+                        list.items
+                            .get(index)
+                            .map_or(LIST_ERROR, |item| item.text.len() as u32),
+                    )
+                } else if msg == ListBoxMessage::GetCount.into() {
+                    Some(list.items.len() as u32)
+                } else if msg == ListBoxMessage::FindString.into()
+                    || msg == ListBoxMessage::FindStringExact.into()
+                {
+                    let exact = msg == ListBoxMessage::FindStringExact.into();
+                    Some(
+                        list.find(start, &text, exact)
+                            .map_or(LIST_ERROR, |index| index as u32),
+                    )
+                } else if msg == ListBoxMessage::GetItemData.into() {
+                    Some(list.items.get(index).map_or(LIST_ERROR, |item| item.data))
+                } else if msg == ListBoxMessage::SetItemData.into() {
+                    Some(match list.items.get_mut(index) {
+                        Some(item) => {
+                            item.data = l_param;
+                            0
+                        }
+                        None => LIST_ERROR,
+                    })
+                } else if msg == ListBoxMessage::GetItemHeight.into() {
+                    Some(LINE_HEIGHT as u32)
+                } else if msg == ListBoxMessage::SetItemHeight.into() {
+                    // TODO: every item is as high as a line of text
+                    Some(0)
+                } else {
+                    None
+                }
+            })
+            .flatten();
+        if changes {
+            self.repaint_control(h_wnd);
+        }
+        Ok(result)
+    }
+
+    /// Tells the parent of a list box about something with WM_COMMAND, if the list box has
+    /// LBS_NOTIFY.
+    fn notify_list_box_parent(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        notification: ListBoxNotification,
+    ) -> Result<(), EmulatorError> {
+        let notify = self
+            .with_list(h_wnd, |_, style| {
+                ListBoxStyles::from_bits_truncate(style).contains(ListBoxStyles::NOTIFY)
+            })
+            .unwrap_or(false);
+        if notify {
+            self.notify_parent(accessor, h_wnd, notification.into())?;
+        }
+        Ok(())
+    }
+
+    #[api_function]
+    pub fn list_box_window_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] LIST BOX WINDOW PROC {:?} {:x} {:x} {:x}",
+            h_wnd, msg, w_param, l_param
+        );
+        let result = self.list_box_proc(&mut accessor, h_wnd, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+
+    fn list_box_proc(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        if let Some(result) = self.list_message(accessor, h_wnd, msg, w_param, l_param)? {
+            return Ok(result);
+        }
+        let index = w_param as usize;
+        let (width, height) = self.client_size_of(h_wnd);
+        if msg == MessageType::Create.into() {
+            let style = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => {
+                    ListBoxStyles::from_bits_truncate(user_window.class_specific_style)
+                }
+                _ => return Ok(0),
+            };
+            let list = ListBox::new(
+                style.contains(ListBoxStyles::SORT),
+                style.intersects(ListBoxStyles::MULTIPLESEL | ListBoxStyles::EXTENDEDSEL),
+            );
+            self.set_control(h_wnd, Control::ListBox(list));
+            Ok(0)
+        } else if msg == MessageType::Paint.into() {
+            self.paint_control_window(accessor, h_wnd)?;
+            Ok(0)
+        } else if msg == MessageType::SetFocus.into() || msg == MessageType::KillFocus.into() {
+            let focused = msg == MessageType::SetFocus.into();
+            self.with_list(h_wnd, |list, _| list.focused = focused);
+            self.repaint_control(h_wnd);
+            let notification = if focused {
+                ListBoxNotification::SetFocus
+            } else {
+                ListBoxNotification::KillFocus
+            };
+            self.notify_list_box_parent(accessor, h_wnd, notification)?;
+            Ok(0)
+        } else if msg == MessageType::LButtonDown.into() || msg == MessageType::LButtonDblClk.into()
+        {
+            self.focus_control(accessor, h_wnd)?;
+            let y = (l_param >> 16) as i16;
+            let changed = self
+                .with_list(h_wnd, |list, _| {
+                    list.item_at(y).map(|index| list.choose(index, true))
+                })
+                .flatten();
+            if let Some(changed) = changed {
+                self.repaint_control(h_wnd);
+                if changed {
+                    self.notify_list_box_parent(accessor, h_wnd, ListBoxNotification::SelChange)?;
+                }
+                if msg == MessageType::LButtonDblClk.into() {
+                    self.notify_list_box_parent(accessor, h_wnd, ListBoxNotification::DblClk)?;
+                }
+            }
+            Ok(0)
+        } else if msg == MessageType::KeyDown.into() || msg == MessageType::Char.into() {
+            let key = msg == MessageType::KeyDown.into();
+            let page = list_capacity(height);
+            let changed = self
+                .with_list(h_wnd, |list, _| {
+                    let (target, toggle) = if key && w_param as u8 == VK_SPACE {
+                        (list.caret, true)
+                    } else if key {
+                        (list.key_target(w_param as u8, page), false)
+                    } else {
+                        // Typing a character goes to the next item that starts with it
+                        (list.find(list.caret, &[w_param as u8], false), false)
+                    };
+                    target.map(|target| {
+                        let changed = list.choose(target, toggle);
+                        list.scroll_to(target, height);
+                        changed
+                    })
+                })
+                .flatten();
+            if let Some(changed) = changed {
+                self.repaint_control(h_wnd);
+                if changed {
+                    self.notify_list_box_parent(accessor, h_wnd, ListBoxNotification::SelChange)?;
+                }
+            }
+            Ok(0)
+        } else if msg == ListBoxMessage::SetSel.into() {
+            // -1 selects or deselects every item
+            let item = (l_param as u16 != 0xffff).then_some(l_param as u16 as usize);
+            let done = self
+                .with_list(h_wnd, |list, _| {
+                    list.multiple && list.set_selected(item, w_param != 0)
+                })
+                .unwrap_or(false);
+            self.repaint_control(h_wnd);
+            Ok(if done { 0 } else { LIST_ERROR })
+        } else if msg == ListBoxMessage::SetCurSel.into()
+            || msg == ListBoxMessage::SelectString.into()
+        {
+            let text = if msg == ListBoxMessage::SelectString.into() {
+                Some(read_string_parameter(accessor, l_param)?)
+            } else {
+                None
+            };
+            let selected = self
+                .with_list(h_wnd, |list, _| {
+                    if list.multiple {
+                        return None;
+                    }
+                    let target = match &text {
+                        Some(text) => {
+                            list.find((w_param != 0xffff).then_some(index), text, false)?
+                        }
+                        None => index,
+                    };
+                    // -1 selects nothing
+                    if target >= list.items.len() {
+                        list.select(None);
+                        return None;
+                    }
+                    list.select(Some(target));
+                    list.scroll_to(target, height);
+                    Some(target as u32)
+                })
+                .flatten();
+            self.repaint_control(h_wnd);
+            Ok(selected.unwrap_or(LIST_ERROR))
+        } else if msg == ListBoxMessage::GetSel.into() {
+            Ok(self
+                .with_list(h_wnd, |list, _| {
+                    list.items
+                        .get(index)
+                        .map_or(LIST_ERROR, |item| item.selected.into())
+                })
+                .unwrap_or(LIST_ERROR))
+        } else if msg == ListBoxMessage::GetCurSel.into() {
+            // A list box where several items can be selected has the caret instead
+            Ok(self
+                .with_list(h_wnd, |list, _| {
+                    let current = if list.multiple {
+                        list.caret
+                    } else {
+                        list.current()
+                    };
+                    current.map_or(LIST_ERROR, |current| current as u32)
+                })
+                .unwrap_or(LIST_ERROR))
+        } else if msg == ListBoxMessage::GetTopIndex.into() {
+            Ok(self
+                .with_list(h_wnd, |list, _| list.top as u32)
+                .unwrap_or(0))
+        } else if msg == ListBoxMessage::SetTopIndex.into() {
+            let done = self
+                .with_list(h_wnd, |list, _| {
+                    let valid = index < list.items.len();
+                    if valid {
+                        list.top = index;
+                    }
+                    valid
+                })
+                .unwrap_or(false);
+            self.repaint_control(h_wnd);
+            Ok(if done { 0 } else { LIST_ERROR })
+        } else if msg == ListBoxMessage::GetSelCount.into()
+            || msg == ListBoxMessage::GetSelItems.into()
+        {
+            let selected = self
+                .with_list(h_wnd, |list, _| {
+                    list.multiple.then(|| {
+                        list.items
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, item)| item.selected)
+                            .map(|(index, _)| index as u16)
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .flatten();
+            match selected {
+                Some(selected) if msg == ListBoxMessage::GetSelItems.into() => {
+                    // The buffer has room for as many indices as wParam says
+                    let buffer = Pointer::from_far(l_param).0;
+                    let count = selected.len().min(index);
+                    for (position, &item) in selected[..count].iter().enumerate() {
+                        accessor
+                            .memory_mut()
+                            .write_u16(buffer + 2 * position as u32, item)?;
+                    }
+                    Ok(count as u32)
+                }
+                Some(selected) => Ok(selected.len() as u32),
+                None => Ok(LIST_ERROR),
+            }
+        } else if msg == ListBoxMessage::GetItemRect.into() {
+            let rect = self
+                .with_list(h_wnd, |list, _| {
+                    (index < list.items.len()).then(|| list.item_rect(index, width))
+                })
+                .flatten();
+            match rect {
+                Some(rect) => {
+                    accessor.write_rect(Pointer::from_far(l_param).0, &rect)?;
+                    Ok(0)
+                }
+                None => Ok(LIST_ERROR),
+            }
+        } else if msg == ListBoxMessage::SelItemRange.into() {
+            let (first, last) = (l_param as u16 as usize, (l_param >> 16) as usize);
+            let done = self
+                .with_list(h_wnd, |list, _| {
+                    list.multiple
+                        && (first..=last).all(|item| list.set_selected(Some(item), w_param != 0))
+                })
+                .unwrap_or(false);
+            self.repaint_control(h_wnd);
+            Ok(if done { 0 } else { LIST_ERROR })
+        } else if msg == ListBoxMessage::SetCaretIndex.into() {
+            let done = self
+                .with_list(h_wnd, |list, _| {
+                    let valid = index < list.items.len();
+                    if valid {
+                        list.caret = Some(index);
+                        list.scroll_to(index, height);
+                    }
+                    valid
+                })
+                .unwrap_or(false);
+            self.repaint_control(h_wnd);
+            Ok(if done { 0 } else { LIST_ERROR })
+        } else if msg == ListBoxMessage::GetCaretIndex.into() {
+            Ok(self
+                .with_list(h_wnd, |list, _| list.caret.unwrap_or(0) as u32)
+                .unwrap_or(0))
+        } else if msg == ListBoxMessage::Dir.into() {
+            // TODO: list the files in a directory
+            Ok(LIST_ERROR)
+        } else {
+            self.default_window_proc(accessor, h_wnd, msg, w_param, l_param)
+        }
+    }
+
+    #[api_function]
+    pub fn combo_box_window_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] COMBO BOX WINDOW PROC {:?} {:x} {:x} {:x}",
+            h_wnd, msg, w_param, l_param
+        );
+        let result = self.combo_box_proc(&mut accessor, h_wnd, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+
+    fn combo_box_proc(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        if let Some(list_message) = combo_box_list_message(msg) {
+            let result = self.list_message(accessor, h_wnd, list_message, w_param, l_param)?;
+            // The selection field shows what is left of the selected item
+            if msg == ComboBoxMessage::ResetContent.into()
+                || msg == ComboBoxMessage::DeleteString.into()
+            {
+                self.with_combo_box(h_wnd, |combo_box| {
+                    if combo_box.list.current().is_none() {
+                        combo_box.select(None);
+                    }
+                });
+                self.repaint_control(h_wnd);
+            }
+            return Ok(result.unwrap_or(LIST_ERROR));
+        }
+        let (width, height) = self.client_size_of(h_wnd);
+        if msg == MessageType::Create.into() {
+            let style = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.class_specific_style,
+                _ => return Ok(0),
+            };
+            let combo_box = ComboBox::new(
+                style,
+                height,
+                ComboBoxStyles::from_bits_truncate(style).contains(ComboBoxStyles::SORT),
+            );
+            let list_shown = combo_box.list_shown();
+            self.set_control(h_wnd, Control::ComboBox(combo_box));
+            // The height of the window is the height with the list dropped down
+            if !list_shown {
+                self.set_combo_box_height(accessor, h_wnd, COMBO_FIELD_HEIGHT)?;
+            }
+            Ok(0)
+        } else if msg == MessageType::Paint.into() {
+            self.paint_control_window(accessor, h_wnd)?;
+            Ok(0)
+        } else if msg == MessageType::SetFocus.into() || msg == MessageType::KillFocus.into() {
+            let focused = msg == MessageType::SetFocus.into();
+            self.with_combo_box(h_wnd, |combo_box| combo_box.edit.focused = focused);
+            self.repaint_control(h_wnd);
+            let notification = if focused {
+                ComboBoxNotification::SetFocus
+            } else {
+                ComboBoxNotification::KillFocus
+            };
+            self.notify_parent(accessor, h_wnd, notification.into())?;
+            Ok(0)
+        } else if msg == MessageType::LButtonDown.into() || msg == MessageType::LButtonDblClk.into()
+        {
+            self.focus_control(accessor, h_wnd)?;
+            let point = Point::new(l_param as i16, (l_param >> 16) as i16);
+            let (combo_box_type, button_rect, field_rect, list_rect) =
+                match self.with_combo_box(h_wnd, |combo_box| {
+                    (
+                        combo_box.combo_box_type,
+                        combo_box.button_rect(width),
+                        combo_box.field_rect(width),
+                        combo_box.list_rect((width, height)),
+                    )
+                }) {
+                    Some(combo_box) => combo_box,
+                    None => return Ok(0),
+                };
+            let on_button = button_rect.is_some_and(|rect| rect.contains(point));
+            if on_button
+                || (combo_box_type == ComboBoxType::DropDownList && field_rect.contains(point))
+            {
+                self.drop_down_combo_box(accessor, h_wnd)?;
+            } else if field_rect.contains(point) {
+                let field_size = (field_rect.right, field_rect.bottom);
+                self.with_combo_box(h_wnd, |combo_box| {
+                    let index = combo_box.edit.index_at(field_size, false, point);
+                    combo_box.edit.move_caret(index, false);
+                });
+                self.repaint_control(h_wnd);
+            } else if combo_box_type == ComboBoxType::Simple && list_rect.contains(point) {
+                let changed = self
+                    .with_combo_box(h_wnd, |combo_box| {
+                        let index = combo_box.list.item_at(point.y - list_rect.top)?;
+                        let changed = combo_box.list.current() != Some(index);
+                        combo_box.select(Some(index));
+                        Some(changed)
+                    })
+                    .flatten();
+                self.repaint_control(h_wnd);
+                if changed == Some(true) {
+                    self.notify_parent(accessor, h_wnd, ComboBoxNotification::SelChange.into())?;
+                }
+                if changed.is_some() && msg == MessageType::LButtonDblClk.into() {
+                    self.notify_parent(accessor, h_wnd, ComboBoxNotification::DblClk.into())?;
+                }
+            }
+            Ok(0)
+        } else if msg == MessageType::KeyDown.into() || msg == MessageType::Char.into() {
+            let vk = w_param as u8;
+            let key = msg == MessageType::KeyDown.into();
+            let combo_box_type =
+                match self.with_combo_box(h_wnd, |combo_box| combo_box.combo_box_type) {
+                    Some(combo_box_type) => combo_box_type,
+                    None => return Ok(0),
+                };
+            if key && vk == VK_F4 && combo_box_type != ComboBoxType::Simple {
+                self.drop_down_combo_box(accessor, h_wnd)?;
+            } else if (key && (vk == VK_UP || vk == VK_DOWN))
+                || (!key && combo_box_type == ComboBoxType::DropDownList)
+            {
+                // Up and Down go through the items, and so does typing in a drop-down list
+                let changed = self
+                    .with_combo_box(h_wnd, |combo_box| {
+                        let list = &combo_box.list;
+                        let target = if key {
+                            list.key_target(vk, 1)
+                        } else {
+                            list.find(list.current(), &[vk], false)
+                        }?;
+                        let changed = list.current() != Some(target);
+                        combo_box.select(Some(target));
+                        Some(changed)
+                    })
+                    .flatten();
+                self.repaint_control(h_wnd);
+                if changed == Some(true) {
+                    self.notify_parent(accessor, h_wnd, ComboBoxNotification::SelChange.into())?;
+                }
+            } else if combo_box_type != ComboBoxType::DropDownList {
+                let shift = self.read_objects().key_states.is_down(VK_SHIFT);
+                let changed = self
+                    .with_combo_box(h_wnd, |combo_box| {
+                        if key {
+                            combo_box.edit.key_down(vk, shift, false, 1, false)
+                        } else {
+                            combo_box
+                                .edit
+                                .type_character(vk, EditStyles::empty())
+                                .map(|_| true)
+                        }
+                    })
+                    .flatten();
+                if changed == Some(true) {
+                    self.notify_parent(accessor, h_wnd, ComboBoxNotification::EditUpdate.into())?;
+                }
+                self.repaint_control(h_wnd);
+                if changed == Some(true) {
+                    self.notify_parent(accessor, h_wnd, ComboBoxNotification::EditChange.into())?;
+                }
+            }
+            Ok(0)
+        } else if msg == MessageType::SetText.into() {
+            let text = read_string_parameter(accessor, l_param)?;
+            self.with_combo_box(h_wnd, |combo_box| combo_box.edit.set_text(&text));
+            self.set_control_text(accessor, h_wnd, l_param)
+        } else if msg == MessageType::GetText.into() {
+            let text = self
+                .with_combo_box(h_wnd, |combo_box| combo_box.edit.text.clone())
+                .unwrap_or_default();
+            get_text(accessor, &text, w_param, l_param)
+        } else if msg == MessageType::GetTextLength.into() {
+            Ok(self
+                .with_combo_box(h_wnd, |combo_box| combo_box.edit.text.len() as u32)
+                .unwrap_or(0))
+        } else if msg == ComboBoxMessage::GetEditSel.into() {
+            Ok(self
+                .with_combo_box(h_wnd, |combo_box| {
+                    let (start, end) = combo_box.edit.selection();
+                    encode_u16_u16_to_u32(start as u16, end as u16)
+                })
+                .unwrap_or(0))
+        } else if msg == ComboBoxMessage::SetEditSel.into()
+            || msg == ComboBoxMessage::LimitText.into()
+        {
+            // A drop-down list has nothing to edit
+            let done = self
+                .with_combo_box(h_wnd, |combo_box| {
+                    if combo_box.combo_box_type == ComboBoxType::DropDownList {
+                        false
+                    } else if msg == ComboBoxMessage::LimitText.into() {
+                        combo_box.edit.limit = if w_param == 0 {
+                            u16::MAX.into()
+                        } else {
+                            w_param.into()
+                        };
+                        true
+                    } else {
+                        let (start, end) = (l_param as u16, (l_param >> 16) as u16);
+                        combo_box.edit.set_selection(start.into(), end.into());
+                        true
+                    }
+                })
+                .unwrap_or(false);
+            self.repaint_control(h_wnd);
+            Ok(if done { 1 } else { LIST_ERROR })
+        } else if msg == ComboBoxMessage::GetCurSel.into() {
+            Ok(self
+                .with_combo_box(h_wnd, |combo_box| {
+                    combo_box
+                        .list
+                        .current()
+                        .map_or(LIST_ERROR, |current| current as u32)
+                })
+                .unwrap_or(LIST_ERROR))
+        } else if msg == ComboBoxMessage::SetCurSel.into()
+            || msg == ComboBoxMessage::SelectString.into()
+        {
+            let text = if msg == ComboBoxMessage::SelectString.into() {
+                Some(read_string_parameter(accessor, l_param)?)
+            } else {
+                None
+            };
+            let list_height = height - COMBO_FIELD_HEIGHT;
+            let selected = self
+                .with_combo_box(h_wnd, |combo_box| {
+                    let target = match &text {
+                        Some(text) => {
+                            let start = (w_param != 0xffff).then_some(w_param as usize);
+                            combo_box.list.find(start, text, false)?
+                        }
+                        None => w_param as usize,
+                    };
+                    // -1 selects nothing
+                    if target >= combo_box.list.items.len() {
+                        combo_box.select(None);
+                        return None;
+                    }
+                    combo_box.select(Some(target));
+                    combo_box.list.scroll_to(target, list_height);
+                    Some(target as u32)
+                })
+                .flatten();
+            self.repaint_control(h_wnd);
+            Ok(selected.unwrap_or(LIST_ERROR))
+        } else if msg == ComboBoxMessage::ShowDropDown.into() {
+            // TODO: the list only drops down while the user chooses from it
+            Ok(1)
+        } else if msg == ComboBoxMessage::GetDroppedControlRect.into() {
+            let rect = {
+                let objects = self.read_objects();
+                self.client_screen_position_of(&objects, h_wnd)
+            }
+            .zip(self.with_combo_box(h_wnd, |combo_box| combo_box.dropped_height))
+            .map(|(origin, dropped_height)| Rect {
+                left: origin.x,
+                top: origin.y,
+                right: origin.x + width,
+                bottom: origin.y + dropped_height,
+            });
+            if let Some(rect) = rect {
+                accessor.write_rect(Pointer::from_far(l_param).0, &rect)?;
+            }
+            Ok(0)
+        } else if msg == ComboBoxMessage::GetDroppedState.into() {
+            Ok(self
+                .with_combo_box(h_wnd, |combo_box| combo_box.dropped.into())
+                .unwrap_or(0))
+        } else if msg == ComboBoxMessage::Dir.into() {
+            // TODO: list the files in a directory
+            Ok(LIST_ERROR)
+        } else {
+            self.default_window_proc(accessor, h_wnd, msg, w_param, l_param)
+        }
+    }
+
+    /// Changes the height of the client area of a combo box, which makes room for the list
+    /// when it drops down.
+    fn set_combo_box_height(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        height: i16,
+    ) -> Result<(), EmulatorError> {
+        let rects = {
+            let objects = self.read_objects();
+            let window_manager = objects.read_window_manager();
+            objects
+                .window_identifier(h_wnd)
+                .and_then(|window_identifier| {
+                    window_manager
+                        .window_rect_of(window_identifier)
+                        .zip(window_manager.client_rect_of(window_identifier))
+                })
+        };
+        if let Some((window_rect, client_rect)) = rects {
+            let frame_height =
+                (window_rect.bottom - window_rect.top) - (client_rect.bottom - client_rect.top);
+            self.set_window_pos(
+                accessor,
+                h_wnd,
+                Handle::null(),
+                Rect {
+                    bottom: window_rect.top + frame_height + height,
+                    ..window_rect
+                },
+                SetWindowPosFlags::NOMOVE
+                    | SetWindowPosFlags::NOZORDER
+                    | SetWindowPosFlags::NOACTIVATE,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Drops the list of a combo box down for the user to choose an item from. Clicking an
+    /// item or pressing Enter chooses, and pressing the mouse button outside of the list or
+    /// pressing Escape cancels.
+    fn drop_down_combo_box(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<(), EmulatorError> {
+        let window_identifier = match self.read_objects().window_identifier(h_wnd) {
+            Some(window_identifier) => window_identifier,
+            None => return Ok(()),
+        };
+        self.notify_parent(accessor, h_wnd, ComboBoxNotification::DropDown.into())?;
+        let dropped = self.with_combo_box(h_wnd, |combo_box| {
+            combo_box.dropped = true;
+            (combo_box.dropped_height, combo_box.list.current())
+        });
+        let (dropped_height, previous) = match dropped {
+            Some(dropped) => dropped,
+            None => return Ok(()),
+        };
+        self.set_combo_box_height(accessor, h_wnd, dropped_height)?;
+        let size = self.client_size_of(h_wnd);
+        let list_rect = self
+            .with_combo_box(h_wnd, |combo_box| combo_box.list_rect(size))
+            .unwrap_or_else(Rect::zero);
+        let list_height = list_rect.bottom - list_rect.top;
+        let highlight = |index: usize| {
+            self.with_combo_box(h_wnd, |combo_box| {
+                combo_box.list.select(Some(index));
+                combo_box.list.scroll_to(index, list_height);
+            });
+            self.repaint_control(h_wnd);
+        };
+        if let Some(previous) = previous {
+            highlight(previous);
+        } else {
+            self.repaint_control(h_wnd);
+        }
+
+        self.read_objects()
+            .write_window_manager()
+            .set_capture(Some(window_identifier));
+        let chosen = loop {
+            let input = self.wait_for_input();
+            let point = self.screen_to_client(h_wnd, input.point);
+            let item = if list_rect.contains(point) {
+                self.with_combo_box(h_wnd, |combo_box| {
+                    combo_box.list.item_at(point.y - list_rect.top)
+                })
+                .flatten()
+            } else {
+                None
+            };
+            match input.message {
+                MessageType::MouseMove | MessageType::LButtonUp => {
+                    if let Some(item) = item {
+                        highlight(item);
+                        if input.message == MessageType::LButtonUp {
+                            break true;
+                        }
+                    }
+                }
+                MessageType::LButtonDown if !list_rect.contains(point) => break false,
+                MessageType::KeyDown => match input.w_param as u8 {
+                    VK_RETURN => break true,
+                    VK_ESCAPE | VK_F4 => break false,
+                    vk => {
+                        let page = list_capacity(list_height);
+                        let target = self
+                            .with_combo_box(h_wnd, |combo_box| combo_box.list.key_target(vk, page))
+                            .flatten();
+                        if let Some(target) = target {
+                            highlight(target);
+                        }
+                    }
+                },
+                _ => {}
+            }
+        };
+        self.read_objects().write_window_manager().set_capture(None);
+
+        let selected = self
+            .with_combo_box(h_wnd, |combo_box| {
+                let selected = if chosen {
+                    combo_box.list.current()
+                } else {
+                    previous
+                };
+                combo_box.select(selected);
+                combo_box.dropped = false;
+                selected
+            })
+            .flatten();
+        self.set_combo_box_height(accessor, h_wnd, COMBO_FIELD_HEIGHT)?;
+        // The list covered what is below the combo box, which its parent paints again right away
+        let parent = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.parent_handle,
+            _ => Handle::null(),
+        };
+        self.update_now(accessor, parent)?;
+        self.notify_parent(accessor, h_wnd, ComboBoxNotification::CloseUp.into())?;
+        if selected != previous {
+            self.notify_parent(accessor, h_wnd, ComboBoxNotification::SelChange.into())?;
+        }
+        Ok(())
+    }
+
+    #[api_function]
+    pub fn scroll_bar_window_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] SCROLL BAR WINDOW PROC {:?} {:x} {:x} {:x}",
+            h_wnd, msg, w_param, l_param
+        );
+        let result = self.scroll_bar_proc(&mut accessor, h_wnd, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+
+    fn scroll_bar_proc(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        if msg == MessageType::Create.into() {
+            let style = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => {
+                    ScrollBarStyles::from_bits_truncate(user_window.class_specific_style)
+                }
+                _ => return Ok(0),
+            };
+            let scroll_bar = ScrollBar::new(style.contains(ScrollBarStyles::VERT));
+            self.set_control(h_wnd, Control::ScrollBar(scroll_bar));
+            Ok(0)
+        } else if msg == MessageType::Paint.into() {
+            self.paint_control_window(accessor, h_wnd)?;
+            Ok(0)
+        } else if msg == MessageType::LButtonDown.into() || msg == MessageType::LButtonDblClk.into()
+        {
+            let point = Point::new(l_param as i16, (l_param >> 16) as i16);
+            self.track_scroll_bar(accessor, h_wnd, point)?;
+            Ok(0)
+        } else if msg == MessageType::KeyDown.into() {
+            let code = match w_param as u8 {
+                VK_UP | VK_LEFT => Some(ScrollCode::LineUp),
+                VK_DOWN | VK_RIGHT => Some(ScrollCode::LineDown),
+                VK_PRIOR => Some(ScrollCode::PageUp),
+                VK_NEXT => Some(ScrollCode::PageDown),
+                VK_HOME => Some(ScrollCode::Top),
+                VK_END => Some(ScrollCode::Bottom),
+                _ => None,
+            };
+            if let Some(code) = code {
+                self.send_scroll(accessor, h_wnd, code)?;
+                self.send_scroll(accessor, h_wnd, ScrollCode::EndScroll)?;
+            }
+            Ok(0)
+        } else if msg == ScrollBarMessage::SetPos.into() {
+            // lParam tells whether to redraw
+            let old = self
+                .with_scroll_bar(h_wnd, |scroll_bar| scroll_bar.set_pos(w_param as i16))
+                .unwrap_or(0);
+            if l_param != 0 {
+                self.repaint_control(h_wnd);
+            }
+            Ok(old as u16 as u32)
+        } else if msg == ScrollBarMessage::GetPos.into() {
+            Ok(self
+                .with_scroll_bar(h_wnd, |scroll_bar| scroll_bar.pos as u16 as u32)
+                .unwrap_or(0))
+        } else if msg == ScrollBarMessage::SetRange.into() {
+            // wParam tells whether to redraw, and lParam has the minimum and the maximum
+            self.with_scroll_bar(h_wnd, |scroll_bar| {
+                scroll_bar.set_range(l_param as i16, (l_param >> 16) as i16)
+            });
+            if w_param != 0 {
+                self.repaint_control(h_wnd);
+            }
+            Ok(0)
+        } else if msg == ScrollBarMessage::GetRange.into() {
+            Ok(self
+                .with_scroll_bar(h_wnd, |scroll_bar| {
+                    encode_u16_u16_to_u32(scroll_bar.min as u16, scroll_bar.max as u16)
+                })
+                .unwrap_or(0))
+        } else if msg == ScrollBarMessage::EnableArrows.into() {
+            // TODO: the arrows are always enabled
+            Ok(1)
+        } else {
+            self.default_window_proc(accessor, h_wnd, msg, w_param, l_param)
+        }
+    }
+
+    /// Tells the parent of a scroll bar control that it is being scrolled, with WM_VSCROLL or
+    /// WM_HSCROLL. lParam has the position of the thumb and the scroll bar.
+    fn send_scroll(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        code: ScrollCode,
+    ) -> Result<(), EmulatorError> {
+        let (parent, vertical, pos) = {
+            let objects = self.read_objects();
+            match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => match user_window.control.as_deref() {
+                    Some(Control::ScrollBar(scroll_bar)) => (
+                        user_window.parent_handle,
+                        scroll_bar.vertical,
+                        scroll_bar.pos,
+                    ),
+                    _ => return Ok(()),
+                },
+                _ => return Ok(()),
+            }
+        };
+        let message = if vertical {
+            MessageType::VScroll
+        } else {
+            MessageType::HScroll
+        };
+        self.send_message(
+            accessor,
+            parent,
+            message.into(),
+            code.into(),
+            encode_u16_u16_to_u32(pos as u16, h_wnd.as_u16()),
+        )?;
+        Ok(())
+    }
+
+    /// Scrolls with the mouse, from the part of a scroll bar that the mouse button went down
+    /// on until it gets released. Dragging the thumb moves it along.
+    fn track_scroll_bar(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        point: Point,
+    ) -> Result<(), EmulatorError> {
+        let window_identifier = match self.read_objects().window_identifier(h_wnd) {
+            Some(window_identifier) => window_identifier,
+            None => return Ok(()),
+        };
+        let size = self.client_size_of(h_wnd);
+        let (part, grab) = match self.with_scroll_bar(h_wnd, |scroll_bar| {
+            (
+                scroll_bar.part_at(size, point),
+                scroll_bar.grab_offset(size, point),
+            )
+        }) {
+            Some((Some(part), grab)) => (part, grab),
+            _ => return Ok(()),
+        };
+        let code = match part {
+            ScrollBarPart::LineUp => ScrollCode::LineUp,
+            ScrollBarPart::PageUp => ScrollCode::PageUp,
+            ScrollBarPart::Thumb => ScrollCode::ThumbTrack,
+            ScrollBarPart::PageDown => ScrollCode::PageDown,
+            ScrollBarPart::LineDown => ScrollCode::LineDown,
+        };
+        // TODO: keep scrolling while the mouse button is held down on an arrow or the track
+        if code != ScrollCode::ThumbTrack {
+            self.send_scroll(accessor, h_wnd, code)?;
+        }
+        self.read_objects()
+            .write_window_manager()
+            .set_capture(Some(window_identifier));
+        loop {
+            let input = self.wait_for_input();
+            match input.message {
+                MessageType::MouseMove if code == ScrollCode::ThumbTrack => {
+                    let point = self.screen_to_client(h_wnd, input.point);
+                    let moved = self
+                        .with_scroll_bar(h_wnd, |scroll_bar| {
+                            let pos = scroll_bar.pos_at(size, point, grab);
+                            scroll_bar.set_pos(pos) != scroll_bar.pos
+                        })
+                        .unwrap_or(false);
+                    if moved {
+                        self.repaint_control(h_wnd);
+                        self.send_scroll(accessor, h_wnd, ScrollCode::ThumbTrack)?;
+                    }
+                }
+                MessageType::LButtonUp => break,
+                _ => {}
+            }
+        }
+        self.read_objects().write_window_manager().set_capture(None);
+        if code == ScrollCode::ThumbTrack {
+            self.send_scroll(accessor, h_wnd, ScrollCode::ThumbPosition)?;
+        }
+        self.send_scroll(accessor, h_wnd, ScrollCode::EndScroll)
+    }
+}
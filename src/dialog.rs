@@ -0,0 +1,706 @@
+//! Dialog boxes: the templates they are made from, the dialog units those are laid out in, what
+//! USER keeps about a dialog box while it is open, and the dialog manager that creates and runs
+//! them and moves the focus between their controls.
+
+use crate::api_helpers::ReturnValue;
+use crate::byte_string::HeapByteString;
+use crate::constants::{
+    ActivateState, ButtonType, DialogStyles, EditMessage, MessageFilterCode, MessageType,
+    ShowWindowCommand, SystemColors, WindowStyles, BN_CLICKED, IDCANCEL, IDOK, RT_DIALOG,
+};
+use crate::controls::{self, Control};
+use crate::emulated_user::{EmulatedUser, WindowCreation};
+use crate::emulator_accessor::EmulatorAccessor;
+use crate::font;
+use crate::handle_table::{GenericHandle, Handle};
+use crate::keyboard::{VK_ESCAPE, VK_RETURN, VK_SHIFT, VK_TAB};
+use crate::memory::SegmentAndOffset;
+use crate::menu;
+use crate::message_queue::{MessageFilter, PostedMessage};
+use crate::non_client;
+use crate::object_environment::{UserObject, UserWindow};
+use crate::two_d::Rect;
+use crate::util::{encode_u16_u16_to_u32, u16_from_slice};
+use crate::{debug, EmulatorError, ResourceName};
+use syscall::api_function;
+
+/// DLGWINDOWEXTRA, the window extra bytes that a class for dialog boxes must have.
+pub const DIALOG_WINDOW_EXTRA: u16 = 30;
+/// Where a dialog box keeps the result of the message its dialog procedure handled, and the
+/// dialog procedure itself, in its extra bytes.
+pub const DWL_MSGRESULT: i16 = 0;
+pub const DWL_DLGPROC: i16 = 4;
+/// The class of dialog boxes whose template doesn't name one.
+pub const DIALOG_CLASS_NAME: &[u8] = b"#32770";
+
+/// What USER keeps about a window that is a dialog box.
+pub struct Dialog {
+    /// The dialog procedure, which DefDlgProc lets handle the messages first.
+    pub proc: SegmentAndOffset,
+    /// The result EndDialog ended a modal dialog box with.
+    pub result: Option<u16>,
+    /// The control that the keys go to.
+    pub focus: Handle,
+}
+
+impl Dialog {
+    pub fn new(proc: SegmentAndOffset) -> Self {
+        Self {
+            proc,
+            result: None,
+            focus: Handle::null(),
+        }
+    }
+}
+
+/// A dialog box template, as found in an RT_DIALOG resource.
+pub struct DialogTemplate {
+    pub style: u32,
+    /// Where the dialog box goes, in dialog units, relative to the client area of its owner.
+    pub rect: Rect,
+    pub menu: Option<ResourceName>,
+    pub class: Option<HeapByteString>,
+    pub caption: HeapByteString,
+    pub items: Vec<DialogItem>,
+}
+
+/// A control of a dialog box template.
+pub struct DialogItem {
+    /// Where the control goes, in dialog units, relative to the client area of the dialog box.
+    pub rect: Rect,
+    pub id: u16,
+    pub style: u32,
+    pub class: HeapByteString,
+    /// The text of the control, or the name of the resource it shows, such as an icon.
+    pub text: HeapByteString,
+}
+
+/// Reads a dialog box template with the controls in it.
+pub fn parse_template(data: &[u8]) -> Option<DialogTemplate> {
+    let style = u32_from_slice(data, 0)?;
+    let item_count = *data.get(4)?;
+    let rect = rect_from_slice(data, 5)?;
+    let mut offset = 13;
+    let menu = match *data.get(offset)? {
+        0 => {
+            offset += 1;
+            None
+        }
+        0xff => {
+            offset += 3;
+            Some(ResourceName::Integer(u16_from_slice(data, offset - 2)?))
+        }
+        _ => Some(ResourceName::String(HeapByteString::from_to_lowercase(
+            string_from_slice(data, &mut offset)?,
+        ))),
+    };
+    let class = string_from_slice(data, &mut offset)?;
+    let class = (!class.is_empty()).then(|| HeapByteString::from(class.into()));
+    let caption = HeapByteString::from(string_from_slice(data, &mut offset)?.into());
+    // There is only the system font, so the font the template asks for is skipped
+    if style & DialogStyles::SETFONT.bits() != 0 {
+        offset += 2;
+        string_from_slice(data, &mut offset)?;
+    }
+
+    let mut items = Vec::with_capacity(item_count.into());
+    for _ in 0..item_count {
+        let rect = rect_from_slice(data, offset)?;
+        let id = u16_from_slice(data, offset + 8)?;
+        let style = u32_from_slice(data, offset + 10)?;
+        offset += 14;
+        // The predefined classes have a number instead of a name
+        let class_number = *data.get(offset)?;
+        let class: &[u8] = if class_number & 0x80 != 0 {
+            offset += 1;
+            match class_number {
+                0x80 => b"BUTTON",
+                0x81 => b"EDIT",
+                0x82 => b"STATIC",
+                0x83 => b"LISTBOX",
+                0x84 => b"SCROLLBAR",
+                0x85 => b"COMBOBOX",
+                _ => b"",
+            }
+        } else {
+            string_from_slice(data, &mut offset)?
+        };
+        let class = HeapByteString::from(class.into());
+        // Resources are named with a number as "#123"
+        let text = if *data.get(offset)? == 0xff {
+            offset += 3;
+            let number = u16_from_slice(data, offset - 2)?;
+            HeapByteString::from(format!("#{}", number).into_bytes().into())
+        } else {
+            HeapByteString::from(string_from_slice(data, &mut offset)?.into())
+        };
+        // The data that the control gets with WM_CREATE follows
+        offset += 1 + *data.get(offset)? as usize;
+        items.push(DialogItem {
+            rect,
+            id,
+            style,
+            class,
+            text,
+        });
+    }
+
+    Some(DialogTemplate {
+        style,
+        rect,
+        menu,
+        class,
+        caption,
+        items,
+    })
+}
+
+/// Turns a rectangle in dialog units into pixels. A horizontal dialog unit is a quarter of the
+/// width of a character, and a vertical one is an eighth of the height of a character.
+pub fn to_pixels(rect: Rect) -> Rect {
+    let x = |units: i16| ((units as i32 * font::GLYPH_ADVANCE as i32) / 4) as i16;
+    let y = |units: i16| ((units as i32 * font::GLYPH_HEIGHT as i32) / 8) as i16;
+    Rect {
+        left: x(rect.left),
+        top: y(rect.top),
+        right: x(rect.right),
+        bottom: y(rect.bottom),
+    }
+}
+
+fn u32_from_slice(data: &[u8], offset: usize) -> Option<u32> {
+    let low = u16_from_slice(data, offset)?;
+    let high = u16_from_slice(data, offset + 2)?;
+    Some((high as u32) << 16 | low as u32)
+}
+
+/// Reads the x, y, width and height that templates use.
+fn rect_from_slice(data: &[u8], offset: usize) -> Option<Rect> {
+    let x = u16_from_slice(data, offset)? as i16;
+    let y = u16_from_slice(data, offset + 2)? as i16;
+    let width = u16_from_slice(data, offset + 4)? as i16;
+    let height = u16_from_slice(data, offset + 6)? as i16;
+    Some(Rect {
+        left: x,
+        top: y,
+        right: x.saturating_add(width),
+        bottom: y.saturating_add(height),
+    })
+}
+
+/// Reads a null-terminated string and moves past it.
+fn string_from_slice<'d>(data: &'d [u8], offset: &mut usize) -> Option<&'d [u8]> {
+    let length = data.get(*offset..)?.iter().position(|&byte| byte == 0)?;
+    let string = &data[*offset..*offset + length];
+    *offset += length + 1;
+    Some(string)
+}
+
+impl EmulatedUser<'_> {
+    /// Loads a dialog box template from an RT_DIALOG resource.
+    pub fn load_dialog_template(
+        &self,
+        accessor: &EmulatorAccessor,
+        template_name: u32,
+    ) -> Result<Option<DialogTemplate>, EmulatorError> {
+        // TODO: keep h_instance into account...
+        let template_name = accessor.resource_name(template_name)?;
+        Ok(self
+            .resource_table
+            .predefined_resource(RT_DIALOG, &template_name)
+            .and_then(parse_template))
+    }
+
+    /// Creates a dialog box with its controls from a template, and lets the dialog procedure
+    /// set it up with WM_INITDIALOG. Returns the null handle if the dialog box can't be created.
+    pub fn create_dialog_from_template(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        template: &DialogTemplate,
+        h_wnd_owner: Handle,
+        dialog_func: u32,
+        param: u32,
+    ) -> Result<Handle, EmulatorError> {
+        let style = WindowStyles::from_bits_truncate(template.style);
+        let mut rect = to_pixels(template.rect);
+        // Dialog boxes go relative to the client area of their owner, unless they ask for the
+        // screen. Child dialog boxes are relative to their parent anyway.
+        if !style.contains(WindowStyles::CHILD)
+            && template.style & DialogStyles::ABSALIGN.bits() == 0
+        {
+            let origin = {
+                let objects = self.read_objects();
+                self.client_screen_position_of(&objects, h_wnd_owner)
+            };
+            if let Some(origin) = origin {
+                rect = rect.offset(origin.x, origin.y);
+            }
+        }
+        // The template has the size of the client area
+        let rect = non_client::calculate_window_rect(style, rect);
+        let h_menu = template
+            .menu
+            .as_ref()
+            .and_then(|menu_name| self.load_menu_resource(menu_name))
+            .unwrap_or(Handle::null());
+        let class_name = template
+            .class
+            .clone()
+            .unwrap_or_else(|| HeapByteString::from(DIALOG_CLASS_NAME.into()));
+        let h_dlg = self.create_window(
+            accessor,
+            WindowCreation {
+                class_name,
+                window_name: template.caption.clone(),
+                // The dialog box shows up once WM_INITDIALOG has set it up
+                style: template.style & !WindowStyles::VISIBLE.bits(),
+                x: rect.left,
+                y: rect.top,
+                width: rect.right - rect.left,
+                height: rect.bottom - rect.top,
+                h_wnd_parent: h_wnd_owner,
+                h_menu,
+                h_instance: self.task.h_instance,
+                param: 0,
+            },
+        )?;
+        let proc = match self.write_objects().user.get_mut(h_dlg) {
+            Some(UserObject::Window(user_window)) => {
+                user_window.dialog = Some(Dialog::new(SegmentAndOffset {
+                    segment: (dialog_func >> 16) as u16,
+                    offset: dialog_func as u16,
+                }));
+                user_window.proc
+            }
+            _ => return Ok(Handle::null()),
+        };
+
+        for item in &template.items {
+            let rect = to_pixels(item.rect);
+            let h_control = self.create_window(
+                accessor,
+                WindowCreation {
+                    class_name: item.class.clone(),
+                    window_name: item.text.clone(),
+                    style: item.style | WindowStyles::CHILD.bits(),
+                    x: rect.left,
+                    y: rect.top,
+                    width: rect.right - rect.left,
+                    height: rect.bottom - rect.top,
+                    h_wnd_parent: h_dlg,
+                    h_menu: item.id.into(),
+                    h_instance: self.task.h_instance,
+                    param: 0,
+                },
+            )?;
+            // Controls of classes that don't exist are left out, instead of failing the whole
+            // dialog box
+            if h_control == Handle::null() {
+                debug!(
+                    "[user] DIALOG CONTROL OF CLASS {:?} NOT CREATED",
+                    item.class
+                );
+            }
+        }
+
+        // The dialog procedure gets the control that gets the focus first, and returns whether
+        // it should get the focus
+        let first_control = self.next_tab_stop(h_dlg, Handle::null(), false);
+        let set_focus = self.call_wndproc_sync(
+            accessor,
+            proc,
+            h_dlg,
+            MessageType::InitDialog.into(),
+            first_control.as_u16(),
+            param,
+        )? as u16
+            != 0;
+        if set_focus {
+            self.set_dialog_focus(accessor, h_dlg, first_control)?;
+        }
+        if style.contains(WindowStyles::VISIBLE) {
+            self.show_window(accessor, h_dlg, ShowWindowCommand::ShowNormal.into())?;
+        }
+        Ok(h_dlg)
+    }
+
+    /// Runs a modal dialog box until EndDialog ends it. Its owner gets no input in the meantime.
+    /// The message filter hooks see the messages first, with the code of the dialog box.
+    /// Returns the result that EndDialog gave, or 0 if the dialog box got destroyed without it.
+    pub fn run_modal_dialog(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_dlg: Handle,
+        filter_code: MessageFilterCode,
+    ) -> Result<u16, EmulatorError> {
+        let h_wnd_owner = match self.read_objects().user.get(h_dlg) {
+            Some(UserObject::Window(user_window)) => user_window.owner_handle,
+            _ => Handle::null(),
+        };
+        let owner_was_enabled = self.enable_window(accessor, h_wnd_owner, false)?;
+        // A dialog box that ended while it was set up never shows up
+        if self
+            .with_dialog(h_dlg, |dialog| dialog.result)
+            .flatten()
+            .is_none()
+        {
+            self.show_window(accessor, h_dlg, ShowWindowCommand::ShowNormal.into())?;
+        }
+
+        let result = loop {
+            match self.with_dialog(h_dlg, |dialog| dialog.result) {
+                Some(Some(result)) => break result,
+                Some(None) => {}
+                // Its owner got destroyed, or the dialog procedure destroyed it itself
+                None => break 0,
+            }
+            let message = self.wait_for_message(accessor, MessageFilter::window(Handle::null()))?;
+            if message.message == MessageType::Quit.into() {
+                // The message loop of the application has to see it too
+                self.message_queue.post(message);
+                break 0;
+            }
+            if self.filter_modal_message(accessor, filter_code, &message)? {
+                continue;
+            }
+            // The other windows of the task still get their messages, such as WM_PAINT
+            if !self.is_dialog_message(accessor, h_dlg, &message)? {
+                self.translate_message(&message);
+                self.dispatch_message(
+                    accessor,
+                    message.h_wnd,
+                    message.message,
+                    message.w_param,
+                    message.l_param,
+                )?;
+            }
+        };
+
+        if owner_was_enabled {
+            self.enable_window(accessor, h_wnd_owner, true)?;
+        }
+        self.destroy_window(accessor, h_dlg)?;
+        Ok(result)
+    }
+
+    /// Lets a dialog box handle a message meant for it or for one of its controls: Tab moves the
+    /// focus to the next control, and Enter and Escape choose OK and Cancel. Other messages get
+    /// translated and dispatched. Returns false if the message isn't for the dialog box.
+    pub fn is_dialog_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_dlg: Handle,
+        message: &PostedMessage,
+    ) -> Result<bool, EmulatorError> {
+        if !self.is_in_dialog(h_dlg, message.h_wnd) {
+            return Ok(false);
+        }
+        let focus = self
+            .with_dialog(h_dlg, |dialog| dialog.focus)
+            .unwrap_or(Handle::null());
+
+        if message.message == MessageType::KeyDown.into() {
+            let vk = message.w_param as u8;
+            if vk == VK_TAB {
+                let backwards = self.read_objects().key_states.is_down(VK_SHIFT);
+                let next = self.next_tab_stop(h_dlg, focus, backwards);
+                if next != Handle::null() {
+                    // The text of an edit control gets selected when Tab moves to it
+                    self.send_message(
+                        accessor,
+                        next,
+                        EditMessage::SetSel.into(),
+                        0,
+                        encode_u16_u16_to_u32(0, 0xffff),
+                    )?;
+                    self.set_dialog_focus(accessor, h_dlg, next)?;
+                }
+                return Ok(true);
+            } else if vk == VK_RETURN || vk == VK_ESCAPE {
+                let id = if vk == VK_RETURN {
+                    self.default_button_id(h_dlg)
+                } else {
+                    IDCANCEL
+                };
+                self.send_dialog_command(accessor, h_dlg, id)?;
+                return Ok(true);
+            }
+        } else if message.message == MessageType::Char.into() {
+            // Typing the mnemonic of a button clicks it, unless the focus is somewhere to type
+            let typing = matches!(
+                self.with_control(focus, |control, _| matches!(
+                    control,
+                    Control::Edit(_) | Control::ComboBox(_)
+                )),
+                Some(true)
+            );
+            let button = if typing {
+                None
+            } else {
+                self.mnemonic_button(h_dlg, message.w_param as u8)
+            };
+            if let Some(button) = button {
+                self.set_dialog_focus(accessor, h_dlg, button)?;
+                self.click_button(accessor, button)?;
+                return Ok(true);
+            }
+        }
+        self.translate_message(message);
+        self.dispatch_message(
+            accessor,
+            message.h_wnd,
+            message.message,
+            message.w_param,
+            message.l_param,
+        )?;
+        Ok(true)
+    }
+
+    /// The enabled button of a dialog box whose mnemonic is the given character.
+    fn mnemonic_button(&self, h_dlg: Handle, character: u8) -> Option<Handle> {
+        let objects = self.read_objects();
+        let children = match objects.user.get(h_dlg) {
+            Some(UserObject::Window(user_window)) => &user_window.children,
+            _ => return None,
+        };
+        children
+            .iter()
+            .copied()
+            .find(|&child| match objects.user.get(child) {
+                Some(UserObject::Window(child_window))
+                    if matches!(child_window.control.as_deref(), Some(Control::Button(_)))
+                        && !child_window.style.contains(WindowStyles::DISABLED)
+                        && controls::button_type(child_window.class_specific_style)
+                            != ButtonType::GroupBox =>
+                {
+                    let (label, mnemonic, _) = menu::split_text(child_window.title.as_slice());
+                    mnemonic.is_some_and(|index| label[index].eq_ignore_ascii_case(&character))
+                }
+                _ => false,
+            })
+    }
+
+    /// Whether a window is a dialog box or one of the windows in it.
+    fn is_in_dialog(&self, h_dlg: Handle, h_wnd: Handle) -> bool {
+        let objects = self.read_objects();
+        let mut h_wnd = h_wnd;
+        while h_wnd != Handle::null() {
+            if h_wnd == h_dlg {
+                return true;
+            }
+            h_wnd = match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.parent_handle,
+                _ => Handle::null(),
+            };
+        }
+        false
+    }
+
+    /// Runs a closure on what a dialog box keeps about itself, if the window is a dialog box.
+    pub fn with_dialog<R>(&self, h_dlg: Handle, f: impl FnOnce(&mut Dialog) -> R) -> Option<R> {
+        match self.write_objects().user.get_mut(h_dlg) {
+            Some(UserObject::Window(user_window)) => user_window.dialog.as_mut().map(f),
+            _ => None,
+        }
+    }
+
+    /// The control of a dialog box with the given id.
+    pub fn dialog_item(&self, h_dlg: Handle, id: u16) -> Option<Handle> {
+        let objects = self.read_objects();
+        match objects.user.get(h_dlg) {
+            Some(UserObject::Window(user_window)) => {
+                user_window.children.iter().copied().find(|&child| {
+                    matches!(
+                        objects.user.get(child),
+                        Some(UserObject::Window(child_window)) if child_window.menu == id.into()
+                    )
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The control that Tab moves the focus to from the given one, or Shift+Tab if backwards.
+    /// Only enabled controls with WS_TABSTOP can get the focus this way. Starts from the first
+    /// or last control if no control is given.
+    fn next_tab_stop(&self, h_dlg: Handle, from: Handle, backwards: bool) -> Handle {
+        let objects = self.read_objects();
+        let children = match objects.user.get(h_dlg) {
+            Some(UserObject::Window(user_window)) => &user_window.children,
+            _ => return Handle::null(),
+        };
+        let count = children.len();
+        let start = children.iter().position(|&child| child == from);
+        (1..=count)
+            .map(|step| {
+                let index = match (start, backwards) {
+                    (Some(start), false) => (start + step) % count,
+                    (Some(start), true) => (start + count - step) % count,
+                    (None, false) => step - 1,
+                    (None, true) => count - step,
+                };
+                children[index]
+            })
+            .find(|&child| {
+                matches!(
+                    objects.user.get(child),
+                    Some(UserObject::Window(child_window))
+                        if child_window.style.contains(WindowStyles::TABSTOP)
+                            && !child_window.style.contains(WindowStyles::DISABLED)
+                )
+            })
+            .unwrap_or(Handle::null())
+    }
+
+    /// Sends WM_COMMAND to a dialog box as if the button with the given id was clicked.
+    fn send_dialog_command(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_dlg: Handle,
+        id: u16,
+    ) -> Result<(), EmulatorError> {
+        let h_control = self.dialog_item(h_dlg, id).unwrap_or(Handle::null());
+        self.send_message(
+            accessor,
+            h_dlg,
+            MessageType::Command.into(),
+            id,
+            encode_u16_u16_to_u32(h_control.as_u16(), BN_CLICKED),
+        )?;
+        Ok(())
+    }
+
+    /// The id of the button that Enter chooses in a dialog box: the push button with the focus,
+    /// or else the default push button, or else IDOK.
+    pub fn default_button_id(&self, h_dlg: Handle) -> u16 {
+        let objects = self.read_objects();
+        let children = match objects.user.get(h_dlg) {
+            Some(UserObject::Window(user_window)) => &user_window.children,
+            _ => return IDOK,
+        };
+        let focus = match objects.user.get(h_dlg) {
+            Some(UserObject::Window(UserWindow {
+                dialog: Some(dialog),
+                ..
+            })) => dialog.focus,
+            _ => Handle::null(),
+        };
+        let button_of = |h_wnd: Handle| match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window))
+                if matches!(user_window.control.as_deref(), Some(Control::Button(_))) =>
+            {
+                Some((
+                    controls::button_type(user_window.class_specific_style),
+                    user_window.menu.as_u16(),
+                ))
+            }
+            _ => None,
+        };
+        match button_of(focus) {
+            Some((ButtonType::PushButton | ButtonType::DefPushButton, id)) => id,
+            _ => children
+                .iter()
+                .filter_map(|&child| button_of(child))
+                .find(|&(button_type, _)| button_type == ButtonType::DefPushButton)
+                .map_or(IDOK, |(_, id)| id),
+        }
+    }
+
+    /// Moves the focus of a dialog box to one of its controls. A dialog box that isn't active
+    /// remembers the control, which gets the focus once the dialog box gets activated.
+    pub fn set_dialog_focus(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_dlg: Handle,
+        h_control: Handle,
+    ) -> Result<(), EmulatorError> {
+        if self
+            .with_dialog(h_dlg, |dialog| dialog.focus = h_control)
+            .is_none()
+        {
+            return Ok(());
+        }
+        if self.active_window() == h_dlg {
+            self.set_focus(accessor, h_control)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a message to the control of a dialog box with the given id.
+    pub fn send_dialog_item_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_dlg: Handle,
+        id: u16,
+        message: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        let h_control = match self.dialog_item(h_dlg, id) {
+            Some(h_control) => h_control,
+            None => return Ok(0),
+        };
+        self.send_message(accessor, h_control, message, w_param, l_param)
+    }
+
+    #[api_function]
+    pub fn def_dlg_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] DEF DLG PROC {:?} {:x} {:x} {:x}",
+            h_dlg, msg, w_param, l_param
+        );
+        // The dialog procedure returns whether it handled the message
+        let dialog_proc = self
+            .with_dialog(h_dlg, |dialog| dialog.proc)
+            .filter(|proc| proc.segment != 0 || proc.offset != 0);
+        if let Some(dialog_proc) = dialog_proc {
+            let handled =
+                self.call_wndproc_sync(&mut accessor, dialog_proc, h_dlg, msg, w_param, l_param)?
+                    as u16;
+            if handled != 0 {
+                // WM_INITDIALOG returns whether the focus is still to be set, the other
+                // messages leave their result in DWL_MSGRESULT
+                if msg == MessageType::InitDialog.into() {
+                    return Ok(ReturnValue::U32(handled.into()));
+                }
+                let result = self.window_value(h_dlg, DWL_MSGRESULT, 4).unwrap_or(0);
+                return Ok(ReturnValue::U32(result));
+            }
+        }
+        if msg == MessageType::Close.into() {
+            self.send_dialog_command(&mut accessor, h_dlg, IDCANCEL)?;
+            return Ok(ReturnValue::U32(0));
+        }
+        if msg == MessageType::Activate.into() {
+            // The control that had the focus gets it back, or else the first one with WS_TABSTOP
+            if w_param != ActivateState::Inactive.into() {
+                let mut focus = self
+                    .with_dialog(h_dlg, |dialog| dialog.focus)
+                    .unwrap_or(Handle::null());
+                if focus == Handle::null() {
+                    focus = self.next_tab_stop(h_dlg, Handle::null(), false);
+                }
+                if focus != Handle::null() {
+                    self.set_focus(&mut accessor, focus)?;
+                }
+            }
+            return Ok(ReturnValue::U32(0));
+        }
+        if msg == MessageType::EraseBkGnd.into() {
+            // The dialog class has no background brush, dialog boxes are in the window color
+            let objects = self.read_objects();
+            let color = self.get_system_color(SystemColors::Window);
+            self.fill_client_area(&objects, h_dlg, w_param.into(), color);
+            return Ok(ReturnValue::U32(1));
+        }
+        let result = self.default_window_proc(&mut accessor, h_dlg, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+}
@@ -11,19 +11,19 @@ use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use syscall::api_function;
 
 pub struct EmulatedGdi<'a> {
-    objects: &'a RwLock<ObjectEnvironment<'a>>,
+    objects: &'a RwLock<ObjectEnvironment>,
 }
 
 impl<'a> EmulatedGdi<'a> {
-    pub fn new(objects: &'a RwLock<ObjectEnvironment<'a>>) -> Self {
+    pub fn new(objects: &'a RwLock<ObjectEnvironment>) -> Self {
         Self { objects }
     }
 
-    fn write_objects(&self) -> RwLockWriteGuard<'_, ObjectEnvironment<'a>> {
+    fn write_objects(&self) -> RwLockWriteGuard<'_, ObjectEnvironment> {
         self.objects.write().unwrap()
     }
 
-    fn read_objects(&self) -> RwLockReadGuard<'_, ObjectEnvironment<'a>> {
+    fn read_objects(&self) -> RwLockReadGuard<'_, ObjectEnvironment> {
         self.objects.read().unwrap()
     }
 
@@ -121,6 +121,40 @@ impl<'a> EmulatedGdi<'a> {
         Ok(ReturnValue::U16(handle.as_u16()))
     }
 
+    fn create_region(&self, rect: Rect) -> Handle {
+        self.write_objects()
+            .gdi
+            .register(GdiObject::Region(rect))
+            .unwrap_or(Handle::null())
+    }
+
+    #[api_function]
+    fn create_rect_rgn(
+        &self,
+        left: i16,
+        top: i16,
+        right: i16,
+        bottom: i16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let handle = self.create_region(Rect {
+            left,
+            top,
+            right,
+            bottom,
+        });
+        Ok(ReturnValue::U16(handle.as_u16()))
+    }
+
+    #[api_function]
+    fn create_rect_rgn_indirect(
+        &self,
+        accessor: EmulatorAccessor,
+        rect: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let handle = self.create_region(accessor.read_rect(rect.0)?);
+        Ok(ReturnValue::U16(handle.as_u16()))
+    }
+
     #[api_function]
     fn delete_object(&self, handle: Handle) -> Result<ReturnValue, EmulatorError> {
         // TODO: which objects may get deleted?
@@ -220,7 +254,7 @@ impl<'a> EmulatedGdi<'a> {
     fn move_to(&self, hdc: Handle, x: i16, y: i16) -> Result<ReturnValue, EmulatorError> {
         match self.write_objects().gdi.get_mut(hdc) {
             Some(GdiObject::DC(dc)) => {
-                let old_position = dc.position.get();
+                let old_position = dc.position;
                 dc.move_to(Point::new(x, y));
                 Ok(ReturnValue::U32(encode_u16_u16_to_u32(
                     old_position.x as u16,
@@ -233,19 +267,22 @@ impl<'a> EmulatedGdi<'a> {
 
     #[api_function]
     fn line_to(&self, hdc: Handle, x: i16, y: i16) -> Result<ReturnValue, EmulatorError> {
-        let objects = self.read_objects();
-        self.read_objects()
-            .with_paint_bitmap_for(hdc, &|mut bitmap, dc| {
+        let to = Point::new(x, y);
+        {
+            let objects = self.read_objects();
+            objects.with_paint_bitmap_for(hdc, &|mut bitmap, dc| {
                 if let Some(GdiObject::Pen(pen)) = objects.gdi.get(dc.selected_pen) {
-                    let to = Point::new(x, y);
                     println!(
                         "OP: {:?}, color {:?}, {:?}",
                         dc.raster_op, pen.color, dc.selected_pen
                     );
                     bitmap.line_to(to, pen);
-                    dc.position.set(to);
                 }
             });
+        }
+        if let Some(GdiObject::DC(dc)) = self.write_objects().gdi.get_mut(hdc) {
+            dc.move_to(to);
+        }
         // TODO
         Ok(ReturnValue::U16(1))
     }
@@ -279,6 +316,8 @@ impl<'a> EmulatedGdi<'a> {
             45 => self.__api_select_object(emulator_accessor),
             53 => self.__api_create_dc(emulator_accessor),
             61 => self.__api_create_pen(emulator_accessor),
+            64 => self.__api_create_rect_rgn(emulator_accessor),
+            65 => self.__api_create_rect_rgn_indirect(emulator_accessor),
             66 => self.__api_create_solid_brush(emulator_accessor),
             68 => self.__api_delete_dc(emulator_accessor),
             69 => self.__api_delete_object(emulator_accessor),
@@ -1,39 +1,142 @@
 use crate::api_helpers::{Pointer, ReturnValue};
-use crate::constants::WinFlags;
+use crate::byte_string::HeapByteString;
+use crate::constants::{ShowWindowCommand, WinFlags};
 use crate::emulator_accessor::EmulatorAccessor;
+use crate::executable::Executable;
 use crate::handle_table::{GenericHandle, Handle, HandleTable};
+use crate::heap::Heap;
+use crate::module::BuiltinModules;
 use crate::registers::Registers;
+use crate::segment_bump_allocator::SegmentBumpAllocator;
+use crate::task::TaskDatabase;
 use crate::util::encode_u16_u16_to_u32;
 use crate::{
-    debug, debug_print_null_terminated_string, EmulatorError, ObjectEnvironment, ResourceTable,
+    debug, debug_print_null_terminated_string, map_library, process_library_header, spawn_task,
+    EmulatorError, EntryPoints, LaunchParameters, ModuleReferenceTable, ResourceTable, Session,
 };
-use std::sync::{RwLock, RwLockWriteGuard};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
 use syscall::api_function;
 
 pub enum KernelObject<'a> {
     Resource(&'a Box<[u8]>),
 }
 
+/// A block of global memory, which has a segment of its own. Its handle is that segment.
+struct GlobalBlock {
+    size: u32,
+    lock_count: u16,
+}
+
+/// A library the task loaded with LoadLibrary.
+struct Library {
+    module_name: HeapByteString,
+    h_module: Handle,
+    h_instance: Handle,
+    data_segment: u16,
+    usage: u16,
+    entry_points: EntryPoints,
+    exported_names: HashMap<HeapByteString, u16>,
+    /// The libraries this library imports, which are freed together with it
+    dependencies: Vec<Handle>,
+}
+
 pub struct EmulatedKernel<'a> {
-    objects: &'a RwLock<ObjectEnvironment<'a>>,
+    session: &'a Arc<Session>,
     resource_table: &'a ResourceTable,
-    kernel_handles: HandleTable<KernelObject<'a>>,
+    task: &'a TaskDatabase,
+    /// The local heaps, by the data segment they live in
+    local_heaps: RefCell<HashMap<u16, Heap>>,
+    kernel_handles: RefCell<HandleTable<KernelObject<'a>>>,
+    builtin_modules: Option<&'a BuiltinModules>,
+    segment_bump_allocator: RefCell<SegmentBumpAllocator>,
+    libraries: RefCell<Vec<Library>>,
+    global_blocks: RefCell<HashMap<Handle, GlobalBlock>>,
 }
 
 impl<'a> EmulatedKernel<'a> {
     pub fn new(
-        objects: &'a RwLock<ObjectEnvironment<'a>>,
+        session: &'a Arc<Session>,
         resource_table: &'a ResourceTable,
+        task: &'a TaskDatabase,
+        local_heap: Heap,
+        builtin_modules: Option<&'a BuiltinModules>,
+        segment_bump_allocator: SegmentBumpAllocator,
     ) -> Self {
+        let mut local_heaps = HashMap::new();
+        local_heaps.insert(task.h_instance.as_u16(), local_heap);
         Self {
-            objects,
+            session,
             resource_table,
-            kernel_handles: HandleTable::new(),
+            task,
+            local_heaps: RefCell::new(local_heaps),
+            kernel_handles: RefCell::new(HandleTable::new()),
+            builtin_modules,
+            segment_bump_allocator: RefCell::new(segment_bump_allocator),
+            libraries: RefCell::new(Vec::new()),
+            global_blocks: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Allocates a block of global memory. GMEM_ZEROINIT clears it. Returns None if the task
+    /// ran out of memory.
+    pub fn allocate_global(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        flags: u16,
+        size: u32,
+    ) -> Result<Option<Handle>, EmulatorError> {
+        // TODO: blocks larger than a segment
+        if size > 0x10000 {
+            return Ok(None);
+        }
+        let segment = match self
+            .segment_bump_allocator
+            .borrow_mut()
+            .allocate(size.max(1) as usize)
+        {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+        let flat_address = (segment as u32) << 4;
+        if accessor
+            .memory()
+            .slice(flat_address, flat_address + size)
+            .is_err()
+        {
+            return Ok(None);
+        }
+        if flags & 0x40 != 0 {
+            accessor
+                .memory_mut()
+                .zero(flat_address, flat_address + size)?;
         }
+        let h_mem = Handle::from(segment);
+        self.global_blocks.borrow_mut().insert(
+            h_mem,
+            GlobalBlock {
+                size,
+                lock_count: 0,
+            },
+        );
+        Ok(Some(h_mem))
     }
 
-    fn write_objects(&self) -> RwLockWriteGuard<ObjectEnvironment<'a>> {
-        self.objects.write().unwrap()
+    /// The flat address and the size of a block of global memory.
+    pub fn global_block(&self, h_mem: Handle) -> Option<(u32, u32)> {
+        self.global_blocks
+            .borrow()
+            .get(&h_mem)
+            .map(|block| ((h_mem.as_u16() as u32) << 4, block.size))
+    }
+
+    /// Frees a block of global memory. Returns whether there was such a block.
+    pub fn free_global(&self, h_mem: Handle) -> bool {
+        // The segments are never handed out again
+        self.global_blocks.borrow_mut().remove(&h_mem).is_some()
     }
 
     #[api_function]
@@ -52,9 +155,13 @@ impl<'a> EmulatedKernel<'a> {
         let is_fixed = (flags & 0b10) == 0;
         let should_zero = (flags & 0x40) > 0;
 
-        // TODO: should select a different local heap based on DS
-        let result = self.write_objects().local_heap.allocate(is_fixed, size);
-        if let Ok((return_value, pointer)) = result {
+        let data_segment = accessor.regs().read_segment(Registers::REG_DS);
+        let result = self
+            .local_heaps
+            .borrow_mut()
+            .get_mut(&data_segment)
+            .map(|local_heap| local_heap.allocate(is_fixed, size));
+        if let Some(Ok((return_value, pointer))) = result {
             if should_zero {
                 let flat_address = accessor.regs().flat_address(Registers::REG_DS, pointer);
                 accessor
@@ -69,16 +176,45 @@ impl<'a> EmulatedKernel<'a> {
     }
 
     #[api_function]
-    fn local_free(&self, handle_or_pointer: u16) -> Result<ReturnValue, EmulatorError> {
+    fn local_free(
+        &self,
+        accessor: EmulatorAccessor,
+        handle_or_pointer: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
         if handle_or_pointer == 0 {
             Ok(ReturnValue::U16(0))
         } else {
-            // TODO: should select a different local heap based on DS
-            Ok(ReturnValue::U16(
-                self.write_objects()
-                    .local_heap
-                    .deallocate(handle_or_pointer),
-            ))
+            let data_segment = accessor.regs().read_segment(Registers::REG_DS);
+            let result = self
+                .local_heaps
+                .borrow_mut()
+                .get_mut(&data_segment)
+                .map(|local_heap| local_heap.deallocate(handle_or_pointer))
+                .unwrap_or(handle_or_pointer);
+            Ok(ReturnValue::U16(result))
+        }
+    }
+
+    #[api_function]
+    fn local_init(
+        &self,
+        accessor: EmulatorAccessor,
+        segment: u16,
+        start: u16,
+        end: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let segment = if segment == 0 {
+            accessor.regs().read_segment(Registers::REG_DS)
+        } else {
+            segment
+        };
+        let mut local_heaps = self.local_heaps.borrow_mut();
+        if start == 0 {
+            // The heap goes after the data of the segment, the loader has set that one up already
+            Ok(ReturnValue::U16(local_heaps.contains_key(&segment).into()))
+        } else {
+            local_heaps.insert(segment, Heap::new(end.saturating_sub(start), start));
+            Ok(ReturnValue::U16(1))
         }
     }
 
@@ -90,21 +226,46 @@ impl<'a> EmulatedKernel<'a> {
 
     #[api_function]
     fn init_task(&self, mut accessor: EmulatorAccessor) -> Result<ReturnValue, EmulatorError> {
-        let regs = accessor.regs_mut();
+        let task = self.task;
+
+        // The instance data at the start of DGROUP holds the stack limits
+        let instance_data = accessor.regs().flat_address(Registers::REG_DS, 0);
+        let memory = accessor.memory_mut();
+        memory.write_u16(instance_data + 0x0A, task.stack_top)?;
+        memory.write_u16(instance_data + 0x0C, task.stack_bottom)?;
+        memory.write_u16(instance_data + 0x0E, task.stack_bottom)?;
 
-        // TODO: hardcoded to inittask rn
-        let es = regs.read_segment(Registers::REG_DS);
-        regs.write_gpr_16(Registers::REG_BX, 0x1234); // TODO: offset into command line
-        regs.write_gpr_16(Registers::REG_CX, 0); // TODO: stack limit
-        regs.write_gpr_16(Registers::REG_DX, 0); // TODO: nCmdShow
-        regs.write_gpr_16(Registers::REG_SI, 0); // TODO: previous instance handle
-        regs.write_gpr_16(Registers::REG_DI, 0xBEEF); // TODO: instance handle
+        // The command line starts after the whitespace that follows the length byte in the PSP
+        let psp = (task.psp_segment as u32) << 4;
+        let command_tail_length = memory.read_8(psp + 0x80)? as u16;
+        let mut command_line_offset = 0x81;
+        while command_line_offset < 0x81 + command_tail_length
+            && matches!(
+                memory.read_8(psp + command_line_offset as u32)?,
+                b' ' | b'\t'
+            )
+        {
+            command_line_offset += 1;
+        }
+
+        let regs = accessor.regs_mut();
+        regs.write_gpr_16(Registers::REG_BX, command_line_offset);
+        regs.write_gpr_16(Registers::REG_CX, task.stack_top);
+        regs.write_gpr_16(Registers::REG_DX, task.cmd_show);
+        regs.write_gpr_16(Registers::REG_SI, task.h_prev_instance.as_u16());
+        regs.write_gpr_16(Registers::REG_DI, task.h_instance.as_u16());
         regs.write_gpr_16(Registers::REG_BP, regs.read_gpr_16(Registers::REG_SP));
-        // TODO: segments
-        regs.write_segment(Registers::REG_ES, es); // TODO
+        regs.write_segment(Registers::REG_ES, task.psp_segment);
 
-        // TODO: must be = ES I believe
-        Ok(ReturnValue::U16(es))
+        Ok(ReturnValue::U16(1))
+    }
+
+    #[api_function]
+    fn get_dos_environment(&self) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U32(encode_u16_u16_to_u32(
+            0,
+            self.task.environment_segment,
+        )))
     }
 
     #[api_function]
@@ -118,20 +279,528 @@ impl<'a> EmulatedKernel<'a> {
     }
 
     #[api_function]
-    fn wait_event(&self) -> Result<ReturnValue, EmulatorError> {
+    fn yield_task(&self) -> Result<ReturnValue, EmulatorError> {
+        self.session.scheduler.yield_now(self.task.process_id);
         Ok(ReturnValue::None)
     }
 
     #[api_function]
-    fn get_proc_address(
+    fn wait_event(&self, _h_task: Handle) -> Result<ReturnValue, EmulatorError> {
+        // TODO: support waiting for other tasks than the current one
+        let had_to_wait = self.session.scheduler.wait(self.task.process_id);
+        Ok(ReturnValue::U16(had_to_wait.into()))
+    }
+
+    /// The directory of the program of this task.
+    fn caller_directory(&self) -> PathBuf {
+        let caller_path = self
+            .session
+            .task_registry
+            .read()
+            .unwrap()
+            .module_path(self.task.h_module);
+        caller_path
+            .as_ref()
+            .and_then(|path| Path::new(std::str::from_utf8(path.as_slice()).ok()?).parent())
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf()
+    }
+
+    /// Loads a program as a new task, and lets it run until it yields back to the caller.
+    /// Returns the instance handle of the new task, or an error code below 32.
+    fn start_task(&self, program: &[u8], command_tail: &[u8], cmd_show: u16) -> u16 {
+        let path = match find_program_file(program, &self.caller_directory(), "EXE") {
+            Some(path) => path,
+            // File not found
+            None => return 2,
+        };
+
+        let load_result = spawn_task(
+            self.session,
+            LaunchParameters {
+                path: path.to_string_lossy().into_owned(),
+                command_tail: command_tail.to_vec(),
+                cmd_show,
+                force_dos: false,
+            },
+        );
+        loop {
+            self.session.scheduler.yield_now(self.task.process_id);
+            match load_result.try_recv() {
+                Ok(result) => return result,
+                // The task ended without even reporting whether it loaded: out of memory
+                Err(TryRecvError::Disconnected) => return 0,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+    }
+
+    #[api_function]
+    fn win_exec(
+        &self,
+        command_line: HeapByteString,
+        cmd_show: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // The program name ends at the first blank, the rest of the line is its command tail
+        let command_line = command_line.as_slice();
+        let program_length = command_line
+            .iter()
+            .position(|&c| c == b' ' || c == b'\t')
+            .unwrap_or(command_line.len());
+        let (program, command_tail) = command_line.split_at(program_length);
+        Ok(ReturnValue::U16(self.start_task(
+            program,
+            command_tail,
+            cmd_show,
+        )))
+    }
+
+    #[api_function]
+    fn load_module(
         &self,
         accessor: EmulatorAccessor,
-        _h_module: Handle,
-        proc_name: Pointer,
+        module_name: HeapByteString,
+        parameter_block: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let (command_tail, cmd_show) = if parameter_block.0 == 0 {
+            (Vec::new(), ShowWindowCommand::ShowNormal.into())
+        } else {
+            // Environment segment, command tail pointer, show command pointer, reserved
+            let memory = accessor.memory();
+            let command_tail_ptr = memory.flat_pointer_read(parameter_block.0 + 2)?;
+            let show_ptr = memory.flat_pointer_read(parameter_block.0 + 6)?;
+            // The command tail starts with its length byte
+            let command_tail_length = memory.read_8(command_tail_ptr)? as u32;
+            let command_tail = memory
+                .slice(
+                    command_tail_ptr + 1,
+                    command_tail_ptr + 1 + command_tail_length,
+                )?
+                .to_vec();
+            // The show command array holds its number of entries (always 2), then the command
+            let cmd_show = if show_ptr == 0 {
+                ShowWindowCommand::ShowNormal.into()
+            } else {
+                memory.read_u16(show_ptr + 2)?
+            };
+            (command_tail, cmd_show)
+        };
+        Ok(ReturnValue::U16(self.start_task(
+            module_name.as_slice(),
+            &command_tail,
+            cmd_show,
+        )))
+    }
+
+    #[api_function]
+    fn get_module_handle(
+        &self,
+        accessor: EmulatorAccessor,
+        module_name: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let task_registry = self.session.task_registry.read().unwrap();
+        let h_module = if (module_name >> 16) == 0 {
+            // An instance handle can be passed instead of a name, in MAKEINTRESOURCE-style
+            task_registry.resolve_module_handle((module_name as u16).into())
+        } else {
+            let module_name = accessor
+                .clone_string(((module_name >> 16) << 4) + (module_name & 0xFFFF), false)?;
+            let module_name = module_name_of_file(module_name.as_slice());
+            task_registry.module_handle(module_name).or_else(|| {
+                let module = self.builtin_modules?.by_name(module_name)?;
+                Some(module.base_module().segment().into())
+            })
+        };
+        Ok(ReturnValue::U16(
+            h_module.unwrap_or(Handle::null()).as_u16(),
+        ))
+    }
+
+    #[api_function]
+    fn get_module_usage(&self, h_module: Handle) -> Result<ReturnValue, EmulatorError> {
+        if let Some(library) = self
+            .libraries
+            .borrow()
+            .iter()
+            .find(|library| library.h_module == h_module || library.h_instance == h_module)
+        {
+            return Ok(ReturnValue::U16(library.usage));
+        }
+        let task_registry = self.session.task_registry.read().unwrap();
+        let usage = task_registry
+            .resolve_module_handle(h_module)
+            .map(|h_module| task_registry.module_usage(h_module))
+            .unwrap_or(0);
+        Ok(ReturnValue::U16(usage))
+    }
+
+    #[api_function]
+    fn get_module_file_name(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_module: Handle,
+        file_name: Pointer,
+        size: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let path = {
+            let task_registry = self.session.task_registry.read().unwrap();
+            task_registry
+                .resolve_module_handle(h_module)
+                .and_then(|h_module| task_registry.module_path(h_module))
+        };
+        match path {
+            Some(path) if size > 0 => {
+                // The copied name is truncated to fit, including its null terminator
+                let path = path.as_slice();
+                let length = path.len().min(size as usize - 1);
+                let memory = accessor.memory_mut();
+                memory.copy_from(&path[..length], file_name.0 as usize)?;
+                memory.write_8(file_name.0 + length as u32, 0)?;
+                Ok(ReturnValue::U16(length as u16))
+            }
+            _ => Ok(ReturnValue::U16(0)),
+        }
+    }
+
+    /// Loads a library into the task, or adds a reference to it if it was loaded already.
+    /// Returns the instance handle of the library, or an error code below 32.
+    fn load_library_module(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        library_name: &[u8],
+    ) -> Result<u16, EmulatorError> {
+        let builtin_modules = match self.builtin_modules {
+            Some(builtin_modules) => builtin_modules,
+            // File not found
+            None => return Ok(2),
+        };
+        let module_name = module_name_of_file(library_name);
+        if let Some(module) = builtin_modules.by_name(module_name) {
+            return Ok(module.base_module().segment());
+        }
+        if let Some(h_instance) = self.add_library_reference(module_name) {
+            return Ok(h_instance.as_u16());
+        }
+
+        let path = match find_program_file(library_name, &self.caller_directory(), "DLL") {
+            Some(path) => path,
+            // File not found
+            None => return Ok(2),
+        };
+        let mut bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(_) => return Ok(2),
+        };
+        let mut executable = Executable::new(bytes.as_mut_slice());
+        let ne_module = match process_library_header(&mut executable) {
+            Ok(ne_module) => ne_module,
+            Err(error) => return Ok(error.load_error_code()),
+        };
+        // The file name does not have to match the module name
+        if let Some(h_instance) = self.add_library_reference(ne_module.module_name.as_slice()) {
+            return Ok(h_instance.as_u16());
+        }
+
+        // The libraries it imports get loaded first, so that its imports can be resolved
+        let mut dependencies = Vec::new();
+        for imported_module_name in &ne_module.imported_module_names {
+            if builtin_modules
+                .by_name(imported_module_name.as_slice())
+                .is_some()
+            {
+                continue;
+            }
+            let result = self.load_library_module(accessor, imported_module_name.as_slice())?;
+            if result < 32 {
+                for dependency in dependencies {
+                    self.free_library_module(accessor, dependency)?;
+                }
+                return Ok(result);
+            }
+            dependencies.push(Handle::from(result));
+        }
+
+        let mapped_library = {
+            let libraries = self.libraries.borrow();
+            let mut segment_bump_allocator = self.segment_bump_allocator.borrow_mut();
+            ModuleReferenceTable::new(
+                &ne_module.imported_module_names,
+                builtin_modules,
+                &mut segment_bump_allocator,
+                &|module_name| {
+                    libraries
+                        .iter()
+                        .find(|library| {
+                            library
+                                .module_name
+                                .as_slice()
+                                .eq_ignore_ascii_case(module_name)
+                        })
+                        .map(|library| library.entry_points.clone())
+                },
+            )
+            .and_then(|module_reference_table| {
+                map_library(
+                    &executable,
+                    &ne_module,
+                    accessor.memory_mut(),
+                    &mut segment_bump_allocator,
+                    &module_reference_table,
+                )
+            })
+        };
+        let mapped_library = match mapped_library {
+            Ok(mapped_library) => mapped_library,
+            Err(error) => {
+                for dependency in dependencies {
+                    self.free_library_module(accessor, dependency)?;
+                }
+                return Ok(error.load_error_code());
+            }
+        };
+
+        let h_instance = mapped_library.h_instance;
+        let h_module = match self
+            .session
+            .task_registry
+            .write()
+            .unwrap()
+            .register_instance(
+                ne_module.module_name.as_slice(),
+                path.to_string_lossy().as_bytes(),
+                h_instance,
+            ) {
+            Some((h_module, _)) => h_module,
+            // Insufficient memory
+            None => return Ok(8),
+        };
+        if let Some(local_heap) = mapped_library.local_heap {
+            self.local_heaps
+                .borrow_mut()
+                .insert(mapped_library.data_segment, local_heap);
+        }
+        self.libraries.borrow_mut().push(Library {
+            module_name: HeapByteString::from(
+                ne_module.module_name.as_slice().to_ascii_uppercase().into(),
+            ),
+            h_module,
+            h_instance,
+            data_segment: mapped_library.data_segment,
+            usage: 1,
+            entry_points: mapped_library.entry_points,
+            exported_names: ne_module.exported_names,
+            dependencies,
+        });
+
+        // LibEntry gets the instance handle in DI, the data segment in DS and the heap size in CX
+        if let Some(lib_entry) = mapped_library.lib_entry {
+            let saved_regs = accessor.regs().clone();
+            let regs = accessor.regs_mut();
+            regs.write_gpr_16(Registers::REG_DI, h_instance.as_u16());
+            regs.write_gpr_16(Registers::REG_CX, mapped_library.heap_initial_size);
+            regs.write_gpr_16(Registers::REG_SI, 0);
+            regs.write_segment(Registers::REG_DS, mapped_library.data_segment);
+            regs.write_segment(Registers::REG_ES, 0);
+            let result = accessor.call_far(lib_entry, &[]);
+            *accessor.regs_mut() = saved_regs;
+            if result? as u16 == 0 {
+                // The library failed to initialize, it gets unloaded without calling its WEP
+                self.unload_library(accessor, h_instance, false)?;
+                return Ok(20);
+            }
+        }
+
+        Ok(h_instance.as_u16())
+    }
+
+    /// Adds a reference to a library that the task loaded already.
+    fn add_library_reference(&self, module_name: &[u8]) -> Option<Handle> {
+        let mut libraries = self.libraries.borrow_mut();
+        let library = libraries.iter_mut().find(|library| {
+            library
+                .module_name
+                .as_slice()
+                .eq_ignore_ascii_case(module_name)
+        })?;
+        library.usage += 1;
+        Some(library.h_instance)
+    }
+
+    /// Removes a reference to a library, and unloads it once the last one is gone.
+    fn free_library_module(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_library: Handle,
+    ) -> Result<(), EmulatorError> {
+        let should_unload = {
+            let mut libraries = self.libraries.borrow_mut();
+            match libraries
+                .iter_mut()
+                .find(|library| library.h_instance == h_library || library.h_module == h_library)
+            {
+                Some(library) => {
+                    library.usage -= 1;
+                    library.usage == 0
+                }
+                // Built-in modules are never unloaded
+                None => false,
+            }
+        };
+        if should_unload {
+            self.unload_library(accessor, h_library, true)?;
+        }
+        Ok(())
+    }
+
+    fn unload_library(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_library: Handle,
+        call_wep: bool,
+    ) -> Result<(), EmulatorError> {
+        let library = {
+            let mut libraries = self.libraries.borrow_mut();
+            match libraries.iter().position(|library| {
+                library.h_instance == h_library || library.h_module == h_library
+            }) {
+                Some(index) => libraries.remove(index),
+                None => return Ok(()),
+            }
+        };
+
+        if call_wep {
+            let wep = library
+                .exported_names
+                .get(&HeapByteString::from(b"WEP".as_slice().into()))
+                .and_then(|ordinal| library.entry_points.get(ordinal));
+            if let Some(&wep) = wep {
+                // WEP_FREE_DLL, as opposed to Windows shutting down
+                accessor.call_far(wep, &[0])?;
+            }
+        }
+
+        // TODO: the memory of the library can't be reused, as segments are bump allocated
+        self.local_heaps.borrow_mut().remove(&library.data_segment);
+        self.session
+            .task_registry
+            .write()
+            .unwrap()
+            .deregister_instance(library.h_module, library.h_instance);
+        for dependency in library.dependencies {
+            self.free_library_module(accessor, dependency)?;
+        }
+        Ok(())
+    }
+
+    /// Unloads the libraries that are still loaded when the task ends.
+    pub fn free_all_libraries(&self, mut accessor: EmulatorAccessor) {
+        loop {
+            let h_instance = match self.libraries.borrow().first() {
+                Some(library) => library.h_instance,
+                None => break,
+            };
+            if self
+                .unload_library(&mut accessor, h_instance, true)
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    #[api_function]
+    fn load_library(
+        &self,
+        mut accessor: EmulatorAccessor,
+        library_name: HeapByteString,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let result = self.load_library_module(&mut accessor, library_name.as_slice())?;
+        Ok(ReturnValue::U16(result))
+    }
+
+    #[api_function]
+    fn free_library(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_library: Handle,
     ) -> Result<ReturnValue, EmulatorError> {
-        println!("GET PROC ADDRESS");
-        debug_print_null_terminated_string(&accessor, proc_name.0);
-        Ok(ReturnValue::U32(0))
+        self.free_library_module(&mut accessor, h_library)?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn get_proc_address(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_module: Handle,
+        proc_name: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // The ordinal can be passed instead of a name, in MAKEINTRESOURCE-style
+        let ordinal = if (proc_name >> 16) == 0 {
+            Some(proc_name as u16)
+        } else {
+            None
+        };
+
+        if let Some(module) = self
+            .builtin_modules
+            .and_then(|builtin_modules| builtin_modules.by_instance(h_module))
+        {
+            let ordinal = match ordinal {
+                Some(ordinal) => Some(ordinal),
+                None => {
+                    let proc_name = accessor
+                        .clone_string(((proc_name >> 16) << 4) + (proc_name & 0xFFFF), false)?;
+                    debug!("[kernel] GET PROC ADDRESS {:?}", proc_name);
+                    module.ordinal_of_procedure(proc_name.as_slice())
+                }
+            };
+            // Procedures that the module doesn't have aren't found
+            let procedure = match ordinal
+                .and_then(|ordinal| Some((ordinal, module.argument_bytes_of_procedure(ordinal)?)))
+            {
+                Some((ordinal, argument_bytes)) => Some(module.base_module().procedure(
+                    accessor.memory_mut(),
+                    ordinal,
+                    argument_bytes,
+                )?),
+                None => None,
+            };
+            return Ok(ReturnValue::U32(procedure.map_or(0, |procedure| {
+                encode_u16_u16_to_u32(procedure.offset, procedure.segment)
+            })));
+        }
+
+        let ordinal = match ordinal {
+            Some(ordinal) => Some(ordinal),
+            None => {
+                let proc_name = accessor
+                    .clone_string(((proc_name >> 16) << 4) + (proc_name & 0xFFFF), false)?;
+                debug!("[kernel] GET PROC ADDRESS {:?}", proc_name);
+                self.libraries
+                    .borrow()
+                    .iter()
+                    .find(|library| library.h_instance == h_module || library.h_module == h_module)
+                    .and_then(|library| {
+                        library
+                            .exported_names
+                            .get(&HeapByteString::from(
+                                proc_name.as_slice().to_ascii_uppercase().into(),
+                            ))
+                            .copied()
+                    })
+            }
+        };
+        let procedure = ordinal.and_then(|ordinal| {
+            self.libraries
+                .borrow()
+                .iter()
+                .find(|library| library.h_instance == h_module || library.h_module == h_module)
+                .and_then(|library| library.entry_points.get(&ordinal).copied())
+        });
+        Ok(ReturnValue::U32(procedure.map_or(0, |procedure| {
+            encode_u16_u16_to_u32(procedure.offset, procedure.segment)
+        })))
     }
 
     #[api_function]
@@ -165,7 +834,7 @@ impl<'a> EmulatedKernel<'a> {
 
     #[api_function]
     fn find_resource(
-        &mut self,
+        &self,
         accessor: EmulatorAccessor,
         _module: Handle,
         name: Pointer,
@@ -180,7 +849,11 @@ impl<'a> EmulatedKernel<'a> {
             .other_resources
             .get(&res_type)
             .and_then(|table| table.get(&name))
-            .and_then(|data| self.kernel_handles.register(KernelObject::Resource(data)));
+            .and_then(|data| {
+                self.kernel_handles
+                    .borrow_mut()
+                    .register(KernelObject::Resource(data))
+            });
         Ok(ReturnValue::U16(handle.unwrap_or(Handle::null()).as_u16()))
     }
 
@@ -194,8 +867,13 @@ impl<'a> EmulatedKernel<'a> {
         // TODO: this should look at the module
         // TODO: this should allocate global memory and load the resource to that location
         //       the return value here should be the handle to the global memory...
-        if let Some(KernelObject::Resource(data)) = self.kernel_handles.get(resource) {
-            accessor.memory_mut().copy_from(&*data, 0xF0000)?;
+        let data = self
+            .kernel_handles
+            .borrow()
+            .get(resource)
+            .map(|KernelObject::Resource(data)| *data);
+        if let Some(data) = data {
+            accessor.memory_mut().copy_from(data, 0xF0000)?;
             Ok(ReturnValue::U16(0xBEEF))
         } else {
             Ok(ReturnValue::U16(Handle::null().as_u16()))
@@ -221,22 +899,53 @@ impl<'a> EmulatedKernel<'a> {
     }
 
     #[api_function]
-    fn global_lock(
+    fn global_alloc(
         &self,
-        accessor: EmulatorAccessor,
-        _h_mem: Handle,
+        mut accessor: EmulatorAccessor,
+        flags: u16,
+        size: u32,
     ) -> Result<ReturnValue, EmulatorError> {
-        println!("{:?}", _h_mem);
-        // TODO
+        let h_mem = self.allocate_global(&mut accessor, flags, size)?;
+        Ok(ReturnValue::U16(h_mem.unwrap_or(Handle::null()).as_u16()))
+    }
+
+    #[api_function]
+    fn global_free(&self, h_mem: Handle) -> Result<ReturnValue, EmulatorError> {
+        if self.free_global(h_mem) {
+            Ok(ReturnValue::U16(0))
+        } else {
+            Ok(ReturnValue::U16(h_mem.as_u16()))
+        }
+    }
+
+    #[api_function]
+    fn global_size(&self, h_mem: Handle) -> Result<ReturnValue, EmulatorError> {
+        let size = self.global_block(h_mem).map_or(0, |(_, size)| size);
+        Ok(ReturnValue::U32(size))
+    }
+
+    #[api_function]
+    fn global_lock(&self, h_mem: Handle) -> Result<ReturnValue, EmulatorError> {
+        if let Some(block) = self.global_blocks.borrow_mut().get_mut(&h_mem) {
+            block.lock_count = block.lock_count.saturating_add(1);
+            return Ok(ReturnValue::U32(encode_u16_u16_to_u32(0, h_mem.as_u16())));
+        }
+        // TODO: LoadResource doesn't give a global memory block yet, it leaves the resource here
         let segment = 0xF000;
         let offset = 0;
         Ok(ReturnValue::U32((segment << 16) | offset))
     }
 
     #[api_function]
-    fn global_unlock(&self, _h_mem: Handle) -> Result<ReturnValue, EmulatorError> {
-        // TODO
-        Ok(ReturnValue::U16(1))
+    fn global_unlock(&self, h_mem: Handle) -> Result<ReturnValue, EmulatorError> {
+        // Returns whether the block is still locked
+        match self.global_blocks.borrow_mut().get_mut(&h_mem) {
+            Some(block) => {
+                block.lock_count = block.lock_count.saturating_sub(1);
+                Ok(ReturnValue::U16((block.lock_count > 0).into()))
+            }
+            None => Ok(ReturnValue::U16(0)),
+        }
     }
 
     #[api_function]
@@ -310,19 +1019,28 @@ impl<'a> EmulatedKernel<'a> {
     }
 
     pub fn syscall(
-        &mut self,
+        &self,
         nr: u16,
         emulator_accessor: EmulatorAccessor,
     ) -> Result<ReturnValue, EmulatorError> {
         match nr {
             3 => self.__api_get_version(emulator_accessor),
+            4 => self.__api_local_init(emulator_accessor),
             5 => self.__api_local_alloc(emulator_accessor),
             7 => self.__api_local_free(emulator_accessor),
+            15 => self.__api_global_alloc(emulator_accessor),
+            17 => self.__api_global_free(emulator_accessor),
             18 => self.__api_global_lock(emulator_accessor),
             19 => self.__api_global_unlock(emulator_accessor),
+            20 => self.__api_global_size(emulator_accessor),
             23 => self.__api_lock_segment(emulator_accessor),
             24 => self.__api_unlock_segment(emulator_accessor),
+            29 => self.__api_yield_task(emulator_accessor),
             30 => self.__api_wait_event(emulator_accessor),
+            45 => self.__api_load_module(emulator_accessor),
+            47 => self.__api_get_module_handle(emulator_accessor),
+            48 => self.__api_get_module_usage(emulator_accessor),
+            49 => self.__api_get_module_file_name(emulator_accessor),
             50 => self.__api_get_proc_address(emulator_accessor),
             51 => self.__api_make_proc_instance(emulator_accessor),
             57 => self.__api_get_profile_int(emulator_accessor),
@@ -332,12 +1050,74 @@ impl<'a> EmulatedKernel<'a> {
             89 => self.__api_lstrcat(emulator_accessor),
             90 => self.__api_strlen(emulator_accessor),
             91 => self.__api_init_task(emulator_accessor),
+            95 => self.__api_load_library(emulator_accessor),
+            96 => self.__api_free_library(emulator_accessor),
             127 => self.__api_get_private_profile_int(emulator_accessor),
             128 => self.__api_get_private_profile_string(emulator_accessor),
+            131 => self.__api_get_dos_environment(emulator_accessor),
             132 => self.__api_get_winflags(emulator_accessor),
+            166 => self.__api_win_exec(emulator_accessor),
             nr => {
                 todo!("unimplemented kernel syscall {}", nr)
             }
         }
     }
 }
+
+/// The module name a file name refers to: its base name without path or extension.
+fn module_name_of_file(file_name: &[u8]) -> &[u8] {
+    let base_name_start = file_name
+        .iter()
+        .rposition(|&c| c == b'\\' || c == b'/' || c == b':')
+        .map(|position| position + 1)
+        .unwrap_or(0);
+    let base_name = &file_name[base_name_start..];
+    let base_name_end = base_name
+        .iter()
+        .position(|&c| c == b'.')
+        .unwrap_or(base_name.len());
+    &base_name[..base_name_end]
+}
+
+/// Finds the host file that a program or library name from the guest refers to. Like the
+/// Windows loader, this looks in the current directory and in the directory of the calling
+/// program. The drive letter is ignored, file names match regardless of case, and the default
+/// extension is implied.
+fn find_program_file(
+    program: &[u8],
+    caller_directory: &Path,
+    default_extension: &str,
+) -> Option<PathBuf> {
+    let program = String::from_utf8_lossy(program).replace('\\', "/");
+    let program = match program.split_once(':') {
+        Some((_drive, path)) => path.to_string(),
+        None => program,
+    };
+    let program = if Path::new(&program).extension().is_none() {
+        program + "." + default_extension
+    } else {
+        program
+    };
+    let file_name = Path::new(&program).file_name()?;
+    let candidates = [
+        PathBuf::from(&program),
+        caller_directory.join(program.trim_start_matches('/')),
+        caller_directory.join(file_name),
+    ];
+    candidates.iter().find_map(|candidate| {
+        if candidate.is_file() {
+            return Some(candidate.clone());
+        }
+        let file_name = candidate.file_name()?;
+        std::fs::read_dir(candidate.parent()?)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| {
+                path.is_file()
+                    && path
+                        .file_name()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(file_name))
+            })
+    })
+}
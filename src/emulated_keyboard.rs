@@ -1,7 +1,14 @@
-use crate::api_helpers::ReturnValue;
+use crate::api_helpers::{Pointer, ReturnValue};
 use crate::emulator_accessor::EmulatorAccessor;
-use crate::EmulatorError;
+use crate::keyboard::{
+    ansi_to_oem, character_of, key_of_character, oem_to_ansi, scan_code_of, virtual_key_of,
+    VK_CAPITAL, VK_CONTROL, VK_MENU, VK_SHIFT,
+};
+use crate::util::encode_u16_u16_to_u32;
+use crate::{debug, EmulatorError};
+use syscall::api_function;
 
+/// The keyboard driver, for a US enhanced keyboard.
 pub struct EmulatedKeyboard {}
 
 impl EmulatedKeyboard {
@@ -9,12 +16,182 @@ impl EmulatedKeyboard {
         Self {}
     }
 
+    #[api_function]
+    fn to_ascii(
+        &self,
+        mut accessor: EmulatorAccessor,
+        virt_key: u16,
+        scan_code: u16,
+        key_state: Pointer,
+        char_buffer: Pointer,
+        _flags: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // The high bit of the scan code is set when the key goes up
+        if scan_code & 0x8000 != 0 {
+            return Ok(ReturnValue::U16(0));
+        }
+        let memory = accessor.memory();
+        let key_down = |vk: u8| -> Result<bool, EmulatorError> {
+            Ok(memory.read_8(key_state.0 + vk as u32)? & 0x80 != 0)
+        };
+        let character = character_of(
+            virt_key as u8,
+            key_down(VK_SHIFT)?,
+            key_down(VK_CONTROL)?,
+            memory.read_8(key_state.0 + VK_CAPITAL as u32)? & 0x01 != 0,
+            key_down(VK_MENU)?,
+        );
+        match character {
+            Some(character) => {
+                accessor
+                    .memory_mut()
+                    .write_u16(char_buffer.0, character as u16)?;
+                Ok(ReturnValue::U16(1))
+            }
+            None => Ok(ReturnValue::U16(0)),
+        }
+    }
+
+    fn convert_string(
+        accessor: &mut EmulatorAccessor,
+        src: Pointer,
+        dst: Pointer,
+        length: Option<u32>,
+        convert: fn(u8) -> u8,
+    ) -> Result<(), EmulatorError> {
+        let mut offset = 0;
+        while length.is_none_or(|length| offset < length) {
+            let character = accessor.memory().read_8(src.0 + offset)?;
+            if length.is_none() && character == 0 {
+                break;
+            }
+            accessor
+                .memory_mut()
+                .write_8(dst.0 + offset, convert(character))?;
+            offset += 1;
+        }
+        if length.is_none() {
+            accessor.memory_mut().write_8(dst.0 + offset, 0)?;
+        }
+        Ok(())
+    }
+
+    #[api_function]
+    fn ansi_to_oem(
+        &self,
+        mut accessor: EmulatorAccessor,
+        src: Pointer,
+        dst: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        Self::convert_string(&mut accessor, src, dst, None, ansi_to_oem)?;
+        Ok(ReturnValue::U16(0xFFFF))
+    }
+
+    #[api_function]
+    fn oem_to_ansi(
+        &self,
+        mut accessor: EmulatorAccessor,
+        src: Pointer,
+        dst: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        Self::convert_string(&mut accessor, src, dst, None, oem_to_ansi)?;
+        Ok(ReturnValue::U16(0xFFFF))
+    }
+
+    #[api_function]
+    fn ansi_to_oem_buff(
+        &self,
+        mut accessor: EmulatorAccessor,
+        src: Pointer,
+        dst: Pointer,
+        length: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // A length of 0 stands for 64K
+        let length = if length == 0 { 0x10000 } else { length as u32 };
+        Self::convert_string(&mut accessor, src, dst, Some(length), ansi_to_oem)?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn oem_to_ansi_buff(
+        &self,
+        mut accessor: EmulatorAccessor,
+        src: Pointer,
+        dst: Pointer,
+        length: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let length = if length == 0 { 0x10000 } else { length as u32 };
+        Self::convert_string(&mut accessor, src, dst, Some(length), oem_to_ansi)?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn oem_key_scan(&self, oem_char: u16) -> Result<ReturnValue, EmulatorError> {
+        let key = key_of_character(oem_to_ansi(oem_char as u8));
+        Ok(ReturnValue::U32(match key {
+            Some((vk, shift_state)) => {
+                encode_u16_u16_to_u32(scan_code_of(vk) as u16, shift_state as u16)
+            }
+            None => 0xFFFF_FFFF,
+        }))
+    }
+
+    #[api_function]
+    fn vk_key_scan(&self, character: u16) -> Result<ReturnValue, EmulatorError> {
+        debug!("[keyboard] VK KEY SCAN {:x}", character);
+        Ok(ReturnValue::U16(match key_of_character(character as u8) {
+            Some((vk, shift_state)) => ((shift_state as u16) << 8) | vk as u16,
+            None => 0xFFFF,
+        }))
+    }
+
+    #[api_function]
+    fn get_keyboard_type(&self, type_flag: i16) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(match type_flag {
+            // IBM enhanced 101/102-key keyboard
+            0 => 4,
+            // Its subtype, which is OEM-specific
+            1 => 0,
+            // The number of function keys
+            2 => 12,
+            _ => 0,
+        }))
+    }
+
+    #[api_function]
+    fn map_virtual_key(&self, code: u16, map_type: u16) -> Result<ReturnValue, EmulatorError> {
+        let code = code as u8;
+        Ok(ReturnValue::U16(match map_type {
+            0 => scan_code_of(code) as u16,
+            1 => virtual_key_of(code) as u16,
+            // Letters are reported in uppercase here
+            2 => character_of(code, false, false, false, false)
+                .map_or(0, |character| character.to_ascii_uppercase() as u16),
+            _ => 0,
+        }))
+    }
+
+    #[api_function]
+    fn get_kb_code_page(&self) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(437))
+    }
+
     pub fn syscall(
         &self,
         nr: u16,
-        _emulator_accessor: EmulatorAccessor,
+        emulator_accessor: EmulatorAccessor,
     ) -> Result<ReturnValue, EmulatorError> {
         match nr {
+            4 => self.__api_to_ascii(emulator_accessor),
+            5 => self.__api_ansi_to_oem(emulator_accessor),
+            6 => self.__api_oem_to_ansi(emulator_accessor),
+            128 => self.__api_oem_key_scan(emulator_accessor),
+            129 => self.__api_vk_key_scan(emulator_accessor),
+            130 => self.__api_get_keyboard_type(emulator_accessor),
+            131 => self.__api_map_virtual_key(emulator_accessor),
+            132 => self.__api_get_kb_code_page(emulator_accessor),
+            134 => self.__api_ansi_to_oem_buff(emulator_accessor),
+            135 => self.__api_oem_to_ansi_buff(emulator_accessor),
             nr => {
                 todo!("unimplemented keyboard syscall {}", nr)
             }
@@ -1,28 +1,54 @@
+use crate::accelerator::{self, Accelerator};
 use crate::api_helpers::{Pointer, ReturnValue};
-use crate::atom_table::AtomTable;
-use crate::bitmap::Color;
+use crate::bitmap::{BitmapView, Color};
 use crate::byte_string::{ByteString, HeapByteString};
-use crate::constants::{ClassStyles, MessageType, RasterOp, SystemColors};
+use crate::caret::Caret;
+use crate::clipboard::{self, ClipboardData};
+use crate::constants::{
+    AcceleratorFlags, ActivateState, ButtonMessage, CbtCode, ClassStyles, ComboBoxMessage,
+    EditMessage, GetWindowCommand, HitTest, ListBoxMessage, MenuFlags, MessageFilterCode,
+    MessageType, MouseActivate, RasterOp, ScrollBarMessage, SetWindowPosFlags, ShowWindowCommand,
+    SizeType, SystemColors, SystemCommand, WindowStyles, CW_USEDEFAULT, DESKTOP_WINDOW,
+    GCL_WNDPROC, GCW_CBCLSEXTRA, GCW_CBWNDEXTRA, GCW_HBRBACKGROUND, GCW_HCURSOR, GCW_HICON,
+    GCW_HMODULE, GCW_STYLE, GWL_EXSTYLE, GWL_STYLE, GWL_WNDPROC, GWW_HINSTANCE, GWW_HWNDPARENT,
+    GWW_ID, HC_ACTION, HC_NOREMOVE, HWND_BROADCAST, IDCANCEL, IDOK, RT_ACCELERATOR, RT_MENU,
+    SB_CTL, WH_CALLWNDPROC, WH_CBT, WH_GETMESSAGE, WH_KEYBOARD, WH_MOUSE, WH_MSGFILTER,
+    WH_SYSMSGFILTER,
+};
+use crate::controls::Control;
+use crate::dialog::{self, DWL_DLGPROC};
 use crate::emulator_accessor::EmulatorAccessor;
 use crate::handle_table::{GenericHandle, Handle};
+use crate::hook::{self, Hook, HookCall, HookParameter};
+use crate::keyboard::{
+    VK_CONTROL, VK_ESCAPE, VK_F10, VK_F4, VK_LBUTTON, VK_MBUTTON, VK_MENU, VK_RBUTTON, VK_RETURN,
+    VK_SHIFT,
+};
 use crate::memory::SegmentAndOffset;
-use crate::message_queue::{MessageQueue, WindowMessage};
+use crate::menu::{self, Menu, MenuItem, MenuStart, MenuTracking, TemplateItem};
+use crate::message_box::{self, AutomaticAnswer};
+use crate::message_queue::{MessageFilter, MessageQueue, PostedMessage, SentCall, WindowMessage};
+use crate::non_client;
 use crate::object_environment::{
     DeviceContext, GdiObject, ObjectEnvironment, UserObject, UserWindow,
 };
+use crate::scheduler::Scheduler;
+use crate::task::TaskDatabase;
 use crate::two_d::{Point, Rect};
-use crate::util::{debug_print_null_terminated_string, encode_u16_u16_to_u32};
+use crate::util::{
+    debug_print_null_terminated_string, encode_u16_u16_to_u32, read_le_value, replace_le_value,
+};
 use crate::window_manager::{ProcessId, WindowIdentifier};
-use crate::{debug, EmulatorError, ResourceTable};
+use crate::{debug, EmulatorError, ResourceName, ResourceTable};
 use num_traits::FromPrimitive;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use syscall::api_function;
 
 #[allow(dead_code)]
 #[derive(Debug)]
-struct WindowClass<'a> {
+struct WindowClass {
     style: ClassStyles,
     proc: SegmentAndOffset,
     cls_extra: u16,
@@ -30,34 +56,406 @@ struct WindowClass<'a> {
     h_icon: Handle,
     h_cursor: Handle,
     h_background: Handle,
-    menu_class_name: Option<ByteString<'a>>,
+    menu_name: Option<ResourceName>,
+    /// The extra bytes of the class, as many as it asked for.
+    extra: Vec<u8>,
+}
+
+/// What a window is created from, the way CreateWindow gets it.
+pub struct WindowCreation {
+    pub class_name: HeapByteString,
+    pub window_name: HeapByteString,
+    /// The window styles, with the styles of the class in the low word.
+    pub style: u32,
+    pub x: i16,
+    pub y: i16,
+    pub width: i16,
+    pub height: i16,
+    /// The parent of a child window, or the owner of a top-level window.
+    pub h_wnd_parent: Handle,
+    /// The menu bar of a top-level window, or the id of a child window.
+    pub h_menu: Handle,
+    pub h_instance: Handle,
+    /// What the window gets in lpCreateParams of its CREATESTRUCT.
+    pub param: u32,
+}
+
+/// The size of a CREATESTRUCT, which the window name and the class name follow on the stack.
+const CREATE_STRUCT_SIZE: u16 = 34;
+/// The size of a MSG.
+const MSG_SIZE: u16 = 18;
+
+impl WindowCreation {
+    /// How much room a CREATESTRUCT about the window takes on the stack, with the names behind
+    /// it. The names are null-terminated, and the stack stays aligned to words.
+    fn create_struct_size(&self) -> u16 {
+        let names_size = self.window_name.as_slice().len() + self.class_name.as_slice().len();
+        (CREATE_STRUCT_SIZE + names_size as u16 + 3) & !1
+    }
+}
+
+/// A hook chain that is being called, which CallNextHookEx and DefHookProc carry on with.
+#[derive(Copy, Clone)]
+struct HookInProgress {
+    hook_type: i16,
+    /// The task whose messages the chain is for.
+    process_id: ProcessId,
+    parameter: Option<HookParameter>,
+}
+
+/// Two presses of the same button within this many milliseconds make a double click.
+const DOUBLE_CLICK_TIME: u32 = 500;
+/// The size of the rectangle around the first press in which the second press must happen.
+const DOUBLE_CLICK_SIZE: i16 = 4;
+
+/// A button press that may become the first half of a double click.
+#[derive(Copy, Clone)]
+struct Click {
+    h_wnd: Handle,
+    message: MessageType,
+    time: u32,
+    point: Point,
 }
 
-struct Paint {
-    hdc: Handle,
+pub struct Paint {
+    pub hdc: Handle,
     f_erase: bool,
     rect: Rect,
 }
 
-// TODO: figure out which parts here need to be shared and in case of sharing, what needs to be protected
+/// What lParam of a message points to. Each task has its own memory, so this memory gets copied
+/// along when the message goes to a window of another task.
+#[derive(Copy, Clone)]
+enum PointerParameter {
+    /// A string that ends with a null byte.
+    String,
+    /// A structure or buffer of this many bytes.
+    Bytes(u16),
+    /// A buffer that starts with a word that tells how many bytes fit in it.
+    CountedBuffer,
+    /// A buffer for the text that the window tells the length of in the reply to this message,
+    /// with the same wParam.
+    TextOfLength(u16),
+    /// A structure that holds pointers itself, which can't be copied.
+    Unsupported,
+}
+
+/// The size of the screen, which is also the size of the area that top-level windows get
+/// maximised to.
+pub const SCREEN_WIDTH: i16 = 800;
+pub const SCREEN_HEIGHT: i16 = 600;
+
+/// A structure that a window procedure or a hook gets a pointer to in lParam.
+trait GuestStructure: Sized {
+    const SIZE: u16;
+
+    fn write(&self, accessor: &mut EmulatorAccessor, address: u32) -> Result<(), EmulatorError>;
+
+    fn read(accessor: &EmulatorAccessor, address: u32) -> Result<Self, EmulatorError>;
+}
+
+impl GuestStructure for Rect {
+    const SIZE: u16 = 8;
+
+    fn write(&self, accessor: &mut EmulatorAccessor, address: u32) -> Result<(), EmulatorError> {
+        accessor.write_rect(address, self)
+    }
+
+    fn read(accessor: &EmulatorAccessor, address: u32) -> Result<Self, EmulatorError> {
+        accessor.read_rect(address)
+    }
+}
+
+/// WINDOWPOS, the new placement of a window that WM_WINDOWPOSCHANGING and WM_WINDOWPOSCHANGED
+/// are about. The rectangle is relative to the client area of the parent.
+#[derive(Copy, Clone)]
+struct WindowPos {
+    h_wnd: Handle,
+    h_wnd_insert_after: Handle,
+    rect: Rect,
+    flags: SetWindowPosFlags,
+}
+
+impl WindowPos {
+    /// Takes the position or the size from the current window rectangle if they must not change.
+    fn keep_unchanged(&mut self, window_rect: Rect) {
+        if self.flags.contains(SetWindowPosFlags::NOMOVE) {
+            self.rect = self.rect.offset(
+                window_rect.left - self.rect.left,
+                window_rect.top - self.rect.top,
+            );
+        }
+        if self.flags.contains(SetWindowPosFlags::NOSIZE) {
+            self.rect.right = self.rect.left + window_rect.right - window_rect.left;
+            self.rect.bottom = self.rect.top + window_rect.bottom - window_rect.top;
+        }
+    }
+}
+
+impl GuestStructure for WindowPos {
+    const SIZE: u16 = 14;
+
+    fn write(&self, accessor: &mut EmulatorAccessor, address: u32) -> Result<(), EmulatorError> {
+        let memory = accessor.memory_mut();
+        memory.write_u16(address, self.h_wnd.as_u16())?;
+        memory.write_u16(address + 2, self.h_wnd_insert_after.as_u16())?;
+        memory.write_i16(address + 4, self.rect.left)?;
+        memory.write_i16(address + 6, self.rect.top)?;
+        memory.write_i16(address + 8, self.rect.right - self.rect.left)?;
+        memory.write_i16(address + 10, self.rect.bottom - self.rect.top)?;
+        memory.write_u16(address + 12, self.flags.bits())
+    }
+
+    fn read(accessor: &EmulatorAccessor, address: u32) -> Result<Self, EmulatorError> {
+        let memory = accessor.memory();
+        let left = memory.read_i16(address + 4)?;
+        let top = memory.read_i16(address + 6)?;
+        Ok(Self {
+            h_wnd: memory.read_u16(address)?.into(),
+            h_wnd_insert_after: memory.read_u16(address + 2)?.into(),
+            rect: Rect {
+                left,
+                top,
+                right: left + memory.read_i16(address + 8)?,
+                bottom: top + memory.read_i16(address + 10)?,
+            },
+            flags: SetWindowPosFlags::from_bits_truncate(memory.read_u16(address + 12)?),
+        })
+    }
+}
+
+/// MINMAXINFO, which tells how big a window may get and where it goes when it is maximised.
+/// Sizes are stored as points.
+struct MinMaxInfo {
+    max_size: Point,
+    max_position: Point,
+    min_track_size: Point,
+    max_track_size: Point,
+}
+
+impl MinMaxInfo {
+    /// Where the outline of a window goes when the caption or an edge of the window is dragged
+    /// over a distance. Edges keep the window within the tracking size.
+    fn track(&self, rect: Rect, hit_test: HitTest, distance: Point) -> Rect {
+        if hit_test == HitTest::Caption {
+            return rect.offset(distance.x, distance.y);
+        }
+        let (min, max) = (self.min_track_size, self.max_track_size);
+        let mut tracked = rect;
+        if matches!(
+            hit_test,
+            HitTest::Left | HitTest::TopLeft | HitTest::BottomLeft
+        ) {
+            tracked.left = (rect.left + distance.x)
+                .max(rect.right - max.x)
+                .min(rect.right - min.x);
+        }
+        if matches!(
+            hit_test,
+            HitTest::Right | HitTest::TopRight | HitTest::BottomRight
+        ) {
+            tracked.right = (rect.right + distance.x)
+                .max(rect.left + min.x)
+                .min(rect.left + max.x);
+        }
+        if matches!(
+            hit_test,
+            HitTest::Top | HitTest::TopLeft | HitTest::TopRight
+        ) {
+            tracked.top = (rect.top + distance.y)
+                .max(rect.bottom - max.y)
+                .min(rect.bottom - min.y);
+        }
+        if matches!(
+            hit_test,
+            HitTest::Bottom | HitTest::BottomLeft | HitTest::BottomRight
+        ) {
+            tracked.bottom = (rect.bottom + distance.y)
+                .max(rect.top + min.y)
+                .min(rect.top + max.y);
+        }
+        tracked
+    }
+}
+
+impl GuestStructure for MinMaxInfo {
+    // The first point is reserved
+    const SIZE: u16 = 20;
+
+    fn write(&self, accessor: &mut EmulatorAccessor, address: u32) -> Result<(), EmulatorError> {
+        let memory = accessor.memory_mut();
+        let points = [
+            self.max_size,
+            self.max_position,
+            self.min_track_size,
+            self.max_track_size,
+        ];
+        for (index, point) in points.iter().enumerate() {
+            let point_address = address + 4 + 4 * index as u32;
+            memory.write_i16(point_address, point.x)?;
+            memory.write_i16(point_address + 2, point.y)?;
+        }
+        Ok(())
+    }
+
+    fn read(accessor: &EmulatorAccessor, address: u32) -> Result<Self, EmulatorError> {
+        let memory = accessor.memory();
+        let point = |index: u32| -> Result<Point, EmulatorError> {
+            let point_address = address + 4 + 4 * index;
+            Ok(Point::new(
+                memory.read_i16(point_address)?,
+                memory.read_i16(point_address + 2)?,
+            ))
+        };
+        Ok(Self {
+            max_size: point(0)?,
+            max_position: point(1)?,
+            min_track_size: point(2)?,
+            max_track_size: point(3)?,
+        })
+    }
+}
+
+/// CWPSTRUCT, a message that is about to be sent to a window, which WH_CALLWNDPROC hooks may
+/// change.
+struct CallWndProcStruct {
+    l_param: u32,
+    w_param: u16,
+    message: u16,
+    h_wnd: Handle,
+}
+
+impl GuestStructure for CallWndProcStruct {
+    const SIZE: u16 = 10;
+
+    fn write(&self, accessor: &mut EmulatorAccessor, address: u32) -> Result<(), EmulatorError> {
+        let memory = accessor.memory_mut();
+        memory.write_32(address, self.l_param)?;
+        memory.write_u16(address + 4, self.w_param)?;
+        memory.write_u16(address + 6, self.message)?;
+        memory.write_u16(address + 8, self.h_wnd.as_u16())
+    }
+
+    fn read(accessor: &EmulatorAccessor, address: u32) -> Result<Self, EmulatorError> {
+        let memory = accessor.memory();
+        Ok(Self {
+            l_param: memory.read_32(address)?,
+            w_param: memory.read_u16(address + 4)?,
+            message: memory.read_u16(address + 6)?,
+            h_wnd: memory.read_u16(address + 8)?.into(),
+        })
+    }
+}
+
+/// MOUSEHOOKSTRUCT, about a mouse message that WH_MOUSE hooks get to see. The point is on the
+/// screen.
+struct MouseHookStruct {
+    point: Point,
+    h_wnd: Handle,
+    hit_test: u16,
+}
+
+impl GuestStructure for MouseHookStruct {
+    // The last field is the extra information of the message, which there never is
+    const SIZE: u16 = 12;
+
+    fn write(&self, accessor: &mut EmulatorAccessor, address: u32) -> Result<(), EmulatorError> {
+        let memory = accessor.memory_mut();
+        memory.write_i16(address, self.point.x)?;
+        memory.write_i16(address + 2, self.point.y)?;
+        memory.write_u16(address + 4, self.h_wnd.as_u16())?;
+        memory.write_u16(address + 6, self.hit_test)?;
+        memory.write_32(address + 8, 0)
+    }
+
+    fn read(accessor: &EmulatorAccessor, address: u32) -> Result<Self, EmulatorError> {
+        let memory = accessor.memory();
+        Ok(Self {
+            point: Point::new(memory.read_i16(address)?, memory.read_i16(address + 2)?),
+            h_wnd: memory.read_u16(address + 4)?.into(),
+            hit_test: memory.read_u16(address + 6)?,
+        })
+    }
+}
+
+/// CBTACTIVATESTRUCT, about a window that WH_CBT hooks are asked to let activate.
+struct CbtActivateStruct {
+    /// Whether a click activates the window.
+    mouse: bool,
+    h_wnd_active: Handle,
+}
+
+impl GuestStructure for CbtActivateStruct {
+    const SIZE: u16 = 4;
+
+    fn write(&self, accessor: &mut EmulatorAccessor, address: u32) -> Result<(), EmulatorError> {
+        let memory = accessor.memory_mut();
+        memory.write_u16(address, self.mouse.into())?;
+        memory.write_u16(address + 2, self.h_wnd_active.as_u16())
+    }
+
+    fn read(accessor: &EmulatorAccessor, address: u32) -> Result<Self, EmulatorError> {
+        let memory = accessor.memory();
+        Ok(Self {
+            mouse: memory.read_u16(address)? != 0,
+            h_wnd_active: memory.read_u16(address + 2)?.into(),
+        })
+    }
+}
+
+/// Where the procedures that USER gives its own windows are, such as the window procedures of
+/// the predefined classes. They live in the USER module.
+#[derive(Default)]
+pub struct BuiltinProcedures {
+    pub button: SegmentAndOffset,
+    pub static_control: SegmentAndOffset,
+    pub edit: SegmentAndOffset,
+    pub list_box: SegmentAndOffset,
+    pub combo_box: SegmentAndOffset,
+    pub scroll_bar: SegmentAndOffset,
+    pub def_dlg_proc: SegmentAndOffset,
+    /// The dialog procedure of message boxes.
+    pub message_box_dialog_proc: SegmentAndOffset,
+}
+
 pub struct EmulatedUser<'a> {
-    user_atom_table: AtomTable<'a>,
-    resource_table: &'a ResourceTable,
-    window_classes: HashMap<ByteString<'a>, WindowClass<'a>>,
-    objects: &'a RwLock<ObjectEnvironment<'a>>,
-    message_queue: &'a MessageQueue,
+    pub resource_table: &'a ResourceTable,
+    window_classes: RefCell<HashMap<ByteString<'a>, WindowClass>>,
+    objects: &'a RwLock<ObjectEnvironment>,
+    scheduler: &'a Scheduler,
+    pub task: &'a TaskDatabase,
+    pub message_queue: Arc<MessageQueue>,
+    /// The message queues of all tasks, for the messages to the windows of other tasks.
+    message_queues: &'a RwLock<HashMap<ProcessId, Arc<MessageQueue>>>,
+    /// Whether a window procedure is handling a message that another task sent.
+    in_send_message: Cell<bool>,
+    /// When GetMessage or PeekMessage last got a message, and where the cursor was then.
+    last_message_time_and_point: Cell<(u32, Point)>,
+    last_click: Cell<Option<Click>>,
+    /// Whether Alt or F10 went down without another key after it, so that releasing it
+    /// opens the menu bar.
+    menu_key_pending: Cell<bool>,
+    message_box_dialog_proc: SegmentAndOffset,
+    /// How message boxes get answered instead of showing them, if they don't show.
+    message_box_answer: Option<AutomaticAnswer>,
+    /// The copies of the clipboard data that the task got with GetClipboardData, by format,
+    /// with the sequence number of the clipboard they were copied at.
+    clipboard_copies: RefCell<(u32, HashMap<u16, Handle>)>,
+    /// The hook chains that are being called. Hooks of those kinds don't get called again until
+    /// they return.
+    hooks_in_progress: RefCell<Vec<HookInProgress>>,
 }
 
-struct SprintfMachine<'a> {
-    accessor: EmulatorAccessor<'a>,
+struct SprintfMachine<'a, 'e> {
+    accessor: EmulatorAccessor<'a, 'e>,
     current_format_address: u32,
     current_dest_address: u32,
     characters_written: u16,
 }
 
-impl<'a> SprintfMachine<'a> {
+impl<'a, 'e> SprintfMachine<'a, 'e> {
     pub fn new(
-        accessor: EmulatorAccessor<'a>,
+        accessor: EmulatorAccessor<'a, 'e>,
         format_string_ptr: Pointer,
         output_buffer_ptr: Pointer,
     ) -> Self {
@@ -122,31 +520,87 @@ impl<'a> SprintfMachine<'a> {
 
 impl<'a> EmulatedUser<'a> {
     pub fn new(
-        objects: &'a RwLock<ObjectEnvironment<'a>>,
-        message_queue: &'a MessageQueue,
+        objects: &'a RwLock<ObjectEnvironment>,
+        scheduler: &'a Scheduler,
+        task: &'a TaskDatabase,
+        message_queues: &'a RwLock<HashMap<ProcessId, Arc<MessageQueue>>>,
         resource_table: &'a ResourceTable,
-        button_wnd_proc: SegmentAndOffset,
+        builtin_procedures: BuiltinProcedures,
+        message_box_answer: Option<AutomaticAnswer>,
     ) -> Self {
         let mut window_classes = HashMap::new();
+        // Controls draw on the dialog box or window they are in
+        let control_classes: [(&[u8], ClassStyles, SegmentAndOffset); 6] = [
+            (b"BUTTON", ClassStyles::PARENT_DC, builtin_procedures.button),
+            (
+                b"STATIC",
+                ClassStyles::PARENT_DC,
+                builtin_procedures.static_control,
+            ),
+            (b"EDIT", ClassStyles::PARENT_DC, builtin_procedures.edit),
+            (
+                b"LISTBOX",
+                ClassStyles::PARENT_DC | ClassStyles::DBLCLKS,
+                builtin_procedures.list_box,
+            ),
+            (
+                b"COMBOBOX",
+                ClassStyles::PARENT_DC | ClassStyles::DBLCLKS,
+                builtin_procedures.combo_box,
+            ),
+            (
+                b"SCROLLBAR",
+                ClassStyles::PARENT_DC,
+                builtin_procedures.scroll_bar,
+            ),
+        ];
+        for (class_name, style, proc) in control_classes {
+            window_classes.insert(
+                ByteString::from_slice(class_name),
+                WindowClass {
+                    style,
+                    proc,
+                    cls_extra: 0,
+                    wnd_extra: 0,
+                    h_icon: Handle::null(),
+                    h_cursor: Handle::null(),
+                    h_background: Handle::null(),
+                    menu_name: None,
+                    extra: Vec::new(),
+                },
+            );
+        }
         window_classes.insert(
-            ByteString::from_slice(b"BUTTON"),
+            ByteString::from_slice(dialog::DIALOG_CLASS_NAME),
             WindowClass {
-                style: ClassStyles::PARENT_DC,
-                proc: button_wnd_proc,
+                style: ClassStyles::empty(),
+                proc: builtin_procedures.def_dlg_proc,
                 cls_extra: 0,
-                wnd_extra: 0,
+                wnd_extra: dialog::DIALOG_WINDOW_EXTRA,
                 h_icon: Handle::null(),
                 h_cursor: Handle::null(),
                 h_background: Handle::null(),
-                menu_class_name: None,
+                menu_name: None,
+                extra: Vec::new(),
             },
         );
+        let message_queue = message_queues.read().unwrap()[&task.process_id].clone();
         Self {
-            user_atom_table: AtomTable::new(),
             message_queue,
+            message_queues,
+            in_send_message: Cell::new(false),
+            last_message_time_and_point: Cell::new((0, Point::origin())),
             resource_table,
-            window_classes,
+            window_classes: RefCell::new(window_classes),
             objects,
+            scheduler,
+            task,
+            last_click: Cell::new(None),
+            menu_key_pending: Cell::new(false),
+            message_box_dialog_proc: builtin_procedures.message_box_dialog_proc,
+            message_box_answer,
+            clipboard_copies: RefCell::new((0, HashMap::new())),
+            hooks_in_progress: RefCell::new(Vec::new()),
         }
     }
 
@@ -160,7 +614,7 @@ impl<'a> EmulatedUser<'a> {
         Ok(ReturnValue::U32(color.as_u32()))
     }
 
-    fn get_system_color(&self, color: SystemColors) -> Color {
+    pub fn get_system_color(&self, color: SystemColors) -> Color {
         match color {
             SystemColors::Background => Color(192, 192, 192),
             SystemColors::AppWorkspace => Color(255, 255, 255),
@@ -192,839 +646,6746 @@ impl<'a> EmulatedUser<'a> {
     }
 
     #[api_function]
-    fn create_window(
-        &mut self,
+    #[allow(clippy::too_many_arguments)]
+    fn internal_create_window(
+        &self,
         mut accessor: EmulatorAccessor,
-        class_name: Pointer,
-        _window_name: HeapByteString,
+        class_name: u32,
+        window_name: Pointer,
         style: u32,
         x: i16,
         y: i16,
         width: i16,
         height: i16,
         h_wnd_parent: Handle,
-        _h_menu: Handle,
-        _h_instance: Handle,
-        _param: Pointer,
+        h_menu: Handle,
+        h_instance: Handle,
+        param: u32,
     ) -> Result<ReturnValue, EmulatorError> {
-        let class_name = accessor.static_string(class_name.0)?;
+        let class_name = match self.read_class_name(&accessor, class_name)? {
+            Some(class_name) => class_name,
+            None => return Ok(ReturnValue::U16(0)),
+        };
+        // Windows without a name get an empty one
+        let window_name = if window_name.0 == 0 {
+            HeapByteString::from(Arc::new([]))
+        } else {
+            accessor.clone_string(window_name.0, false)?
+        };
+        let h_wnd = self.create_window(
+            &mut accessor,
+            WindowCreation {
+                class_name,
+                window_name,
+                style,
+                x,
+                y,
+                width,
+                height,
+                h_wnd_parent,
+                h_menu,
+                h_instance,
+                param,
+            },
+        )?;
+        Ok(ReturnValue::U16(h_wnd.as_u16()))
+    }
 
+    /// Creates a window of a registered class. The window gets WM_GETMINMAXINFO, WM_NCCREATE,
+    /// WM_NCCALCSIZE and WM_CREATE, and then WM_MOVE and WM_SIZE through DefWindowProc. A
+    /// window with WS_VISIBLE gets shown. Returns the null handle if there is no such class or
+    /// no such parent, or if the window fails WM_NCCREATE or WM_CREATE.
+    pub fn create_window(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        mut creation: WindowCreation,
+    ) -> Result<Handle, EmulatorError> {
         println!(
             "CREATE WINDOW: {:?} {:x} {:x} {:x} {:x} {:x}",
-            _window_name, style, x, y, width, height
+            creation.window_name,
+            creation.style,
+            creation.x,
+            creation.y,
+            creation.width,
+            creation.height
         );
+        let class_specific_style = creation.style as u16;
+        let style = WindowStyles::from_bits_truncate(creation.style);
+        // Choose the position and size for CW_USEDEFAULT before WM_GETMINMAXINFO and the
+        // CREATESTRUCT get to see them
+        for (number, default) in [
+            (&mut creation.x, 0),
+            (&mut creation.y, 0),
+            (&mut creation.width, 400),
+            (&mut creation.height, 300),
+        ] {
+            if *number == CW_USEDEFAULT {
+                *number = default;
+            }
+        }
 
-        // TODO: support atom lookup here (that's the case if segment == 0)
-        if let Some(class) = self.window_classes.get(&class_name) {
-            let parent_dc = class.style.contains(ClassStyles::PARENT_DC);
-            let user_window = UserWindow::new(class.proc, parent_dc, h_wnd_parent);
-            let proc = user_window.proc;
-            let mut objects = self.write_objects();
-            if let Some(window_handle) = objects.user.register(UserObject::Window(user_window)) {
-                if h_wnd_parent != Handle::null() {
-                    if let Some(UserObject::Window(parent_window)) =
-                        objects.user.get_mut(h_wnd_parent)
-                    {
-                        parent_window.children.push(window_handle);
-                    } else {
-                        objects.user.deregister(window_handle);
-                        return Ok(ReturnValue::U16(Handle::null().as_u16()));
-                    }
-                }
+        let class = self
+            .window_classes
+            .borrow()
+            .get(&ByteString::from_slice(creation.class_name.as_slice()))
+            .map(|class| {
+                (
+                    class.style,
+                    class.proc,
+                    class.h_background,
+                    class.menu_name.clone(),
+                    class.wnd_extra,
+                )
+            });
+        let (class_style, proc, background, menu_name, wnd_extra) = match class {
+            Some(class) => class,
+            None => return Ok(Handle::null()),
+        };
+        let parent_dc = class_style.contains(ClassStyles::PARENT_DC);
+        // Only child windows go inside their parent, top-level windows belong to it instead
+        let (h_wnd_parent, h_wnd_owner) = if style.contains(WindowStyles::CHILD) {
+            (creation.h_wnd_parent, Handle::null())
+        } else {
+            (Handle::null(), creation.h_wnd_parent)
+        };
+        // Child windows have no menu bar, the menu handle is their id instead
+        let menu = if style.contains(WindowStyles::CHILD) || creation.h_menu != Handle::null() {
+            creation.h_menu
+        } else {
+            menu_name
+                .and_then(|menu_name| self.load_menu_resource(&menu_name))
+                .unwrap_or(Handle::null())
+        };
+        let mut user_window = UserWindow::new(
+            self.process_id(),
+            proc,
+            creation.class_name.clone(),
+            class_style,
+            style,
+            creation.window_name.clone(),
+            h_wnd_parent,
+        );
+        user_window.class_specific_style = class_specific_style;
+        user_window.menu = menu;
+        user_window.owner_handle = h_wnd_owner;
+        user_window.background = background;
+        user_window.h_instance = creation.h_instance;
+        user_window.extra = vec![0; wnd_extra as usize];
+        let mut objects = self.write_objects();
+        let window_handle = match objects.user.register(UserObject::Window(user_window)) {
+            Some(window_handle) => window_handle,
+            None => return Ok(Handle::null()),
+        };
+        if h_wnd_parent != Handle::null() {
+            if let Some(UserObject::Window(parent_window)) = objects.user.get_mut(h_wnd_parent) {
+                parent_window.children.push(window_handle);
+            } else {
+                objects.user.deregister(window_handle);
+                return Ok(Handle::null());
+            }
+        }
 
-                let (width, height) = objects.write_window_manager().create_window(
-                    WindowIdentifier {
-                        window_handle,
-                        process_id: self.process_id(),
-                    },
-                    x,
-                    y,
-                    width,
-                    height,
-                    parent_dc,
+        let window_identifier = WindowIdentifier {
+            window_handle,
+            process_id: self.process_id(),
+        };
+        objects.write_window_manager().create_window(
+            window_identifier,
+            creation.x,
+            creation.y,
+            creation.width,
+            creation.height,
+            parent_dc,
+        );
+        drop(objects);
+
+        if self.call_cbt_create_hook(accessor, window_handle, &creation)? {
+            self.free_window(&mut self.write_objects(), window_handle);
+            return Ok(Handle::null());
+        }
+
+        // Windows that can be sized by the user, and top-level windows, start out within their
+        // tracking size
+        let sizeable = style.contains(WindowStyles::THICKFRAME)
+            || !style.intersects(WindowStyles::POPUP | WindowStyles::CHILD);
+        if sizeable {
+            if let Some(min_max_info) = self.min_max_info(accessor, window_handle)? {
+                let (min, max) = (min_max_info.min_track_size, min_max_info.max_track_size);
+                creation.width = creation.width.max(min.x).min(max.x);
+                creation.height = creation.height.max(min.y).min(max.y);
+                self.read_objects().write_window_manager().set_window_rect(
+                    window_identifier,
+                    Point::new(creation.x, creation.y),
+                    creation.width,
+                    creation.height,
                 );
+            }
+        }
 
-                // TODO: l_param should get a pointer to a CREATESTRUCT that contains info about the window being created
-                // TODO: this actually needs to come via the def window proc, (see remark at https://docs.microsoft.com/en-us/windows/win32/winmsg/wm-size)
-                self.message_queue.send(WindowMessage {
-                    h_wnd: window_handle,
-                    message: MessageType::Size,
-                    w_param: 0,
-                    l_param: encode_u16_u16_to_u32(width as u16, height as u16),
-                    time: 0,
-                    point: Point::origin(),
-                });
+        let created = self.send_create_struct(
+            accessor,
+            proc,
+            window_handle,
+            MessageType::NcCreate,
+            &creation,
+        )? != 0;
+        if !created {
+            self.destroy_window(accessor, window_handle)?;
+            return Ok(Handle::null());
+        }
+        self.calculate_client_rect(accessor, proc, window_handle)?;
+        let created = self.send_create_struct(
+            accessor,
+            proc,
+            window_handle,
+            MessageType::Create,
+            &creation,
+        )? as i32
+            != -1;
+        if !created {
+            self.destroy_window(accessor, window_handle)?;
+            return Ok(Handle::null());
+        }
 
-                self.call_wndproc_sync(
-                    &mut accessor,
-                    proc,
-                    window_handle,
-                    MessageType::Create.into(),
-                    0,
-                    (0xD000 << 4) | 0,
-                )?;
+        // DefWindowProc tells the window where it went and how big it is
+        let window_rect = self
+            .read_objects()
+            .read_window_manager()
+            .window_rect_of(window_identifier);
+        if let Some(window_rect) = window_rect {
+            let window_pos = WindowPos {
+                h_wnd: window_handle,
+                h_wnd_insert_after: Handle::null(),
+                rect: window_rect,
+                flags: SetWindowPosFlags::NOZORDER | SetWindowPosFlags::NOACTIVATE,
+            };
+            self.send_structure(
+                accessor,
+                proc,
+                window_handle,
+                MessageType::WindowPosChanged,
+                &window_pos,
+            )?;
+        }
 
-                return Ok(ReturnValue::DelayedU16(window_handle.as_u16()));
-            }
+        // Windows created with WS_VISIBLE show up right away, without getting activated
+        if style.contains(WindowStyles::VISIBLE) {
+            self.set_window_pos(
+                accessor,
+                window_handle,
+                Handle::null(),
+                Rect::zero(),
+                SetWindowPosFlags::SHOWWINDOW
+                    | SetWindowPosFlags::NOMOVE
+                    | SetWindowPosFlags::NOSIZE
+                    | SetWindowPosFlags::NOZORDER
+                    | SetWindowPosFlags::NOACTIVATE,
+            )?;
         }
-        Ok(ReturnValue::U16(Handle::null().as_u16()))
-    }
 
-    fn read_objects(&self) -> RwLockReadGuard<'_, ObjectEnvironment<'a>> {
-        self.objects.read().unwrap()
+        Ok(window_handle)
     }
 
-    fn write_objects(&self) -> RwLockWriteGuard<'_, ObjectEnvironment<'a>> {
-        self.objects.write().unwrap()
+    /// Sends WM_NCCREATE or WM_CREATE with a CREATESTRUCT about the window being created.
+    /// The structure, and the names it points to, live on the stack of the task for as long as
+    /// the call takes.
+    fn send_create_struct(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        proc: SegmentAndOffset,
+        h_wnd: Handle,
+        message: MessageType,
+        creation: &WindowCreation,
+    ) -> Result<u32, EmulatorError> {
+        let size = creation.create_struct_size();
+        let pointer = accessor.reserve_stack_space(size);
+        let window_name_pointer = pointer + CREATE_STRUCT_SIZE as u32;
+        let address = Pointer::from_far(pointer).0;
+        let result = self
+            .write_create_struct(accessor, address, creation, window_name_pointer)
+            .and_then(|_| {
+                self.call_wndproc_sync(accessor, proc, h_wnd, message.into(), 0, pointer)
+            });
+        accessor.release_stack_space(size);
+        result
     }
 
-    fn process_id(&self) -> ProcessId {
-        // TODO
-        ProcessId::null()
+    /// Writes a CREATESTRUCT with the window name and the class name right behind it.
+    fn write_create_struct(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        address: u32,
+        creation: &WindowCreation,
+        window_name_pointer: u32,
+    ) -> Result<(), EmulatorError> {
+        let window_name = creation.window_name.as_slice();
+        let class_name = creation.class_name.as_slice();
+        let class_name_pointer = window_name_pointer + window_name.len() as u32 + 1;
+        let memory = accessor.memory_mut();
+        memory.write_32(address, creation.param)?;
+        memory.write_u16(address + 4, creation.h_instance.as_u16())?;
+        memory.write_u16(address + 6, creation.h_menu.as_u16())?;
+        memory.write_u16(address + 8, creation.h_wnd_parent.as_u16())?;
+        memory.write_i16(address + 10, creation.height)?;
+        memory.write_i16(address + 12, creation.width)?;
+        memory.write_i16(address + 14, creation.y)?;
+        memory.write_i16(address + 16, creation.x)?;
+        memory.write_32(address + 18, creation.style)?;
+        memory.write_32(address + 22, window_name_pointer)?;
+        memory.write_32(address + 26, class_name_pointer)?;
+        memory.write_32(address + 30, 0)?;
+        for (name, name_pointer) in [
+            (window_name, window_name_pointer),
+            (class_name, class_name_pointer),
+        ] {
+            let name_address = Pointer::from_far(name_pointer).0;
+            memory.copy_from(name, name_address as usize)?;
+            memory.write_8(name_address + name.len() as u32, 0)?;
+        }
+        Ok(())
     }
 
-    #[api_function]
-    fn show_window(&self, h_wnd: Handle, cmd_show: u16) -> Result<ReturnValue, EmulatorError> {
-        println!("show window {:?} {:x}", h_wnd, cmd_show);
-
-        let objects = self.write_objects();
-        let success = match objects.user.get(h_wnd) {
-            Some(UserObject::Window(_)) => {
-                // TODO: do something with cmd_show
-                objects
-                    .write_window_manager()
-                    .show_window(WindowIdentifier {
-                        window_handle: h_wnd,
-                        process_id: self.process_id(),
-                    });
-                true
-            }
-            None => false,
+    /// Destroys a window. It gets hidden, and the windows it owns are destroyed first. Then it
+    /// gets WM_DESTROY, its children are destroyed, and it gets WM_NCDESTROY before it is freed.
+    /// Returns false if there is no such window.
+    pub fn destroy_window(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<bool, EmulatorError> {
+        if !matches!(
+            self.read_objects().user.get(h_wnd),
+            Some(UserObject::Window(_))
+        ) {
+            return Ok(false);
+        }
+        if self.call_cbt_hook(accessor, CbtCode::DestroyWnd, h_wnd.as_u16(), 0)? {
+            return Ok(false);
+        }
+        let owned_windows = {
+            let objects = self.read_objects();
+            objects
+                .user
+                .iter()
+                .filter_map(|(handle, object)| match object {
+                    UserObject::Window(user_window) if user_window.owner_handle == h_wnd => {
+                        Some(handle)
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
         };
-
-        if success {
-            // TODO: iirc this should only be done when the window was not visible yet and now became visible
-            //       ... And if the message queue is empty? (not sure about this)
-            self.message_queue.send(WindowMessage {
+        if self.is_shown(&self.read_objects(), h_wnd) {
+            self.set_window_pos(
+                accessor,
                 h_wnd,
-                message: MessageType::ShowWindow,
-                w_param: 1,
-                l_param: 0,
-                time: 0,
-                point: Point::origin(),
-            });
+                Handle::null(),
+                Rect::zero(),
+                SetWindowPosFlags::HIDEWINDOW
+                    | SetWindowPosFlags::NOMOVE
+                    | SetWindowPosFlags::NOSIZE
+                    | SetWindowPosFlags::NOZORDER
+                    | SetWindowPosFlags::NOACTIVATE,
+            )?;
+        }
+        for owned_window in owned_windows {
+            self.destroy_window(accessor, owned_window)?;
+        }
 
-            // TODO: also what about child windows?
-            /*self.message_queue.send(WindowMessage {
-                h_wnd,
-                message: MessageType::Paint,
-                w_param: 0,
-                l_param: 0,
-                time: 0,
-                point: Point::origin(),
-            });*/
+        let is_clipboard_owner = self
+            .read_objects()
+            .clipboard
+            .owner()
+            .is_some_and(|owner| owner.window_handle == h_wnd);
+        if is_clipboard_owner {
+            self.send_message(accessor, h_wnd, MessageType::RenderAllFormats.into(), 0, 0)?;
+        }
+        self.send_message(accessor, h_wnd, MessageType::Destroy.into(), 0, 0)?;
+        // The window may have gotten other children while it heard about it
+        let children = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.children.clone(),
+            _ => Vec::new(),
+        };
+        for child in children {
+            self.destroy_window(accessor, child)?;
         }
+        self.send_message(accessor, h_wnd, MessageType::NcDestroy.into(), 0, 0)?;
 
-        Ok(ReturnValue::U16(success.into()))
+        self.free_window(&mut self.write_objects(), h_wnd);
+        Ok(true)
     }
 
-    fn recursive_window_paint(
-        &self,
-        accessor: &mut EmulatorAccessor,
-        objects: &RwLockReadGuard<ObjectEnvironment>,
-        h_wnd: Handle,
-    ) -> bool {
-        println!("recursive window paint: {:?}", h_wnd);
-        match objects.user.get(h_wnd) {
-            Some(UserObject::Window(user_window)) => {
-                for child in &user_window.children {
-                    self.recursive_window_paint(accessor, objects, *child);
-                }
-                // TODO: only do this if update region is non-empty
-                self.call_wndproc_sync(
-                    accessor,
-                    user_window.proc,
-                    h_wnd,
-                    MessageType::Paint.into(),
-                    0,
-                    0,
-                )
-                .is_ok()
+    /// Frees a window and what belongs to it: its menus, device contexts, timers and messages.
+    /// It gets unlinked from its parent.
+    fn free_window(&self, objects: &mut ObjectEnvironment, h_wnd: Handle) {
+        let (window_identifier, parent_handle, style, menu, system_menu) =
+            match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => (
+                    WindowIdentifier {
+                        process_id: user_window.process_id,
+                        window_handle: h_wnd,
+                    },
+                    user_window.parent_handle,
+                    user_window.style,
+                    user_window.menu,
+                    user_window.system_menu,
+                ),
+                _ => return,
+            };
+        if let Some(UserObject::Window(parent_window)) = objects.user.get_mut(parent_handle) {
+            parent_window.children.retain(|&child| child != h_wnd);
+        }
+        // The menu handle of a child window is its id instead
+        if !style.contains(WindowStyles::CHILD) {
+            self.destroy_menu(objects, menu);
+        }
+        self.destroy_menu(objects, system_menu);
+        let device_contexts = objects
+            .gdi
+            .iter()
+            .filter_map(|(handle, object)| match object {
+                GdiObject::DC(dc) if dc.h_wnd == h_wnd => Some((handle, dc.hides_caret)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for (hdc, hides_caret) in device_contexts {
+            if hides_caret {
+                objects.write_window_manager().show_caret();
             }
-            _ => false,
+            objects.gdi.deregister(hdc);
+        }
+        objects.timers.kill_timers_of_window(h_wnd);
+        objects.clipboard.forget_window(window_identifier);
+        objects
+            .write_window_manager()
+            .destroy_window(window_identifier);
+        objects.user.deregister(h_wnd);
+        self.message_queue.discard_messages_of(h_wnd);
+    }
+
+    /// Frees the windows that the task left behind once it ended, without sending them any
+    /// messages.
+    pub fn free_task_windows(&self) {
+        let mut objects = self.write_objects();
+        let windows = objects
+            .user
+            .iter()
+            .filter_map(|(handle, object)| match object {
+                UserObject::Window(user_window) if user_window.process_id == self.process_id() => {
+                    Some(handle)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for h_wnd in windows {
+            self.free_window(&mut objects, h_wnd);
+        }
+        let (_, released) = objects.clipboard.close(self.process_id());
+        for handle in released {
+            objects.gdi.deregister(handle);
         }
     }
 
     #[api_function]
-    fn update_window(
+    fn internal_destroy_window(
         &self,
         mut accessor: EmulatorAccessor,
         h_wnd: Handle,
     ) -> Result<ReturnValue, EmulatorError> {
-        let objects = self.read_objects();
-        let success = self.recursive_window_paint(&mut accessor, &objects, h_wnd);
-        Ok(ReturnValue::DelayedU16(success.into()))
+        let destroyed = self.destroy_window(&mut accessor, h_wnd)?;
+        Ok(ReturnValue::U16(destroyed.into()))
     }
 
-    #[api_function]
-    fn invalidate_rect(
+    /// Lets the window decide on its client area with WM_NCCALCSIZE, given its window rectangle.
+    /// Returns the new client rectangle.
+    fn calculate_client_rect(
         &self,
-        accessor: EmulatorAccessor,
+        accessor: &mut EmulatorAccessor,
+        proc: SegmentAndOffset,
         h_wnd: Handle,
-        _rect: Pointer,
-        _erase: u16,
-    ) -> Result<ReturnValue, EmulatorError> {
-        println!("{:?} {:?}", h_wnd, accessor.read_rect(_rect.0));
-        // TODO
-        Ok(ReturnValue::U16(0))
+    ) -> Result<Rect, EmulatorError> {
+        let (window_identifier, window_rect) = {
+            let objects = self.read_objects();
+            match objects
+                .window_identifier(h_wnd)
+                .and_then(|window_identifier| {
+                    objects
+                        .read_window_manager()
+                        .window_rect_of(window_identifier)
+                        .map(|window_rect| (window_identifier, window_rect))
+                }) {
+                Some(window) => window,
+                None => return Ok(Rect::zero()),
+            }
+        };
+
+        // The window manager keeps the client area relative to the window
+        let client_rect = self
+            .send_structure(accessor, proc, h_wnd, MessageType::NcCalcSize, &window_rect)?
+            .offset(-window_rect.left, -window_rect.top);
+        self.read_objects()
+            .write_window_manager()
+            .set_client_rect(window_identifier, client_rect);
+        Ok(Rect {
+            left: 0,
+            top: 0,
+            right: client_rect.right - client_rect.left,
+            bottom: client_rect.bottom - client_rect.top,
+        })
     }
 
-    #[api_function]
-    fn register_class(
-        &mut self,
-        accessor: EmulatorAccessor,
-        wnd_class_ptr: Pointer,
-    ) -> Result<ReturnValue, EmulatorError> {
-        let wnd_class_style = accessor.memory().read_u16(wnd_class_ptr.0)?;
-        let wnd_class_proc_offset = accessor.memory().read_u16(wnd_class_ptr.0 + 2)?;
-        let wnd_class_proc_segment = accessor.memory().read_u16(wnd_class_ptr.0 + 4)?;
-        let wnd_class_cls_extra = accessor.memory().read_u16(wnd_class_ptr.0 + 6)?;
-        let wnd_class_wnd_extra = accessor.memory().read_u16(wnd_class_ptr.0 + 8)?;
-        let _wnd_class_h_instance = accessor.memory().read_u16(wnd_class_ptr.0 + 10)?;
+    /// Sends a message whose lParam points to a structure that the window may change.
+    /// Returns the structure the way the window left it.
+    fn send_structure<T: GuestStructure>(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        proc: SegmentAndOffset,
+        h_wnd: Handle,
+        message: MessageType,
+        structure: &T,
+    ) -> Result<T, EmulatorError> {
+        // The structure lives on the stack of the task for as long as the call takes
+        let pointer = accessor.reserve_stack_space(T::SIZE);
+        let flat_pointer = Pointer::from_far(pointer).0;
+        let result = structure
+            .write(accessor, flat_pointer)
+            .and_then(|_| self.call_wndproc_sync(accessor, proc, h_wnd, message.into(), 0, pointer))
+            .and_then(|_| T::read(accessor, flat_pointer));
+        accessor.release_stack_space(T::SIZE);
+        result
+    }
+
+    /// Moves, sizes, shows, hides and reorders a window. The window gets to change its new
+    /// placement with WM_WINDOWPOSCHANGING, and hears about it with WM_WINDOWPOSCHANGED.
+    /// The rectangle is relative to the client area of the parent.
+    pub fn set_window_pos(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        h_wnd_insert_after: Handle,
+        rect: Rect,
+        flags: SetWindowPosFlags,
+    ) -> Result<bool, EmulatorError> {
+        let window = self.window_proc_of(h_wnd).and_then(|proc| {
+            let objects = self.read_objects();
+            let window_identifier = objects.window_identifier(h_wnd)?;
+            let window_rect = objects
+                .read_window_manager()
+                .window_rect_of(window_identifier)?;
+            Some((proc, window_identifier, window_rect))
+        });
+        let (proc, window_identifier, old_rect) = match window {
+            Some(window) => window,
+            None => return Ok(false),
+        };
+
+        let mut window_pos = WindowPos {
+            h_wnd,
+            h_wnd_insert_after,
+            rect,
+            flags,
+        };
+        window_pos.keep_unchanged(old_rect);
+        window_pos = self.send_structure(
+            accessor,
+            proc,
+            h_wnd,
+            MessageType::WindowPosChanging,
+            &window_pos,
+        )?;
+        window_pos.keep_unchanged(old_rect);
+        let (rect, flags) = (window_pos.rect, window_pos.flags);
+        let frame_changed = rect.right - rect.left != old_rect.right - old_rect.left
+            || rect.bottom - rect.top != old_rect.bottom - old_rect.top
+            || flags.contains(SetWindowPosFlags::FRAMECHANGED);
+
+        let moved = rect.left != old_rect.left || rect.top != old_rect.top;
+
+        let (was_shown, shown) = {
+            let objects = self.read_objects();
+            let above = objects.window_identifier(window_pos.h_wnd_insert_after);
+            let mut window_manager = objects.write_window_manager();
+            let was_shown = window_manager.is_shown(window_identifier);
+            window_manager.set_window_rect(
+                window_identifier,
+                Point::new(rect.left, rect.top),
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+            );
+            if flags.contains(SetWindowPosFlags::HIDEWINDOW) {
+                window_manager.hide_window(window_identifier);
+            } else if flags.contains(SetWindowPosFlags::SHOWWINDOW)
+                && !window_manager.is_shown(window_identifier)
+            {
+                window_manager.show_window(window_identifier);
+            }
+            if !flags.contains(SetWindowPosFlags::NOZORDER)
+                && window_manager.is_shown(window_identifier)
+            {
+                // HWND_TOP is null and HWND_BOTTOM is 1
+                if window_pos.h_wnd_insert_after == Handle::null() {
+                    window_manager.show_window(window_identifier);
+                } else if window_pos.h_wnd_insert_after.as_u16() == 1 {
+                    window_manager.place_below(window_identifier, None);
+                } else if above.is_some() {
+                    window_manager.place_below(window_identifier, above);
+                }
+            }
+            (was_shown, window_manager.is_shown(window_identifier))
+        };
+
+        if frame_changed {
+            self.calculate_client_rect(accessor, proc, h_wnd)?;
+        }
+        self.send_structure(
+            accessor,
+            proc,
+            h_wnd,
+            MessageType::WindowPosChanged,
+            &window_pos,
+        )?;
+        if !flags.contains(SetWindowPosFlags::NOREDRAW) {
+            // A child window is drawn on its parent, which has to paint what it uncovered
+            let h_wnd_parent = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.parent_handle,
+                _ => Handle::null(),
+            };
+            let is_child = h_wnd_parent != Handle::null();
+            if is_child && was_shown && (moved || frame_changed || !shown) {
+                self.invalidate(h_wnd_parent, Some(old_rect), true);
+            }
+            if frame_changed && shown {
+                self.recursive_window_paint(accessor, h_wnd);
+            } else if shown && (!was_shown || (is_child && moved)) {
+                self.redraw(h_wnd);
+            }
+        }
+
+        // Showing or raising a top-level window activates it. A window that gets hidden loses the
+        // focus to its parent, and the active window passes the activation on.
+        let h_wnd_parent = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.parent_handle,
+            _ => return Ok(true),
+        };
+        if was_shown && !shown {
+            let focus = self.focus();
+            if focus != Handle::null() && self.is_within(&self.read_objects(), focus, h_wnd) {
+                self.set_focus(accessor, h_wnd_parent)?;
+            }
+            if self.active_window() == h_wnd {
+                self.activate_next(accessor, h_wnd)?;
+            }
+        } else if shown
+            && h_wnd_parent == Handle::null()
+            && !flags.contains(SetWindowPosFlags::NOACTIVATE)
+            && (!was_shown || !flags.contains(SetWindowPosFlags::NOZORDER))
+        {
+            self.activate_window(accessor, h_wnd, ActivateState::Active)?;
+        }
+        Ok(true)
+    }
+
+    /// Asks a window how big it may get with WM_GETMINMAXINFO. The defaults let it cover the
+    /// screen, or the client area of its parent, with its frame just outside.
+    fn min_max_info(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<Option<MinMaxInfo>, EmulatorError> {
+        let window = {
+            let objects = self.read_objects();
+            match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => {
+                    let area = if user_window.parent_handle == Handle::null() {
+                        Some(Rect {
+                            left: 0,
+                            top: 0,
+                            right: SCREEN_WIDTH,
+                            bottom: SCREEN_HEIGHT,
+                        })
+                    } else {
+                        self.get_client_rect(user_window.parent_handle, &objects)
+                    };
+                    area.map(|area| (user_window.proc, user_window.style, area))
+                }
+                _ => None,
+            }
+        };
+        let (proc, style, area) = match window {
+            Some(window) => window,
+            None => return Ok(None),
+        };
+        let frame_width = non_client::frame_width(style);
+        let max_size = Point::new(area.right + 2 * frame_width, area.bottom + 2 * frame_width);
+        let defaults = MinMaxInfo {
+            max_size,
+            max_position: Point::new(-frame_width, -frame_width),
+            min_track_size: Point::new(non_client::MIN_TRACK_WIDTH, non_client::MIN_TRACK_HEIGHT),
+            max_track_size: max_size,
+        };
+        self.send_structure(accessor, proc, h_wnd, MessageType::GetMinMaxInfo, &defaults)
+            .map(Some)
+    }
+
+    /// Minimises, maximises or restores a window, depending on whether the state is WS_MINIMIZE,
+    /// WS_MAXIMIZE or neither. The window remembers where it was before it got minimised or
+    /// maximised, and goes back there when it gets restored.
+    fn set_window_state(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        state: WindowStyles,
+    ) -> Result<(), EmulatorError> {
+        let old_state = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                user_window.style & (WindowStyles::MINIMIZE | WindowStyles::MAXIMIZE)
+            }
+            _ => return Ok(()),
+        };
+        if old_state != state {
+            let show_command = if state.contains(WindowStyles::MINIMIZE) {
+                ShowWindowCommand::Minimize
+            } else if state.contains(WindowStyles::MAXIMIZE) {
+                ShowWindowCommand::ShowMaximized
+            } else {
+                ShowWindowCommand::Restore
+            };
+            let show_command: u16 = show_command.into();
+            if self.call_cbt_hook(
+                accessor,
+                CbtCode::MinMax,
+                h_wnd.as_u16(),
+                show_command.into(),
+            )? {
+                return Ok(());
+            }
+        }
+        let restored_rect = {
+            let mut objects = self.write_objects();
+            let window_rect = match objects
+                .window_identifier(h_wnd)
+                .and_then(|window_identifier| {
+                    objects
+                        .read_window_manager()
+                        .window_rect_of(window_identifier)
+                }) {
+                Some(window_rect) => window_rect,
+                None => return Ok(()),
+            };
+            let user_window = match objects.user.get_mut(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window,
+                _ => return Ok(()),
+            };
+            let old_state = user_window.style & (WindowStyles::MINIMIZE | WindowStyles::MAXIMIZE);
+            if old_state == state {
+                return Ok(());
+            }
+            if old_state.is_empty() {
+                user_window.restored_rect = Some(window_rect);
+            }
+            user_window
+                .style
+                .remove(WindowStyles::MINIMIZE | WindowStyles::MAXIMIZE);
+            user_window.style.insert(state);
+            if state.is_empty() {
+                user_window.restored_rect.take()
+            } else {
+                None
+            }
+            .unwrap_or(window_rect)
+        };
+
+        let rect = if state.contains(WindowStyles::MINIMIZE) {
+            self.icon_rect_for(h_wnd)
+        } else if state.contains(WindowStyles::MAXIMIZE) {
+            match self.min_max_info(accessor, h_wnd)? {
+                Some(min_max_info) => Rect {
+                    left: min_max_info.max_position.x,
+                    top: min_max_info.max_position.y,
+                    right: min_max_info.max_position.x + min_max_info.max_size.x,
+                    bottom: min_max_info.max_position.y + min_max_info.max_size.y,
+                },
+                None => return Ok(()),
+            }
+        } else {
+            restored_rect
+        };
+        self.set_window_pos(
+            accessor,
+            h_wnd,
+            Handle::null(),
+            rect,
+            SetWindowPosFlags::NOZORDER
+                | SetWindowPosFlags::NOACTIVATE
+                | SetWindowPosFlags::FRAMECHANGED,
+        )?;
+        Ok(())
+    }
+
+    /// The first free spot for an icon, along the bottom of the screen for top-level windows,
+    /// or along the bottom of the client area of the parent for child windows.
+    fn icon_rect_for(&self, h_wnd: Handle) -> Rect {
+        let objects = self.read_objects();
+        let parent = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.parent_handle,
+            _ => Handle::null(),
+        };
+        let area_height = if parent == Handle::null() {
+            SCREEN_HEIGHT
+        } else {
+            self.get_client_rect(parent, &objects)
+                .map_or(SCREEN_HEIGHT, |rect| rect.bottom)
+        };
+        let taken: Vec<i16> = objects
+            .user
+            .iter()
+            .filter_map(|(handle, object)| match object {
+                UserObject::Window(user_window)
+                    if handle != h_wnd
+                        && user_window.parent_handle == parent
+                        && user_window.style.contains(WindowStyles::MINIMIZE) =>
+                {
+                    let window_identifier = objects.window_identifier(handle)?;
+                    let rect = objects
+                        .read_window_manager()
+                        .window_rect_of(window_identifier)?;
+                    Some(rect.left)
+                }
+                _ => None,
+            })
+            .collect();
+        let left = (0..)
+            .map(|slot| slot * non_client::ICON_SPACING)
+            .find(|left| !taken.contains(left))
+            .unwrap_or(0);
+        Rect {
+            left,
+            top: area_height - non_client::ICON_CELL_HEIGHT,
+            right: left + non_client::ICON_SPACING,
+            bottom: area_height,
+        }
+    }
+
+    pub fn read_objects(&self) -> RwLockReadGuard<'_, ObjectEnvironment> {
+        self.objects.read().unwrap()
+    }
+
+    pub fn write_objects(&self) -> RwLockWriteGuard<'_, ObjectEnvironment> {
+        self.objects.write().unwrap()
+    }
+
+    fn process_id(&self) -> ProcessId {
+        self.task.process_id
+    }
+
+    #[api_function]
+    fn internal_show_window(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        cmd_show: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        println!("show window {:?} {:x}", h_wnd, cmd_show);
+        let was_visible = self.show_window(&mut accessor, h_wnd, cmd_show)?;
+        Ok(ReturnValue::U16(was_visible.into()))
+    }
+
+    /// Shows, hides, minimises, maximises or restores a window. Returns whether the window was
+    /// visible before.
+    pub fn show_window(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        cmd_show: u16,
+    ) -> Result<bool, EmulatorError> {
+        let was_visible = {
+            let objects = self.read_objects();
+            match objects.window_identifier(h_wnd) {
+                Some(window_identifier) => {
+                    objects.read_window_manager().is_shown(window_identifier)
+                }
+                None => return Ok(false),
+            }
+        };
+        let proc = match self.window_proc_of(h_wnd) {
+            Some(proc) => proc,
+            None => return Ok(false),
+        };
+
+        if cmd_show == ShowWindowCommand::Hide.into() {
+            if was_visible {
+                self.call_wndproc_sync(
+                    accessor,
+                    proc,
+                    h_wnd,
+                    MessageType::ShowWindow.into(),
+                    0,
+                    0,
+                )?;
+                self.set_window_pos(
+                    accessor,
+                    h_wnd,
+                    Handle::null(),
+                    Rect::zero(),
+                    SetWindowPosFlags::HIDEWINDOW
+                        | SetWindowPosFlags::NOMOVE
+                        | SetWindowPosFlags::NOSIZE
+                        | SetWindowPosFlags::NOZORDER
+                        | SetWindowPosFlags::NOACTIVATE,
+                )?;
+            }
+            return Ok(was_visible);
+        }
+
+        if !was_visible {
+            self.call_wndproc_sync(accessor, proc, h_wnd, MessageType::ShowWindow.into(), 1, 0)?;
+        }
+        let mut flags =
+            SetWindowPosFlags::SHOWWINDOW | SetWindowPosFlags::NOMOVE | SetWindowPosFlags::NOSIZE;
+        if [
+            ShowWindowCommand::ShowNoActivate,
+            ShowWindowCommand::ShowNA,
+            ShowWindowCommand::Minimize,
+            ShowWindowCommand::ShowMinNoActive,
+        ]
+        .into_iter()
+        .any(|command| cmd_show == command.into())
+        {
+            flags |= SetWindowPosFlags::NOZORDER | SetWindowPosFlags::NOACTIVATE;
+        }
+        self.set_window_pos(accessor, h_wnd, Handle::null(), Rect::zero(), flags)?;
+
+        let state = if [
+            ShowWindowCommand::ShowMinimized,
+            ShowWindowCommand::Minimize,
+            ShowWindowCommand::ShowMinNoActive,
+        ]
+        .into_iter()
+        .any(|command| cmd_show == command.into())
+        {
+            Some(WindowStyles::MINIMIZE)
+        } else if cmd_show == ShowWindowCommand::ShowMaximized.into() {
+            Some(WindowStyles::MAXIMIZE)
+        } else if cmd_show == ShowWindowCommand::ShowNormal.into()
+            || cmd_show == ShowWindowCommand::Restore.into()
+        {
+            Some(WindowStyles::empty())
+        } else {
+            None
+        };
+        if let Some(state) = state {
+            self.set_window_state(accessor, h_wnd, state)?;
+        }
+
+        Ok(was_visible)
+    }
+
+    #[api_function]
+    #[allow(clippy::too_many_arguments)]
+    fn move_window(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        x: i16,
+        y: i16,
+        width: i16,
+        height: i16,
+        repaint: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let mut flags = SetWindowPosFlags::NOZORDER | SetWindowPosFlags::NOACTIVATE;
+        if repaint == 0 {
+            flags |= SetWindowPosFlags::NOREDRAW;
+        }
+        let rect = Rect {
+            left: x,
+            top: y,
+            right: x.saturating_add(width),
+            bottom: y.saturating_add(height),
+        };
+        let success = self.set_window_pos(&mut accessor, h_wnd, Handle::null(), rect, flags)?;
+        Ok(ReturnValue::U16(success.into()))
+    }
+
+    #[api_function]
+    #[allow(clippy::too_many_arguments)]
+    fn internal_set_window_pos(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        h_wnd_insert_after: Handle,
+        x: i16,
+        y: i16,
+        width: i16,
+        height: i16,
+        flags: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let rect = Rect {
+            left: x,
+            top: y,
+            right: x.saturating_add(width),
+            bottom: y.saturating_add(height),
+        };
+        let success = self.set_window_pos(
+            &mut accessor,
+            h_wnd,
+            h_wnd_insert_after,
+            rect,
+            SetWindowPosFlags::from_bits_truncate(flags),
+        )?;
+        Ok(ReturnValue::U16(success.into()))
+    }
+
+    #[api_function]
+    fn is_iconic(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let iconic = self
+            .style_of(h_wnd)
+            .is_some_and(|style| style.contains(WindowStyles::MINIMIZE));
+        Ok(ReturnValue::U16(iconic.into()))
+    }
+
+    #[api_function]
+    fn is_zoomed(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let zoomed = self
+            .style_of(h_wnd)
+            .is_some_and(|style| style.contains(WindowStyles::MAXIMIZE));
+        Ok(ReturnValue::U16(zoomed.into()))
+    }
+
+    #[api_function]
+    fn internal_enable_window(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        enable: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let was_enabled = self.enable_window(&mut accessor, h_wnd, enable != 0)?;
+        Ok(ReturnValue::U16((!was_enabled).into()))
+    }
+
+    #[api_function]
+    fn is_window_enabled(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let enabled = self
+            .style_of(h_wnd)
+            .is_some_and(|style| !style.contains(WindowStyles::DISABLED));
+        Ok(ReturnValue::U16(enabled.into()))
+    }
+
+    #[api_function]
+    fn internal_set_focus(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let previous = self.set_focus(&mut accessor, h_wnd)?;
+        Ok(ReturnValue::U16(previous.as_u16()))
+    }
+
+    #[api_function]
+    fn get_focus(&self) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(self.focus().as_u16()))
+    }
+
+    #[api_function]
+    fn set_active_window(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // Only top-level windows can be active
+        let is_top_level = matches!(
+            self.read_objects().user.get(h_wnd),
+            Some(UserObject::Window(user_window)) if user_window.parent_handle == Handle::null()
+        );
+        if !is_top_level {
+            return Ok(ReturnValue::U16(0));
+        }
+        let previous = self.activate_window(&mut accessor, h_wnd, ActivateState::Active)?;
+        Ok(ReturnValue::U16(previous.as_u16()))
+    }
+
+    #[api_function]
+    fn get_active_window(&self) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(self.active_window().as_u16()))
+    }
+
+    #[api_function]
+    fn get_parent(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let h_wnd_parent = match self.read_objects().user.get(h_wnd) {
+            // Popup windows give their owner instead
+            Some(UserObject::Window(user_window))
+                if user_window.parent_handle == Handle::null() =>
+            {
+                user_window.owner_handle
+            }
+            Some(UserObject::Window(user_window)) => user_window.parent_handle,
+            _ => Handle::null(),
+        };
+        Ok(ReturnValue::U16(h_wnd_parent.as_u16()))
+    }
+
+    #[api_function]
+    fn set_parent(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd_child: Handle,
+        h_wnd_new_parent: Handle,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // TODO: top-level windows have a bitmap of their own, so they don't become children
+        let (h_wnd_old_parent, old_rect) = {
+            let objects = self.read_objects();
+            let (h_wnd_old_parent, process_id) = match objects.user.get(h_wnd_child) {
+                Some(UserObject::Window(user_window)) => {
+                    (user_window.parent_handle, user_window.process_id)
+                }
+                _ => return Ok(ReturnValue::U16(0)),
+            };
+            // A null parent makes the window a top-level window
+            let can_be_parent = h_wnd_new_parent == Handle::null()
+                || matches!(
+                    objects.user.get(h_wnd_new_parent),
+                    Some(UserObject::Window(user_window)) if user_window.process_id == process_id
+                ) && !self.is_within(&objects, h_wnd_new_parent, h_wnd_child);
+            if h_wnd_old_parent == Handle::null() || !can_be_parent {
+                return Ok(ReturnValue::U16(0));
+            }
+            let old_rect = objects
+                .window_identifier(h_wnd_child)
+                .and_then(|window_identifier| {
+                    objects
+                        .read_window_manager()
+                        .window_rect_of(window_identifier)
+                });
+            (h_wnd_old_parent, old_rect)
+        };
+        if h_wnd_old_parent == h_wnd_new_parent {
+            return Ok(ReturnValue::U16(h_wnd_old_parent.as_u16()));
+        }
+
+        {
+            let mut objects = self.write_objects();
+            let old_parent_origin = self.client_screen_position_of(&objects, h_wnd_old_parent);
+            if let Some(UserObject::Window(old_parent)) = objects.user.get_mut(h_wnd_old_parent) {
+                old_parent.children.retain(|&child| child != h_wnd_child);
+            }
+            if let Some(UserObject::Window(new_parent)) = objects.user.get_mut(h_wnd_new_parent) {
+                new_parent.children.push(h_wnd_child);
+            }
+            if let Some(UserObject::Window(child)) = objects.user.get_mut(h_wnd_child) {
+                child.parent_handle = h_wnd_new_parent;
+            }
+            // A top-level window stays where it was on the screen, on a bitmap of its own
+            let window_identifier = objects.window_identifier(h_wnd_child);
+            let top_level = (h_wnd_new_parent == Handle::null()).then_some((
+                window_identifier,
+                old_rect,
+                old_parent_origin,
+            ));
+            if let Some((Some(window_identifier), Some(rect), Some(origin))) = top_level {
+                let mut window_manager = objects.write_window_manager();
+                window_manager.give_own_bitmap(window_identifier);
+                window_manager.set_window_rect(
+                    window_identifier,
+                    Point::new(rect.left, rect.top) + origin,
+                    rect.right - rect.left,
+                    rect.bottom - rect.top,
+                );
+                if window_manager.is_shown(window_identifier) {
+                    window_manager.show_window(window_identifier);
+                }
+            }
+        }
+        // A child window keeps its position, which is now in the client area of the new parent
+        if self.is_shown(&self.read_objects(), h_wnd_child) {
+            self.invalidate(h_wnd_old_parent, old_rect, true);
+            self.recursive_window_paint(&mut accessor, h_wnd_child);
+        }
+        Ok(ReturnValue::U16(h_wnd_old_parent.as_u16()))
+    }
+
+    #[api_function]
+    fn get_window(&self, h_wnd: Handle, command: u16) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        Ok(ReturnValue::U16(
+            self.related_window(&objects, h_wnd, command).as_u16(),
+        ))
+    }
+
+    #[api_function]
+    fn get_top_window(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        let h_wnd_top = self
+            .z_order(&objects, h_wnd)
+            .first()
+            .copied()
+            .unwrap_or(Handle::null());
+        Ok(ReturnValue::U16(h_wnd_top.as_u16()))
+    }
+
+    #[api_function]
+    fn get_next_window(&self, h_wnd: Handle, flag: u16) -> Result<ReturnValue, EmulatorError> {
+        if flag != GetWindowCommand::HwndNext.into() && flag != GetWindowCommand::HwndPrev.into() {
+            return Ok(ReturnValue::U16(0));
+        }
+        let objects = self.read_objects();
+        Ok(ReturnValue::U16(
+            self.related_window(&objects, h_wnd, flag).as_u16(),
+        ))
+    }
+
+    #[api_function]
+    fn is_window(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let exists = h_wnd.as_u16() == DESKTOP_WINDOW
+            || matches!(
+                self.read_objects().user.get(h_wnd),
+                Some(UserObject::Window(_))
+            );
+        Ok(ReturnValue::U16(exists.into()))
+    }
+
+    #[api_function]
+    fn is_child(&self, h_wnd_parent: Handle, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        let is_child = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                user_window.parent_handle != Handle::null()
+                    && self.is_within(&objects, user_window.parent_handle, h_wnd_parent)
+            }
+            _ => false,
+        };
+        Ok(ReturnValue::U16(is_child.into()))
+    }
+
+    #[api_function]
+    fn is_window_visible(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        if h_wnd.as_u16() == DESKTOP_WINDOW {
+            return Ok(ReturnValue::U16(1));
+        }
+        // A window is only visible when the windows it is in are shown too
+        let objects = self.read_objects();
+        let mut visible = false;
+        let mut h_wnd = h_wnd;
+        while let Some(UserObject::Window(user_window)) = objects.user.get(h_wnd) {
+            visible = self.is_shown(&objects, h_wnd);
+            if !visible {
+                break;
+            }
+            h_wnd = user_window.parent_handle;
+        }
+        Ok(ReturnValue::U16(visible.into()))
+    }
+
+    #[api_function]
+    fn enum_windows(
+        &self,
+        mut accessor: EmulatorAccessor,
+        enum_func: u32,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let windows = self.z_order(&self.read_objects(), Handle::null());
+        let completed = self.enumerate_windows(&mut accessor, windows, enum_func, l_param)?;
+        Ok(ReturnValue::U16(completed.into()))
+    }
+
+    #[api_function]
+    fn enum_child_windows(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd_parent: Handle,
+        enum_func: u32,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let windows = self.descendants_of(&self.read_objects(), h_wnd_parent);
+        let completed = self.enumerate_windows(&mut accessor, windows, enum_func, l_param)?;
+        Ok(ReturnValue::U16(completed.into()))
+    }
+
+    #[api_function]
+    fn find_window(
+        &self,
+        accessor: EmulatorAccessor,
+        class_name: u32,
+        window_name: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let class_name = if class_name == 0 {
+            None
+        } else {
+            match self.read_class_name(&accessor, class_name)? {
+                Some(class_name) => Some(class_name),
+                None => return Ok(ReturnValue::U16(0)),
+            }
+        };
+        let window_name = if window_name.0 == 0 {
+            None
+        } else {
+            Some(accessor.clone_string(window_name.0, false)?)
+        };
+        // Both names are compared without regard to case
+        let objects = self.read_objects();
+        let found = self
+            .z_order(&objects, Handle::null())
+            .into_iter()
+            .find(|&h_wnd| match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => {
+                    class_name.as_ref().is_none_or(|class_name| {
+                        class_name
+                            .as_slice()
+                            .eq_ignore_ascii_case(user_window.class_name.as_slice())
+                    }) && window_name.as_ref().is_none_or(|window_name| {
+                        window_name
+                            .as_slice()
+                            .eq_ignore_ascii_case(user_window.title.as_slice())
+                    })
+                }
+                _ => false,
+            });
+        Ok(ReturnValue::U16(found.unwrap_or(Handle::null()).as_u16()))
+    }
+
+    #[api_function]
+    fn internal_window_from_point(&self, point: u32) -> Result<ReturnValue, EmulatorError> {
+        let point = Point::new(point as i16, (point >> 16) as i16);
+        let top_level = self.read_objects().read_window_manager().window_at(point);
+        let h_wnd = top_level.map_or(Handle::null(), |window_identifier| {
+            self.window_from_point(window_identifier.window_handle, point)
+        });
+        Ok(ReturnValue::U16(h_wnd.as_u16()))
+    }
+
+    #[api_function]
+    fn child_window_from_point(
+        &self,
+        h_wnd_parent: Handle,
+        point: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let point = Point::new(point as i16, (point >> 16) as i16);
+        let objects = self.read_objects();
+        let inside = self
+            .get_client_rect(h_wnd_parent, &objects)
+            .is_some_and(|rect| rect.contains(point));
+        if !inside {
+            return Ok(ReturnValue::U16(0));
+        }
+        // Hidden and disabled children count too, unlike with WindowFromPoint
+        let children = self.z_order(&objects, h_wnd_parent);
+        let window_manager = objects.read_window_manager();
+        let child = children.into_iter().find(|&child| {
+            objects
+                .window_identifier(child)
+                .and_then(|window_identifier| window_manager.window_rect_of(window_identifier))
+                .is_some_and(|rect| rect.contains(point))
+        });
+        Ok(ReturnValue::U16(child.unwrap_or(h_wnd_parent).as_u16()))
+    }
+
+    #[api_function]
+    fn get_desktop_window(&self) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(DESKTOP_WINDOW))
+    }
+
+    #[api_function]
+    fn client_to_screen(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        point: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        self.map_window_points_in(&mut accessor, h_wnd, Handle::null(), point, 1)?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn internal_screen_to_client(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        point: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        self.map_window_points_in(&mut accessor, Handle::null(), h_wnd, point, 1)?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn map_window_points(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd_from: Handle,
+        h_wnd_to: Handle,
+        points: Pointer,
+        count: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        self.map_window_points_in(&mut accessor, h_wnd_from, h_wnd_to, points, count)?;
+        Ok(ReturnValue::None)
+    }
+
+    /// Converts points in memory from the client area of one window to that of another one.
+    /// Nothing changes if one of the windows doesn't exist.
+    fn map_window_points_in(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd_from: Handle,
+        h_wnd_to: Handle,
+        points: Pointer,
+        count: u16,
+    ) -> Result<(), EmulatorError> {
+        let offset = {
+            let objects = self.read_objects();
+            match (
+                self.mapping_origin_of(&objects, h_wnd_from),
+                self.mapping_origin_of(&objects, h_wnd_to),
+            ) {
+                (Some(from), Some(to)) => from - to,
+                _ => return Ok(()),
+            }
+        };
+        for index in 0..count as u32 {
+            let address = points.0 + index * 4;
+            let point = accessor.read_point(address)?;
+            accessor.write_point(address, point + offset)?;
+        }
+        Ok(())
+    }
+
+    #[api_function]
+    fn open_clipboard(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let opened = self.write_objects().clipboard.open(WindowIdentifier {
+            process_id: self.process_id(),
+            window_handle: h_wnd,
+        });
+        Ok(ReturnValue::U16(opened.into()))
+    }
+
+    #[api_function]
+    fn close_clipboard(&self) -> Result<ReturnValue, EmulatorError> {
+        let mut objects = self.write_objects();
+        let (closed, released) = objects.clipboard.close(self.process_id());
+        for handle in released {
+            objects.gdi.deregister(handle);
+        }
+        Ok(ReturnValue::U16(closed.into()))
+    }
+
+    #[api_function]
+    fn empty_clipboard(
+        &self,
+        mut accessor: EmulatorAccessor,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let owner = {
+            let objects = self.read_objects();
+            if !objects.clipboard.is_open_by(self.process_id()) {
+                return Ok(ReturnValue::U16(0));
+            }
+            objects.clipboard.owner()
+        };
+        if let Some(owner) = owner {
+            self.send_message(
+                &mut accessor,
+                owner.window_handle,
+                MessageType::DestroyClipboard.into(),
+                0,
+                0,
+            )?;
+        }
+        let mut objects = self.write_objects();
+        for handle in objects.clipboard.empty() {
+            objects.gdi.deregister(handle);
+        }
+        Ok(ReturnValue::U16(1))
+    }
+
+    #[api_function]
+    fn get_clipboard_owner(&self) -> Result<ReturnValue, EmulatorError> {
+        let owner = self
+            .read_objects()
+            .clipboard
+            .owner()
+            .map_or(Handle::null(), |owner| owner.window_handle);
+        Ok(ReturnValue::U16(owner.as_u16()))
+    }
+
+    #[api_function]
+    fn set_clipboard_data(
+        &self,
+        accessor: EmulatorAccessor,
+        format: u16,
+        h_mem: Handle,
+    ) -> Result<ReturnValue, EmulatorError> {
+        {
+            // The owner renders its formats while another task has the clipboard open
+            let objects = self.read_objects();
+            let is_owner = objects
+                .clipboard
+                .owner()
+                .is_some_and(|owner| owner.process_id == self.process_id());
+            if !objects.clipboard.is_open_by(self.process_id()) && !is_owner {
+                return Ok(ReturnValue::U16(0));
+            }
+        }
+        let data = if h_mem == Handle::null() {
+            ClipboardData::Delayed
+        } else if clipboard::is_object_format(format) {
+            ClipboardData::Object(h_mem)
+        } else {
+            // The clipboard takes the block over, so that other tasks can get it
+            let kernel = accessor.kernel();
+            let (address, size) = match kernel.global_block(h_mem) {
+                Some(block) => block,
+                None => return Ok(ReturnValue::U16(0)),
+            };
+            let bytes = accessor.memory().slice(address, address + size)?.into();
+            kernel.free_global(h_mem);
+            ClipboardData::Memory(bytes)
+        };
+        let mut objects = self.write_objects();
+        if let Some(released) = objects.clipboard.set(format, data) {
+            if released != h_mem {
+                objects.gdi.deregister(released);
+            }
+        }
+        Ok(ReturnValue::U16(h_mem.as_u16()))
+    }
+
+    #[api_function]
+    fn get_clipboard_data(
+        &self,
+        mut accessor: EmulatorAccessor,
+        format: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let (owner, delayed) = {
+            let objects = self.read_objects();
+            if !objects.clipboard.is_open_by(self.process_id()) {
+                return Ok(ReturnValue::U16(0));
+            }
+            (
+                objects.clipboard.owner(),
+                matches!(
+                    objects.clipboard.get(format).as_deref(),
+                    Some(ClipboardData::Delayed)
+                ),
+            )
+        };
+        if let (Some(owner), true) = (owner, delayed) {
+            self.send_message(
+                &mut accessor,
+                owner.window_handle,
+                MessageType::RenderFormat.into(),
+                format,
+                0,
+            )?;
+        }
+
+        let (sequence_number, bytes) = {
+            let objects = self.read_objects();
+            match objects.clipboard.get(format).as_deref() {
+                Some(ClipboardData::Memory(bytes)) => {
+                    (objects.clipboard.sequence_number(), bytes.clone())
+                }
+                Some(ClipboardData::Object(handle)) => {
+                    return Ok(ReturnValue::U16(handle.as_u16()))
+                }
+                _ => return Ok(ReturnValue::U16(0)),
+            }
+        };
+        let kernel = accessor.kernel();
+        let mut clipboard_copies = self.clipboard_copies.borrow_mut();
+        if clipboard_copies.0 != sequence_number {
+            for (_, h_mem) in clipboard_copies.1.drain() {
+                kernel.free_global(h_mem);
+            }
+            clipboard_copies.0 = sequence_number;
+        }
+        if let Some(&h_mem) = clipboard_copies.1.get(&format) {
+            return Ok(ReturnValue::U16(h_mem.as_u16()));
+        }
+        let h_mem = match kernel.allocate_global(&mut accessor, 0, bytes.len() as u32)? {
+            Some(h_mem) => h_mem,
+            None => return Ok(ReturnValue::U16(0)),
+        };
+        if let Some((address, _)) = kernel.global_block(h_mem) {
+            accessor.memory_mut().copy_from(&bytes, address as usize)?;
+        }
+        clipboard_copies.1.insert(format, h_mem);
+        Ok(ReturnValue::U16(h_mem.as_u16()))
+    }
+
+    #[api_function]
+    fn count_clipboard_formats(&self) -> Result<ReturnValue, EmulatorError> {
+        let count = self.read_objects().clipboard.formats().len();
+        Ok(ReturnValue::U16(count as u16))
+    }
+
+    #[api_function]
+    fn enum_clipboard_formats(&self, format: u16) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        if !objects.clipboard.is_open_by(self.process_id()) {
+            return Ok(ReturnValue::U16(0));
+        }
+        let formats = objects.clipboard.formats();
+        let next = if format == 0 {
+            formats.first()
+        } else {
+            formats.iter().skip_while(|&&other| other != format).nth(1)
+        };
+        Ok(ReturnValue::U16(next.copied().unwrap_or(0)))
+    }
+
+    #[api_function]
+    fn register_clipboard_format(
+        &self,
+        accessor: EmulatorAccessor,
+        name: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let name = accessor.clone_string(name.0, false)?;
+        let format = self.write_objects().clipboard.register_format(name);
+        Ok(ReturnValue::U16(format.unwrap_or(0)))
+    }
+
+    #[api_function]
+    fn get_clipboard_format_name(
+        &self,
+        mut accessor: EmulatorAccessor,
+        format: u16,
+        name: Pointer,
+        max_count: i16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let format_name = match self.read_objects().clipboard.format_name(format) {
+            Some(format_name) => format_name.as_slice().to_vec(),
+            None => return Ok(ReturnValue::U16(0)),
+        };
+        if max_count <= 0 {
+            return Ok(ReturnValue::U16(0));
+        }
+        let length = format_name.len().min(max_count as usize - 1);
+        write_string(&mut accessor, name.0, &format_name[..length])?;
+        Ok(ReturnValue::U16(length as u16))
+    }
+
+    #[api_function]
+    fn is_clipboard_format_available(&self, format: u16) -> Result<ReturnValue, EmulatorError> {
+        let available = self.read_objects().clipboard.get(format).is_some();
+        Ok(ReturnValue::U16(available.into()))
+    }
+
+    /// Reads a word or a long of what USER keeps about a window. The negative indices stand for
+    /// the GWW_ and GWL_ values, the others are offsets into the extra bytes.
+    pub fn window_value(&self, h_wnd: Handle, index: i16, size: usize) -> Option<u32> {
+        let objects = self.read_objects();
+        let user_window = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window,
+            _ => return None,
+        };
+        let value = match index {
+            GWL_WNDPROC if size == 4 => {
+                encode_u16_u16_to_u32(user_window.proc.offset, user_window.proc.segment)
+            }
+            GWW_HINSTANCE => user_window.h_instance.as_u16().into(),
+            // Top-level windows have an owner instead of a parent
+            GWW_HWNDPARENT if user_window.parent_handle == Handle::null() => {
+                user_window.owner_handle.as_u16().into()
+            }
+            GWW_HWNDPARENT => user_window.parent_handle.as_u16().into(),
+            GWW_ID => user_window.menu.as_u16().into(),
+            GWL_STYLE if size == 4 => {
+                user_window.style.bits() | user_window.class_specific_style as u32
+            }
+            GWL_EXSTYLE if size == 4 => user_window.ex_style,
+            DWL_DLGPROC if size == 4 && user_window.dialog.is_some() => {
+                let proc = user_window.dialog.as_ref()?.proc;
+                encode_u16_u16_to_u32(proc.offset, proc.segment)
+            }
+            index if index >= 0 => read_le_value(&user_window.extra, index as usize, size)?,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Changes a word or a long of what USER keeps about a window, like `window_value` reads
+    /// it. Returns the value it had before.
+    fn set_window_value(&self, h_wnd: Handle, index: i16, size: usize, value: u32) -> Option<u32> {
+        let previous = self.window_value(h_wnd, index, size)?;
+        let mut objects = self.write_objects();
+        let user_window = match objects.user.get_mut(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window,
+            _ => return None,
+        };
+        let proc = SegmentAndOffset {
+            segment: (value >> 16) as u16,
+            offset: value as u16,
+        };
+        match index {
+            GWL_WNDPROC if size == 4 => user_window.proc = proc,
+            GWW_HINSTANCE => user_window.h_instance = (value as u16).into(),
+            // Child windows only move to another parent with SetParent
+            GWW_HWNDPARENT if user_window.parent_handle == Handle::null() => {
+                user_window.owner_handle = (value as u16).into()
+            }
+            GWW_HWNDPARENT => {}
+            GWW_ID => user_window.menu = (value as u16).into(),
+            GWL_STYLE if size == 4 => {
+                user_window.style = WindowStyles::from_bits_truncate(value);
+                user_window.class_specific_style = value as u16;
+            }
+            GWL_EXSTYLE if size == 4 => user_window.ex_style = value,
+            DWL_DLGPROC if size == 4 && user_window.dialog.is_some() => {
+                user_window.dialog.as_mut()?.proc = proc
+            }
+            index if index >= 0 => {
+                replace_le_value(&mut user_window.extra, index as usize, size, value)?;
+            }
+            _ => return None,
+        }
+        Some(previous)
+    }
+
+    #[api_function]
+    fn get_window_word(&self, h_wnd: Handle, index: i16) -> Result<ReturnValue, EmulatorError> {
+        let value = self.window_value(h_wnd, index, 2).unwrap_or(0);
+        Ok(ReturnValue::U16(value as u16))
+    }
+
+    #[api_function]
+    fn set_window_word(
+        &self,
+        h_wnd: Handle,
+        index: i16,
+        new_word: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let previous = self
+            .set_window_value(h_wnd, index, 2, new_word.into())
+            .unwrap_or(0);
+        Ok(ReturnValue::U16(previous as u16))
+    }
+
+    #[api_function]
+    fn get_window_long(&self, h_wnd: Handle, index: i16) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U32(
+            self.window_value(h_wnd, index, 4).unwrap_or(0),
+        ))
+    }
+
+    #[api_function]
+    fn set_window_long(
+        &self,
+        h_wnd: Handle,
+        index: i16,
+        new_long: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U32(
+            self.set_window_value(h_wnd, index, 4, new_long)
+                .unwrap_or(0),
+        ))
+    }
+
+    /// Reads a class name that is either a string or an atom without a segment. Returns None for
+    /// an atom that doesn't exist.
+    fn read_class_name(
+        &self,
+        accessor: &EmulatorAccessor,
+        class_name: u32,
+    ) -> Result<Option<HeapByteString>, EmulatorError> {
+        if class_name >> 16 == 0 {
+            let objects = self.read_objects();
+            Ok(objects
+                .atoms
+                .get((class_name as u16).into())
+                .map(|name| HeapByteString::from(name.as_slice().into())))
+        } else {
+            accessor
+                .clone_string(Pointer::from_far(class_name).0, false)
+                .map(Some)
+        }
+    }
+
+    /// The name of the class a window of this task was created from. The classes of other
+    /// tasks aren't known here.
+    fn class_name_of(&self, h_wnd: Handle) -> Option<HeapByteString> {
+        match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window))
+                if user_window.process_id == self.process_id() =>
+            {
+                Some(user_window.class_name.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads a word or a long of the class of a window. The negative indices stand for the GCW_
+    /// and GCL_ values, the others are offsets into the extra bytes of the class.
+    fn class_value(&self, h_wnd: Handle, index: i16, size: usize) -> Option<u32> {
+        let class_name = self.class_name_of(h_wnd)?;
+        let window_classes = self.window_classes.borrow();
+        let class = window_classes.get(&ByteString::from_slice(class_name.as_slice()))?;
+        let value = match index {
+            GCW_HBRBACKGROUND => class.h_background.as_u16().into(),
+            GCW_HCURSOR => class.h_cursor.as_u16().into(),
+            GCW_HICON => class.h_icon.as_u16().into(),
+            GCW_HMODULE => self.task.h_module.as_u16().into(),
+            GCW_CBWNDEXTRA => class.wnd_extra.into(),
+            GCW_CBCLSEXTRA => class.cls_extra.into(),
+            GCL_WNDPROC if size == 4 => {
+                encode_u16_u16_to_u32(class.proc.offset, class.proc.segment)
+            }
+            GCW_STYLE => class.style.bits().into(),
+            index if index >= 0 => read_le_value(&class.extra, index as usize, size)?,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Changes a word or a long of the class of a window, like `class_value` reads it. The
+    /// windows already created from the class get the new style and background too.
+    /// Returns the value it had before.
+    fn set_class_value(&self, h_wnd: Handle, index: i16, size: usize, value: u32) -> Option<u32> {
+        let previous = self.class_value(h_wnd, index, size)?;
+        let class_name = self.class_name_of(h_wnd)?;
+        let mut window_classes = self.window_classes.borrow_mut();
+        let class = window_classes.get_mut(&class_name.clone().into())?;
+        match index {
+            GCW_HBRBACKGROUND => class.h_background = (value as u16).into(),
+            GCW_HCURSOR => class.h_cursor = (value as u16).into(),
+            GCW_HICON => class.h_icon = (value as u16).into(),
+            GCL_WNDPROC if size == 4 => {
+                class.proc = SegmentAndOffset {
+                    segment: (value >> 16) as u16,
+                    offset: value as u16,
+                }
+            }
+            GCW_STYLE => class.style = ClassStyles::from_bits_truncate(value as u16),
+            index if index >= 0 => {
+                replace_le_value(&mut class.extra, index as usize, size, value)?;
+            }
+            _ => return None,
+        }
+        let (class_style, background) = (class.style, class.h_background);
+        drop(window_classes);
+
+        let mut objects = self.write_objects();
+        for (_, object) in objects.user.iter_mut() {
+            if let UserObject::Window(user_window) = object {
+                if user_window.process_id == self.process_id()
+                    && user_window.class_name.as_slice() == class_name.as_slice()
+                {
+                    user_window.class_style = class_style;
+                    user_window.background = background;
+                }
+            }
+        }
+        Some(previous)
+    }
+
+    #[api_function]
+    fn get_class_word(&self, h_wnd: Handle, index: i16) -> Result<ReturnValue, EmulatorError> {
+        let value = self.class_value(h_wnd, index, 2).unwrap_or(0);
+        Ok(ReturnValue::U16(value as u16))
+    }
+
+    #[api_function]
+    fn set_class_word(
+        &self,
+        h_wnd: Handle,
+        index: i16,
+        new_word: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let previous = self
+            .set_class_value(h_wnd, index, 2, new_word.into())
+            .unwrap_or(0);
+        Ok(ReturnValue::U16(previous as u16))
+    }
+
+    #[api_function]
+    fn get_class_long(&self, h_wnd: Handle, index: i16) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U32(
+            self.class_value(h_wnd, index, 4).unwrap_or(0),
+        ))
+    }
+
+    #[api_function]
+    fn set_class_long(
+        &self,
+        h_wnd: Handle,
+        index: i16,
+        new_long: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U32(
+            self.set_class_value(h_wnd, index, 4, new_long).unwrap_or(0),
+        ))
+    }
+
+    #[api_function]
+    fn call_window_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        prev_wnd_func: u32,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let proc = SegmentAndOffset {
+            segment: (prev_wnd_func >> 16) as u16,
+            offset: prev_wnd_func as u16,
+        };
+        let result = self.call_wndproc_sync(&mut accessor, proc, h_wnd, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+
+    pub fn window_proc_of(&self, h_wnd: Handle) -> Option<SegmentAndOffset> {
+        match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => Some(user_window.proc),
+            _ => None,
+        }
+    }
+
+    fn style_of(&self, h_wnd: Handle) -> Option<WindowStyles> {
+        match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => Some(user_window.style),
+            _ => None,
+        }
+    }
+
+    /// Draws a window with its children right away: the frame first, and then all of the client
+    /// area.
+    fn recursive_window_paint(&self, accessor: &mut EmulatorAccessor, h_wnd: Handle) -> bool {
+        println!("recursive window paint: {:?}", h_wnd);
+        let result = self.paint_frame(accessor, h_wnd);
+        self.invalidate(h_wnd, None, true);
+        result
+            .and_then(|_| self.update_now(accessor, h_wnd))
+            .is_ok()
+    }
+
+    /// Makes a window draw its frame and all of its client area again, once it gets to painting.
+    fn redraw(&self, h_wnd: Handle) {
+        if let Some(UserObject::Window(user_window)) = self.write_objects().user.get_mut(h_wnd) {
+            user_window.redraw_frame = true;
+        }
+        self.invalidate(h_wnd, None, true);
+    }
+
+    /// Lets a window draw its frame with WM_NCPAINT.
+    fn paint_frame(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<(), EmulatorError> {
+        if let Some(UserObject::Window(user_window)) = self.write_objects().user.get_mut(h_wnd) {
+            user_window.redraw_frame = false;
+        }
+        // A region of 1 stands for the whole window
+        self.send_message(accessor, h_wnd, MessageType::NcPaint.into(), 1, 0)?;
+        Ok(())
+    }
+
+    /// Sends WM_PAINT to a window and to those of its children that need to be painted, parents
+    /// first, without waiting for them to get it from the message queue.
+    pub fn update_now(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<(), EmulatorError> {
+        let window = {
+            let objects = self.read_objects();
+            match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) if self.is_shown(&objects, h_wnd) => Some((
+                    Self::needs_painting(user_window),
+                    user_window.children.clone(),
+                )),
+                _ => None,
+            }
+        };
+        let (needs_painting, children) = match window {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        if needs_painting {
+            self.send_message(accessor, h_wnd, MessageType::Paint.into(), 0, 0)?;
+        }
+        // Children cover their parent, so they are painted after it
+        for child in children {
+            self.update_now(accessor, child)?;
+        }
+        Ok(())
+    }
+
+    fn needs_painting(user_window: &UserWindow) -> bool {
+        user_window.update_region.is_some() || user_window.redraw_frame
+    }
+
+    /// Adds a rectangle of the client area of a window, or all of it, to the update region of
+    /// the window. Children are painted over their parent, so those that the rectangle overlaps
+    /// get invalidated as well, frame included.
+    fn invalidate(&self, h_wnd: Handle, rect: Option<Rect>, erase: bool) {
+        let mut objects = self.write_objects();
+        self.invalidate_in(&mut objects, h_wnd, rect, erase);
+    }
+
+    fn invalidate_in(
+        &self,
+        objects: &mut ObjectEnvironment,
+        h_wnd: Handle,
+        rect: Option<Rect>,
+        erase: bool,
+    ) {
+        let client_rect = match self.get_client_rect(h_wnd, objects) {
+            Some(client_rect) => client_rect,
+            None => return,
+        };
+        let rect = rect.map_or(client_rect, |rect| rect.intersect(&client_rect));
+        if rect.is_empty() {
+            return;
+        }
+        match objects.user.get_mut(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                user_window.update_region = Some(
+                    user_window
+                        .update_region
+                        .map_or(rect, |region| region.union(&rect)),
+                );
+                user_window.erase_background |= erase;
+            }
+            _ => return,
+        }
+        self.invalidate_children_in(objects, h_wnd, rect, erase);
+    }
+
+    /// Invalidates the parts of the children of a window that a rectangle of its client area
+    /// overlaps.
+    fn invalidate_children_in(
+        &self,
+        objects: &mut ObjectEnvironment,
+        h_wnd: Handle,
+        rect: Rect,
+        erase: bool,
+    ) {
+        let children = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.children.clone(),
+            _ => return,
+        };
+        for child in children {
+            // The window rectangle of a child is relative to the client area of its parent
+            let placement = objects.window_identifier(child).and_then(|identifier| {
+                let window_manager = objects.read_window_manager();
+                if !window_manager.is_shown(identifier) {
+                    return None;
+                }
+                Some((
+                    window_manager.window_rect_of(identifier)?,
+                    window_manager.client_origin_of(identifier)?,
+                ))
+            });
+            let (window_rect, client_origin) = match placement {
+                Some(placement) => placement,
+                None => continue,
+            };
+            if window_rect.intersect(&rect).is_empty() {
+                continue;
+            }
+            if let Some(UserObject::Window(child_window)) = objects.user.get_mut(child) {
+                child_window.redraw_frame = true;
+            }
+            let origin = Point::new(window_rect.left, window_rect.top) + client_origin;
+            self.invalidate_in(
+                objects,
+                child,
+                Some(rect.offset(-origin.x, -origin.y)),
+                erase,
+            );
+        }
+    }
+
+    /// Takes a rectangle of the client area of a window, or all of it, out of the update region
+    /// of the window.
+    fn validate(&self, h_wnd: Handle, rect: Option<Rect>) {
+        if let Some(UserObject::Window(user_window)) = self.write_objects().user.get_mut(h_wnd) {
+            // The update region is a single rectangle, so it only gets smaller once all of it is
+            // validated
+            let validated = match (user_window.update_region, rect) {
+                (_, None) => {
+                    user_window.redraw_frame = false;
+                    true
+                }
+                (Some(region), Some(rect)) => {
+                    rect.left <= region.left
+                        && rect.top <= region.top
+                        && rect.right >= region.right
+                        && rect.bottom >= region.bottom
+                }
+                (None, Some(_)) => false,
+            };
+            if validated {
+                user_window.update_region = None;
+                user_window.erase_background = false;
+            }
+        }
+    }
+
+    /// The first window that needs to be painted, looking through the windows of the task from
+    /// top-level windows down to their children. With a filter only that window is looked at.
+    fn window_to_paint(&self, h_wnd: Handle) -> Option<Handle> {
+        let objects = self.read_objects();
+        let mut pending = if h_wnd == Handle::null() {
+            let mut top_level_windows = objects
+                .user
+                .iter()
+                .filter_map(|(handle, object)| match object {
+                    UserObject::Window(user_window)
+                        if user_window.process_id == self.process_id()
+                            && user_window.parent_handle == Handle::null() =>
+                    {
+                        Some(handle)
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            // The windows are taken from the back, so the oldest window goes first
+            top_level_windows.sort_by_key(|handle| std::cmp::Reverse(handle.as_u16()));
+            top_level_windows
+        } else {
+            vec![h_wnd]
+        };
+        while let Some(handle) = pending.pop() {
+            let user_window = match objects.user.get(handle) {
+                Some(UserObject::Window(user_window)) if self.is_shown(&objects, handle) => {
+                    user_window
+                }
+                _ => continue,
+            };
+            if Self::needs_painting(user_window) {
+                return Some(handle);
+            }
+            if h_wnd == Handle::null() {
+                pending.extend(user_window.children.iter().rev());
+            }
+        }
+        None
+    }
+
+    /// Reads the rectangle that a pointer points to, where a null pointer stands for all of the
+    /// client area.
+    fn read_optional_rect(
+        accessor: &EmulatorAccessor,
+        rect: Pointer,
+    ) -> Result<Option<Rect>, EmulatorError> {
+        if rect.0 == 0 {
+            Ok(None)
+        } else {
+            accessor.read_rect(rect.0).map(Some)
+        }
+    }
+
+    #[api_function]
+    fn update_window(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<ReturnValue, EmulatorError> {
+        self.update_now(&mut accessor, h_wnd)?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn invalidate_rect(
+        &self,
+        accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        rect: Pointer,
+        erase: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let rect = Self::read_optional_rect(&accessor, rect)?;
+        self.invalidate(h_wnd, rect, erase != 0);
+        Ok(ReturnValue::None)
+    }
+
+    /// The rectangle of a region, where the null handle stands for all of the client area.
+    /// Returns None for handles that are not regions.
+    fn region_rect(&self, h_rgn: Handle) -> Option<Option<Rect>> {
+        if h_rgn == Handle::null() {
+            return Some(None);
+        }
+        match self.read_objects().gdi.get(h_rgn) {
+            Some(GdiObject::Region(rect)) => Some(Some(*rect)),
+            _ => None,
+        }
+    }
+
+    #[api_function]
+    fn invalidate_rgn(
+        &self,
+        h_wnd: Handle,
+        h_rgn: Handle,
+        erase: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        if let Some(rect) = self.region_rect(h_rgn) {
+            self.invalidate(h_wnd, rect, erase != 0);
+        }
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn validate_rect(
+        &self,
+        accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        rect: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let rect = Self::read_optional_rect(&accessor, rect)?;
+        self.validate(h_wnd, rect);
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn validate_rgn(&self, h_wnd: Handle, h_rgn: Handle) -> Result<ReturnValue, EmulatorError> {
+        if let Some(rect) = self.region_rect(h_rgn) {
+            self.validate(h_wnd, rect);
+        }
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn get_update_rect(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        rect: Pointer,
+        erase: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let (region, erase_background) = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                (user_window.update_region, user_window.erase_background)
+            }
+            _ => (None, false),
+        };
+        if let Some(region) = region.filter(|_| erase != 0 && erase_background) {
+            if let Some(UserObject::Window(user_window)) = self.write_objects().user.get_mut(h_wnd)
+            {
+                user_window.erase_background = false;
+            }
+            if let Some(hdc) = self.get_clipped_dc(h_wnd, region) {
+                self.send_message(
+                    &mut accessor,
+                    h_wnd,
+                    MessageType::EraseBkGnd.into(),
+                    hdc.as_u16(),
+                    0,
+                )?;
+                self.release_dc(h_wnd, hdc);
+            }
+        }
+        if rect.0 != 0 {
+            accessor.write_rect(rect.0, &region.unwrap_or_else(Rect::zero))?;
+        }
+        Ok(ReturnValue::U16(region.is_some().into()))
+    }
+
+    #[api_function]
+    fn register_class(
+        &self,
+        accessor: EmulatorAccessor,
+        wnd_class_ptr: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let wnd_class_style = accessor.memory().read_u16(wnd_class_ptr.0)?;
+        let wnd_class_proc_offset = accessor.memory().read_u16(wnd_class_ptr.0 + 2)?;
+        let wnd_class_proc_segment = accessor.memory().read_u16(wnd_class_ptr.0 + 4)?;
+        let wnd_class_cls_extra = accessor.memory().read_u16(wnd_class_ptr.0 + 6)?;
+        let wnd_class_wnd_extra = accessor.memory().read_u16(wnd_class_ptr.0 + 8)?;
+        let _wnd_class_h_instance = accessor.memory().read_u16(wnd_class_ptr.0 + 10)?;
         let wnd_class_h_icon = accessor.memory().read_u16(wnd_class_ptr.0 + 12)?;
         let wnd_class_h_cursor = accessor.memory().read_u16(wnd_class_ptr.0 + 14)?;
         let wnd_class_h_background = accessor.memory().read_u16(wnd_class_ptr.0 + 16)?;
-        let wnd_class_menu_name = accessor.memory().flat_pointer_read(wnd_class_ptr.0 + 18)?;
+        let wnd_class_menu_name = accessor.memory().read_32(wnd_class_ptr.0 + 18)?;
         let wnd_class_class_name = accessor.memory().flat_pointer_read(wnd_class_ptr.0 + 22)?;
 
-        let cloned_class_name = accessor.clone_string(wnd_class_class_name, false)?;
-        if let Some(atom) = self
-            .user_atom_table
-            .register(cloned_class_name.clone().into())
-        {
-            let window_class = WindowClass {
-                style: ClassStyles::from_bits_truncate(wnd_class_style),
-                proc: SegmentAndOffset {
-                    segment: wnd_class_proc_segment,
-                    offset: wnd_class_proc_offset,
-                },
-                cls_extra: wnd_class_cls_extra,
-                wnd_extra: wnd_class_wnd_extra,
-                h_icon: wnd_class_h_icon.into(),
-                h_cursor: wnd_class_h_cursor.into(),
-                h_background: wnd_class_h_background.into(),
-                menu_class_name: if wnd_class_menu_name != 0 {
-                    Some(accessor.clone_string(wnd_class_menu_name, false)?.into())
-                } else {
-                    None
-                },
+        let cloned_class_name = accessor.clone_string(wnd_class_class_name, false)?;
+        let atom = self
+            .write_objects()
+            .atoms
+            .register(cloned_class_name.clone().into());
+        if let Some(atom) = atom {
+            let window_class = WindowClass {
+                style: ClassStyles::from_bits_truncate(wnd_class_style),
+                proc: SegmentAndOffset {
+                    segment: wnd_class_proc_segment,
+                    offset: wnd_class_proc_offset,
+                },
+                cls_extra: wnd_class_cls_extra,
+                wnd_extra: wnd_class_wnd_extra,
+                h_icon: wnd_class_h_icon.into(),
+                h_cursor: wnd_class_h_cursor.into(),
+                h_background: wnd_class_h_background.into(),
+                menu_name: if wnd_class_menu_name != 0 {
+                    Some(accessor.resource_name(wnd_class_menu_name)?)
+                } else {
+                    None
+                },
+                extra: vec![0; wnd_class_cls_extra as usize],
+            };
+
+            debug!(
+                "[user] REGISTER CLASS SUCCESS {:?} => {:#?}",
+                cloned_class_name, window_class
+            );
+            if self
+                .window_classes
+                .borrow_mut()
+                .insert(cloned_class_name.into(), window_class)
+                .is_none()
+            {
+                return Ok(ReturnValue::U16(atom.as_u16()));
+            }
+
+            self.write_objects().atoms.deregister(atom);
+        }
+
+        Ok(ReturnValue::U16(0))
+    }
+
+    #[api_function]
+    fn get_message(
+        &self,
+        mut accessor: EmulatorAccessor,
+        msg: u32,
+        h_wnd: Handle,
+        msg_filter_min: u16,
+        msg_filter_max: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let filter = MessageFilter {
+            h_wnd,
+            min: msg_filter_min,
+            max: msg_filter_max,
+        };
+        let message = self.wait_for_message(&mut accessor, filter)?;
+        self.write_message(&mut accessor, Pointer::from_far(msg), &message)?;
+        self.call_hook(
+            &mut accessor,
+            WH_GETMESSAGE,
+            HC_ACTION,
+            0,
+            msg,
+            Some(HookParameter::of_size(MSG_SIZE)),
+        )?;
+        Ok(ReturnValue::U16(
+            (message.message != MessageType::Quit.into()).into(),
+        ))
+    }
+
+    #[api_function]
+    fn post_quit_message(&self, exit_code: u16) -> Result<ReturnValue, EmulatorError> {
+        // The message loop ends once it gets to WM_QUIT, after the messages posted before it
+        self.message_queue.post(WindowMessage {
+            h_wnd: Handle::null(),
+            message: MessageType::Quit,
+            w_param: exit_code,
+            l_param: 0,
+            time: self.read_objects().timers.tick_count(),
+            point: Point::origin(),
+        });
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn peek_message(
+        &self,
+        mut accessor: EmulatorAccessor,
+        msg: u32,
+        h_wnd: Handle,
+        msg_filter_min: u16,
+        msg_filter_max: u16,
+        remove_msg: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let filter = MessageFilter {
+            h_wnd,
+            min: msg_filter_min,
+            max: msg_filter_max,
+        };
+        // Peeking gives the other tasks a chance to run, even if there are messages waiting
+        self.scheduler.yield_now(self.process_id());
+        if let Some(message) = self.receive_message(&mut accessor, filter, (remove_msg & 1) == 1)? {
+            self.write_message(&mut accessor, Pointer::from_far(msg), &message)?;
+            self.call_hook(
+                &mut accessor,
+                WH_GETMESSAGE,
+                HC_ACTION,
+                0,
+                msg,
+                Some(HookParameter::of_size(MSG_SIZE)),
+            )?;
+            Ok(ReturnValue::U16(1))
+        } else {
+            Ok(ReturnValue::U16(0))
+        }
+    }
+
+    /// Takes the next message from the queue, and waits for one if there is none.
+    /// Other tasks get to run while this task waits for a message, or for its next timer.
+    pub fn wait_for_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        filter: MessageFilter,
+    ) -> Result<PostedMessage, EmulatorError> {
+        loop {
+            if let Some(message) = self.receive_message(accessor, filter, true)? {
+                return Ok(message);
+            }
+            let next_timer_due = self.read_objects().timers.next_due(self.process_id());
+            match next_timer_due {
+                Some(deadline) => {
+                    self.scheduler.wait_until(self.process_id(), deadline);
+                }
+                None => {
+                    self.scheduler.wait(self.process_id());
+                }
+            }
+        }
+    }
+
+    /// Gets the next message that the filter accepts from the queue. The messages that other
+    /// tasks sent get handled first. Once the queue is empty, the next message comes from the
+    /// input, then from a window that needs painting, and only then from an elapsed timer.
+    fn receive_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        filter: MessageFilter,
+        remove: bool,
+    ) -> Result<Option<PostedMessage>, EmulatorError> {
+        self.handle_sent_messages(accessor)?;
+        let message = loop {
+            let message = if remove {
+                self.message_queue.receive(filter)
+            } else {
+                self.message_queue.peek(filter)
+            };
+            if let Some(found) = message {
+                if self.is_discarded_by_hook(accessor, &found, remove)? {
+                    if !remove {
+                        self.message_queue.receive(filter);
+                    }
+                    continue;
+                }
+                break message;
+            }
+            // The message made from the input goes via the queue, so peeking leaves it there,
+            // and so it waits for the right filter if it is for another window.
+            match self.message_queue.receive_input() {
+                Some(input) => {
+                    self.update_key_states(&input);
+                    let message = if input.message.is_key_message() {
+                        self.cook_key_input(input)
+                    } else {
+                        self.cook_mouse_input(accessor, input)?
+                    };
+                    if let Some(message) = message {
+                        self.message_queue.post(message);
+                    }
+                }
+                None => break self.synthesized_message(filter, remove),
+            }
+        };
+        // Whatever the message is, GetMessageTime and GetMessagePos tell when it was read, and
+        // where the cursor was at the time
+        if message.is_some() {
+            let objects = self.read_objects();
+            self.last_message_time_and_point
+                .set((objects.timers.tick_count(), objects.cursor_position));
+        }
+        Ok(message)
+    }
+
+    /// The messages that aren't in the queue, but get made once it is empty: WM_PAINT for a
+    /// window that needs painting, and WM_TIMER for an elapsed timer.
+    fn synthesized_message(&self, filter: MessageFilter, remove: bool) -> Option<PostedMessage> {
+        // WM_PAINT stays until the window validates its update region, so it is never removed
+        if filter.accepts_message(MessageType::Paint.into()) {
+            if let Some(h_wnd) = self.window_to_paint(filter.h_wnd) {
+                return Some(PostedMessage {
+                    h_wnd,
+                    message: MessageType::Paint.into(),
+                    w_param: 0,
+                    l_param: 0,
+                    time: self.read_objects().timers.tick_count(),
+                    point: Point::origin(),
+                });
+            }
+        }
+        if !filter.accepts_message(MessageType::Timer.into()) {
+            return None;
+        }
+        self.write_objects()
+            .timers
+            .elapsed_timer_message(self.process_id(), filter.h_wnd, remove)
+            .map(Into::into)
+    }
+
+    /// Lets the window procedures handle the messages that other tasks sent to this task, and
+    /// the hooks of this task the calls that other tasks made, and wakes up the senders once the
+    /// replies are there.
+    fn handle_sent_messages(&self, accessor: &mut EmulatorAccessor) -> Result<(), EmulatorError> {
+        while let Some(sent) = self.message_queue.receive_sent() {
+            let data = sent.data.as_deref();
+            let result = match &sent.call {
+                SentCall::Message(message) => {
+                    let was_in_send_message = self.in_send_message.replace(true);
+                    let result = self.call_sent_message(accessor, message, data);
+                    self.in_send_message.set(was_in_send_message);
+                    result
+                }
+                SentCall::Hook(call) => self.call_sent_hook(accessor, call, sent.sender, data),
+            };
+            let (value, data) = result.as_ref().ok().cloned().unwrap_or_default();
+            sent.reply.set(value, data);
+            self.scheduler.wake(sent.sender);
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Lets the window procedure handle a message that another task sent. The copy of the memory
+    /// that lParam pointed to in the sender lives on the stack of this task for as long as the
+    /// call takes, and goes back to the sender with the result.
+    fn call_sent_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        message: &PostedMessage,
+        data: Option<&[u8]>,
+    ) -> Result<(u32, Vec<u8>), EmulatorError> {
+        self.call_with_copy(accessor, message.l_param, data, &[], |accessor, l_param| {
+            self.send_message(
+                accessor,
+                message.h_wnd,
+                message.message,
+                message.w_param,
+                l_param,
+            )
+        })
+    }
+
+    /// Lets a hook of this task handle a call that another task made for its own messages. The
+    /// copy of the memory that lParam pointed to in the sender lives on the stack of this task
+    /// for as long as the call takes, and goes back to the sender with the result.
+    fn call_sent_hook(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        call: &HookCall,
+        sender: ProcessId,
+        data: Option<&[u8]>,
+    ) -> Result<(u32, Vec<u8>), EmulatorError> {
+        let pointers = call
+            .parameter
+            .map_or(&[][..], |parameter| parameter.pointers);
+        // CallNextHookEx carries on with the chain for the messages of the sender
+        self.hooks_in_progress.borrow_mut().push(HookInProgress {
+            hook_type: call.hook_type,
+            process_id: sender,
+            parameter: call.parameter,
+        });
+        let result = self.call_with_copy(
+            accessor,
+            call.l_param,
+            data,
+            pointers,
+            |accessor, l_param| {
+                self.call_hook_proc(accessor, call.proc, call.code, call.w_param, l_param)
+            },
+        );
+        self.hooks_in_progress.borrow_mut().pop();
+        result
+    }
+
+    /// Makes a call for another task, with l_param pointing to a copy of the memory it pointed
+    /// to in that task, if there is one. The far pointers at the given offsets that point into
+    /// that memory get pointed into the copy, and back again in what is returned.
+    fn call_with_copy(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        l_param: u32,
+        data: Option<&[u8]>,
+        pointers: &[u16],
+        call: impl FnOnce(&mut EmulatorAccessor, u32) -> Result<u32, EmulatorError>,
+    ) -> Result<(u32, Vec<u8>), EmulatorError> {
+        let data = match data {
+            Some(data) => data,
+            None => return call(accessor, l_param).map(|result| (result, Vec::new())),
+        };
+        // Keep the stack pointer even
+        let size = data.len().next_multiple_of(2) as u16;
+        let pointer = accessor.reserve_stack_space(size);
+        let flat_pointer = Pointer::from_far(pointer).0;
+        let relocate = |accessor: &mut EmulatorAccessor| -> Result<(), EmulatorError> {
+            for &offset in pointers {
+                let address = flat_pointer + offset as u32;
+                let target = accessor.memory().read_32(address)?;
+                let distance = target.wrapping_sub(l_param);
+                if target >> 16 == l_param >> 16 && distance < data.len() as u32 {
+                    accessor
+                        .memory_mut()
+                        .write_32(address, pointer.wrapping_add(distance))?;
+                }
+            }
+            Ok(())
+        };
+        let result = accessor
+            .memory_mut()
+            .copy_from(data, flat_pointer as usize)
+            .and_then(|_| relocate(accessor))
+            .and_then(|_| call(accessor, pointer))
+            .and_then(|result| {
+                let end = flat_pointer + data.len() as u32;
+                let mut copy = accessor.memory().slice(flat_pointer, end)?.to_vec();
+                for &offset in pointers {
+                    let range = offset as usize..offset as usize + 4;
+                    copy[range.clone()].copy_from_slice(&data[range]);
+                }
+                Ok((result, copy))
+            });
+        accessor.release_stack_space(size);
+        result
+    }
+
+    /// Keeps track of which keys and mouse buttons are down, as the input comes in.
+    fn update_key_states(&self, input: &WindowMessage) {
+        let key = match input.message {
+            MessageType::KeyDown | MessageType::SysKeyDown => Some((input.w_param as u8, true)),
+            MessageType::KeyUp | MessageType::SysKeyUp => Some((input.w_param as u8, false)),
+            MessageType::LButtonDown => Some((VK_LBUTTON, true)),
+            MessageType::LButtonUp => Some((VK_LBUTTON, false)),
+            MessageType::RButtonDown => Some((VK_RBUTTON, true)),
+            MessageType::RButtonUp => Some((VK_RBUTTON, false)),
+            MessageType::MButtonDown => Some((VK_MBUTTON, true)),
+            MessageType::MButtonUp => Some((VK_MBUTTON, false)),
+            _ => None,
+        };
+        if let Some((vk, down)) = key {
+            self.write_objects().key_states.set_key(vk, down);
+        }
+    }
+
+    /// Waits for the next input, for loops that handle the input themselves instead of letting
+    /// it become messages.
+    pub fn wait_for_input(&self) -> WindowMessage {
+        loop {
+            if let Some(input) = self.message_queue.receive_input() {
+                self.update_key_states(&input);
+                return input;
+            }
+            self.scheduler.wait(self.process_id());
+        }
+    }
+
+    /// Turns keyboard input into a message for the window with the focus. Without a focus, the
+    /// active window gets the keys as system keys.
+    fn cook_key_input(&self, input: WindowMessage) -> Option<WindowMessage> {
+        let (focus, active) = {
+            let objects = self.read_objects();
+            let window_manager = objects.read_window_manager();
+            (window_manager.focus(), window_manager.active_window())
+        };
+        let of_this_task = |identifier: Option<WindowIdentifier>| {
+            identifier
+                .filter(|identifier| identifier.process_id == self.process_id())
+                .map(|identifier| identifier.window_handle)
+        };
+        if let Some(h_wnd) = of_this_task(focus) {
+            return Some(WindowMessage { h_wnd, ..input });
+        }
+        if let Some(h_wnd) = of_this_task(active) {
+            let message = match input.message {
+                MessageType::KeyDown => MessageType::SysKeyDown,
+                MessageType::KeyUp => MessageType::SysKeyUp,
+                message => message,
+            };
+            return Some(WindowMessage {
+                h_wnd,
+                message,
+                ..input
+            });
+        }
+        self.read_objects().window_identifier(input.h_wnd)?;
+        Some(input)
+    }
+
+    /// Turns mouse input into a message for the window under the cursor. The window decides
+    /// with WM_NCHITTEST whether the cursor is in its client area, and gets to change the cursor
+    /// with WM_SETCURSOR.
+    fn cook_mouse_input(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        input: WindowMessage,
+    ) -> Result<Option<WindowMessage>, EmulatorError> {
+        let h_wnd = self.window_from_point(input.h_wnd, input.point);
+        let (proc, class_style) = {
+            let objects = self.read_objects();
+            // Disabled windows, such as the owner of a modal dialog box, get no mouse input
+            if !self.accepts_input(&objects, h_wnd) {
+                return Ok(None);
+            }
+            match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => {
+                    (user_window.proc, user_window.class_style)
+                }
+                _ => return Ok(None),
+            }
+        };
+        let screen_point = encode_u16_u16_to_u32(input.point.x as u16, input.point.y as u16);
+        let hit_test = self.call_wndproc_sync(
+            accessor,
+            proc,
+            h_wnd,
+            MessageType::NcHitTest.into(),
+            0,
+            screen_point,
+        )? as u16;
+        // Pressing a button in a window that isn't active activates it, unless the window says not to
+        if input.message.double_click().is_some() {
+            let top_level = self.top_level_of(&self.read_objects(), h_wnd);
+            if top_level != self.active_window() {
+                let answer = self.call_wndproc_sync(
+                    accessor,
+                    proc,
+                    h_wnd,
+                    MessageType::MouseActivate.into(),
+                    top_level.as_u16(),
+                    encode_u16_u16_to_u32(hit_test, input.message.into()),
+                )? as u16;
+                if answer == MouseActivate::Activate.into()
+                    || answer == MouseActivate::ActivateAndEat.into()
+                {
+                    self.activate_window(accessor, top_level, ActivateState::ClickActive)?;
+                }
+                if answer == MouseActivate::ActivateAndEat.into()
+                    || answer == MouseActivate::NoActivateAndEat.into()
+                {
+                    return Ok(None);
+                }
+            }
+        }
+        self.call_wndproc_sync(
+            accessor,
+            proc,
+            h_wnd,
+            MessageType::SetCursor.into(),
+            h_wnd.as_u16(),
+            encode_u16_u16_to_u32(hit_test, input.message.into()),
+        )?;
+        if hit_test == HitTest::Error.into() || hit_test == HitTest::Transparent.into() {
+            return Ok(None);
+        }
+
+        let in_client_area = hit_test == HitTest::Client.into();
+        // The non-client area always gets double clicks, the client area only if the class wants them
+        let message = if in_client_area && !class_style.contains(ClassStyles::DBLCLKS) {
+            input.message
+        } else {
+            self.recognize_double_click(h_wnd, &input)
+        };
+
+        if in_client_area {
+            let client_point = {
+                let objects = self.read_objects();
+                input.point
+                    - self
+                        .client_screen_position_of(&objects, h_wnd)
+                        .unwrap_or_else(Point::origin)
+            };
+            Ok(Some(WindowMessage {
+                h_wnd,
+                message,
+                w_param: input.w_param,
+                l_param: encode_u16_u16_to_u32(client_point.x as u16, client_point.y as u16),
+                time: input.time,
+                point: input.point,
+            }))
+        } else {
+            Ok(Some(WindowMessage {
+                h_wnd,
+                message: message.non_client().unwrap_or(message),
+                w_param: hit_test,
+                l_param: screen_point,
+                time: input.time,
+                point: input.point,
+            }))
+        }
+    }
+
+    /// Turns a button press into a double click if it closely follows the previous press.
+    fn recognize_double_click(&self, h_wnd: Handle, input: &WindowMessage) -> MessageType {
+        let double_click = match input.message.double_click() {
+            Some(double_click) => double_click,
+            None => return input.message,
+        };
+        let is_double_click = self.last_click.get().is_some_and(|last_click| {
+            last_click.h_wnd == h_wnd
+                && last_click.message == input.message
+                && input.time.wrapping_sub(last_click.time) <= DOUBLE_CLICK_TIME
+                && (input.point.x - last_click.point.x).abs() <= DOUBLE_CLICK_SIZE / 2
+                && (input.point.y - last_click.point.y).abs() <= DOUBLE_CLICK_SIZE / 2
+        });
+        if is_double_click {
+            // A third press starts a new double click
+            self.last_click.set(None);
+            double_click
+        } else {
+            self.last_click.set(Some(Click {
+                h_wnd,
+                message: input.message,
+                time: input.time,
+                point: input.point,
+            }));
+            input.message
+        }
+    }
+
+    /// Finds the deepest shown child below a point on the screen, starting from a top-level
+    /// window.
+    fn window_from_point(&self, h_wnd: Handle, point: Point) -> Handle {
+        let objects = self.read_objects();
+        let mut h_wnd = h_wnd;
+        let mut origin = match self.client_screen_position_of(&objects, h_wnd) {
+            Some(origin) => origin,
+            None => return h_wnd,
+        };
+        // Children cover their parent, and later children cover the earlier ones
+        while let Some(UserObject::Window(user_window)) = objects.user.get(h_wnd) {
+            let child = user_window.children.iter().rev().find_map(|&child| {
+                let window_identifier = objects.window_identifier(child)?;
+                let window_manager = objects.read_window_manager();
+                if !window_manager.is_shown(window_identifier) {
+                    return None;
+                }
+                let rect = window_manager.window_rect_of(window_identifier)?;
+                let client_origin = window_manager.client_origin_of(window_identifier)?;
+                rect.contains(point - origin)
+                    .then(|| (child, Point::new(rect.left, rect.top) + client_origin))
+            });
+            match child {
+                Some((child, client_position)) => {
+                    h_wnd = child;
+                    origin += client_position;
+                }
+                None => break,
+            }
+        }
+        h_wnd
+    }
+
+    /// The position of the top left corner of a window on the screen.
+    /// Child windows are positioned relative to the client area of their parent.
+    pub fn screen_position_of(&self, objects: &ObjectEnvironment, h_wnd: Handle) -> Option<Point> {
+        let window_manager = objects.read_window_manager();
+        let mut position = window_manager.position_of(objects.window_identifier(h_wnd)?)?;
+        let mut h_wnd = h_wnd;
+        loop {
+            h_wnd = match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.parent_handle,
+                _ => Handle::null(),
+            };
+            if h_wnd == Handle::null() {
+                break;
+            }
+            let window_identifier = objects.window_identifier(h_wnd)?;
+            position += window_manager.position_of(window_identifier)?
+                + window_manager.client_origin_of(window_identifier)?;
+        }
+        Some(position)
+    }
+
+    /// The position of the top left corner of the client area of a window on the screen.
+    pub fn client_screen_position_of(
+        &self,
+        objects: &ObjectEnvironment,
+        h_wnd: Handle,
+    ) -> Option<Point> {
+        let client_origin = objects
+            .read_window_manager()
+            .client_origin_of(objects.window_identifier(h_wnd)?)?;
+        Some(self.screen_position_of(objects, h_wnd)? + client_origin)
+    }
+
+    /// The position of the client area of a window on the screen, for mapping points between
+    /// windows. The desktop window and the null handle stand for the screen itself.
+    fn mapping_origin_of(&self, objects: &ObjectEnvironment, h_wnd: Handle) -> Option<Point> {
+        if h_wnd == Handle::null() || h_wnd.as_u16() == DESKTOP_WINDOW {
+            Some(Point::origin())
+        } else {
+            self.client_screen_position_of(objects, h_wnd)
+        }
+    }
+
+    /// The windows in a window, from the topmost one down. The windows in the desktop window,
+    /// or in the null handle, are the top-level windows: the shown ones above the hidden ones.
+    fn z_order(&self, objects: &ObjectEnvironment, h_wnd_parent: Handle) -> Vec<Handle> {
+        if h_wnd_parent != Handle::null() && h_wnd_parent.as_u16() != DESKTOP_WINDOW {
+            return match objects.user.get(h_wnd_parent) {
+                Some(UserObject::Window(user_window)) => {
+                    user_window.children.iter().rev().copied().collect()
+                }
+                _ => Vec::new(),
+            };
+        }
+        let is_top_level = |h_wnd: Handle| {
+            matches!(
+                objects.user.get(h_wnd),
+                Some(UserObject::Window(user_window)) if user_window.parent_handle == Handle::null()
+            )
+        };
+        let mut windows = objects
+            .read_window_manager()
+            .shown_windows()
+            .map(|identifier| identifier.window_handle)
+            .filter(|&h_wnd| is_top_level(h_wnd))
+            .collect::<Vec<_>>();
+        let mut hidden = objects
+            .user
+            .iter()
+            .map(|(handle, _)| handle)
+            .filter(|&h_wnd| is_top_level(h_wnd) && !windows.contains(&h_wnd))
+            .collect::<Vec<_>>();
+        hidden.sort_by_key(|handle| handle.as_u16());
+        windows.extend(hidden);
+        windows
+    }
+
+    /// The window that GetWindow finds from another one with a GW_ command.
+    fn related_window(&self, objects: &ObjectEnvironment, h_wnd: Handle, command: u16) -> Handle {
+        if h_wnd.as_u16() == DESKTOP_WINDOW {
+            if command != GetWindowCommand::Child.into() {
+                return Handle::null();
+            }
+            return self
+                .z_order(objects, h_wnd)
+                .first()
+                .copied()
+                .unwrap_or(Handle::null());
+        }
+        let (h_wnd_parent, h_wnd_owner) = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                (user_window.parent_handle, user_window.owner_handle)
+            }
+            _ => return Handle::null(),
+        };
+        let related = if command == GetWindowCommand::Owner.into() {
+            Some(h_wnd_owner)
+        } else if command == GetWindowCommand::Child.into() {
+            self.z_order(objects, h_wnd).first().copied()
+        } else {
+            let siblings = self.z_order(objects, h_wnd_parent);
+            let index = siblings.iter().position(|&sibling| sibling == h_wnd);
+            if command == GetWindowCommand::HwndFirst.into() {
+                siblings.first().copied()
+            } else if command == GetWindowCommand::HwndLast.into() {
+                siblings.last().copied()
+            } else if command == GetWindowCommand::HwndNext.into() {
+                index.and_then(|index| siblings.get(index + 1)).copied()
+            } else if command == GetWindowCommand::HwndPrev.into() {
+                index
+                    .and_then(|index| index.checked_sub(1))
+                    .and_then(|index| siblings.get(index))
+                    .copied()
+            } else {
+                None
+            }
+        };
+        related.unwrap_or(Handle::null())
+    }
+
+    /// All of the windows in a window, also those in its children, each one before the windows
+    /// in it.
+    fn descendants_of(&self, objects: &ObjectEnvironment, h_wnd: Handle) -> Vec<Handle> {
+        let mut descendants = Vec::new();
+        let mut pending = self.z_order(objects, h_wnd);
+        pending.reverse();
+        while let Some(h_wnd) = pending.pop() {
+            descendants.push(h_wnd);
+            if let Some(UserObject::Window(user_window)) = objects.user.get(h_wnd) {
+                pending.extend(user_window.children.iter());
+            }
+        }
+        descendants
+    }
+
+    /// Calls an enumeration procedure of the application with each window and the given
+    /// parameter, until it returns FALSE. Windows that got destroyed in the meantime are
+    /// skipped. Returns whether the procedure went through all of the windows.
+    fn enumerate_windows(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        windows: Vec<Handle>,
+        enum_func: u32,
+        l_param: u32,
+    ) -> Result<bool, EmulatorError> {
+        let proc = SegmentAndOffset {
+            segment: (enum_func >> 16) as u16,
+            offset: enum_func as u16,
+        };
+        for h_wnd in windows {
+            if !matches!(
+                self.read_objects().user.get(h_wnd),
+                Some(UserObject::Window(_))
+            ) {
+                continue;
+            }
+            let result = accessor.call_far(
+                proc,
+                &[h_wnd.as_u16(), (l_param >> 16) as u16, l_param as u16],
+            )?;
+            if result as u16 == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn is_shown(&self, objects: &ObjectEnvironment, h_wnd: Handle) -> bool {
+        objects
+            .window_identifier(h_wnd)
+            .is_some_and(|window_identifier| {
+                objects.read_window_manager().is_shown(window_identifier)
+            })
+    }
+
+    /// The width and height of a window, including its non-client area.
+    pub fn window_size_of(&self, objects: &ObjectEnvironment, h_wnd: Handle) -> Option<(i16, i16)> {
+        let rect = objects
+            .read_window_manager()
+            .window_rect_of(objects.window_identifier(h_wnd)?)?;
+        Some((rect.right - rect.left, rect.bottom - rect.top))
+    }
+
+    fn write_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        msg: Pointer,
+        message: &PostedMessage,
+    ) -> Result<(), EmulatorError> {
+        let memory = accessor.memory_mut();
+        memory.write_u16(msg.0, message.h_wnd.as_u16())?;
+        memory.write_u16(msg.0 + 2, message.message)?;
+        memory.write_u16(msg.0 + 4, message.w_param)?;
+        memory.write_32(msg.0 + 6, message.l_param)?;
+        memory.write_32(msg.0 + 10, message.time)?;
+        memory.write_i16(msg.0 + 14, message.point.x)?;
+        memory.write_i16(msg.0 + 16, message.point.y)
+    }
+
+    fn read_message(
+        &self,
+        accessor: &EmulatorAccessor,
+        msg: Pointer,
+    ) -> Result<PostedMessage, EmulatorError> {
+        let memory = accessor.memory();
+        Ok(PostedMessage {
+            h_wnd: memory.read_u16(msg.0)?.into(),
+            message: memory.read_u16(msg.0 + 2)?,
+            w_param: memory.read_u16(msg.0 + 4)?,
+            l_param: memory.read_32(msg.0 + 6)?,
+            time: memory.read_32(msg.0 + 10)?,
+            point: Point::new(memory.read_i16(msg.0 + 14)?, memory.read_i16(msg.0 + 16)?),
+        })
+    }
+
+    #[api_function]
+    fn internal_translate_message(
+        &self,
+        accessor: EmulatorAccessor,
+        msg: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let message = self.read_message(&accessor, msg)?;
+        Ok(ReturnValue::U16(self.translate_message(&message).into()))
+    }
+
+    /// Posts the character message for a key message, if the key stands for a character.
+    /// Returns whether it did.
+    pub fn translate_message(&self, message: &PostedMessage) -> bool {
+        let char_message = if message.message == MessageType::KeyDown.into() {
+            MessageType::Char
+        } else if message.message == MessageType::SysKeyDown.into() {
+            MessageType::SysChar
+        } else {
+            return false;
+        };
+        let character = self
+            .read_objects()
+            .key_states
+            .character_of(message.w_param as u8);
+        match character {
+            Some(character) => {
+                self.message_queue.post(PostedMessage {
+                    message: char_message.into(),
+                    w_param: character as u16,
+                    ..*message
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[api_function]
+    fn load_accelerators(
+        &self,
+        mut accessor: EmulatorAccessor,
+        _h_instance: Handle,
+        table_name: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // TODO: keep h_instance into account...
+        let table_name = accessor.resource_name(table_name)?;
+        let data = match self
+            .resource_table
+            .predefined_resource(RT_ACCELERATOR, &table_name)
+        {
+            Some(data) => data,
+            None => return Ok(ReturnValue::U16(0)),
+        };
+        // The table goes in a block of global memory, like the tables that tasks make themselves
+        let kernel = accessor.kernel();
+        let h_accel = match kernel.allocate_global(&mut accessor, 0, data.len() as u32)? {
+            Some(h_accel) => h_accel,
+            None => return Ok(ReturnValue::U16(0)),
+        };
+        if let Some((address, _)) = kernel.global_block(h_accel) {
+            accessor.memory_mut().copy_from(data, address as usize)?;
+        }
+        Ok(ReturnValue::U16(h_accel.as_u16()))
+    }
+
+    #[api_function]
+    fn translate_accelerator(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        h_accel: Handle,
+        msg: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let message = self.read_message(&accessor, msg)?;
+        let table = match accessor.kernel().global_block(h_accel) {
+            Some((address, size)) => {
+                accelerator::parse_table(accessor.memory().slice(address, address + size)?)
+            }
+            None => return Ok(ReturnValue::U16(0)),
+        };
+        let (is_window, shift, control) = {
+            let objects = self.read_objects();
+            (
+                matches!(objects.user.get(h_wnd), Some(UserObject::Window(_))),
+                objects.key_states.is_down(VK_SHIFT),
+                objects.key_states.is_down(VK_CONTROL),
+            )
+        };
+        if !is_window {
+            return Ok(ReturnValue::U16(0));
+        }
+        let accelerator = match accelerator::find(
+            &table,
+            message.message,
+            message.w_param,
+            message.l_param,
+            shift,
+            control,
+        ) {
+            Some(accelerator) => accelerator,
+            None => return Ok(ReturnValue::U16(0)),
+        };
+        self.send_accelerator_command(&mut accessor, h_wnd, accelerator)?;
+        Ok(ReturnValue::U16(1))
+    }
+
+    /// Sends the command of an accelerator to a window: WM_SYSCOMMAND for the commands of the
+    /// system menu, WM_COMMAND for the others. The menu that has the command hears about it
+    /// first with WM_INITMENU, and nothing is sent if its item is disabled or grayed. Unless
+    /// the accelerator is NOINVERT, the item of the menu bar that leads to the command is
+    /// highlighted while the window handles it.
+    fn send_accelerator_command(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        accelerator: &Accelerator,
+    ) -> Result<(), EmulatorError> {
+        let command = accelerator.command;
+        let (style, h_menu) = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => (user_window.style, user_window.menu),
+            _ => return Ok(()),
+        };
+        if let Some(h_system_menu) = self.system_menu_of(h_wnd) {
+            let in_system_menu = self
+                .find_menu_item(
+                    &self.read_objects(),
+                    h_system_menu,
+                    command,
+                    MenuFlags::empty(),
+                )
+                .is_some();
+            if in_system_menu {
+                self.send_message(
+                    accessor,
+                    h_wnd,
+                    MessageType::InitMenu.into(),
+                    h_system_menu.as_u16(),
+                    0,
+                )?;
+                if self.is_menu_command_enabled(h_system_menu, command) {
+                    self.send_message(
+                        accessor,
+                        h_wnd,
+                        MessageType::SysCommand.into(),
+                        command,
+                        accelerator::FROM_ACCELERATOR,
+                    )?;
+                }
+                return Ok(());
+            }
+        }
+        // The menu handle of a child window is its id instead
+        let in_menu = !style.contains(WindowStyles::CHILD)
+            && self
+                .find_menu_item(&self.read_objects(), h_menu, command, MenuFlags::empty())
+                .is_some();
+        if in_menu {
+            // The menu bar of a minimised window can't be used
+            if style.contains(WindowStyles::MINIMIZE) {
+                return Ok(());
+            }
+            self.send_message(
+                accessor,
+                h_wnd,
+                MessageType::InitMenu.into(),
+                h_menu.as_u16(),
+                0,
+            )?;
+            if !self.is_menu_command_enabled(h_menu, command) {
+                return Ok(());
+            }
+        }
+        let highlighted = if in_menu && !accelerator.flags.contains(AcceleratorFlags::NOINVERT) {
+            self.menu_bar_item_leading_to(h_menu, command)
+        } else {
+            None
+        };
+        if highlighted.is_some() {
+            self.paint_menu_bar(h_wnd, highlighted);
+        }
+        self.send_message(
+            accessor,
+            h_wnd,
+            MessageType::Command.into(),
+            command,
+            accelerator::FROM_ACCELERATOR,
+        )?;
+        if highlighted.is_some() {
+            self.paint_menu_bar(h_wnd, None);
+        }
+        Ok(())
+    }
+
+    /// Whether the item of a menu, or of the popup menus it opens, with a command can be
+    /// chosen.
+    fn is_menu_command_enabled(&self, h_menu: Handle, command: u16) -> bool {
+        let objects = self.read_objects();
+        match self.find_menu_item(&objects, h_menu, command, MenuFlags::empty()) {
+            Some((h_menu, index)) => matches!(
+                objects.user.get(h_menu),
+                Some(UserObject::Menu(menu)) if menu.items[index].is_enabled()
+            ),
+            None => false,
+        }
+    }
+
+    /// The item of a menu bar that has a command, or opens the popup menu that has it.
+    fn menu_bar_item_leading_to(&self, h_menu: Handle, command: u16) -> Option<usize> {
+        let objects = self.read_objects();
+        let menu = match objects.user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu,
+            _ => return None,
+        };
+        menu.items.iter().position(|item| match item.popup() {
+            Some(popup) => self
+                .find_menu_item(&objects, popup, command, MenuFlags::empty())
+                .is_some(),
+            None => item.is_enabled() && item.id == command,
+        })
+    }
+
+    #[api_function]
+    fn get_key_state(&self, virt_key: u16) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(
+            self.read_objects().key_states.key_state(virt_key as u8),
+        ))
+    }
+
+    #[api_function]
+    fn get_async_key_state(&self, virt_key: u16) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(
+            self.write_objects()
+                .async_key_states
+                .query_async_key_state(virt_key as u8),
+        ))
+    }
+
+    #[api_function]
+    fn get_keyboard_state(
+        &self,
+        mut accessor: EmulatorAccessor,
+        key_state: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let keys = *self.read_objects().key_states.as_bytes();
+        accessor
+            .memory_mut()
+            .copy_from(&keys, key_state.0 as usize)?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn set_keyboard_state(
+        &self,
+        accessor: EmulatorAccessor,
+        key_state: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let mut keys = [0; 256];
+        for (index, key) in keys.iter_mut().enumerate() {
+            *key = accessor.memory().read_8(key_state.0 + index as u32)?;
+        }
+        self.write_objects().key_states.set_bytes(keys);
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn internal_dispatch_message(
+        &self,
+        mut accessor: EmulatorAccessor,
+        msg: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let h_wnd: Handle = accessor.memory().read_u16(msg.0)?.into();
+        let message_type = accessor.memory().read_u16(msg.0 + 2)?;
+        let w_param = accessor.memory().read_u16(msg.0 + 4)?;
+        let l_param = accessor.memory().read_32(msg.0 + 6)?;
+        let result = self.dispatch_message(&mut accessor, h_wnd, message_type, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+
+    /// Calls the window procedure of the window a message is for.
+    pub fn dispatch_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        message_type: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        // Timers with a TIMERPROC get it called instead of the window procedure,
+        // and it gets the system time instead of lParam
+        let (proc, l_param) = if message_type == MessageType::Timer.into() && l_param != 0 {
+            let timer_proc = SegmentAndOffset {
+                segment: (l_param >> 16) as u16,
+                offset: l_param as u16,
+            };
+            (Some(timer_proc), self.read_objects().timers.tick_count())
+        } else {
+            (self.window_proc_of(h_wnd), l_param)
+        };
+
+        match proc {
+            Some(proc) => {
+                self.call_wndproc_sync(accessor, proc, h_wnd, message_type, w_param, l_param)
+            }
+            None => Ok(0),
+        }
+    }
+
+    #[api_function]
+    fn load_string(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_instance: Handle,
+        uid: u16,
+        buffer: Pointer,
+        buffer_max: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        println!(
+            "LOAD STRING {:?} {:x} {:x} {:x}",
+            h_instance, uid, buffer.0, buffer_max
+        );
+
+        // TODO: keep h_instance into account...
+        if let Some(string) = self.resource_table.strings_resources.get(&uid) {
+            let string = string.as_slice();
+            let length = string.len();
+            // TODO: handle buffer_max == 0 special case (see docs)
+            let amount_of_bytes_to_copy = (buffer_max as usize).min(length);
+
+            accessor
+                .memory_mut()
+                .copy_from(&string[0..amount_of_bytes_to_copy], buffer.0 as usize)?;
+            accessor
+                .memory_mut()
+                .write_8(buffer.0 + (amount_of_bytes_to_copy as u32), 0)?;
+
+            debug_print_null_terminated_string(&accessor, buffer.0);
+            // String lengths from the resource table will fit in 16 bits, because their length
+            // was originally 8 bits.
+            Ok(ReturnValue::U16(amount_of_bytes_to_copy as u16))
+        } else {
+            Ok(ReturnValue::U16(0))
+        }
+    }
+
+    #[api_function]
+    fn load_cursor(
+        &self,
+        _h_instance: Handle,
+        _cursor_name: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(Handle::null().as_u16()))
+    }
+
+    pub fn call_wndproc_sync(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        proc: SegmentAndOffset,
+        h_wnd: Handle,
+        message: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        accessor.call_far(
+            proc,
+            &[
+                h_wnd.as_u16(),
+                message,
+                w_param,
+                (l_param >> 16) as u16,
+                l_param as u16,
+            ],
+        )
+    }
+
+    /// The first hook of a kind for the messages of this task. Hooks of a kind that is being
+    /// called already don't get called again until it returns.
+    fn first_hook(&self, hook_type: i16) -> Option<Hook> {
+        let in_progress = self.hooks_in_progress.borrow();
+        if in_progress.iter().any(|hook| hook.hook_type == hook_type) {
+            return None;
+        }
+        self.read_objects()
+            .hooks
+            .first(hook_type, self.process_id())
+    }
+
+    /// The hook chain of a kind that is being called, or one for the messages of this task
+    /// without a parameter if there is none.
+    fn hook_in_progress(&self, hook_type: i16) -> HookInProgress {
+        self.hooks_in_progress
+            .borrow()
+            .iter()
+            .rev()
+            .find(|hook| hook.hook_type == hook_type)
+            .copied()
+            .unwrap_or(HookInProgress {
+                hook_type,
+                process_id: self.process_id(),
+                parameter: None,
+            })
+    }
+
+    fn call_hook_proc(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        proc: SegmentAndOffset,
+        code: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        accessor.call_far(
+            proc,
+            &[code, w_param, (l_param >> 16) as u16, l_param as u16],
+        )
+    }
+
+    /// Calls a hook in the task that installed it. A hook of another task gets a copy of the
+    /// memory that l_param points to, which comes back the way the hook left it.
+    fn call_hook_of_task(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        hook: &Hook,
+        code: u16,
+        w_param: u16,
+        l_param: u32,
+        parameter: Option<HookParameter>,
+    ) -> Result<u32, EmulatorError> {
+        if hook.process_id == self.process_id() {
+            return self.call_hook_proc(accessor, hook.proc, code, w_param, l_param);
+        }
+        let address = Pointer::from_far(l_param).0;
+        let size = parameter
+            .filter(|_| l_param != 0)
+            .map(|parameter| parameter.size as u32);
+        let data = size
+            .map(|size| {
+                accessor
+                    .memory()
+                    .slice(address, address + size)
+                    .map(<[u8]>::to_vec)
+            })
+            .transpose()?;
+        let call = HookCall {
+            hook_type: hook.hook_type,
+            proc: hook.proc,
+            code,
+            w_param,
+            l_param,
+            parameter,
+        };
+        let (result, data) =
+            self.send_to_task(accessor, hook.process_id, SentCall::Hook(call), data)?;
+        // Nothing comes back if the task of the hook ended in the meantime
+        if size == Some(data.len() as u32) {
+            accessor.memory_mut().copy_from(&data, address as usize)?;
+        }
+        Ok(result)
+    }
+
+    /// Calls the first hook of a kind, which passes the call on to the rest of the chain if it
+    /// wants to. The parameter tells what l_param points to, for the hooks of other tasks.
+    /// Returns what the hook returned, or None if there are no hooks of that kind.
+    fn call_hook(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        hook_type: i16,
+        code: u16,
+        w_param: u16,
+        l_param: u32,
+        parameter: Option<HookParameter>,
+    ) -> Result<Option<u32>, EmulatorError> {
+        let hook = match self.first_hook(hook_type) {
+            Some(hook) => hook,
+            None => return Ok(None),
+        };
+        self.hooks_in_progress.borrow_mut().push(HookInProgress {
+            hook_type,
+            process_id: self.process_id(),
+            parameter,
+        });
+        let result = self.call_hook_of_task(accessor, &hook, code, w_param, l_param, parameter);
+        self.hooks_in_progress.borrow_mut().pop();
+        result.map(Some)
+    }
+
+    /// Calls the first hook of a kind with lParam pointing to a structure that it may change.
+    /// Returns what the hook returned and the structure the way it left it, or None if there
+    /// are no hooks of that kind.
+    fn call_hook_with_structure<T: GuestStructure>(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        hook_type: i16,
+        code: u16,
+        w_param: u16,
+        structure: &T,
+    ) -> Result<Option<(u32, T)>, EmulatorError> {
+        if self.first_hook(hook_type).is_none() {
+            return Ok(None);
+        }
+        // The structure lives on the stack of the task for as long as the call takes
+        let pointer = accessor.reserve_stack_space(T::SIZE);
+        let flat_pointer = Pointer::from_far(pointer).0;
+        let result = structure
+            .write(accessor, flat_pointer)
+            .and_then(|_| {
+                let parameter = HookParameter::of_size(T::SIZE);
+                self.call_hook(accessor, hook_type, code, w_param, pointer, Some(parameter))
+            })
+            .and_then(|result| match result {
+                Some(result) => Ok(Some((result, T::read(accessor, flat_pointer)?))),
+                None => Ok(None),
+            });
+        accessor.release_stack_space(T::SIZE);
+        result
+    }
+
+    /// Asks the WH_CBT hooks whether something may happen, with an lParam that isn't a
+    /// pointer. Returns true if a hook prevents it.
+    fn call_cbt_hook(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        code: CbtCode,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<bool, EmulatorError> {
+        let result = self.call_hook(accessor, WH_CBT, code.into(), w_param, l_param, None)?;
+        Ok(result.unwrap_or(0) != 0)
+    }
+
+    /// Asks the WH_CBT hooks whether a window may be created. They get a CBT_CREATEWND that
+    /// points to a CREATESTRUCT about the window. Returns true if a hook prevents it.
+    fn call_cbt_create_hook(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        creation: &WindowCreation,
+    ) -> Result<bool, EmulatorError> {
+        // The CREATESTRUCT and its names follow the CBT_CREATEWND on the stack
+        const CBT_CREATE_WND_SIZE: u16 = 6;
+        // The pointer to the CREATESTRUCT, and its pointers to the names
+        const POINTERS: [u16; 3] = [0, CBT_CREATE_WND_SIZE + 22, CBT_CREATE_WND_SIZE + 26];
+        if self.first_hook(WH_CBT).is_none() {
+            return Ok(false);
+        }
+        let size = CBT_CREATE_WND_SIZE + creation.create_struct_size();
+        let pointer = accessor.reserve_stack_space(size);
+        let create_struct_pointer = pointer + CBT_CREATE_WND_SIZE as u32;
+        let window_name_pointer = create_struct_pointer + CREATE_STRUCT_SIZE as u32;
+        let address = Pointer::from_far(pointer).0;
+        let result = self
+            .write_create_struct(
+                accessor,
+                Pointer::from_far(create_struct_pointer).0,
+                creation,
+                window_name_pointer,
+            )
+            .and_then(|_| {
+                let memory = accessor.memory_mut();
+                memory.write_32(address, create_struct_pointer)?;
+                // The window goes on top of its siblings
+                memory.write_u16(address + 4, Handle::null().as_u16())
+            })
+            .and_then(|_| {
+                let parameter = HookParameter {
+                    size,
+                    pointers: &POINTERS,
+                };
+                let code = CbtCode::CreateWnd.into();
+                self.call_hook(
+                    accessor,
+                    WH_CBT,
+                    code,
+                    h_wnd.as_u16(),
+                    pointer,
+                    Some(parameter),
+                )
+            })
+            .map(|result| result.unwrap_or(0) != 0);
+        accessor.release_stack_space(size);
+        result
+    }
+
+    /// Lets the WH_CALLWNDPROC hooks see a message that is about to be sent to a window of
+    /// this task. Returns the message, wParam and lParam the way the hooks left them.
+    fn call_wndproc_hook(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<(u16, u16, u32), EmulatorError> {
+        let structure = CallWndProcStruct {
+            l_param,
+            w_param,
+            message: msg,
+            h_wnd,
+        };
+        // wParam tells whether this task sent the message
+        let from_this_task = !self.in_send_message.get();
+        let result = self.call_hook_with_structure(
+            accessor,
+            WH_CALLWNDPROC,
+            HC_ACTION,
+            from_this_task.into(),
+            &structure,
+        )?;
+        Ok(match result {
+            Some((_, structure)) => (structure.message, structure.w_param, structure.l_param),
+            None => (msg, w_param, l_param),
+        })
+    }
+
+    /// Lets the WH_KEYBOARD or the WH_MOUSE hooks see a key or mouse message that is about to
+    /// be retrieved from the queue. Returns whether a hook discarded it.
+    fn is_discarded_by_hook(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        message: &PostedMessage,
+        remove: bool,
+    ) -> Result<bool, EmulatorError> {
+        let code = if remove { HC_ACTION } else { HC_NOREMOVE };
+        let result = if hook::is_keyboard_message(message.message) {
+            self.call_hook(
+                accessor,
+                WH_KEYBOARD,
+                code,
+                message.w_param,
+                message.l_param,
+                None,
+            )?
+        } else if let Some(hit_test) = hook::mouse_hit_test(message.message, message.w_param) {
+            let structure = MouseHookStruct {
+                point: message.point,
+                h_wnd: message.h_wnd,
+                hit_test,
+            };
+            self.call_hook_with_structure(accessor, WH_MOUSE, code, message.message, &structure)?
+                .map(|(result, _)| result)
+        } else {
+            None
+        };
+        Ok(result.unwrap_or(0) != 0)
+    }
+
+    /// Calls the WH_SYSMSGFILTER hooks and then the WH_MSGFILTER hooks with a far pointer to a
+    /// MSG, until one of them handles it. Returns whether one did.
+    fn call_message_filter_hooks(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        code: u16,
+        msg: u32,
+    ) -> Result<bool, EmulatorError> {
+        for hook_type in [WH_SYSMSGFILTER, WH_MSGFILTER] {
+            if self
+                .call_hook(
+                    accessor,
+                    hook_type,
+                    code,
+                    0,
+                    msg,
+                    Some(HookParameter::of_size(MSG_SIZE)),
+                )?
+                .unwrap_or(0)
+                != 0
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Lets the message filter hooks see a message that a modal loop of USER is about to
+    /// handle. Returns whether a hook handled it instead.
+    pub fn filter_modal_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        code: MessageFilterCode,
+        message: &PostedMessage,
+    ) -> Result<bool, EmulatorError> {
+        if self.first_hook(WH_SYSMSGFILTER).is_none() && self.first_hook(WH_MSGFILTER).is_none() {
+            return Ok(false);
+        }
+        let pointer = accessor.reserve_stack_space(MSG_SIZE);
+        let result = self
+            .write_message(accessor, Pointer::from_far(pointer), message)
+            .and_then(|_| self.call_message_filter_hooks(accessor, code.into(), pointer));
+        accessor.release_stack_space(MSG_SIZE);
+        result
+    }
+
+    #[api_function]
+    fn set_windows_hook(&self, id_hook: i16, proc: u32) -> Result<ReturnValue, EmulatorError> {
+        if !hook::is_supported(id_hook) || proc == 0 {
+            return Ok(ReturnValue::U32(0));
+        }
+        let proc = SegmentAndOffset {
+            segment: (proc >> 16) as u16,
+            offset: proc as u16,
+        };
+        let mut objects = self.write_objects();
+        // The hook gets the hook that was first before it, to pass calls on to with DefHookProc
+        let previous = objects
+            .hooks
+            .first(id_hook, self.process_id())
+            .map_or(0, |previous| {
+                encode_u16_u16_to_u32(previous.proc.offset, previous.proc.segment)
+            });
+        // Only the message filters are for this task alone
+        let task = (id_hook == WH_MSGFILTER).then(|| self.process_id());
+        objects
+            .hooks
+            .install(id_hook, self.process_id(), task, proc);
+        Ok(ReturnValue::U32(previous))
+    }
+
+    #[api_function]
+    fn unhook_windows_hook(&self, id_hook: i16, proc: u32) -> Result<ReturnValue, EmulatorError> {
+        let proc = SegmentAndOffset {
+            segment: (proc >> 16) as u16,
+            offset: proc as u16,
+        };
+        let removed = self
+            .write_objects()
+            .hooks
+            .remove_proc(id_hook, self.process_id(), proc);
+        Ok(ReturnValue::U16(removed.into()))
+    }
+
+    #[api_function]
+    fn def_hook_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        code: i16,
+        w_param: u16,
+        l_param: u32,
+        next_hook: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // The hook keeps what SetWindowsHook returned, which is the hook to pass the call on to
+        let next = accessor.memory().read_32(next_hook.0)?;
+        if next == 0 {
+            return Ok(ReturnValue::U32(0));
+        }
+        let proc = SegmentAndOffset {
+            segment: (next >> 16) as u16,
+            offset: next as u16,
+        };
+        // The procedure may belong to a hook of another task
+        let result = match self.read_objects().hooks.find_proc(proc) {
+            Some(hook) => {
+                let in_progress = self.hook_in_progress(hook.hook_type);
+                self.call_hook_of_task(
+                    &mut accessor,
+                    &hook,
+                    code as u16,
+                    w_param,
+                    l_param,
+                    in_progress.parameter,
+                )?
+            }
+            None => self.call_hook_proc(&mut accessor, proc, code as u16, w_param, l_param)?,
+        };
+        Ok(ReturnValue::U32(result))
+    }
+
+    #[api_function]
+    fn set_windows_hook_ex(
+        &self,
+        id_hook: i16,
+        proc: u32,
+        _h_instance: Handle,
+        h_task: Handle,
+    ) -> Result<ReturnValue, EmulatorError> {
+        if !hook::is_supported(id_hook) || proc == 0 {
+            return Ok(ReturnValue::U32(0));
+        }
+        let proc = SegmentAndOffset {
+            segment: (proc >> 16) as u16,
+            offset: proc as u16,
+        };
+        // TODO: there are no task handles yet, so a hook for a task is a hook for this task
+        let task = (h_task != Handle::null()).then(|| self.process_id());
+        let h_hook = self
+            .write_objects()
+            .hooks
+            .install(id_hook, self.process_id(), task, proc);
+        Ok(ReturnValue::U32(h_hook))
+    }
+
+    #[api_function]
+    fn unhook_windows_hook_ex(&self, h_hook: u32) -> Result<ReturnValue, EmulatorError> {
+        let removed = self.write_objects().hooks.remove(h_hook);
+        Ok(ReturnValue::U16(removed.into()))
+    }
+
+    #[api_function]
+    fn call_next_hook_ex(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_hook: u32,
+        code: i16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // The chain may be for the messages of another task, which called a hook of this task
+        let in_progress = match self.read_objects().hooks.get(h_hook) {
+            Some(hook) => self.hook_in_progress(hook.hook_type),
+            None => return Ok(ReturnValue::U32(0)),
+        };
+        let next = self
+            .read_objects()
+            .hooks
+            .next(h_hook, in_progress.process_id);
+        let result = match next {
+            Some(hook) => self.call_hook_of_task(
+                &mut accessor,
+                &hook,
+                code as u16,
+                w_param,
+                l_param,
+                in_progress.parameter,
+            )?,
+            None => 0,
+        };
+        Ok(ReturnValue::U32(result))
+    }
+
+    #[api_function]
+    fn call_msg_filter(
+        &self,
+        mut accessor: EmulatorAccessor,
+        msg: u32,
+        code: i16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let handled = self.call_message_filter_hooks(&mut accessor, code as u16, msg)?;
+        Ok(ReturnValue::U16(handled.into()))
+    }
+
+    #[api_function]
+    fn get_system_metrics(&self, metric: u16) -> Result<ReturnValue, EmulatorError> {
+        println!("metric {} requested", metric);
+        // TODO: other metrics
+        if metric == 0 {
+            // SM_CXSCREEN
+            Ok(ReturnValue::U16(SCREEN_WIDTH as u16))
+        } else if metric == 1 {
+            // SM_CYSCREEN
+            Ok(ReturnValue::U16(SCREEN_HEIGHT as u16))
+        } else if metric == 4 {
+            // SM_CYCAPTION
+            Ok(ReturnValue::U16(non_client::CAPTION_HEIGHT as u16))
+        } else if metric == 5 || metric == 6 {
+            // SM_CXBORDER and SM_CYBORDER
+            Ok(ReturnValue::U16(non_client::BORDER_WIDTH as u16))
+        } else if metric == 7 || metric == 8 {
+            // SM_CXDLGFRAME and SM_CYDLGFRAME
+            Ok(ReturnValue::U16(non_client::DIALOG_FRAME_WIDTH as u16))
+        } else if metric == 11 || metric == 12 {
+            // SM_CXICON and SM_CYICON
+            Ok(ReturnValue::U16(non_client::ICON_SIZE as u16))
+        } else if metric == 0x16 {
+            // 1 if debug version is installed, 0 otherwise
+            Ok(ReturnValue::U16(1))
+        } else if metric == 15 {
+            // SM_CYMENU
+            Ok(ReturnValue::U16(menu::MENU_BAR_HEIGHT as u16))
+        } else if metric == 30 || metric == 31 {
+            // SM_CXSIZE and SM_CYSIZE
+            Ok(ReturnValue::U16(non_client::BUTTON_SIZE as u16))
+        } else if metric == 32 || metric == 33 {
+            // SM_CXFRAME and SM_CYFRAME
+            Ok(ReturnValue::U16(non_client::FRAME_WIDTH as u16))
+        } else if metric == 34 {
+            // SM_CXMIN
+            Ok(ReturnValue::U16(non_client::MIN_TRACK_WIDTH as u16))
+        } else if metric == 35 {
+            // SM_CYMIN
+            Ok(ReturnValue::U16(non_client::MIN_TRACK_HEIGHT as u16))
+        } else if metric == 41 {
+            // As the Windows for Pen computing extension is not installed, return 0
+            Ok(ReturnValue::U16(0))
+        } else {
+            Ok(ReturnValue::U16(0))
+        }
+    }
+
+    #[api_function]
+    fn wsprintf(
+        &self,
+        accessor: EmulatorAccessor,
+        format_string_ptr: Pointer,
+        output_buffer_ptr: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug_print_null_terminated_string(&accessor, format_string_ptr.0);
+        let mut machine = SprintfMachine::new(accessor, format_string_ptr, output_buffer_ptr);
+        machine.process()
+    }
+
+    #[api_function]
+    fn def_window_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] DEF WINDOW PROC {:?} {:x} {:x} {:x}",
+            h_wnd, msg, w_param, l_param
+        );
+        let result = self.default_window_proc(&mut accessor, h_wnd, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+
+    pub fn default_window_proc(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        if msg == MessageType::NcCreate.into() {
+            // The title was already set from the CREATESTRUCT when the window was made
+            return Ok(1);
+        }
+        if msg == MessageType::NcCalcSize.into() {
+            let (style, h_menu) = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => (user_window.style, user_window.menu),
+                _ => return Ok(0),
+            };
+            let rect_pointer = Pointer::from_far(l_param).0;
+            let rect = accessor.read_rect(rect_pointer)?;
+            let mut client_rect = non_client::calculate_client_rect(style, rect);
+            // The menu bar goes between the caption bar and the client area
+            if !style.intersects(WindowStyles::CHILD | WindowStyles::MINIMIZE) {
+                if let Some(UserObject::Menu(menu)) = self.read_objects().user.get(h_menu) {
+                    let bar_height = menu::bar_height(menu, client_rect.right - client_rect.left);
+                    client_rect.top = (client_rect.top + bar_height).min(client_rect.bottom);
+                }
+            }
+            accessor.write_rect(rect_pointer, &client_rect)?;
+            Ok(0)
+        } else if msg == MessageType::NcPaint.into() {
+            self.paint_non_client(h_wnd, None);
+            self.paint_menu_bar(h_wnd, None);
+            Ok(0)
+        } else if msg == MessageType::NcActivate.into() {
+            if let Some(UserObject::Window(user_window)) = self.write_objects().user.get_mut(h_wnd)
+            {
+                user_window.active_frame = w_param != 0;
+            }
+            self.paint_non_client(h_wnd, None);
+            Ok(1)
+        } else if msg == MessageType::Activate.into() {
+            // A window that gets activated takes the focus, unless it is minimised
+            let minimized = matches!(
+                self.read_objects().user.get(h_wnd),
+                Some(UserObject::Window(user_window)) if user_window.style.contains(WindowStyles::MINIMIZE)
+            );
+            if w_param != ActivateState::Inactive.into() && !minimized {
+                self.set_focus(accessor, h_wnd)?;
+            }
+            Ok(0)
+        } else if msg == MessageType::MouseActivate.into() {
+            // Child windows let their parent decide first
+            let parent = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.parent_handle,
+                _ => Handle::null(),
+            };
+            if parent != Handle::null() {
+                let result = self.send_message(accessor, parent, msg, w_param, l_param)?;
+                if result != 0 {
+                    return Ok(result);
+                }
+            }
+            Ok(u16::from(MouseActivate::Activate).into())
+        } else if msg == MessageType::Close.into() {
+            self.destroy_window(accessor, h_wnd)?;
+            Ok(0)
+        } else if msg == MessageType::Paint.into() {
+            if let Some(paint) = self.begin_paint(accessor, h_wnd)? {
+                self.end_paint(h_wnd, paint.hdc);
+            }
+            Ok(0)
+        } else if msg == MessageType::EraseBkGnd.into() {
+            Ok(self.erase_background(h_wnd, w_param.into()).into())
+        } else if msg == MessageType::SetText.into() {
+            let text = if l_param == 0 {
+                HeapByteString::from(Arc::new([]))
+            } else {
+                accessor.clone_string(Pointer::from_far(l_param).0, false)?
+            };
+            let proc = match self.write_objects().user.get_mut(h_wnd) {
+                Some(UserObject::Window(user_window)) => {
+                    user_window.title = text;
+                    user_window.proc
+                }
+                _ => return Ok(0),
+            };
+            // The caption shows the text
+            self.call_wndproc_sync(accessor, proc, h_wnd, MessageType::NcPaint.into(), 1, 0)?;
+            Ok(1)
+        } else if msg == MessageType::GetText.into() {
+            let text = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.title.clone(),
+                _ => return Ok(0),
+            };
+            get_text(accessor, text.as_slice(), w_param, l_param)
+        } else if msg == MessageType::GetTextLength.into() {
+            match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => {
+                    Ok(user_window.title.as_slice().len() as u32)
+                }
+                _ => Ok(0),
+            }
+        } else if msg == MessageType::NcHitTest.into() {
+            let point = Point::new(l_param as i16, (l_param >> 16) as i16);
+            Ok(u16::from(self.non_client_hit_test(h_wnd, point)) as u32)
+        } else if msg == MessageType::NcLButtonDown.into() {
+            let style = self.style_of(h_wnd).unwrap_or_else(WindowStyles::empty);
+            let first_edge = u16::from(HitTest::Left);
+            let command = if w_param == HitTest::Caption.into() {
+                // The lowest bits tell the move started from the caption
+                Some(u16::from(SystemCommand::Move) | u16::from(HitTest::Caption))
+            } else if (first_edge..=u16::from(HitTest::BottomRight)).contains(&w_param) {
+                Some(u16::from(SystemCommand::Size) + w_param - first_edge + 1)
+            } else if w_param == HitTest::Menu.into() || w_param == HitTest::SysMenu.into() {
+                // The lowest bits tell whether it is the menu bar or the system menu
+                Some(u16::from(SystemCommand::MouseMenu) | w_param)
+            } else if w_param == HitTest::MinButton.into() {
+                self.track_caption_button(h_wnd, HitTest::MinButton)
+                    .then(|| SystemCommand::Minimize.into())
+            } else if w_param == HitTest::MaxButton.into() {
+                let command = if style.contains(WindowStyles::MAXIMIZE) {
+                    SystemCommand::Restore
+                } else {
+                    SystemCommand::Maximize
+                };
+                self.track_caption_button(h_wnd, HitTest::MaxButton)
+                    .then(|| command.into())
+            } else {
+                None
+            };
+            if let Some(command) = command {
+                self.send_system_command(accessor, h_wnd, command, l_param)?;
+            }
+            Ok(0)
+        } else if msg == MessageType::NcLButtonDblClk.into() {
+            let style = self.style_of(h_wnd).unwrap_or_else(WindowStyles::empty);
+            // Double clicking the system menu box closes the window, and double clicking an icon
+            // or the caption of a maximisable window toggles its size
+            let command = if w_param == HitTest::SysMenu.into() {
+                Some(SystemCommand::Close)
+            } else if style.intersects(WindowStyles::MINIMIZE | WindowStyles::MAXIMIZE) {
+                Some(SystemCommand::Restore)
+            } else if w_param == HitTest::Caption.into()
+                && style.contains(WindowStyles::MAXIMIZEBOX)
+            {
+                Some(SystemCommand::Maximize)
+            } else {
+                None
+            };
+            if let Some(command) = command {
+                self.send_system_command(accessor, h_wnd, command.into(), l_param)?;
+            }
+            Ok(0)
+        } else if msg == MessageType::SysCommand.into() {
+            if self.call_cbt_hook(accessor, CbtCode::SysCommand, w_param, l_param)? {
+                return Ok(0);
+            }
+            let point = Point::new(l_param as i16, (l_param >> 16) as i16);
+            let command = w_param & 0xfff0;
+            if command == SystemCommand::Move.into() {
+                // Moves chosen from the system menu start from the middle of the window
+                let point = if l_param == 0 {
+                    let objects = self.read_objects();
+                    self.screen_position_of(&objects, h_wnd)
+                        .zip(self.window_size_of(&objects, h_wnd))
+                        .map_or(point, |(position, (width, height))| {
+                            position + Point::new(width / 2, height / 2)
+                        })
+                } else {
+                    point
+                };
+                self.track_move_size(accessor, h_wnd, HitTest::Caption, point)?;
+            } else if command == SystemCommand::Size.into() {
+                let edge = match w_param & 0x000f {
+                    1 => Some(HitTest::Left),
+                    2 => Some(HitTest::Right),
+                    3 => Some(HitTest::Top),
+                    4 => Some(HitTest::TopLeft),
+                    5 => Some(HitTest::TopRight),
+                    6 => Some(HitTest::Bottom),
+                    7 => Some(HitTest::BottomLeft),
+                    8 => Some(HitTest::BottomRight),
+                    // TODO: sizing with the keyboard
+                    _ => None,
+                };
+                if let Some(edge) = edge {
+                    self.track_move_size(accessor, h_wnd, edge, point)?;
+                }
+            } else if command == SystemCommand::Minimize.into() {
+                self.set_window_state(accessor, h_wnd, WindowStyles::MINIMIZE)?;
+            } else if command == SystemCommand::Maximize.into() {
+                self.set_window_state(accessor, h_wnd, WindowStyles::MAXIMIZE)?;
+            } else if command == SystemCommand::Restore.into() {
+                self.set_window_state(accessor, h_wnd, WindowStyles::empty())?;
+            } else if command == SystemCommand::Close.into() {
+                self.send_message(accessor, h_wnd, MessageType::Close.into(), 0, 0)?;
+            } else if command == SystemCommand::MouseMenu.into() {
+                let system = w_param & 0x000f == HitTest::SysMenu.into();
+                self.enter_menu(accessor, h_wnd, system, MenuStart::Mouse(point))?;
+            } else if command == SystemCommand::KeyMenu.into() {
+                let character = l_param as u8;
+                let has_menu_bar = self.menu_bar_of(&self.read_objects(), h_wnd).is_some();
+                // Space opens the system menu, and so does Alt by itself without a menu bar
+                if character == b' ' || (character == 0 && !has_menu_bar) {
+                    self.enter_menu(accessor, h_wnd, true, MenuStart::Key(0))?;
+                } else if has_menu_bar {
+                    self.enter_menu(accessor, h_wnd, false, MenuStart::Key(character))?;
+                }
+            }
+            Ok(0)
+        } else if msg == MessageType::SysKeyDown.into() {
+            // Alt or F10 by itself opens the menu bar once it is released
+            self.menu_key_pending
+                .set(w_param == VK_MENU as u16 || w_param == VK_F10 as u16);
+            // Alt+F4 closes a top-level window
+            let style = self.style_of(h_wnd).unwrap_or_else(WindowStyles::empty);
+            if w_param == VK_F4 as u16 && !style.contains(WindowStyles::CHILD) {
+                self.send_system_command(accessor, h_wnd, SystemCommand::Close.into(), 0)?;
+            }
+            Ok(0)
+        } else if msg == MessageType::SysKeyUp.into() {
+            if self.menu_key_pending.replace(false)
+                && (w_param == VK_MENU as u16 || w_param == VK_F10 as u16)
+            {
+                self.send_system_command(accessor, h_wnd, SystemCommand::KeyMenu.into(), 0)?;
+            }
+            Ok(0)
+        } else if msg == MessageType::SysChar.into() {
+            // Alt with a character chooses the item of the menu bar with that mnemonic
+            self.send_system_command(
+                accessor,
+                h_wnd,
+                SystemCommand::KeyMenu.into(),
+                w_param.into(),
+            )?;
+            Ok(0)
+        } else if msg == MessageType::WindowPosChanging.into() {
+            let window_pos_pointer = Pointer::from_far(l_param).0;
+            let mut window_pos = WindowPos::read(accessor, window_pos_pointer)?;
+            let style = self.style_of(h_wnd).unwrap_or_else(WindowStyles::empty);
+            // Windows that can be sized by the user, and top-level windows, stay within their
+            // tracking size
+            let sizeable = style.contains(WindowStyles::THICKFRAME)
+                || !style.intersects(WindowStyles::POPUP | WindowStyles::CHILD);
+            if sizeable
+                && !style.contains(WindowStyles::MINIMIZE)
+                && !window_pos.flags.contains(SetWindowPosFlags::NOSIZE)
+            {
+                if let Some(min_max_info) = self.min_max_info(accessor, h_wnd)? {
+                    let (min, max) = (min_max_info.min_track_size, min_max_info.max_track_size);
+                    let rect = &mut window_pos.rect;
+                    rect.right = rect.left + (rect.right - rect.left).max(min.x).min(max.x);
+                    rect.bottom = rect.top + (rect.bottom - rect.top).max(min.y).min(max.y);
+                    window_pos.write(accessor, window_pos_pointer)?;
+                }
+            }
+            Ok(0)
+        } else if msg == MessageType::WindowPosChanged.into() {
+            let window_pos = WindowPos::read(accessor, Pointer::from_far(l_param).0)?;
+            self.send_move_and_size(accessor, h_wnd, &window_pos)?;
+            Ok(0)
+        } else if msg == MessageType::SetCursor.into() {
+            // Child windows let their parent decide first
+            let parent = match self.read_objects().user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.parent_handle,
+                _ => Handle::null(),
+            };
+            let parent_proc = match self.read_objects().user.get(parent) {
+                Some(UserObject::Window(parent_window)) => Some(parent_window.proc),
+                _ => None,
+            };
+            if let Some(parent_proc) = parent_proc {
+                let result =
+                    self.call_wndproc_sync(accessor, parent_proc, parent, msg, w_param, l_param)?;
+                if result != 0 {
+                    return Ok(result);
+                }
+            }
+            // TODO: show the cursor of the window class in the client area
+            Ok(0)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Tells a window where its client area went with WM_MOVE, and how big it is with WM_SIZE.
+    fn send_move_and_size(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        window_pos: &WindowPos,
+    ) -> Result<(), EmulatorError> {
+        let window = {
+            let objects = self.read_objects();
+            match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => objects
+                    .window_identifier(h_wnd)
+                    .and_then(|window_identifier| {
+                        let window_manager = objects.read_window_manager();
+                        window_manager
+                            .client_origin_of(window_identifier)
+                            .zip(window_manager.client_rect_of(window_identifier))
+                    })
+                    .map(|(client_origin, client_rect)| {
+                        (
+                            user_window.proc,
+                            user_window.style,
+                            client_origin,
+                            client_rect,
+                        )
+                    }),
+                _ => None,
+            }
+        };
+        let (proc, style, client_origin, client_rect) = match window {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+
+        if !window_pos.flags.contains(SetWindowPosFlags::NOMOVE) {
+            // The position of the client area is relative to the client area of the parent
+            let position = Point::new(window_pos.rect.left, window_pos.rect.top) + client_origin;
+            self.call_wndproc_sync(
+                accessor,
+                proc,
+                h_wnd,
+                MessageType::Move.into(),
+                0,
+                encode_u16_u16_to_u32(position.x as u16, position.y as u16),
+            )?;
+        }
+        if !window_pos.flags.contains(SetWindowPosFlags::NOSIZE) {
+            let size_type = if style.contains(WindowStyles::MINIMIZE) {
+                SizeType::Minimized
+            } else if style.contains(WindowStyles::MAXIMIZE) {
+                SizeType::Maximized
+            } else {
+                SizeType::Restored
+            };
+            self.call_wndproc_sync(
+                accessor,
+                proc,
+                h_wnd,
+                MessageType::Size.into(),
+                size_type.into(),
+                encode_u16_u16_to_u32(client_rect.right as u16, client_rect.bottom as u16),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sends WM_SYSCOMMAND to a window. For commands chosen with the mouse, lParam is the
+    /// point on the screen, and for SC_KEYMENU it is the character that was typed.
+    fn send_system_command(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        command: u16,
+        l_param: u32,
+    ) -> Result<(), EmulatorError> {
+        if let Some(proc) = self.window_proc_of(h_wnd) {
+            self.call_wndproc_sync(
+                accessor,
+                proc,
+                h_wnd,
+                MessageType::SysCommand.into(),
+                command,
+                l_param,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Which part of a window is at a point on the screen.
+    fn non_client_hit_test(&self, h_wnd: Handle, point: Point) -> HitTest {
+        let objects = self.read_objects();
+        let window = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => self
+                .screen_position_of(&objects, h_wnd)
+                .zip(self.window_size_of(&objects, h_wnd))
+                .map(|(position, size)| (user_window.style, position, size)),
+            _ => None,
+        };
+        match window {
+            Some((style, position, (width, height))) => {
+                let point = point - position;
+                let in_menu_bar = self
+                    .menu_bar_of(&objects, h_wnd)
+                    .is_some_and(|(_, bar)| bar.contains(point));
+                if in_menu_bar {
+                    HitTest::Menu
+                } else {
+                    non_client::hit_test(style, width, height, point)
+                }
+            }
+            None => HitTest::Nowhere,
+        }
+    }
+
+    /// The menu bar of a window and where it is, relative to the top left corner of the window.
+    /// It lies between the caption bar and the client area.
+    pub fn menu_bar_of(
+        &self,
+        objects: &ObjectEnvironment,
+        h_wnd: Handle,
+    ) -> Option<(Handle, Rect)> {
+        let user_window = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window,
+            _ => return None,
+        };
+        if user_window.menu == Handle::null()
+            || user_window
+                .style
+                .intersects(WindowStyles::CHILD | WindowStyles::MINIMIZE)
+        {
+            return None;
+        }
+        let window_identifier = objects.window_identifier(h_wnd)?;
+        let window_manager = objects.read_window_manager();
+        let window_rect = window_manager.window_rect_of(window_identifier)?;
+        let client_top = window_manager.client_origin_of(window_identifier)?.y;
+        let frame_rect = non_client::calculate_client_rect(
+            user_window.style,
+            Rect {
+                left: 0,
+                top: 0,
+                right: window_rect.right - window_rect.left,
+                bottom: window_rect.bottom - window_rect.top,
+            },
+        );
+        Some((
+            user_window.menu,
+            Rect {
+                bottom: client_top,
+                ..frame_rect
+            },
+        ))
+    }
+
+    /// Lets the user move a window by dragging its caption, or size it by dragging an edge,
+    /// starting from a point on the screen. Only an outline follows the mouse, and the window
+    /// goes there once the mouse button is released or Enter is pressed. Escape cancels.
+    fn track_move_size(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        hit_test: HitTest,
+        start: Point,
+    ) -> Result<(), EmulatorError> {
+        let window = {
+            let objects = self.read_objects();
+            objects
+                .window_identifier(h_wnd)
+                .and_then(|window_identifier| {
+                    let rect = objects
+                        .read_window_manager()
+                        .window_rect_of(window_identifier)?;
+                    let screen_position = self.screen_position_of(&objects, h_wnd)?;
+                    Some((window_identifier, rect, screen_position))
+                })
+        };
+        let (window_identifier, rect, screen_position) = match window {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        let min_max_info = match self.min_max_info(accessor, h_wnd)? {
+            Some(min_max_info) => min_max_info,
+            None => return Ok(()),
+        };
+
+        // The outline is drawn on the screen, and the mouse input goes to this window meanwhile
+        let parent_origin = screen_position - Point::new(rect.left, rect.top);
+        let start_rect = rect.offset(parent_origin.x, parent_origin.y);
+        {
+            let objects = self.read_objects();
+            let mut window_manager = objects.write_window_manager();
+            window_manager.set_capture(Some(window_identifier));
+            window_manager.set_tracking_rect(Some(start_rect));
+        }
+        let mut tracked_rect = start_rect;
+        let finished = loop {
+            let input = self.wait_for_input();
+            match input.message {
+                MessageType::MouseMove => {
+                    tracked_rect = min_max_info.track(start_rect, hit_test, input.point - start);
+                    self.read_objects()
+                        .write_window_manager()
+                        .set_tracking_rect(Some(tracked_rect));
+                }
+                MessageType::LButtonUp => break true,
+                MessageType::KeyDown if input.w_param == VK_RETURN as u16 => break true,
+                MessageType::KeyDown if input.w_param == VK_ESCAPE as u16 => break false,
+                _ => {}
+            }
+        };
+        {
+            let objects = self.read_objects();
+            let mut window_manager = objects.write_window_manager();
+            window_manager.set_capture(None);
+            window_manager.set_tracking_rect(None);
+        }
+
+        if finished {
+            let mut flags = SetWindowPosFlags::NOZORDER | SetWindowPosFlags::NOACTIVATE;
+            if hit_test == HitTest::Caption {
+                flags |= SetWindowPosFlags::NOSIZE;
+            }
+            self.set_window_pos(
+                accessor,
+                h_wnd,
+                Handle::null(),
+                tracked_rect.offset(-parent_origin.x, -parent_origin.y),
+                flags,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Shows a caption button pressed for as long as the mouse button is held down on it.
+    /// Returns whether the mouse button was released on the caption button.
+    fn track_caption_button(&self, h_wnd: Handle, button: HitTest) -> bool {
+        let window_identifier = match self.read_objects().window_identifier(h_wnd) {
+            Some(window_identifier) => window_identifier,
+            None => return false,
+        };
+        self.read_objects()
+            .write_window_manager()
+            .set_capture(Some(window_identifier));
+        let mut pressed = true;
+        self.paint_non_client(h_wnd, Some(button));
+        loop {
+            let input = self.wait_for_input();
+            match input.message {
+                MessageType::MouseMove => {
+                    let on_button = self.non_client_hit_test(h_wnd, input.point) == button;
+                    if on_button != pressed {
+                        pressed = on_button;
+                        self.paint_non_client(h_wnd, pressed.then_some(button));
+                    }
+                }
+                MessageType::LButtonUp => break,
+                _ => {}
+            }
+        }
+        self.read_objects().write_window_manager().set_capture(None);
+        self.paint_non_client(h_wnd, None);
+        pressed
+    }
+
+    /// The width and height of the client area of a window.
+    pub fn client_size_of(&self, h_wnd: Handle) -> (i16, i16) {
+        let objects = self.read_objects();
+        let rect = self
+            .get_client_rect(h_wnd, &objects)
+            .unwrap_or_else(Rect::zero);
+        (rect.right - rect.left, rect.bottom - rect.top)
+    }
+
+    /// Where a point on the screen is, relative to the client area of a window.
+    pub fn screen_to_client(&self, h_wnd: Handle, point: Point) -> Point {
+        let objects = self.read_objects();
+        self.client_screen_position_of(&objects, h_wnd)
+            .map_or(point, |origin| point - origin)
+    }
+
+    /// Sends a message to a window and returns the result of its window procedure. A window of
+    /// this task gets it straight away. A window of another task gets it once that task gets to
+    /// it, and this task handles the messages sent to it in the meantime.
+    pub fn send_message(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<u32, EmulatorError> {
+        let window = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                Some((user_window.process_id, user_window.proc))
+            }
+            _ => None,
+        };
+        match window {
+            Some((process_id, proc)) if process_id == self.process_id() => {
+                let (msg, w_param, l_param) =
+                    self.call_wndproc_hook(accessor, h_wnd, msg, w_param, l_param)?;
+                self.call_wndproc_sync(accessor, proc, h_wnd, msg, w_param, l_param)
+            }
+            Some((process_id, _)) => {
+                // The memory that l_param points to only means something to this task
+                let buffer = Pointer::from_far(l_param).0;
+                let parameter = if l_param == 0 {
+                    None
+                } else {
+                    self.pointer_parameter(h_wnd, msg, w_param)
+                };
+                let size = match parameter {
+                    None => None,
+                    Some(PointerParameter::String) => Some(accessor.strlen(buffer)? + 1),
+                    Some(PointerParameter::Bytes(size)) => Some(size),
+                    Some(PointerParameter::CountedBuffer) => {
+                        Some(accessor.memory().read_u16(buffer)?.max(2))
+                    }
+                    Some(PointerParameter::TextOfLength(length_message)) => {
+                        let length =
+                            self.send_message(accessor, h_wnd, length_message, w_param, 0)?;
+                        // LB_ERR and CB_ERR leave the buffer alone
+                        Some(u16::try_from(length).map_or(0, |length| length.saturating_add(1)))
+                    }
+                    Some(PointerParameter::Unsupported) => {
+                        debug!(
+                            "[user] Message {:x} points to memory that can't go to another task",
+                            msg
+                        );
+                        return Ok(0);
+                    }
+                };
+                let data = size
+                    .map(|size| {
+                        accessor
+                            .memory()
+                            .slice(buffer, buffer + size as u32)
+                            .map(<[u8]>::to_vec)
+                    })
+                    .transpose()?;
+                let message = PostedMessage {
+                    h_wnd,
+                    message: msg,
+                    w_param,
+                    l_param,
+                    time: self.read_objects().timers.tick_count(),
+                    point: Point::origin(),
+                };
+                let (result, data) =
+                    self.send_to_task(accessor, process_id, SentCall::Message(message), data)?;
+                if !data.is_empty() {
+                    accessor.memory_mut().copy_from(&data, buffer as usize)?;
+                }
+                Ok(result)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// What l_param points to for a message to a window, if it is a pointer. The messages of
+    /// the predefined controls mean something else for each kind of control. The messages that
+    /// applications define themselves are taken to carry no pointer.
+    fn pointer_parameter(&self, h_wnd: Handle, msg: u16, w_param: u16) -> Option<PointerParameter> {
+        if msg == MessageType::SetText.into() {
+            return Some(PointerParameter::String);
+        } else if msg == MessageType::GetText.into() {
+            return Some(PointerParameter::Bytes(w_param));
+        } else if msg == MessageType::GetMinMaxInfo.into() {
+            return Some(PointerParameter::Bytes(MinMaxInfo::SIZE));
+        } else if msg == MessageType::WindowPosChanging.into()
+            || msg == MessageType::WindowPosChanged.into()
+        {
+            return Some(PointerParameter::Bytes(WindowPos::SIZE));
+        } else if msg == MessageType::NcCalcSize.into() {
+            // With wParam set, NCCALCSIZE_PARAMS points to a WINDOWPOS
+            return Some(if w_param == 0 {
+                PointerParameter::Bytes(Rect::SIZE)
+            } else {
+                PointerParameter::Unsupported
+            });
+        } else if msg == MessageType::Create.into() || msg == MessageType::NcCreate.into() {
+            return Some(PointerParameter::Unsupported);
+        }
+        let tab_stops = PointerParameter::Bytes(w_param.saturating_mul(2));
+        self.with_control(h_wnd, |control, _| match control {
+            Control::Edit(_) => {
+                if msg == EditMessage::ReplaceSel.into() {
+                    Some(PointerParameter::String)
+                } else if msg == EditMessage::GetLine.into() {
+                    Some(PointerParameter::CountedBuffer)
+                } else if msg == EditMessage::GetRect.into()
+                    || msg == EditMessage::SetRect.into()
+                    || msg == EditMessage::SetRectNp.into()
+                {
+                    Some(PointerParameter::Bytes(Rect::SIZE))
+                } else if msg == EditMessage::SetTabStops.into() {
+                    Some(tab_stops)
+                } else if msg == EditMessage::SetWordBreak.into() {
+                    Some(PointerParameter::Unsupported)
+                } else {
+                    None
+                }
+            }
+            Control::ListBox(_) => {
+                if msg == ListBoxMessage::AddString.into()
+                    || msg == ListBoxMessage::InsertString.into()
+                    || msg == ListBoxMessage::FindString.into()
+                    || msg == ListBoxMessage::FindStringExact.into()
+                    || msg == ListBoxMessage::SelectString.into()
+                    || msg == ListBoxMessage::Dir.into()
+                {
+                    Some(PointerParameter::String)
+                } else if msg == ListBoxMessage::GetText.into() {
+                    Some(PointerParameter::TextOfLength(
+                        ListBoxMessage::GetTextLen.into(),
+                    ))
+                } else if msg == ListBoxMessage::GetItemRect.into() {
+                    Some(PointerParameter::Bytes(Rect::SIZE))
+                } else if msg == ListBoxMessage::GetSelItems.into()
+                    || msg == ListBoxMessage::SetTabStops.into()
+                {
+                    Some(tab_stops)
+                } else {
+                    None
+                }
+            }
+            Control::ComboBox(_) => {
+                if msg == ComboBoxMessage::AddString.into()
+                    || msg == ComboBoxMessage::InsertString.into()
+                    || msg == ComboBoxMessage::FindString.into()
+                    || msg == ComboBoxMessage::FindStringExact.into()
+                    || msg == ComboBoxMessage::SelectString.into()
+                    || msg == ComboBoxMessage::Dir.into()
+                {
+                    Some(PointerParameter::String)
+                } else if msg == ComboBoxMessage::GetLbText.into() {
+                    Some(PointerParameter::TextOfLength(
+                        ComboBoxMessage::GetLbTextLen.into(),
+                    ))
+                } else if msg == ComboBoxMessage::GetDroppedControlRect.into() {
+                    Some(PointerParameter::Bytes(Rect::SIZE))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .flatten()
+    }
+
+    /// Sends a message to a window of another task, or calls a hook of another task, and waits
+    /// for the reply. The data is the memory that l_param points to, if any, and comes back as
+    /// the window procedure or the hook left it.
+    fn send_to_task(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        process_id: ProcessId,
+        call: SentCall,
+        data: Option<Vec<u8>>,
+    ) -> Result<(u32, Vec<u8>), EmulatorError> {
+        let message_queue = self
+            .message_queues
+            .read()
+            .unwrap()
+            .get(&process_id)
+            .cloned();
+        let reply = match message_queue {
+            Some(message_queue) => message_queue.send_from(self.process_id(), call, data),
+            None => return Ok((0, Vec::new())),
+        };
+        self.scheduler.wake(process_id);
+        loop {
+            // The other task may send messages back before it replies
+            self.handle_sent_messages(accessor)?;
+            if let Some(answer) = reply.take() {
+                return Ok(answer);
+            }
+            self.scheduler.wait(self.process_id());
+        }
+    }
+
+    /// Posts a message to the queue of the task that a window belongs to, without waiting for it
+    /// to be handled. A null window posts it to the queue of this task.
+    /// Returns false if there is no such window.
+    fn post_message_to(&self, message: PostedMessage) -> bool {
+        let process_id = if message.h_wnd == Handle::null() {
+            self.process_id()
+        } else {
+            match self.read_objects().user.get(message.h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.process_id,
+                _ => return false,
+            }
+        };
+        match self.message_queues.read().unwrap().get(&process_id) {
+            Some(message_queue) => message_queue.post(message),
+            None => return false,
+        }
+        self.scheduler.wake(process_id);
+        true
+    }
+
+    /// The top-level windows of one task, or of all tasks. Those of all tasks get the messages
+    /// sent or posted to HWND_BROADCAST.
+    fn top_level_windows(&self, process_id: Option<ProcessId>) -> Vec<Handle> {
+        let objects = self.read_objects();
+        let mut windows = objects
+            .user
+            .iter()
+            .filter_map(|(handle, object)| match object {
+                UserObject::Window(user_window) if user_window.parent_handle == Handle::null() => {
+                    Some(handle)
+                }
+                _ => None,
+            })
+            .filter(|&handle| {
+                process_id.is_none()
+                    || objects
+                        .window_identifier(handle)
+                        .is_some_and(|identifier| Some(identifier.process_id) == process_id)
+            })
+            .collect::<Vec<_>>();
+        windows.sort_by_key(|handle| handle.as_u16());
+        windows
+    }
+
+    #[api_function]
+    fn post_message(
+        &self,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let message = PostedMessage {
+            h_wnd,
+            message: msg,
+            w_param,
+            l_param,
+            time: self.read_objects().timers.tick_count(),
+            point: Point::origin(),
+        };
+        let posted = if h_wnd.as_u16() == HWND_BROADCAST {
+            for h_wnd in self.top_level_windows(None) {
+                self.post_message_to(PostedMessage { h_wnd, ..message });
+            }
+            true
+        } else {
+            self.post_message_to(message)
+        };
+        Ok(ReturnValue::U16(posted.into()))
+    }
+
+    #[api_function]
+    fn internal_send_message(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        if h_wnd.as_u16() == HWND_BROADCAST {
+            for h_wnd in self.top_level_windows(None) {
+                self.send_message(&mut accessor, h_wnd, msg, w_param, l_param)?;
+            }
+            return Ok(ReturnValue::U32(0));
+        }
+        let result = self.send_message(&mut accessor, h_wnd, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
+
+    #[api_function]
+    fn in_send_message(&self) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(self.in_send_message.get().into()))
+    }
+
+    #[api_function]
+    fn get_message_time(&self) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U32(self.last_message_time_and_point.get().0))
+    }
+
+    #[api_function]
+    fn get_message_pos(&self) -> Result<ReturnValue, EmulatorError> {
+        let point = self.last_message_time_and_point.get().1;
+        Ok(ReturnValue::U32(encode_u16_u16_to_u32(
+            point.x as u16,
+            point.y as u16,
+        )))
+    }
+
+    #[api_function]
+    fn check_radio_button(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        first_id: u16,
+        last_id: u16,
+        check_id: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        for id in first_id..=last_id {
+            self.send_dialog_item_message(
+                &mut accessor,
+                h_dlg,
+                id,
+                ButtonMessage::SetCheck.into(),
+                (id == check_id).into(),
+                0,
+            )?;
+        }
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn set_scroll_pos(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        bar: u16,
+        pos: u16,
+        redraw: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // TODO: the scroll bars that windows with WS_HSCROLL and WS_VSCROLL have
+        if bar != SB_CTL {
+            return Ok(ReturnValue::U16(0));
+        }
+        let old = self.send_message(
+            &mut accessor,
+            h_wnd,
+            ScrollBarMessage::SetPos.into(),
+            pos,
+            redraw.into(),
+        )?;
+        Ok(ReturnValue::U16(old as u16))
+    }
+
+    #[api_function]
+    fn get_scroll_pos(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        bar: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        if bar != SB_CTL {
+            return Ok(ReturnValue::U16(0));
+        }
+        let pos = self.send_message(&mut accessor, h_wnd, ScrollBarMessage::GetPos.into(), 0, 0)?;
+        Ok(ReturnValue::U16(pos as u16))
+    }
+
+    #[api_function]
+    fn set_scroll_range(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        bar: u16,
+        min: u16,
+        max: u16,
+        redraw: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        if bar == SB_CTL {
+            self.send_message(
+                &mut accessor,
+                h_wnd,
+                ScrollBarMessage::SetRange.into(),
+                redraw,
+                encode_u16_u16_to_u32(min, max),
+            )?;
+        }
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn get_scroll_range(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        bar: u16,
+        min: Pointer,
+        max: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let range = if bar == SB_CTL {
+            self.send_message(
+                &mut accessor,
+                h_wnd,
+                ScrollBarMessage::GetRange.into(),
+                0,
+                0,
+            )?
+        } else {
+            0
+        };
+        let memory = accessor.memory_mut();
+        memory.write_u16(min.0, range as u16)?;
+        memory.write_u16(max.0, (range >> 16) as u16)?;
+        Ok(ReturnValue::None)
+    }
+
+    pub fn get_client_rect(&self, h_wnd: Handle, objects: &ObjectEnvironment) -> Option<Rect> {
+        if h_wnd.as_u16() == DESKTOP_WINDOW {
+            return Some(Rect {
+                left: 0,
+                top: 0,
+                right: SCREEN_WIDTH,
+                bottom: SCREEN_HEIGHT,
+            });
+        }
+        objects
+            .window_identifier(h_wnd)
+            .and_then(|window_identifier| {
+                objects
+                    .read_window_manager()
+                    .client_rect_of(window_identifier)
+            })
+    }
+
+    /// The window whose bitmap a device context of a window draws on, and where the window lies
+    /// on that bitmap. A device context for the client area starts at the client area.
+    fn device_context_target(
+        &self,
+        objects: &ObjectEnvironment,
+        h_wnd: Handle,
+        client_area: bool,
+    ) -> Option<(WindowIdentifier, Point)> {
+        let user_window = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window,
+            _ => return None,
+        };
+        let window_identifier = WindowIdentifier {
+            process_id: user_window.process_id,
+            window_handle: h_wnd,
+        };
+        let window_manager = objects.read_window_manager();
+        let mut translation = if client_area {
+            window_manager.client_origin_of(window_identifier)?
+        } else {
+            Point::origin()
+        };
+        if user_window.class_style.contains(ClassStyles::PARENT_DC)
+            && user_window.parent_handle != Handle::null()
+        {
+            // TODO: nested CS_PARENTDC: how to handle them?
+            let parent_window_identifier =
+                window_identifier.other_handle(user_window.parent_handle);
+            translation += window_manager.position_of(window_identifier)?
+                + window_manager.client_origin_of(parent_window_identifier)?;
+            Some((parent_window_identifier, translation))
+        } else {
+            Some((window_identifier, translation))
+        }
+    }
+
+    pub fn get_dc(&self, h_wnd: Handle) -> Option<Handle> {
+        let mut objects = self.write_objects();
+        let (bitmap_window_identifier, translation) =
+            self.device_context_target(&objects, h_wnd, true)?;
+        // The caret must not get in the way of drawing on the bitmap it is on
+        let hides_caret = {
+            let mut window_manager = objects.write_window_manager();
+            let hides_caret = window_manager
+                .caret()
+                .is_some_and(|caret| caret.bitmap_window_identifier() == bitmap_window_identifier);
+            if hides_caret {
+                window_manager.hide_caret();
+            }
+            hides_caret
+        };
+        let dc = DeviceContext {
+            h_wnd,
+            bitmap_window_identifier,
+            bitmap_translation: translation,
+            position: Point::origin(),
+            selected_brush: Handle::null(),
+            selected_pen: Handle::null(),
+            raster_op: RasterOp::CopyPen,
+            clip: None,
+            hides_caret,
+        };
+        objects.gdi.register(GdiObject::DC(dc))
+    }
+
+    /// Draws the frame and the caption bar of a window, possibly with a caption button pressed.
+    fn paint_non_client(&self, h_wnd: Handle, pressed: Option<HitTest>) {
+        let objects = self.read_objects();
+        let (style, title, active) = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => (
+                user_window.style,
+                user_window.title.clone(),
+                user_window.active_frame,
+            ),
+            _ => return,
+        };
+        let (width, height) = match self.window_size_of(&objects, h_wnd) {
+            Some(size) => size,
+            None => return,
+        };
+        let (bitmap_window_identifier, translation) =
+            match self.device_context_target(&objects, h_wnd, false) {
+                Some(target) => target,
+                None => return,
+            };
+        let mut window_manager = objects.write_window_manager();
+        if let Some(bitmap) = window_manager.paint_bitmap_for(bitmap_window_identifier) {
+            let mut bitmap = BitmapView::new(bitmap, translation);
+            non_client::paint(
+                &mut bitmap,
+                style,
+                (width, height),
+                title.as_slice(),
+                active,
+                pressed,
+                &|color| self.get_system_color(color),
+            );
+        }
+    }
+
+    /// Draws the menu bar of a window, possibly with an item selected.
+    pub fn paint_menu_bar(&self, h_wnd: Handle, selected: Option<usize>) {
+        let objects = self.read_objects();
+        let (h_menu, bar) = match self.menu_bar_of(&objects, h_wnd) {
+            Some(menu_bar) => menu_bar,
+            None => return,
+        };
+        let menu = match objects.user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu,
+            _ => return,
+        };
+        let (bitmap_window_identifier, translation) =
+            match self.device_context_target(&objects, h_wnd, false) {
+                Some(target) => target,
+                None => return,
+            };
+        let mut window_manager = objects.write_window_manager();
+        if let Some(bitmap) = window_manager.paint_bitmap_for(bitmap_window_identifier) {
+            let mut bitmap = BitmapView::new(bitmap, translation);
+            menu::paint_bar(&mut bitmap, menu, bar, selected, &|color| {
+                self.get_system_color(color)
+            });
+        }
+    }
+
+    #[api_function]
+    fn internal_get_dc(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(
+            self.get_dc(h_wnd).unwrap_or(Handle::null()).as_u16(),
+        ))
+    }
+
+    /// Gets a device context for the client area of a window that only draws inside of the
+    /// given rectangle.
+    fn get_clipped_dc(&self, h_wnd: Handle, rect: Rect) -> Option<Handle> {
+        let hdc = self.get_dc(h_wnd)?;
+        if let Some(GdiObject::DC(dc)) = self.write_objects().gdi.get_mut(hdc) {
+            dc.clip = Some(rect);
+        }
+        Some(hdc)
+    }
+
+    /// Starts painting the update region of a window: draws the frame if it has to be, lets
+    /// the window erase the background with WM_ERASEBKGND, and validates the region.
+    /// The device context only draws inside of the region.
+    pub fn begin_paint(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<Option<Paint>, EmulatorError> {
+        let redraw_frame = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.redraw_frame,
+            _ => return Ok(None),
+        };
+        if redraw_frame {
+            self.paint_frame(accessor, h_wnd)?;
+        }
+        let (region, erase) = match self.write_objects().user.get_mut(h_wnd) {
+            Some(UserObject::Window(user_window)) => (
+                user_window.update_region.take(),
+                std::mem::take(&mut user_window.erase_background),
+            ),
+            _ => return Ok(None),
+        };
+        let rect = region.unwrap_or_else(Rect::zero);
+        // Children get painted after their parent, so whatever the parent paints over them gets
+        // painted again, even if they were painted before
+        self.invalidate_children_in(&mut self.write_objects(), h_wnd, rect, true);
+        let hdc = match self.get_clipped_dc(h_wnd, rect) {
+            Some(hdc) => hdc,
+            None => return Ok(None),
+        };
+        // The window procedure has to erase the background itself if WM_ERASEBKGND didn't
+        let f_erase = erase
+            && self.send_message(
+                accessor,
+                h_wnd,
+                MessageType::EraseBkGnd.into(),
+                hdc.as_u16(),
+                0,
+            )? == 0;
+        Ok(Some(Paint { hdc, f_erase, rect }))
+    }
+
+    #[api_function]
+    fn internal_begin_paint(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        paint_ptr: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        if let Some(paint) = self.begin_paint(&mut accessor, h_wnd)? {
+            accessor
+                .memory_mut()
+                .write_u16(paint_ptr.0, paint.hdc.as_u16())?;
+            accessor
+                .memory_mut()
+                .write_8(paint_ptr.0.wrapping_add(2), paint.f_erase.into())?;
+            accessor.write_rect(paint_ptr.0.wrapping_add(4), &paint.rect)?;
+            Ok(ReturnValue::U16(paint.hdc.as_u16()))
+        } else {
+            Ok(ReturnValue::U16(0))
+        }
+    }
+
+    pub fn release_dc(&self, _h_wnd: Handle, hdc: Handle) -> bool {
+        // TODO: check whether the hdc belongs to the h_wnd ?
+        // TODO: this should probably cause a flip of the front and back bitmap for the given window
+        let mut objects = self.write_objects();
+        if let Some(GdiObject::DC(dc)) = objects.gdi.get(hdc) {
+            if dc.hides_caret {
+                objects.write_window_manager().show_caret();
+            }
+        }
+        objects.gdi.deregister(hdc);
+        true
+    }
+
+    #[api_function]
+    fn internal_release_dc(
+        &self,
+        _h_wnd: Handle,
+        hdc: Handle,
+    ) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(self.release_dc(_h_wnd, hdc).into()))
+    }
+
+    pub fn end_paint(&self, _h_wnd: Handle, hdc: Handle) -> u16 {
+        self.release_dc(_h_wnd, hdc).into()
+    }
+
+    #[api_function]
+    fn internal_end_paint(
+        &self,
+        accessor: EmulatorAccessor,
+        _h_wnd: Handle,
+        paint: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // TODO: this should probably cause a flip of the front and back bitmap for the given window
+        let handle = accessor.memory().read_u16(paint.0)?;
+        Ok(ReturnValue::U16(self.end_paint(_h_wnd, handle.into())))
+    }
+
+    /// Moves the caret to a position in the client area of the window that owns it.
+    fn place_caret(&self, objects: &ObjectEnvironment, position: Point) {
+        let owner = match objects.read_window_manager().caret() {
+            Some(caret) => caret.owner,
+            None => return,
+        };
+        let (bitmap_window_identifier, translation) =
+            match self.device_context_target(objects, owner.window_handle, true) {
+                Some(target) => target,
+                None => return,
+            };
+        let mut window_manager = objects.write_window_manager();
+        let client_rect = window_manager
+            .client_rect_of(owner)
+            .unwrap_or_else(Rect::zero);
+        window_manager.place_caret(position, bitmap_window_identifier, translation, client_rect);
+    }
+
+    /// Whether the caret belongs to this task, and to the given window unless it is null.
+    fn owns_caret(&self, objects: &ObjectEnvironment, h_wnd: Handle) -> bool {
+        objects.read_window_manager().caret().is_some_and(|caret| {
+            caret.owner.process_id == self.process_id()
+                && (h_wnd == Handle::null() || caret.owner.window_handle == h_wnd)
+        })
+    }
+
+    #[api_function]
+    fn create_caret(
+        &self,
+        h_wnd: Handle,
+        h_bitmap: Handle,
+        width: i16,
+        height: i16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        let owner = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window))
+                if user_window.process_id == self.process_id() =>
+            {
+                WindowIdentifier {
+                    process_id: user_window.process_id,
+                    window_handle: h_wnd,
+                }
+            }
+            _ => return Ok(ReturnValue::None),
+        };
+        // A zero size stands for the width of a window border
+        let size_or_border = |size: i16| {
+            if size == 0 {
+                non_client::BORDER_WIDTH
+            } else {
+                size
+            }
+        };
+        // TODO: carets in the shape of a bitmap, they are solid for now
+        let gray = h_bitmap.as_u16() == 1;
+        objects.write_window_manager().create_caret(Caret::new(
+            owner,
+            size_or_border(width),
+            size_or_border(height),
+            gray,
+        ));
+        self.place_caret(&objects, Point::origin());
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn destroy_caret(&self) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        if self.owns_caret(&objects, Handle::null()) {
+            objects.write_window_manager().destroy_caret();
+        }
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn hide_caret(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        if self.owns_caret(&objects, h_wnd) {
+            objects.write_window_manager().hide_caret();
+        }
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn show_caret(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        if self.owns_caret(&objects, h_wnd) {
+            objects.write_window_manager().show_caret();
+        }
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn set_caret_pos(&self, x: i16, y: i16) -> Result<ReturnValue, EmulatorError> {
+        let objects = self.read_objects();
+        if self.owns_caret(&objects, Handle::null()) {
+            self.place_caret(&objects, Point::new(x, y));
+        }
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn get_caret_pos(
+        &self,
+        mut accessor: EmulatorAccessor,
+        point: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let position = self
+            .read_objects()
+            .read_window_manager()
+            .caret()
+            .map_or(Point::origin(), |caret| caret.position());
+        accessor.write_point(point.0, position)?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn set_caret_blink_time(&self, milliseconds: u16) -> Result<ReturnValue, EmulatorError> {
+        self.read_objects()
+            .write_window_manager()
+            .set_caret_blink_time(milliseconds);
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn get_caret_blink_time(&self) -> Result<ReturnValue, EmulatorError> {
+        let blink_time = self.read_objects().read_window_manager().caret_blink_time();
+        Ok(ReturnValue::U16(blink_time))
+    }
+
+    #[api_function]
+    fn fill_rect(
+        &self,
+        accessor: EmulatorAccessor,
+        h_dc: Handle,
+        rect: Pointer,
+        h_brush: Handle,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let rect = accessor.read_rect(rect.0)?;
+        let objects = self.read_objects();
+        if let Some(GdiObject::SolidBrush(color)) = objects.gdi.get(h_brush) {
+            objects
+                .with_paint_bitmap_for(h_dc, &|mut bitmap, _| bitmap.fill_rectangle(rect, *color));
+            Ok(ReturnValue::U16(1))
+        } else {
+            Ok(ReturnValue::U16(0))
+        }
+    }
+
+    #[api_function]
+    fn set_timer(
+        &self,
+        h_wnd: Handle,
+        id_event: u16,
+        elapse: u16,
+        timer_proc_segment: u16,
+        timer_proc_offset: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        debug!(
+            "[user] SET TIMER {:?}, {:x}, {}, {:x}, {:x}",
+            h_wnd, id_event, elapse, timer_proc_segment, timer_proc_offset
+        );
+        let mut objects = self.write_objects();
+        if h_wnd != Handle::null() && objects.window_identifier(h_wnd).is_none() {
+            return Ok(ReturnValue::U16(0));
+        }
+        let timer_proc = if timer_proc_segment == 0 && timer_proc_offset == 0 {
+            None
+        } else {
+            Some(SegmentAndOffset {
+                segment: timer_proc_segment,
+                offset: timer_proc_offset,
+            })
+        };
+        let id = objects
+            .timers
+            .set_timer(self.process_id(), h_wnd, id_event, elapse, timer_proc);
+        // Timers of a window are identified by the id the caller chose, which may be 0.
+        // The return value must be non-zero on success though.
+        Ok(ReturnValue::U16(match id {
+            Some(id) if h_wnd == Handle::null() => id,
+            Some(_) => id_event.max(1),
+            None => 0,
+        }))
+    }
+
+    #[api_function]
+    fn kill_timer(&self, h_wnd: Handle, id_event: u16) -> Result<ReturnValue, EmulatorError> {
+        let success = self
+            .write_objects()
+            .timers
+            .kill_timer(self.process_id(), h_wnd, id_event);
+        Ok(ReturnValue::U16(success.into()))
+    }
+
+    #[api_function]
+    fn get_window_rect(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        rect_ptr: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        println!("GET WINDOW RECT {:?}", h_wnd);
+        let rect = {
+            let objects = self.read_objects();
+            if h_wnd.as_u16() == DESKTOP_WINDOW {
+                self.get_client_rect(h_wnd, &objects)
+            } else {
+                // The rectangle is on the screen, also for child windows
+                self.screen_position_of(&objects, h_wnd)
+                    .zip(self.window_size_of(&objects, h_wnd))
+                    .map(|(position, (width, height))| Rect {
+                        left: position.x,
+                        top: position.y,
+                        right: position.x + width,
+                        bottom: position.y + height,
+                    })
+            }
+        };
+        if let Some(rect) = rect {
+            println!("Rect is {:?}", rect);
+            accessor.write_rect(rect_ptr.0, &rect)?;
+            Ok(ReturnValue::U16(1))
+        } else {
+            Ok(ReturnValue::U16(0))
+        }
+    }
+
+    #[api_function]
+    fn internal_get_client_rect(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        rect_ptr: Pointer,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let rect = {
+            let objects = self.read_objects();
+            self.get_client_rect(h_wnd, &objects)
+        };
+        if let Some(rect) = rect {
+            accessor.write_rect(rect_ptr.0, &rect)?;
+            println!("GET CLIENT RECT {:?}", rect);
+            Ok(ReturnValue::U16(1))
+        } else {
+            Ok(ReturnValue::U16(0))
+        }
+    }
+
+    #[api_function]
+    fn set_window_text(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        text: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let proc = match self.window_proc_of(h_wnd) {
+            Some(proc) => proc,
+            None => return Ok(ReturnValue::None),
+        };
+        self.call_wndproc_sync(
+            &mut accessor,
+            proc,
+            h_wnd,
+            MessageType::SetText.into(),
+            0,
+            text,
+        )?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn message_box(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_wnd: Handle,
+        text: Pointer,
+        caption: Pointer,
+        style: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        if let Some(answer) = self.message_box_answer {
+            return Ok(ReturnValue::U16(answer.answer(style)));
+        }
+        let text = if text.0 == 0 {
+            Vec::new()
+        } else {
+            accessor.clone_string(text.0, false)?.as_slice().to_vec()
+        };
+        let caption = if caption.0 == 0 {
+            None
+        } else {
+            Some(accessor.clone_string(caption.0, false)?)
+        };
+        let template = message_box::template(
+            &text,
+            caption.as_ref().map(|caption| caption.as_slice()),
+            style,
+            (SCREEN_WIDTH, SCREEN_HEIGHT),
+        );
+        let proc = self.message_box_dialog_proc;
+        let h_dlg = self.create_dialog_from_template(
+            &mut accessor,
+            &template,
+            h_wnd,
+            encode_u16_u16_to_u32(proc.offset, proc.segment),
+            0,
+        )?;
+        // MessageBox fails with 0
+        if h_dlg == Handle::null() {
+            return Ok(ReturnValue::U16(0));
+        }
+        let result = self.run_modal_dialog(&mut accessor, h_dlg, MessageFilterCode::MessageBox)?;
+        Ok(ReturnValue::U16(result))
+    }
+
+    #[api_function]
+    fn message_box_dialog_proc(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        msg: u16,
+        w_param: u16,
+        _l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let handled = if msg == MessageType::InitDialog.into() {
+            // The default button starts with the focus instead of the first button
+            let default_button = self.dialog_item(h_dlg, self.default_button_id(h_dlg));
+            if let Some(default_button) = default_button {
+                self.set_dialog_focus(&mut accessor, h_dlg, default_button)?;
+            }
+            false
+        } else if msg == MessageType::Command.into() {
+            // Escape stands for OK in the message box with only an OK button, which is the only
+            // one with OK and without Cancel, and does nothing in the others without Cancel
+            let id = if w_param == IDCANCEL && self.dialog_item(h_dlg, IDCANCEL).is_none() {
+                IDOK
+            } else {
+                w_param
+            };
+            if self.dialog_item(h_dlg, id).is_some() {
+                self.with_dialog(h_dlg, |dialog| dialog.result = Some(id));
+            }
+            true
+        } else {
+            false
+        };
+        Ok(ReturnValue::U16(handled.into()))
+    }
+
+    /// Enables or disables a window, and tells it with WM_ENABLE if that changes anything. A
+    /// window that gets disabled loses the focus. Returns whether it was enabled before.
+    pub fn enable_window(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        enable: bool,
+    ) -> Result<bool, EmulatorError> {
+        if self.window_proc_of(h_wnd).is_none() {
+            return Ok(false);
+        }
+        let was_enabled = self.set_enabled(h_wnd, enable);
+        if was_enabled == enable {
+            return Ok(was_enabled);
+        }
+        if !enable {
+            let focus = self.focus();
+            if focus != Handle::null() && self.is_within(&self.read_objects(), focus, h_wnd) {
+                self.set_focus(accessor, Handle::null())?;
+            }
+        }
+        self.send_message(
+            accessor,
+            h_wnd,
+            MessageType::Enable.into(),
+            enable.into(),
+            0,
+        )?;
+        // The predefined controls look different when they are disabled
+        if self.with_control(h_wnd, |_, _| ()).is_some() {
+            self.repaint_control(h_wnd);
+        }
+        Ok(was_enabled)
+    }
+
+    /// The window that has the keyboard focus, of any task.
+    fn focus(&self) -> Handle {
+        self.read_objects()
+            .read_window_manager()
+            .focus()
+            .map_or(Handle::null(), |identifier| identifier.window_handle)
+    }
+
+    /// The active top-level window, of any task.
+    pub fn active_window(&self) -> Handle {
+        self.read_objects()
+            .read_window_manager()
+            .active_window()
+            .map_or(Handle::null(), |identifier| identifier.window_handle)
+    }
+
+    /// The top-level window that a window is in, or the window itself if it is top-level.
+    fn top_level_of(&self, objects: &ObjectEnvironment, h_wnd: Handle) -> Handle {
+        let mut h_wnd = h_wnd;
+        while let Some(UserObject::Window(user_window)) = objects.user.get(h_wnd) {
+            if user_window.parent_handle == Handle::null() {
+                break;
+            }
+            h_wnd = user_window.parent_handle;
+        }
+        h_wnd
+    }
+
+    /// Whether a window is the given ancestor or is somewhere in it.
+    fn is_within(&self, objects: &ObjectEnvironment, h_wnd: Handle, ancestor: Handle) -> bool {
+        let mut h_wnd = h_wnd;
+        while h_wnd != Handle::null() {
+            if h_wnd == ancestor {
+                return true;
+            }
+            h_wnd = match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.parent_handle,
+                _ => return false,
+            };
+        }
+        false
+    }
+
+    /// Gives the keyboard focus to a window, or to no window for the null handle. The top-level
+    /// window it is in gets activated first. The window that had the focus gets WM_KILLFOCUS and
+    /// the one that gets it WM_SETFOCUS. Returns the window that had the focus.
+    pub fn set_focus(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<Handle, EmulatorError> {
+        let (identifier, top_level, h_wnd_parent) = if h_wnd == Handle::null() {
+            (None, Handle::null(), Handle::null())
+        } else {
+            let objects = self.read_objects();
+            match (objects.window_identifier(h_wnd), objects.user.get(h_wnd)) {
+                (Some(identifier), Some(UserObject::Window(user_window))) => (
+                    Some(identifier),
+                    self.top_level_of(&objects, h_wnd),
+                    user_window.parent_handle,
+                ),
+                _ => return Ok(Handle::null()),
+            }
+        };
+        let focus = self.focus();
+        if focus != h_wnd
+            && self.call_cbt_hook(
+                accessor,
+                CbtCode::SetFocus,
+                h_wnd.as_u16(),
+                focus.as_u16().into(),
+            )?
+        {
+            return Ok(Handle::null());
+        }
+        if top_level != Handle::null() && top_level != self.active_window() {
+            self.activate_window(accessor, top_level, ActivateState::Active)?;
+        }
+
+        let previous = self.focus();
+        if previous == h_wnd {
+            return Ok(previous);
+        }
+        self.read_objects()
+            .write_window_manager()
+            .set_focus(identifier);
+        // A dialog box remembers which of its controls had the focus last
+        if h_wnd_parent != Handle::null() {
+            self.with_dialog(h_wnd_parent, |dialog| dialog.focus = h_wnd);
+        }
+        if previous != Handle::null() {
+            self.send_message(
+                accessor,
+                previous,
+                MessageType::KillFocus.into(),
+                h_wnd.as_u16(),
+                0,
+            )?;
+        }
+        if h_wnd != Handle::null() {
+            self.send_message(
+                accessor,
+                h_wnd,
+                MessageType::SetFocus.into(),
+                previous.as_u16(),
+                0,
+            )?;
+        }
+        Ok(previous)
+    }
+
+    /// Activates a top-level window, or no window for the null handle, and raises it together
+    /// with the windows it owns. The window that was active gets WM_NCACTIVATE and WM_ACTIVATE
+    /// to tell it that it isn't anymore, and then the new one gets them. The tasks get
+    /// WM_ACTIVATEAPP when the active window moves to another task. Returns the window that was
+    /// active.
+    fn activate_window(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        state: ActivateState,
+    ) -> Result<Handle, EmulatorError> {
+        let (identifier, minimized) = if h_wnd == Handle::null() {
+            (None, false)
+        } else {
+            let objects = self.read_objects();
+            match (objects.window_identifier(h_wnd), objects.user.get(h_wnd)) {
+                (Some(identifier), Some(UserObject::Window(user_window))) => (
+                    Some(identifier),
+                    user_window.style.contains(WindowStyles::MINIMIZE),
+                ),
+                _ => return Ok(Handle::null()),
+            }
+        };
+        let active = self.active_window();
+        if h_wnd != Handle::null() && h_wnd != active {
+            let structure = CbtActivateStruct {
+                mouse: state == ActivateState::ClickActive,
+                h_wnd_active: active,
+            };
+            let result = self.call_hook_with_structure(
+                accessor,
+                WH_CBT,
+                CbtCode::Activate.into(),
+                h_wnd.as_u16(),
+                &structure,
+            )?;
+            if matches!(result, Some((result, _)) if result != 0) {
+                return Ok(Handle::null());
+            }
+        }
+        let previous = {
+            let objects = self.read_objects();
+            let mut window_manager = objects.write_window_manager();
+            let previous = window_manager.active_window();
+            if previous == identifier {
+                return Ok(h_wnd);
+            }
+            window_manager.set_active_window(identifier);
+            if let Some(identifier) =
+                identifier.filter(|&identifier| window_manager.is_shown(identifier))
+            {
+                window_manager.show_window(identifier);
+                let owned = objects
+                    .user
+                    .iter()
+                    .filter_map(|(handle, object)| match object {
+                        UserObject::Window(user_window) if user_window.owner_handle == h_wnd => {
+                            objects.window_identifier(handle)
+                        }
+                        _ => None,
+                    })
+                    .filter(|&owned| window_manager.is_shown(owned))
+                    .collect::<Vec<_>>();
+                for owned in owned {
+                    window_manager.show_window(owned);
+                }
+            }
+            previous
+        };
+        let previous_handle =
+            previous.map_or(Handle::null(), |identifier| identifier.window_handle);
+
+        // The focus goes along with the window that it was in
+        let focus = self.focus();
+        if previous_handle != Handle::null()
+            && focus != Handle::null()
+            && self.is_within(&self.read_objects(), focus, previous_handle)
+        {
+            self.set_focus(accessor, Handle::null())?;
+        }
+        if previous_handle != Handle::null() {
+            self.send_message(
+                accessor,
+                previous_handle,
+                MessageType::NcActivate.into(),
+                0,
+                0,
+            )?;
+            self.send_message(
+                accessor,
+                previous_handle,
+                MessageType::Activate.into(),
+                ActivateState::Inactive.into(),
+                encode_u16_u16_to_u32(h_wnd.as_u16(), 0),
+            )?;
+        }
+        let previous_process_id = previous.map(|identifier| identifier.process_id);
+        let process_id = identifier.map(|identifier| identifier.process_id);
+        if previous_process_id != process_id {
+            // TODO: lParam should be the task that the other windows belong to
+            if let Some(previous_process_id) = previous_process_id {
+                for h_wnd in self.top_level_windows(Some(previous_process_id)) {
+                    self.send_message(accessor, h_wnd, MessageType::ActivateApp.into(), 0, 0)?;
+                }
+            }
+            if let Some(process_id) = process_id {
+                for h_wnd in self.top_level_windows(Some(process_id)) {
+                    self.send_message(accessor, h_wnd, MessageType::ActivateApp.into(), 1, 0)?;
+                }
+            }
+        }
+        if h_wnd != Handle::null() {
+            self.send_message(accessor, h_wnd, MessageType::NcActivate.into(), 1, 0)?;
+            self.send_message(
+                accessor,
+                h_wnd,
+                MessageType::Activate.into(),
+                state.into(),
+                encode_u16_u16_to_u32(previous_handle.as_u16(), minimized.into()),
+            )?;
+        }
+        Ok(previous_handle)
+    }
+
+    /// Activates another window once the active window got hidden: its owner if that can be
+    /// activated, or else the topmost top-level window that can.
+    fn activate_next(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<(), EmulatorError> {
+        let next = {
+            let objects = self.read_objects();
+            let window_manager = objects.read_window_manager();
+            let can_activate = |handle: Handle| {
+                handle != h_wnd
+                    && matches!(
+                        objects.user.get(handle),
+                        Some(UserObject::Window(user_window))
+                            if user_window.parent_handle == Handle::null()
+                                && !user_window.style.contains(WindowStyles::DISABLED)
+                    )
+                    && objects
+                        .window_identifier(handle)
+                        .is_some_and(|identifier| window_manager.is_shown(identifier))
+            };
+            let owner = match objects.user.get(h_wnd) {
+                Some(UserObject::Window(user_window)) => user_window.owner_handle,
+                _ => Handle::null(),
             };
+            if can_activate(owner) {
+                owner
+            } else {
+                window_manager
+                    .shown_windows()
+                    .map(|identifier| identifier.window_handle)
+                    .find(|&handle| can_activate(handle))
+                    .unwrap_or(Handle::null())
+            }
+        };
+        self.activate_window(accessor, next, ActivateState::Active)?;
+        Ok(())
+    }
 
-            debug!(
-                "[user] REGISTER CLASS SUCCESS {:?} => {:#?}",
-                cloned_class_name, window_class
-            );
-            if self
-                .window_classes
-                .insert(cloned_class_name.into(), window_class)
-                .is_none()
-            {
-                return Ok(ReturnValue::U16(atom.as_u16()));
+    /// Enables or disables a window, which decides whether it gets input. Returns whether it
+    /// was enabled before.
+    fn set_enabled(&self, h_wnd: Handle, enabled: bool) -> bool {
+        match self.write_objects().user.get_mut(h_wnd) {
+            Some(UserObject::Window(user_window)) => {
+                let was_enabled = !user_window.style.contains(WindowStyles::DISABLED);
+                user_window.style.set(WindowStyles::DISABLED, !enabled);
+                was_enabled
             }
+            _ => false,
+        }
+    }
 
-            self.user_atom_table.deregister(atom);
+    /// Whether a window and the windows it is in are all enabled, so that it can get input.
+    fn accepts_input(&self, objects: &ObjectEnvironment, h_wnd: Handle) -> bool {
+        let mut h_wnd = h_wnd;
+        while let Some(UserObject::Window(user_window)) = objects.user.get(h_wnd) {
+            if user_window.style.contains(WindowStyles::DISABLED) {
+                return false;
+            }
+            h_wnd = user_window.parent_handle;
         }
+        true
+    }
 
-        Ok(ReturnValue::U16(0))
+    /// Fills the client area of a window with the background brush of its class, for
+    /// WM_ERASEBKGND. Returns whether there was a brush to fill it with.
+    fn erase_background(&self, h_wnd: Handle, hdc: Handle) -> bool {
+        let objects = self.read_objects();
+        let background = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.background,
+            _ => return false,
+        };
+        // A class can also give a system color index plus one. Those overlap with the handles of
+        // the stock objects, and a brush takes precedence.
+        let color = match objects.gdi.get(background) {
+            Some(GdiObject::SolidBrush(color)) => *color,
+            _ => match SystemColors::from_u16(background.as_u16().wrapping_sub(1)) {
+                Some(system_color) => self.get_system_color(system_color),
+                None => return false,
+            },
+        };
+        self.fill_client_area(&objects, h_wnd, hdc, color);
+        true
+    }
+
+    pub fn fill_client_area(
+        &self,
+        objects: &ObjectEnvironment,
+        h_wnd: Handle,
+        hdc: Handle,
+        color: Color,
+    ) {
+        let rect = self
+            .get_client_rect(h_wnd, objects)
+            .unwrap_or_else(Rect::zero);
+        objects.with_paint_bitmap_for(hdc, &|mut bitmap, _| bitmap.fill_rectangle(rect, color));
     }
 
     #[api_function]
     fn dialog_box(
         &self,
+        accessor: EmulatorAccessor,
         h_instance: Handle,
-        template: Pointer,
-        h_wnd_parent: Handle,
+        template_name: u32,
+        h_wnd_owner: Handle,
         dialog_func: u32,
     ) -> Result<ReturnValue, EmulatorError> {
-        println!(
-            "DIALOG BOX {:?} {:x} {:?} {:x}",
-            h_instance, template.0, h_wnd_parent, dialog_func
-        );
-        Ok(ReturnValue::U16(0))
+        self.dialog_box_param(
+            accessor,
+            h_instance,
+            template_name,
+            h_wnd_owner,
+            dialog_func,
+            0,
+        )
     }
 
     #[api_function]
-    fn get_message(
+    fn dialog_box_param(
         &self,
         mut accessor: EmulatorAccessor,
-        msg: Pointer,
-        h_wnd: Handle,
-        _msg_filter_min: u16,
-        _msg_filer_max: u16,
+        _h_instance: Handle,
+        template_name: u32,
+        h_wnd_owner: Handle,
+        dialog_func: u32,
+        init_param: u32,
     ) -> Result<ReturnValue, EmulatorError> {
-        // TODO: implement min & max filters
-        let return_value = if let Some(message) = self.message_queue.receive(h_wnd) {
-            let message_type = message.message;
-            accessor
-                .memory_mut()
-                .write_u16(msg.0, message.h_wnd.as_u16())?;
-            accessor
-                .memory_mut()
-                .write_u16(msg.0 + 2, message_type.into())?;
-            accessor
-                .memory_mut()
-                .write_u16(msg.0 + 4, message.w_param)?;
-            accessor.memory_mut().write_32(msg.0 + 6, message.l_param)?;
-            accessor.memory_mut().write_32(msg.0 + 10, message.time)?;
-            accessor
-                .memory_mut()
-                .write_i16(msg.0 + 14, message.point.x)?;
-            accessor
-                .memory_mut()
-                .write_i16(msg.0 + 16, message.point.y)?;
+        let template = match self.load_dialog_template(&accessor, template_name)? {
+            Some(template) => template,
+            None => return Ok(ReturnValue::U16(0xFFFF)),
+        };
+        let h_dlg = self.create_dialog_from_template(
+            &mut accessor,
+            &template,
+            h_wnd_owner,
+            dialog_func,
+            init_param,
+        )?;
+        if h_dlg == Handle::null() {
+            return Ok(ReturnValue::U16(0xFFFF));
+        }
+        let result = self.run_modal_dialog(&mut accessor, h_dlg, MessageFilterCode::DialogBox)?;
+        Ok(ReturnValue::U16(result))
+    }
 
-            if message_type == MessageType::Quit {
-                0
-            } else {
-                1
-            }
-        } else {
-            0xFFFF
+    #[api_function]
+    fn create_dialog(
+        &self,
+        accessor: EmulatorAccessor,
+        h_instance: Handle,
+        template_name: u32,
+        h_wnd_owner: Handle,
+        dialog_func: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        self.create_dialog_param(
+            accessor,
+            h_instance,
+            template_name,
+            h_wnd_owner,
+            dialog_func,
+            0,
+        )
+    }
+
+    #[api_function]
+    fn create_dialog_param(
+        &self,
+        mut accessor: EmulatorAccessor,
+        _h_instance: Handle,
+        template_name: u32,
+        h_wnd_owner: Handle,
+        dialog_func: u32,
+        init_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let h_dlg = match self.load_dialog_template(&accessor, template_name)? {
+            Some(template) => self.create_dialog_from_template(
+                &mut accessor,
+                &template,
+                h_wnd_owner,
+                dialog_func,
+                init_param,
+            )?,
+            None => Handle::null(),
         };
-        Ok(ReturnValue::U16(return_value))
+        Ok(ReturnValue::U16(h_dlg.as_u16()))
     }
 
     #[api_function]
-    fn translate_message(&self, _msg: Pointer) -> Result<ReturnValue, EmulatorError> {
-        Ok(ReturnValue::U16(0))
+    fn end_dialog(&self, h_dlg: Handle, result: u16) -> Result<ReturnValue, EmulatorError> {
+        let ended = self
+            .with_dialog(h_dlg, |dialog| dialog.result = Some(result))
+            .is_some();
+        Ok(ReturnValue::U16(ended.into()))
     }
 
     #[api_function]
-    fn dispatch_message(
+    fn internal_is_dialog_message(
         &self,
         mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
         msg: Pointer,
     ) -> Result<ReturnValue, EmulatorError> {
-        let h_wnd: Handle = accessor.memory().read_u16(msg.0)?.into();
-        let message_type = accessor.memory().read_u16(msg.0 + 2)?;
-        let w_param = accessor.memory().read_u16(msg.0 + 4)?;
-        let l_param = accessor.memory().read_32(msg.0 + 6)?;
+        let message = self.read_message(&accessor, msg)?;
+        let handled = self.is_dialog_message(&mut accessor, h_dlg, &message)?;
+        Ok(ReturnValue::U16(handled.into()))
+    }
 
-        if let Some(UserObject::Window(user_window)) = self.read_objects().user.get(h_wnd) {
-            self.call_wndproc_sync(
-                &mut accessor,
-                user_window.proc,
-                h_wnd,
-                message_type,
-                w_param,
-                l_param,
-            )?;
-        }
+    #[api_function]
+    fn get_dlg_item(&self, h_dlg: Handle, id: u16) -> Result<ReturnValue, EmulatorError> {
+        Ok(ReturnValue::U16(
+            self.dialog_item(h_dlg, id)
+                .unwrap_or(Handle::null())
+                .as_u16(),
+        ))
+    }
+
+    #[api_function]
+    fn send_dlg_item_message(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        id: u16,
+        msg: u16,
+        w_param: u16,
+        l_param: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let result =
+            self.send_dialog_item_message(&mut accessor, h_dlg, id, msg, w_param, l_param)?;
+        Ok(ReturnValue::U32(result))
+    }
 
-        // Return value will be set by the wnd proc
+    #[api_function]
+    fn set_dlg_item_text(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        id: u16,
+        text: u32,
+    ) -> Result<ReturnValue, EmulatorError> {
+        self.send_dialog_item_message(
+            &mut accessor,
+            h_dlg,
+            id,
+            MessageType::SetText.into(),
+            0,
+            text,
+        )?;
         Ok(ReturnValue::None)
     }
 
     #[api_function]
-    fn load_string(
+    fn get_dlg_item_text(
         &self,
         mut accessor: EmulatorAccessor,
-        h_instance: Handle,
-        uid: u16,
-        buffer: Pointer,
-        buffer_max: u16,
+        h_dlg: Handle,
+        id: u16,
+        text: u32,
+        max_count: u16,
     ) -> Result<ReturnValue, EmulatorError> {
-        println!(
-            "LOAD STRING {:?} {:x} {:x} {:x}",
-            h_instance, uid, buffer.0, buffer_max
-        );
+        let length = self.send_dialog_item_message(
+            &mut accessor,
+            h_dlg,
+            id,
+            MessageType::GetText.into(),
+            max_count,
+            text,
+        )?;
+        Ok(ReturnValue::U16(length as u16))
+    }
 
-        // TODO: keep h_instance into account...
-        if let Some(string) = self.resource_table.strings_resources.get(&uid) {
-            let string = string.as_slice();
-            let length = string.len();
-            // TODO: handle buffer_max == 0 special case (see docs)
-            let amount_of_bytes_to_copy = (buffer_max as usize).min(length);
+    #[api_function]
+    fn set_dlg_item_int(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        id: u16,
+        value: u16,
+        signed: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let text = if signed != 0 {
+            format!("{}\0", value as i16)
+        } else {
+            format!("{}\0", value)
+        };
+        // The text lives on the stack of the task for as long as the call takes
+        let size = (text.len() as u16 + 1) & !1;
+        let pointer = accessor.reserve_stack_space(size);
+        let result = accessor
+            .memory_mut()
+            .copy_from(text.as_bytes(), Pointer::from_far(pointer).0 as usize)
+            .and_then(|_| {
+                self.send_dialog_item_message(
+                    &mut accessor,
+                    h_dlg,
+                    id,
+                    MessageType::SetText.into(),
+                    0,
+                    pointer,
+                )
+            });
+        accessor.release_stack_space(size);
+        result?;
+        Ok(ReturnValue::None)
+    }
 
+    #[api_function]
+    fn get_dlg_item_int(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        id: u16,
+        translated: Pointer,
+        signed: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        // The text lives on the stack of the task for as long as the call takes
+        const SIZE: u16 = 32;
+        let pointer = accessor.reserve_stack_space(SIZE);
+        let result = self
+            .send_dialog_item_message(
+                &mut accessor,
+                h_dlg,
+                id,
+                MessageType::GetText.into(),
+                SIZE,
+                pointer,
+            )
+            .and_then(|length| {
+                let mut text = vec![0; length.min(SIZE as u32 - 1) as usize];
+                for (index, character) in text.iter_mut().enumerate() {
+                    *character = accessor
+                        .memory()
+                        .read_8(Pointer::from_far(pointer).0 + index as u32)?;
+                }
+                Ok(text)
+            });
+        accessor.release_stack_space(SIZE);
+
+        // The number may have spaces around it, and a minus sign if it is signed
+        let text = result?;
+        let text = std::str::from_utf8(&text).unwrap_or("").trim();
+        let value = if signed != 0 {
+            text.parse::<i16>().ok().map(|value| value as u16)
+        } else {
+            text.parse::<u16>().ok()
+        };
+        if translated.0 != 0 {
             accessor
                 .memory_mut()
-                .copy_from(&string[0..amount_of_bytes_to_copy], buffer.0 as usize)?;
-            accessor
-                .memory_mut()
-                .write_8(buffer.0 + (amount_of_bytes_to_copy as u32), 0)?;
+                .write_u16(translated.0, value.is_some().into())?;
+        }
+        Ok(ReturnValue::U16(value.unwrap_or(0)))
+    }
 
-            debug_print_null_terminated_string(&accessor, buffer.0);
-            // String lengths from the resource table will fit in 16 bits, because their length
-            // was originally 8 bits.
-            Ok(ReturnValue::U16(amount_of_bytes_to_copy as u16))
+    #[api_function]
+    fn check_dlg_button(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        id: u16,
+        check: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        self.send_dialog_item_message(
+            &mut accessor,
+            h_dlg,
+            id,
+            ButtonMessage::SetCheck.into(),
+            check,
+            0,
+        )?;
+        Ok(ReturnValue::None)
+    }
+
+    #[api_function]
+    fn is_dlg_button_checked(
+        &self,
+        mut accessor: EmulatorAccessor,
+        h_dlg: Handle,
+        id: u16,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let state = self.send_dialog_item_message(
+            &mut accessor,
+            h_dlg,
+            id,
+            ButtonMessage::GetCheck.into(),
+            0,
+            0,
+        )?;
+        Ok(ReturnValue::U16(state as u16))
+    }
+
+    /// Loads a menu from an RT_MENU resource, with the popup menus its items open.
+    pub fn load_menu_resource(&self, name: &ResourceName) -> Option<Handle> {
+        let data = self.resource_table.predefined_resource(RT_MENU, name)?;
+        let items = menu::parse_template(data)?;
+        self.register_menu(&mut self.write_objects(), items)
+    }
+
+    fn register_menu(
+        &self,
+        objects: &mut ObjectEnvironment,
+        items: Vec<TemplateItem>,
+    ) -> Option<Handle> {
+        let mut menu = Menu::new();
+        for item in items {
+            // Items that open a popup menu have the handle of the popup menu as their id
+            let id = if item.flags.contains(MenuFlags::POPUP) {
+                self.register_menu(objects, item.children)?.as_u16()
+            } else {
+                item.id
+            };
+            menu.items.push(MenuItem {
+                flags: item.flags,
+                id,
+                text: item.text,
+            });
+        }
+        objects.user.register(UserObject::Menu(menu))
+    }
+
+    /// Deregisters a menu, together with the popup menus its items open.
+    fn destroy_menu(&self, objects: &mut ObjectEnvironment, h_menu: Handle) -> bool {
+        let popups: Vec<Handle> = match objects.user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu.items.iter().filter_map(MenuItem::popup).collect(),
+            _ => return false,
+        };
+        for popup in popups {
+            self.destroy_menu(objects, popup);
+        }
+        objects.user.deregister(h_menu)
+    }
+
+    /// Finds an item by its position in a menu, or by its command. Commands are also looked for
+    /// in the popup menus the items open. Returns the menu the item is in and its position.
+    fn find_menu_item(
+        &self,
+        objects: &ObjectEnvironment,
+        h_menu: Handle,
+        item: u16,
+        flags: MenuFlags,
+    ) -> Option<(Handle, usize)> {
+        let menu = match objects.user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu,
+            _ => return None,
+        };
+        if let Some(index) = menu.find_item(item, flags) {
+            return Some((h_menu, index));
+        }
+        if flags.contains(MenuFlags::BYPOSITION) {
+            return None;
+        }
+        menu.items
+            .iter()
+            .filter_map(MenuItem::popup)
+            .find_map(|popup| self.find_menu_item(objects, popup, item, flags))
+    }
+
+    pub fn with_menu_item<R>(
+        &self,
+        h_menu: Handle,
+        index: usize,
+        f: impl FnOnce(&MenuItem) -> R,
+    ) -> Option<R> {
+        match self.read_objects().user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu.items.get(index).map(f),
+            _ => None,
+        }
+    }
+
+    /// Changes an item, found by its position or by its command.
+    fn change_menu_item<R>(
+        &self,
+        h_menu: Handle,
+        item: u16,
+        flags: MenuFlags,
+        change: impl FnOnce(&mut MenuItem) -> R,
+    ) -> Option<R> {
+        let mut objects = self.write_objects();
+        let (h_menu, index) = self.find_menu_item(&objects, h_menu, item, flags)?;
+        match objects.user.get_mut(h_menu) {
+            Some(UserObject::Menu(menu)) => Some(change(&mut menu.items[index])),
+            _ => None,
+        }
+    }
+
+    /// Makes an item for InsertMenu, AppendMenu and ModifyMenu. The item has a string,
+    /// unless the flags make it a separator, a bitmap or an owner-drawn item.
+    fn new_menu_item(
+        &self,
+        accessor: &EmulatorAccessor,
+        flags: u16,
+        id: u16,
+        new_item: u32,
+    ) -> Result<MenuItem, EmulatorError> {
+        let flags = MenuFlags::from_bits_truncate(flags) - MenuFlags::BYPOSITION;
+        // TODO: bitmap and owner-drawn items
+        let text = if new_item == 0
+            || flags.intersects(MenuFlags::SEPARATOR | MenuFlags::BITMAP | MenuFlags::OWNERDRAW)
+        {
+            HeapByteString::from(Arc::new([]))
         } else {
-            Ok(ReturnValue::U16(0))
+            accessor.clone_string(Pointer::from_far(new_item).0, false)?
+        };
+        Ok(MenuItem { flags, id, text })
+    }
+
+    /// Inserts an item before the item at a position or with a command. Items go at the end
+    /// of the menu if there is no such item.
+    fn insert_menu_item(&self, h_menu: Handle, position: u16, flags: u16, item: MenuItem) -> bool {
+        let mut objects = self.write_objects();
+        let (h_menu, index) = self
+            .find_menu_item(
+                &objects,
+                h_menu,
+                position,
+                MenuFlags::from_bits_truncate(flags),
+            )
+            .unwrap_or((h_menu, usize::MAX));
+        match objects.user.get_mut(h_menu) {
+            Some(UserObject::Menu(menu)) => {
+                let index = index.min(menu.items.len());
+                menu.items.insert(index, item);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes an item from its menu. Returns the item if it was found.
+    fn remove_menu_item(&self, h_menu: Handle, position: u16, flags: u16) -> Option<MenuItem> {
+        let mut objects = self.write_objects();
+        let (h_menu, index) = self.find_menu_item(
+            &objects,
+            h_menu,
+            position,
+            MenuFlags::from_bits_truncate(flags),
+        )?;
+        match objects.user.get_mut(h_menu) {
+            Some(UserObject::Menu(menu)) => Some(menu.items.remove(index)),
+            _ => None,
+        }
+    }
+
+    /// The system menu of a window, which the window gets its own copy of the first time it
+    /// is needed. Only windows with the WS_SYSMENU style have one.
+    pub fn system_menu_of(&self, h_wnd: Handle) -> Option<Handle> {
+        let mut objects = self.write_objects();
+        let (style, system_menu) = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => (user_window.style, user_window.system_menu),
+            _ => return None,
+        };
+        if !style.contains(WindowStyles::SYSMENU) {
+            return None;
+        }
+        if system_menu != Handle::null() {
+            return Some(system_menu);
         }
+        let system_menu = objects
+            .user
+            .register(UserObject::Menu(Menu::system_menu()))?;
+        if let Some(UserObject::Window(user_window)) = objects.user.get_mut(h_wnd) {
+            user_window.system_menu = system_menu;
+        }
+        Some(system_menu)
+    }
+
+    /// Lays out the non-client area of a window again, after its menu bar changed.
+    fn redraw_frame(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+    ) -> Result<bool, EmulatorError> {
+        self.set_window_pos(
+            accessor,
+            h_wnd,
+            Handle::null(),
+            Rect::zero(),
+            SetWindowPosFlags::NOMOVE
+                | SetWindowPosFlags::NOSIZE
+                | SetWindowPosFlags::NOZORDER
+                | SetWindowPosFlags::NOACTIVATE
+                | SetWindowPosFlags::FRAMECHANGED,
+        )
     }
 
     #[api_function]
-    fn load_cursor(
+    fn load_menu(
         &self,
+        accessor: EmulatorAccessor,
         _h_instance: Handle,
-        _cursor_name: u32,
+        menu_name: u32,
     ) -> Result<ReturnValue, EmulatorError> {
-        Ok(ReturnValue::U16(Handle::null().as_u16()))
+        // TODO: keep h_instance into account...
+        let menu_name = accessor.resource_name(menu_name)?;
+        let h_menu = self.load_menu_resource(&menu_name);
+        Ok(ReturnValue::U16(h_menu.unwrap_or(Handle::null()).as_u16()))
     }
 
-    fn call_wndproc_sync(
-        &self,
-        accessor: &mut EmulatorAccessor,
-        proc: SegmentAndOffset,
-        h_wnd: Handle,
-        message: u16,
-        w_param: u16,
-        l_param: u32,
-    ) -> Result<(), EmulatorError> {
-        accessor.far_call_into_proc_setup()?;
-        accessor.push_16(h_wnd.as_u16())?;
-        accessor.push_16(message)?;
-        accessor.push_16(w_param)?;
-        accessor.push_16((l_param >> 16) as u16)?;
-        accessor.push_16(l_param as u16)?;
-        accessor.far_call_into_proc_execute(proc.segment, proc.offset)
+    #[api_function]
+    fn create_menu(&self) -> Result<ReturnValue, EmulatorError> {
+        let h_menu = self
+            .write_objects()
+            .user
+            .register(UserObject::Menu(Menu::new()));
+        Ok(ReturnValue::U16(h_menu.unwrap_or(Handle::null()).as_u16()))
     }
 
     #[api_function]
-    fn get_system_metrics(&self, metric: u16) -> Result<ReturnValue, EmulatorError> {
-        println!("metric {} requested", metric);
-        // TODO: other metrics
-        if metric == 0x16 {
-            // 1 if debug version is installed, 0 otherwise
-            Ok(ReturnValue::U16(1))
-        } else if metric == 30 || metric == 31 {
-            // TODO: this is just to let the system continue
-            Ok(ReturnValue::U16(18))
-        } else if metric == 32 || metric == 33 {
-            // TODO: this is just to let the system continue
-            Ok(ReturnValue::U16(4))
-        } else if metric == 41 {
-            // As the Windows for Pen computing extension is not installed, return 0
-            Ok(ReturnValue::U16(0))
-        } else {
-            Ok(ReturnValue::U16(0))
-        }
+    fn create_popup_menu(&self) -> Result<ReturnValue, EmulatorError> {
+        let h_menu = self
+            .write_objects()
+            .user
+            .register(UserObject::Menu(Menu::new()));
+        Ok(ReturnValue::U16(h_menu.unwrap_or(Handle::null()).as_u16()))
     }
 
     #[api_function]
-    fn wsprintf(
-        &self,
-        accessor: EmulatorAccessor,
-        format_string_ptr: Pointer,
-        output_buffer_ptr: Pointer,
-    ) -> Result<ReturnValue, EmulatorError> {
-        debug_print_null_terminated_string(&accessor, format_string_ptr.0);
-        let mut machine = SprintfMachine::new(accessor, format_string_ptr, output_buffer_ptr);
-        machine.process()
+    fn internal_destroy_menu(&self, h_menu: Handle) -> Result<ReturnValue, EmulatorError> {
+        let success = self.destroy_menu(&mut self.write_objects(), h_menu);
+        Ok(ReturnValue::U16(success.into()))
     }
 
     #[api_function]
-    fn def_window_proc(
-        &self,
-        h_wnd: Handle,
-        msg: u16,
-        w_param: u16,
-        l_param: u32,
-    ) -> Result<ReturnValue, EmulatorError> {
-        debug!(
-            "[user] DEF WINDOW PROC {:?} {:x} {:x} {:x}",
-            h_wnd, msg, w_param, l_param
-        );
-        Ok(ReturnValue::U16(0))
+    fn get_menu(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
+        let h_menu = match self.read_objects().user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.menu,
+            _ => Handle::null(),
+        };
+        Ok(ReturnValue::U16(h_menu.as_u16()))
     }
 
     #[api_function]
-    fn button_window_proc(
+    fn set_menu(
         &self,
+        mut accessor: EmulatorAccessor,
         h_wnd: Handle,
-        msg: u16,
-        w_param: u16,
-        l_param: u32,
+        h_menu: Handle,
     ) -> Result<ReturnValue, EmulatorError> {
-        debug!(
-            "[user] BUTTON WINDOW PROC {:?} {:x} {:x} {:x}",
-            h_wnd, msg, w_param, l_param
-        );
-        if msg == MessageType::Paint.into() {
-            // Paint button
-            if let Some(paint) = self.begin_paint(h_wnd) {
-                let objects = self.read_objects();
-                let containing_rect = self
-                    .get_client_rect(h_wnd, &objects)
-                    .unwrap_or_else(Rect::zero);
-                objects.with_paint_bitmap_for(paint.hdc, &|mut bitmap, _| {
-                    // Black rounded frame
-                    bitmap.draw_horizontal_line(
-                        1,
-                        0,
-                        containing_rect.right.wrapping_sub(1),
-                        self.get_system_color(SystemColors::WindowFrame),
-                    );
-                    bitmap.draw_horizontal_line(
-                        1,
-                        containing_rect.bottom.saturating_sub(1),
-                        containing_rect.right.saturating_sub(1),
-                        self.get_system_color(SystemColors::WindowFrame),
-                    );
-                    bitmap.draw_vertical_line(
-                        0,
-                        1,
-                        containing_rect.bottom.saturating_sub(1),
-                        self.get_system_color(SystemColors::WindowFrame),
-                    );
-                    bitmap.draw_vertical_line(
-                        containing_rect.right.saturating_sub(1),
-                        1,
-                        containing_rect.bottom.saturating_sub(1),
-                        self.get_system_color(SystemColors::WindowFrame),
-                    );
-
-                    // Highlight top
-                    bitmap.draw_horizontal_line(
-                        1,
-                        1,
-                        containing_rect.right.saturating_sub(1),
-                        self.get_system_color(SystemColors::ButtonHighlight),
-                    );
-                    bitmap.draw_horizontal_line(
-                        1,
-                        2,
-                        containing_rect.right.saturating_sub(2),
-                        self.get_system_color(SystemColors::ButtonHighlight),
-                    );
-                    // Highlight left
-                    bitmap.draw_vertical_line(
-                        1,
-                        3,
-                        containing_rect.bottom.saturating_sub(1),
-                        self.get_system_color(SystemColors::ButtonHighlight),
-                    );
-                    bitmap.draw_vertical_line(
-                        2,
-                        3,
-                        containing_rect.bottom.saturating_sub(2),
-                        self.get_system_color(SystemColors::ButtonHighlight),
-                    );
-
-                    // Shadow right
-                    bitmap.draw_vertical_line(
-                        containing_rect.right.saturating_sub(2),
-                        1,
-                        containing_rect.bottom.saturating_sub(3),
-                        self.get_system_color(SystemColors::ButtonShadow),
-                    );
-                    bitmap.draw_vertical_line(
-                        containing_rect.right.saturating_sub(3),
-                        2,
-                        containing_rect.bottom.saturating_sub(3),
-                        self.get_system_color(SystemColors::ButtonShadow),
-                    );
-                    // Shadow bottom
-                    bitmap.draw_horizontal_line(
-                        2,
-                        containing_rect.bottom.saturating_sub(3),
-                        containing_rect.right.saturating_sub(1),
-                        self.get_system_color(SystemColors::ButtonShadow),
-                    );
-                    bitmap.draw_horizontal_line(
-                        1,
-                        containing_rect.bottom.saturating_sub(2),
-                        containing_rect.right.saturating_sub(1),
-                        self.get_system_color(SystemColors::ButtonShadow),
-                    );
-
-                    // Face
-                    let bg_rect = containing_rect.shrink(3);
-                    bitmap.fill_rectangle(bg_rect, self.get_system_color(SystemColors::ButtonFace));
-                });
-                drop(objects);
-                self.end_paint(h_wnd, paint.hdc);
+        match self.write_objects().user.get_mut(h_wnd) {
+            Some(UserObject::Window(user_window))
+                if !user_window.style.contains(WindowStyles::CHILD) =>
+            {
+                user_window.menu = h_menu;
             }
+            _ => return Ok(ReturnValue::U16(0)),
         }
-        Ok(ReturnValue::U16(0))
+        self.redraw_frame(&mut accessor, h_wnd)?;
+        Ok(ReturnValue::U16(1))
     }
 
-    fn get_client_rect(
+    #[api_function]
+    fn draw_menu_bar(
         &self,
+        mut accessor: EmulatorAccessor,
         h_wnd: Handle,
-        objects: &RwLockReadGuard<ObjectEnvironment>,
-    ) -> Option<Rect> {
-        objects
-            .read_window_manager()
-            .client_rect_of(WindowIdentifier {
-                process_id: self.process_id(),
-                window_handle: h_wnd,
-            })
+    ) -> Result<ReturnValue, EmulatorError> {
+        self.redraw_frame(&mut accessor, h_wnd)?;
+        Ok(ReturnValue::None)
     }
 
-    fn get_dc(&self, h_wnd: Handle) -> Option<Handle> {
+    #[api_function]
+    fn get_system_menu(&self, h_wnd: Handle, revert: u16) -> Result<ReturnValue, EmulatorError> {
+        if revert == 0 {
+            let h_menu = self.system_menu_of(h_wnd);
+            return Ok(ReturnValue::U16(h_menu.unwrap_or(Handle::null()).as_u16()));
+        }
+        // Reverting throws the copy away, the next copy is the default system menu again
         let mut objects = self.write_objects();
-        match objects.user.get(h_wnd) {
+        let system_menu = match objects.user.get_mut(h_wnd) {
             Some(UserObject::Window(user_window)) => {
-                let window_identifier = WindowIdentifier {
-                    process_id: self.process_id(),
-                    window_handle: h_wnd,
-                };
-                let (bitmap_window_identifier, translation) = if user_window.parent_dc {
-                    // TODO: nested CS_PARENTDC: how to handle them?
-                    let translation = objects
-                        .read_window_manager()
-                        .position_of(window_identifier)
-                        .unwrap_or_else(Point::origin);
-                    let parent_window_identifier =
-                        window_identifier.other_handle(user_window.parent_handle);
-                    (parent_window_identifier, translation)
-                } else {
-                    (window_identifier, Point::origin())
-                };
-                let dc = DeviceContext {
-                    bitmap_window_identifier,
-                    bitmap_translation: translation,
-                    position: Cell::new(Point::origin()),
-                    selected_brush: Handle::null(),
-                    selected_pen: Handle::null(),
-                    raster_op: RasterOp::CopyPen,
-                };
-                objects.gdi.register(GdiObject::DC(dc))
+                std::mem::replace(&mut user_window.system_menu, Handle::null())
             }
-            None => None,
+            _ => Handle::null(),
+        };
+        if system_menu != Handle::null() {
+            self.destroy_menu(&mut objects, system_menu);
         }
+        Ok(ReturnValue::U16(Handle::null().as_u16()))
     }
 
     #[api_function]
-    fn internal_get_dc(&self, h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
-        Ok(ReturnValue::U16(
-            self.get_dc(h_wnd).unwrap_or(Handle::null()).as_u16(),
-        ))
-    }
-
-    fn begin_paint(&self, h_wnd: Handle) -> Option<Paint> {
-        self.get_dc(h_wnd).map(|hdc| Paint {
-            hdc,
-            f_erase: false,
-            rect: Rect {
-                left: 0,
-                top: 0,
-                right: 200,
-                bottom: 200,
-            },
-        })
+    fn get_sub_menu(&self, h_menu: Handle, position: u16) -> Result<ReturnValue, EmulatorError> {
+        let popup = self
+            .with_menu_item(h_menu, position.into(), MenuItem::popup)
+            .flatten();
+        Ok(ReturnValue::U16(popup.unwrap_or(Handle::null()).as_u16()))
     }
 
     #[api_function]
-    fn internal_begin_paint(
-        &self,
-        mut accessor: EmulatorAccessor,
-        h_wnd: Handle,
-        paint_ptr: Pointer,
-    ) -> Result<ReturnValue, EmulatorError> {
-        if let Some(paint) = self.begin_paint(h_wnd) {
-            accessor
-                .memory_mut()
-                .write_u16(paint_ptr.0, paint.hdc.as_u16())?;
-            accessor
-                .memory_mut()
-                .write_8(paint_ptr.0.wrapping_add(2), paint.f_erase.into())?;
-            accessor.write_rect(paint_ptr.0.wrapping_add(4), &paint.rect)?;
-            Ok(ReturnValue::U16(paint.hdc.as_u16()))
-        } else {
-            Ok(ReturnValue::U16(0))
-        }
-    }
-
-    fn release_dc(&self, _h_wnd: Handle, hdc: Handle) -> bool {
-        // TODO: check whether the hdc belongs to the h_wnd ?
-        // TODO: this should probably cause a flip of the front and back bitmap for the given window
-        self.write_objects().gdi.deregister(hdc);
-        true
+    fn get_menu_item_count(&self, h_menu: Handle) -> Result<ReturnValue, EmulatorError> {
+        let count = match self.read_objects().user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu.items.len() as u16,
+            _ => 0xFFFF,
+        };
+        Ok(ReturnValue::U16(count))
     }
 
     #[api_function]
-    fn internal_release_dc(
+    fn get_menu_item_id(
         &self,
-        _h_wnd: Handle,
-        hdc: Handle,
+        h_menu: Handle,
+        position: u16,
     ) -> Result<ReturnValue, EmulatorError> {
-        Ok(ReturnValue::U16(self.release_dc(_h_wnd, hdc).into()))
-    }
-
-    fn end_paint(&self, _h_wnd: Handle, hdc: Handle) -> u16 {
-        self.release_dc(_h_wnd, hdc).into()
+        // Items that open a popup menu have no command
+        let id = self
+            .with_menu_item(h_menu, position.into(), |item| {
+                item.popup().is_none().then_some(item.id)
+            })
+            .flatten();
+        Ok(ReturnValue::U16(id.unwrap_or(0xFFFF)))
     }
 
     #[api_function]
-    fn internal_end_paint(
+    fn get_menu_state(
         &self,
-        accessor: EmulatorAccessor,
-        _h_wnd: Handle,
-        paint: Pointer,
+        h_menu: Handle,
+        id: u16,
+        flags: u16,
     ) -> Result<ReturnValue, EmulatorError> {
-        // TODO: this should probably cause a flip of the front and back bitmap for the given window
-        let handle = accessor.memory().read_u16(paint.0)?;
-        Ok(ReturnValue::U16(self.end_paint(_h_wnd, handle.into())))
+        let objects = self.read_objects();
+        let item = self
+            .find_menu_item(&objects, h_menu, id, MenuFlags::from_bits_truncate(flags))
+            .and_then(|(h_menu, index)| match objects.user.get(h_menu) {
+                Some(UserObject::Menu(menu)) => menu.items.get(index),
+                _ => None,
+            });
+        let state = match item {
+            // For popup menus, the high byte is the number of items in the popup menu
+            Some(item) => match item.popup().map(|popup| objects.user.get(popup)) {
+                Some(Some(UserObject::Menu(popup))) => {
+                    (popup.items.len() as u16) << 8 | (item.flags.bits() & 0xff)
+                }
+                _ => item.flags.bits(),
+            },
+            None => 0xFFFF,
+        };
+        Ok(ReturnValue::U16(state))
     }
 
     #[api_function]
-    fn fill_rect(
+    fn get_menu_string(
         &self,
-        accessor: EmulatorAccessor,
-        h_dc: Handle,
-        rect: Pointer,
-        h_brush: Handle,
+        mut accessor: EmulatorAccessor,
+        h_menu: Handle,
+        id: u16,
+        buffer: Pointer,
+        buffer_max: u16,
+        flags: u16,
     ) -> Result<ReturnValue, EmulatorError> {
-        let rect = accessor.read_rect(rect.0)?;
-        let objects = self.read_objects();
-        if let Some(GdiObject::SolidBrush(color)) = objects.gdi.get(h_brush) {
-            objects
-                .with_paint_bitmap_for(h_dc, &|mut bitmap, _| bitmap.fill_rectangle(rect, *color));
-            Ok(ReturnValue::U16(1))
-        } else {
-            Ok(ReturnValue::U16(0))
+        let text = {
+            let objects = self.read_objects();
+            self.find_menu_item(&objects, h_menu, id, MenuFlags::from_bits_truncate(flags))
+                .and_then(|(h_menu, index)| match objects.user.get(h_menu) {
+                    Some(UserObject::Menu(menu)) => Some(menu.items[index].text.clone()),
+                    _ => None,
+                })
+        };
+        match text {
+            Some(text) if buffer_max > 0 => {
+                // The buffer also holds the terminating null byte
+                let string = text.as_slice();
+                let length = string.len().min(buffer_max as usize - 1);
+                accessor
+                    .memory_mut()
+                    .copy_from(&string[..length], buffer.0 as usize)?;
+                accessor.memory_mut().write_8(buffer.0 + length as u32, 0)?;
+                Ok(ReturnValue::U16(length as u16))
+            }
+            _ => Ok(ReturnValue::U16(0)),
         }
     }
 
     #[api_function]
-    fn set_timer(
+    fn check_menu_item(
         &self,
-        h_wnd: Handle,
-        id_event: u16,
-        elapse: u16,
-        timer_proc_segment: u16,
-        timer_proc_offset: u16,
+        h_menu: Handle,
+        id_check_item: u16,
+        u_check: u16,
     ) -> Result<ReturnValue, EmulatorError> {
-        println!(
-            "SET TIMER {:?}, {:x}, {}, {:x}, {:x}",
-            h_wnd, id_event, elapse, timer_proc_segment, timer_proc_offset
-        );
-        // TODO: this fakes success
-        Ok(ReturnValue::U16(1))
-    }
-
-    #[api_function]
-    fn kill_timer(&self, _h_wnd: Handle, _u_id_event: u16) -> Result<ReturnValue, EmulatorError> {
-        // TODO: this fakes success
-        Ok(ReturnValue::U16(1))
+        let flags = MenuFlags::from_bits_truncate(u_check);
+        let previous = self.change_menu_item(h_menu, id_check_item, flags, |item| {
+            let previous = item.flags & MenuFlags::CHECKED;
+            item.flags
+                .set(MenuFlags::CHECKED, flags.contains(MenuFlags::CHECKED));
+            previous.bits()
+        });
+        Ok(ReturnValue::U16(previous.unwrap_or(0xFFFF)))
     }
 
     #[api_function]
-    fn get_window_rect(
+    fn enable_menu_item(
         &self,
-        mut accessor: EmulatorAccessor,
-        h_wnd: Handle,
-        rect_ptr: Pointer,
+        h_menu: Handle,
+        id_enable_item: u16,
+        u_enable: u16,
     ) -> Result<ReturnValue, EmulatorError> {
-        println!("GET WINDOW RECT {:?}", h_wnd);
-        if let Some(rect) =
-            self.read_objects()
-                .read_window_manager()
-                .window_rect_of(WindowIdentifier {
-                    process_id: self.process_id(),
-                    window_handle: h_wnd,
-                })
-        {
-            println!("Rect is {:?}", rect);
-            accessor.write_rect(rect_ptr.0, &rect)?;
-            Ok(ReturnValue::U16(1))
-        } else {
-            Ok(ReturnValue::U16(0))
-        }
+        let flags = MenuFlags::from_bits_truncate(u_enable);
+        let state = MenuFlags::GRAYED | MenuFlags::DISABLED;
+        let previous = self.change_menu_item(h_menu, id_enable_item, flags, |item| {
+            let previous = item.flags & state;
+            item.flags.remove(state);
+            item.flags.insert(flags & state);
+            previous.bits()
+        });
+        Ok(ReturnValue::U16(previous.unwrap_or(0xFFFF)))
     }
 
     #[api_function]
-    fn internal_get_client_rect(
+    fn insert_menu(
         &self,
-        mut accessor: EmulatorAccessor,
-        h_wnd: Handle,
-        rect_ptr: Pointer,
+        accessor: EmulatorAccessor,
+        h_menu: Handle,
+        position: u16,
+        flags: u16,
+        id_new_item: u16,
+        new_item: u32,
     ) -> Result<ReturnValue, EmulatorError> {
-        let rect = {
-            let objects = self.read_objects();
-            self.get_client_rect(h_wnd, &objects)
-        };
-        if let Some(rect) = rect {
-            accessor.write_rect(rect_ptr.0, &rect)?;
-            println!("GET CLIENT RECT {:?}", rect);
-            Ok(ReturnValue::U16(1))
-        } else {
-            Ok(ReturnValue::U16(0))
-        }
+        let item = self.new_menu_item(&accessor, flags, id_new_item, new_item)?;
+        let success = self.insert_menu_item(h_menu, position, flags, item);
+        Ok(ReturnValue::U16(success.into()))
     }
 
     #[api_function]
-    fn set_window_text(
+    fn append_menu(
         &self,
-        _h_wnd: Handle,
-        text: HeapByteString,
+        accessor: EmulatorAccessor,
+        h_menu: Handle,
+        flags: u16,
+        id_new_item: u16,
+        new_item: u32,
     ) -> Result<ReturnValue, EmulatorError> {
-        println!("SET WINDOW TEXT: {:?}", text);
-        Ok(ReturnValue::U16(1))
+        let item = self.new_menu_item(&accessor, flags, id_new_item, new_item)?;
+        // There is no item at position -1, so the item goes at the end
+        let success = self.insert_menu_item(h_menu, 0xFFFF, MenuFlags::BYPOSITION.bits(), item);
+        Ok(ReturnValue::U16(success.into()))
     }
 
     #[api_function]
-    fn message_box(
+    fn modify_menu(
         &self,
         accessor: EmulatorAccessor,
-        h_wnd: Handle,
-        text: Pointer,
-        caption: Pointer,
-        _type: u16,
+        h_menu: Handle,
+        position: u16,
+        flags: u16,
+        id_new_item: u16,
+        new_item: u32,
     ) -> Result<ReturnValue, EmulatorError> {
-        println!(
-            "MESSAGE BOX {:?}, {:x}, {:x}, {:x}",
-            h_wnd, text.0, caption.0, _type
+        let item = self.new_menu_item(&accessor, flags, id_new_item, new_item)?;
+        let new_popup = item.popup();
+        let old_item = self.change_menu_item(
+            h_menu,
+            position,
+            MenuFlags::from_bits_truncate(flags),
+            |old_item| std::mem::replace(old_item, item),
         );
-        debug_print_null_terminated_string(&accessor, text.0);
-        debug_print_null_terminated_string(&accessor, caption.0);
-        Ok(ReturnValue::U16(0))
-    }
-
-    #[api_function]
-    fn get_menu(&self, _h_wnd: Handle) -> Result<ReturnValue, EmulatorError> {
-        // TODO
-        Ok(ReturnValue::U16(Handle::null().as_u16()))
-    }
-
-    #[api_function]
-    fn get_system_menu(&self, _h_wnd: Handle, _revert: u16) -> Result<ReturnValue, EmulatorError> {
-        // TODO
-        Ok(ReturnValue::U16(Handle::null().as_u16()))
+        // A popup menu that the item doesn't open anymore gets destroyed
+        if let Some(old_popup) = old_item.as_ref().and_then(MenuItem::popup) {
+            if new_popup != Some(old_popup) {
+                self.destroy_menu(&mut self.write_objects(), old_popup);
+            }
+        }
+        Ok(ReturnValue::U16(old_item.is_some().into()))
     }
 
     #[api_function]
-    fn check_menu_item(
+    fn remove_menu(
         &self,
-        _h_menu: Handle,
-        _id_check_item: u16,
-        _u_check: u16,
+        h_menu: Handle,
+        position: u16,
+        flags: u16,
     ) -> Result<ReturnValue, EmulatorError> {
-        // TODO
-        Ok(ReturnValue::U16(0xFFFF))
+        let item = self.remove_menu_item(h_menu, position, flags);
+        Ok(ReturnValue::U16(item.is_some().into()))
     }
 
     #[api_function]
-    fn enable_menu_item(
+    fn delete_menu(
         &self,
-        _h_menu: Handle,
-        _id_enable_item: u16,
-        _u_enable: u16,
+        h_menu: Handle,
+        position: u16,
+        flags: u16,
     ) -> Result<ReturnValue, EmulatorError> {
-        // TODO
-        Ok(ReturnValue::U16(0xFFFF))
+        let item = self.remove_menu_item(h_menu, position, flags);
+        // Unlike RemoveMenu, DeleteMenu also destroys the popup menu of the item
+        if let Some(popup) = item.as_ref().and_then(MenuItem::popup) {
+            self.destroy_menu(&mut self.write_objects(), popup);
+        }
+        Ok(ReturnValue::U16(item.is_some().into()))
     }
 
     #[api_function]
-    fn append_menu(
+    #[allow(clippy::too_many_arguments)]
+    fn track_popup_menu(
         &self,
-        _h_menu: Handle,
-        _flags: u16,
-        _id_new_item: u16,
-        _new_item_str: Pointer,
+        mut accessor: EmulatorAccessor,
+        h_menu: Handle,
+        flags: u16,
+        x: i16,
+        y: i16,
+        _reserved: i16,
+        h_wnd: Handle,
+        _rect: Pointer,
     ) -> Result<ReturnValue, EmulatorError> {
-        // TODO
+        let width = match self.read_objects().user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu::popup_layout(menu).1 .0,
+            _ => return Ok(ReturnValue::U16(0)),
+        };
+        let proc = match self.window_proc_of(h_wnd) {
+            Some(proc) => proc,
+            None => return Ok(ReturnValue::U16(0)),
+        };
+        // TPM_CENTERALIGN and TPM_RIGHTALIGN
+        let left = if flags & 0x0004 != 0 {
+            x - width / 2
+        } else if flags & 0x0008 != 0 {
+            x - width
+        } else {
+            x
+        };
+        let mut tracking = MenuTracking {
+            h_wnd,
+            proc,
+            bar: false,
+            system: false,
+            button_down: true,
+            menus: Vec::new(),
+        };
+        self.open_popup_menu(&mut accessor, &mut tracking, h_menu, 0, Point::new(left, y))?;
+        self.run_menu(&mut accessor, tracking, MenuStart::Mouse(Point::new(x, y)))?;
         Ok(ReturnValue::U16(1))
     }
 
@@ -1085,23 +7446,47 @@ impl<'a> EmulatedUser<'a> {
     }
 
     pub fn syscall(
-        &mut self,
+        &self,
         nr: u16,
         emulator_accessor: EmulatorAccessor,
     ) -> Result<ReturnValue, EmulatorError> {
         match nr {
             1 => self.__api_message_box(emulator_accessor),
             5 => self.__api_init_app(emulator_accessor),
+            6 => self.__api_post_quit_message(emulator_accessor),
             10 => self.__api_set_timer(emulator_accessor),
             12 => self.__api_kill_timer(emulator_accessor),
+            22 => self.__api_internal_set_focus(emulator_accessor),
+            23 => self.__api_get_focus(emulator_accessor),
+            28 => self.__api_client_to_screen(emulator_accessor),
+            29 => self.__api_internal_screen_to_client(emulator_accessor),
+            30 => self.__api_internal_window_from_point(emulator_accessor),
+            31 => self.__api_is_iconic(emulator_accessor),
             32 => self.__api_get_window_rect(emulator_accessor),
             33 => self.__api_internal_get_client_rect(emulator_accessor),
+            34 => self.__api_internal_enable_window(emulator_accessor),
+            35 => self.__api_is_window_enabled(emulator_accessor),
             37 => self.__api_set_window_text(emulator_accessor),
             39 => self.__api_internal_begin_paint(emulator_accessor),
             40 => self.__api_internal_end_paint(emulator_accessor),
-            41 => self.__api_create_window(emulator_accessor),
-            42 => self.__api_show_window(emulator_accessor),
+            41 => self.__api_internal_create_window(emulator_accessor),
+            42 => self.__api_internal_show_window(emulator_accessor),
+            46 => self.__api_get_parent(emulator_accessor),
+            47 => self.__api_is_window(emulator_accessor),
+            48 => self.__api_is_child(emulator_accessor),
+            49 => self.__api_is_window_visible(emulator_accessor),
+            50 => self.__api_find_window(emulator_accessor),
+            53 => self.__api_internal_destroy_window(emulator_accessor),
+            54 => self.__api_enum_windows(emulator_accessor),
+            55 => self.__api_enum_child_windows(emulator_accessor),
+            56 => self.__api_move_window(emulator_accessor),
             57 => self.__api_register_class(emulator_accessor),
+            59 => self.__api_set_active_window(emulator_accessor),
+            60 => self.__api_get_active_window(emulator_accessor),
+            62 => self.__api_set_scroll_pos(emulator_accessor),
+            63 => self.__api_get_scroll_pos(emulator_accessor),
+            64 => self.__api_set_scroll_range(emulator_accessor),
+            65 => self.__api_get_scroll_range(emulator_accessor),
             66 => self.__api_internal_get_dc(emulator_accessor),
             68 => self.__api_internal_release_dc(emulator_accessor),
             69 => self.__api_set_cursor(emulator_accessor),
@@ -1110,22 +7495,119 @@ impl<'a> EmulatedUser<'a> {
             78 => self.__api_inflate_rect(emulator_accessor),
             81 => self.__api_fill_rect(emulator_accessor),
             87 => self.__api_dialog_box(emulator_accessor),
+            88 => self.__api_end_dialog(emulator_accessor),
+            89 => self.__api_create_dialog(emulator_accessor),
+            90 => self.__api_internal_is_dialog_message(emulator_accessor),
+            91 => self.__api_get_dlg_item(emulator_accessor),
+            92 => self.__api_set_dlg_item_text(emulator_accessor),
+            93 => self.__api_get_dlg_item_text(emulator_accessor),
+            94 => self.__api_set_dlg_item_int(emulator_accessor),
+            95 => self.__api_get_dlg_item_int(emulator_accessor),
+            96 => self.__api_check_radio_button(emulator_accessor),
+            97 => self.__api_check_dlg_button(emulator_accessor),
+            98 => self.__api_is_dlg_button_checked(emulator_accessor),
+            101 => self.__api_send_dlg_item_message(emulator_accessor),
+            106 => self.__api_get_key_state(emulator_accessor),
             107 => self.__api_def_window_proc(emulator_accessor),
             108 => self.__api_get_message(emulator_accessor),
-            113 => self.__api_translate_message(emulator_accessor),
-            114 => self.__api_dispatch_message(emulator_accessor),
+            109 => self.__api_peek_message(emulator_accessor),
+            110 => self.__api_post_message(emulator_accessor),
+            111 => self.__api_internal_send_message(emulator_accessor),
+            113 => self.__api_internal_translate_message(emulator_accessor),
+            114 => self.__api_internal_dispatch_message(emulator_accessor),
+            119 => self.__api_get_message_pos(emulator_accessor),
+            120 => self.__api_get_message_time(emulator_accessor),
+            121 => self.__api_set_windows_hook(emulator_accessor),
+            122 => self.__api_call_window_proc(emulator_accessor),
+            123 => self.__api_call_msg_filter(emulator_accessor),
             124 => self.__api_update_window(emulator_accessor),
             125 => self.__api_invalidate_rect(emulator_accessor),
+            126 => self.__api_invalidate_rgn(emulator_accessor),
+            127 => self.__api_validate_rect(emulator_accessor),
+            128 => self.__api_validate_rgn(emulator_accessor),
+            129 => self.__api_get_class_word(emulator_accessor),
+            130 => self.__api_set_class_word(emulator_accessor),
+            131 => self.__api_get_class_long(emulator_accessor),
+            132 => self.__api_set_class_long(emulator_accessor),
+            133 => self.__api_get_window_word(emulator_accessor),
+            134 => self.__api_set_window_word(emulator_accessor),
+            135 => self.__api_get_window_long(emulator_accessor),
+            136 => self.__api_set_window_long(emulator_accessor),
+            137 => self.__api_open_clipboard(emulator_accessor),
+            138 => self.__api_close_clipboard(emulator_accessor),
+            139 => self.__api_empty_clipboard(emulator_accessor),
+            140 => self.__api_get_clipboard_owner(emulator_accessor),
+            141 => self.__api_set_clipboard_data(emulator_accessor),
+            142 => self.__api_get_clipboard_data(emulator_accessor),
+            143 => self.__api_count_clipboard_formats(emulator_accessor),
+            144 => self.__api_enum_clipboard_formats(emulator_accessor),
+            145 => self.__api_register_clipboard_format(emulator_accessor),
+            146 => self.__api_get_clipboard_format_name(emulator_accessor),
+            150 => self.__api_load_menu(emulator_accessor),
+            151 => self.__api_create_menu(emulator_accessor),
+            152 => self.__api_internal_destroy_menu(emulator_accessor),
             154 => self.__api_check_menu_item(emulator_accessor),
             155 => self.__api_enable_menu_item(emulator_accessor),
             156 => self.__api_get_system_menu(emulator_accessor),
             157 => self.__api_get_menu(emulator_accessor),
+            158 => self.__api_set_menu(emulator_accessor),
+            159 => self.__api_get_sub_menu(emulator_accessor),
+            160 => self.__api_draw_menu_bar(emulator_accessor),
+            161 => self.__api_get_menu_string(emulator_accessor),
+            163 => self.__api_create_caret(emulator_accessor),
+            164 => self.__api_destroy_caret(emulator_accessor),
+            165 => self.__api_set_caret_pos(emulator_accessor),
+            166 => self.__api_hide_caret(emulator_accessor),
+            167 => self.__api_show_caret(emulator_accessor),
+            168 => self.__api_set_caret_blink_time(emulator_accessor),
+            169 => self.__api_get_caret_blink_time(emulator_accessor),
             173 => self.__api_load_cursor(emulator_accessor),
             176 => self.__api_load_string(emulator_accessor),
+            177 => self.__api_load_accelerators(emulator_accessor),
+            178 => self.__api_translate_accelerator(emulator_accessor),
             179 => self.__api_get_system_metrics(emulator_accessor),
             180 => self.__api_internal_get_sys_color(emulator_accessor),
+            183 => self.__api_get_caret_pos(emulator_accessor),
+            190 => self.__api_get_update_rect(emulator_accessor),
+            191 => self.__api_child_window_from_point(emulator_accessor),
+            192 => self.__api_in_send_message(emulator_accessor),
+            193 => self.__api_is_clipboard_format_available(emulator_accessor),
+            222 => self.__api_get_keyboard_state(emulator_accessor),
+            223 => self.__api_set_keyboard_state(emulator_accessor),
+            229 => self.__api_get_top_window(emulator_accessor),
+            230 => self.__api_get_next_window(emulator_accessor),
+            232 => self.__api_internal_set_window_pos(emulator_accessor),
+            233 => self.__api_set_parent(emulator_accessor),
+            234 => self.__api_unhook_windows_hook(emulator_accessor),
+            235 => self.__api_def_hook_proc(emulator_accessor),
+            239 => self.__api_dialog_box_param(emulator_accessor),
+            241 => self.__api_create_dialog_param(emulator_accessor),
+            249 => self.__api_get_async_key_state(emulator_accessor),
+            250 => self.__api_get_menu_state(emulator_accessor),
+            258 => self.__api_map_window_points(emulator_accessor),
+            262 => self.__api_get_window(emulator_accessor),
+            263 => self.__api_get_menu_item_count(emulator_accessor),
+            264 => self.__api_get_menu_item_id(emulator_accessor),
+            272 => self.__api_is_zoomed(emulator_accessor),
+            286 => self.__api_get_desktop_window(emulator_accessor),
+            291 => self.__api_set_windows_hook_ex(emulator_accessor),
+            292 => self.__api_unhook_windows_hook_ex(emulator_accessor),
+            293 => self.__api_call_next_hook_ex(emulator_accessor),
+            308 => self.__api_def_dlg_proc(emulator_accessor),
+            410 => self.__api_insert_menu(emulator_accessor),
             411 => self.__api_append_menu(emulator_accessor),
+            412 => self.__api_remove_menu(emulator_accessor),
+            413 => self.__api_delete_menu(emulator_accessor),
+            414 => self.__api_modify_menu(emulator_accessor),
+            415 => self.__api_create_popup_menu(emulator_accessor),
+            416 => self.__api_track_popup_menu(emulator_accessor),
             420 => self.__api_wsprintf(emulator_accessor),
+            0xfff9 => self.__api_message_box_dialog_proc(emulator_accessor),
+            0xfffa => self.__api_scroll_bar_window_proc(emulator_accessor),
+            0xfffb => self.__api_combo_box_window_proc(emulator_accessor),
+            0xfffc => self.__api_list_box_window_proc(emulator_accessor),
+            0xfffd => self.__api_edit_window_proc(emulator_accessor),
+            0xfffe => self.__api_static_window_proc(emulator_accessor),
             0xffff => self.__api_button_window_proc(emulator_accessor),
             nr => {
                 todo!("unimplemented user syscall {}", nr)
@@ -1133,3 +7615,45 @@ impl<'a> EmulatedUser<'a> {
         }
     }
 }
+
+/// Reads the string that lParam points to, which may be null for an empty string.
+pub fn read_string_parameter(
+    accessor: &EmulatorAccessor,
+    l_param: u32,
+) -> Result<Vec<u8>, EmulatorError> {
+    if l_param == 0 {
+        return Ok(Vec::new());
+    }
+    let text = accessor.clone_string(Pointer::from_far(l_param).0, false)?;
+    Ok(text.as_slice().to_vec())
+}
+
+/// Writes a string with its terminating null byte.
+pub fn write_string(
+    accessor: &mut EmulatorAccessor,
+    address: u32,
+    text: &[u8],
+) -> Result<(), EmulatorError> {
+    accessor.memory_mut().copy_from(text, address as usize)?;
+    accessor
+        .memory_mut()
+        .write_8(address + text.len() as u32, 0)?;
+    Ok(())
+}
+
+/// Copies text into the buffer of WM_GETTEXT, which is wParam bytes long and which lParam
+/// points to. Returns how many bytes were copied, without the null byte.
+pub fn get_text(
+    accessor: &mut EmulatorAccessor,
+    text: &[u8],
+    w_param: u16,
+    l_param: u32,
+) -> Result<u32, EmulatorError> {
+    if w_param == 0 {
+        return Ok(0);
+    }
+    // The buffer also holds the terminating null byte
+    let length = text.len().min(w_param as usize - 1);
+    write_string(accessor, Pointer::from_far(l_param).0, &text[..length])?;
+    Ok(length as u32)
+}
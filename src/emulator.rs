@@ -1,41 +1,46 @@
 use crate::api_helpers::ReturnValue;
 use crate::constants::{
-    GDI_INT_VECTOR, KERNEL_INT_VECTOR, KEYBOARD_INT_VECTOR, LOWEST_SYSCALL_INT_VECTOR,
-    USER_INT_VECTOR,
+    CONVENTIONAL_MEMORY_TOP, GDI_INT_VECTOR, KERNEL_INT_VECTOR, KEYBOARD_INT_VECTOR,
+    LOWEST_SYSCALL_INT_VECTOR, USER_INT_VECTOR,
 };
 use crate::emulated_gdi::EmulatedGdi;
 use crate::emulated_kernel::EmulatedKernel;
 use crate::emulated_keyboard::EmulatedKeyboard;
 use crate::emulator_accessor::EmulatorAccessor;
 use crate::emulator_error::EmulatorError;
-use crate::memory::Memory;
+use crate::memory::{Memory, SegmentAndOffset};
 use crate::mod_rm::{ModRM, ModRMByte};
 use crate::registers::Registers;
 use crate::util::{
-    add_with_flags, add_with_flags_16, add_with_flags_8, sub_with_flags, sub_with_flags_16,
-    sub_with_flags_8,
+    add_with_flags, add_with_flags_16, add_with_flags_8, encode_u16_u16_to_u32, sub_with_flags,
+    sub_with_flags_16, sub_with_flags_8,
 };
 use crate::{debug, EmulatedUser};
 use chrono::{Datelike, Timelike, Weekday};
+use std::collections::HashMap;
+use std::io::Write;
 
 pub struct Emulator<'a> {
     regs: Registers,
     segment_override: u8,
     memory: Memory,
-    emulated_kernel: EmulatedKernel<'a>,
-    emulated_user: EmulatedUser<'a>,
-    emulated_gdi: EmulatedGdi<'a>,
-    emulated_keyboard: EmulatedKeyboard,
+    emulated_kernel: &'a EmulatedKernel<'a>,
+    emulated_user: &'a EmulatedUser<'a>,
+    emulated_gdi: &'a EmulatedGdi<'a>,
+    emulated_keyboard: &'a EmulatedKeyboard,
+    /// The interrupt vectors that the program set with INT 21h AH=25h. The program can read
+    /// them back, but they don't get called.
+    interrupt_vectors: HashMap<u8, SegmentAndOffset>,
 }
 
 impl<'a> Emulator<'a> {
     pub fn new(
         regs: Registers,
         memory: Memory,
-        emulated_kernel: EmulatedKernel<'a>,
-        emulated_user: EmulatedUser<'a>,
-        emulated_gdi: EmulatedGdi<'a>,
-        emulated_keyboard: EmulatedKeyboard,
+        emulated_kernel: &'a EmulatedKernel<'a>,
+        emulated_user: &'a EmulatedUser<'a>,
+        emulated_gdi: &'a EmulatedGdi<'a>,
+        emulated_keyboard: &'a EmulatedKeyboard,
     ) -> Self {
         Self {
             regs,
@@ -45,9 +50,30 @@ impl<'a> Emulator<'a> {
             emulated_user,
             emulated_gdi,
             emulated_keyboard,
+            interrupt_vectors: HashMap::new(),
         }
     }
 
+    pub fn regs(&self) -> &Registers {
+        &self.regs
+    }
+
+    pub fn regs_mut(&mut self) -> &mut Registers {
+        &mut self.regs
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn emulated_kernel(&self) -> &'a EmulatedKernel<'a> {
+        self.emulated_kernel
+    }
+
     fn push_value_16(&mut self, data: u16) -> Result<(), EmulatorError> {
         self.regs.dec_sp(2);
         self.memory.write_u16(
@@ -524,17 +550,100 @@ impl<'a> Emulator<'a> {
         Ok(())
     }
 
+    fn write_to_console(&self, handle: u16, bytes: &[u8]) -> bool {
+        let result = match handle {
+            1 => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(bytes).and_then(|_| stdout.flush())
+            }
+            2 => std::io::stderr().write_all(bytes),
+            _ => return false,
+        };
+        result.is_ok()
+    }
+
     fn int(&mut self) -> Result<(), EmulatorError> {
         let nr = self.read_ip_u8()?;
-        if nr == 0x21 {
+        if nr == 0x20 {
+            debug!("[cpu] Exit with {}", 0);
+            Err(EmulatorError::Exit)
+        } else if nr == 0x21 {
             let ah = self.regs.read_gpr_hi_8(Registers::REG_AH);
             if ah == 0x4C {
                 debug!(
                     "[cpu] Exit with {}",
                     self.regs.read_gpr_lo_8(Registers::REG_AL)
                 );
+                return Err(EmulatorError::Exit);
             } else if ah == 0 {
                 debug!("[cpu] Exit with {}", 0);
+                return Err(EmulatorError::Exit);
+            } else if ah == 0x02 {
+                // Write character to standard output
+                let character = self.regs.read_gpr_lo_8(Registers::REG_DL);
+                self.write_to_console(1, &[character]);
+                self.regs.write_gpr_lo_8(Registers::REG_AL, character);
+                return Ok(());
+            } else if ah == 0x09 {
+                // Write '$'-terminated string to standard output
+                let start = self.regs.flat_reg(Registers::REG_DS, Registers::REG_DX);
+                let mut end = start;
+                while self.memory.read_8(end)? != b'$' {
+                    end += 1;
+                }
+                self.write_to_console(1, self.memory.slice(start, end)?);
+                self.regs.write_gpr_lo_8(Registers::REG_AL, b'$');
+                return Ok(());
+            } else if ah == 0x40 {
+                // Write to file or device, only standard output and standard error are supported
+                let handle = self.regs.read_gpr_16(Registers::REG_BX);
+                let count = self.regs.read_gpr_16(Registers::REG_CX);
+                let start = self.regs.flat_reg(Registers::REG_DS, Registers::REG_DX);
+                let bytes = self.memory.slice(start, start + count as u32)?;
+                if self.write_to_console(handle, bytes) {
+                    self.regs.write_gpr_16(Registers::REG_AX, count);
+                    self.regs.set_carry_flag(false);
+                } else {
+                    // Invalid handle
+                    self.regs.write_gpr_16(Registers::REG_AX, 6);
+                    self.regs.set_carry_flag(true);
+                }
+                return Ok(());
+            } else if ah == 0x25 {
+                // Set interrupt vector
+                let vector = self.regs.read_gpr_lo_8(Registers::REG_AL);
+                let handler = SegmentAndOffset {
+                    segment: self.regs.read_segment(Registers::REG_DS),
+                    offset: self.regs.read_gpr_16(Registers::REG_DX),
+                };
+                self.interrupt_vectors.insert(vector, handler);
+                return Ok(());
+            } else if ah == 0x35 {
+                // Get interrupt vector, vectors that weren't set point nowhere
+                let vector = self.regs.read_gpr_lo_8(Registers::REG_AL);
+                let handler = self
+                    .interrupt_vectors
+                    .get(&vector)
+                    .copied()
+                    .unwrap_or_default();
+                self.regs.write_segment(Registers::REG_ES, handler.segment);
+                self.regs.write_gpr_16(Registers::REG_BX, handler.offset);
+                return Ok(());
+            } else if ah == 0x4A {
+                // Resize memory block. The blocks aren't kept track of, so a block can grow up
+                // to the top of conventional memory that the PSP gives.
+                let segment = self.regs.read_segment(Registers::REG_ES);
+                let paragraphs = self.regs.read_gpr_16(Registers::REG_BX);
+                let available = CONVENTIONAL_MEMORY_TOP.saturating_sub(segment);
+                if paragraphs <= available {
+                    self.regs.set_carry_flag(false);
+                } else {
+                    // Insufficient memory, with the largest size that would fit
+                    self.regs.write_gpr_16(Registers::REG_AX, 8);
+                    self.regs.write_gpr_16(Registers::REG_BX, available);
+                    self.regs.set_carry_flag(true);
+                }
+                return Ok(());
             } else if ah == 0x30 {
                 // Get DOS version, fake MS-DOS 5.0
                 // TODO: only al and ah are set right now
@@ -577,21 +686,32 @@ impl<'a> Emulator<'a> {
                 );
                 return Ok(());
             }
-            Err(EmulatorError::Exit)
+            // Other functions fail with "invalid function", so the program can carry on
+            debug!("[cpu] Unsupported DOS function {:x}", ah);
+            self.regs.write_gpr_16(Registers::REG_AX, 1);
+            self.regs.set_carry_flag(true);
+            Ok(())
         } else if nr >= LOWEST_SYSCALL_INT_VECTOR {
             // System call handler
             let function = self.regs.read_gpr_16(Registers::REG_AX);
-            let accessor = EmulatorAccessor::new(&mut self.memory, &mut self.regs);
+            // The modules are shared references, so system calls can run guest code in turn
+            let (emulated_kernel, emulated_user, emulated_gdi, emulated_keyboard) = (
+                self.emulated_kernel,
+                self.emulated_user,
+                self.emulated_gdi,
+                self.emulated_keyboard,
+            );
+            let accessor = EmulatorAccessor::new(self);
             let result = if nr == KERNEL_INT_VECTOR {
-                self.emulated_kernel.syscall(function, accessor)
+                emulated_kernel.syscall(function, accessor)
             } else if nr == USER_INT_VECTOR {
-                self.emulated_user.syscall(function, accessor)
+                emulated_user.syscall(function, accessor)
             } else if nr == GDI_INT_VECTOR {
-                self.emulated_gdi.syscall(function, accessor)
+                emulated_gdi.syscall(function, accessor)
             } else if nr == KEYBOARD_INT_VECTOR {
-                self.emulated_keyboard.syscall(function, accessor)
+                emulated_keyboard.syscall(function, accessor)
             } else {
-                Err(EmulatorError::Exit)
+                Err(EmulatorError::UnsupportedInterrupt(nr))
             }?;
             match result {
                 ReturnValue::U16(value) => {
@@ -602,15 +722,15 @@ impl<'a> Emulator<'a> {
                     self.regs
                         .write_gpr_16(Registers::REG_DX, (value >> 16) as u16);
                 }
-                ReturnValue::DelayedU16(value) => {
-                    self.memory
-                        .write_u16(self.regs.flat_sp().wrapping_add(14), value)?;
-                }
                 ReturnValue::None => {}
             }
             Ok(())
         } else {
-            Err(EmulatorError::Exit)
+            // There are no handlers for the other interrupts, the program carries on as if the
+            // interrupt failed
+            debug!("[cpu] Unsupported interrupt {:x}", nr);
+            self.regs.set_carry_flag(true);
+            Ok(())
         }
     }
 
@@ -1276,17 +1396,66 @@ impl<'a> Emulator<'a> {
         );
     }
 
-    pub fn step(&mut self) {
+    fn try_step(&mut self) -> Result<(), EmulatorError> {
         let old_ip = self.regs.ip;
-        if let Err(error) = self.execute_opcode() {
-            self.log(old_ip);
-            panic!("TODO: error handling for {:?}", error);
+        match self.execute_opcode() {
+            Ok(_) => Ok(()),
+            Err(EmulatorError::Exit) => Err(EmulatorError::Exit),
+            Err(EmulatorError::UnsupportedInterrupt(nr)) => {
+                // Only this task ends
+                self.log(old_ip);
+                debug!("[cpu] Interrupt {:x} is not supported", nr);
+                Err(EmulatorError::Exit)
+            }
+            Err(error) => {
+                self.log(old_ip);
+                panic!("TODO: error handling for {:?}", error);
+            }
         }
     }
 
-    pub fn run(&mut self) {
-        loop {
-            self.step();
+    /// Executes one instruction, returns false once the program has exited.
+    pub fn step(&mut self) -> bool {
+        self.try_step().is_ok()
+    }
+
+    /// Calls a far procedure with the Pascal calling convention and runs it until it returns.
+    /// The procedure returns to the current CS:IP, and it is done once it does so with the
+    /// arguments popped off the stack again. Registers are restored afterwards.
+    /// Returns DX:AX.
+    pub fn call_far(
+        &mut self,
+        proc: SegmentAndOffset,
+        arguments: &[u16],
+    ) -> Result<u32, EmulatorError> {
+        let saved_regs = self.regs.clone();
+        let saved_segment_override = self.segment_override;
+        for &argument in arguments {
+            self.push_value_16(argument)?;
         }
+        self.push_cs()?;
+        self.push_ip()?;
+        self.regs.write_segment(Registers::REG_CS, proc.segment);
+        self.regs.ip = proc.offset;
+
+        while self.regs.ip != saved_regs.ip
+            || self.regs.read_segment(Registers::REG_CS)
+                != saved_regs.read_segment(Registers::REG_CS)
+            || self.regs.read_gpr_16(Registers::REG_SP) != saved_regs.read_gpr_16(Registers::REG_SP)
+        {
+            self.try_step()?;
+        }
+
+        let result = encode_u16_u16_to_u32(
+            self.regs.read_gpr_16(Registers::REG_AX),
+            self.regs.read_gpr_16(Registers::REG_DX),
+        );
+        self.regs = saved_regs;
+        self.segment_override = saved_segment_override;
+        Ok(result)
+    }
+
+    pub fn run(&mut self) {
+        while self.step() {}
     }
 }
@@ -1,72 +1,75 @@
-use crate::byte_string::{ByteString, HeapByteString};
+use crate::api_helpers::Pointer;
+use crate::byte_string::HeapByteString;
+use crate::emulated_kernel::EmulatedKernel;
+use crate::emulator::Emulator;
+use crate::memory::SegmentAndOffset;
 use crate::registers::Registers;
-use crate::two_d::Rect;
-use crate::{EmulatorError, Memory};
+use crate::two_d::{Point, Rect};
+use crate::util::encode_u16_u16_to_u32;
+use crate::{EmulatorError, Memory, ResourceName};
 
-pub struct EmulatorAccessor<'a> {
-    memory: &'a mut Memory,
-    regs: &'a mut Registers,
+pub struct EmulatorAccessor<'a, 'e> {
+    emulator: &'a mut Emulator<'e>,
 }
 
-impl<'a> EmulatorAccessor<'a> {
-    pub fn new(memory: &'a mut Memory, regs: &'a mut Registers) -> Self {
-        Self { memory, regs }
+impl<'a, 'e> EmulatorAccessor<'a, 'e> {
+    pub fn new(emulator: &'a mut Emulator<'e>) -> Self {
+        Self { emulator }
     }
 
     pub fn regs(&self) -> &Registers {
-        self.regs
+        self.emulator.regs()
     }
 
     pub fn regs_mut(&mut self) -> &mut Registers {
-        self.regs
+        self.emulator.regs_mut()
     }
 
     pub fn memory(&self) -> &Memory {
-        self.memory
+        self.emulator.memory()
     }
 
     pub fn memory_mut(&mut self) -> &mut Memory {
-        self.memory
+        self.emulator.memory_mut()
     }
 
-    pub fn word_argument(&self, nr: u32) -> Result<u16, EmulatorError> {
-        let address = self.regs.flat_sp() + 4 + nr * 2;
-        self.memory.read_u16(address)
+    /// The KERNEL of the task, for the other modules that need its memory management.
+    pub fn kernel(&self) -> &'e EmulatedKernel<'e> {
+        self.emulator.emulated_kernel()
     }
 
-    pub fn push_16(&mut self, value: u16) -> Result<(), EmulatorError> {
-        self.regs.dec_sp(2);
-        self.memory.write_u16(
-            self.regs.flat_reg(Registers::REG_SS, Registers::REG_SP),
-            value,
-        )
+    pub fn word_argument(&self, nr: u32) -> Result<u16, EmulatorError> {
+        let address = self.regs().flat_sp() + 4 + nr * 2;
+        self.memory().read_u16(address)
     }
 
-    pub fn far_call_into_proc_setup(&mut self) -> Result<(), EmulatorError> {
-        // The actual place to return to in the system call
-        self.push_16(self.regs.ip)?;
-        // Return value will be filled in later by `ReturnValue::DelayedU16`
-        self.regs.dec_sp(2);
-        Ok(())
+    /// Calls a far procedure in the guest with the given arguments, pushed in order,
+    /// and runs it until it returns. Returns DX:AX.
+    pub fn call_far(
+        &mut self,
+        proc: SegmentAndOffset,
+        arguments: &[u16],
+    ) -> Result<u32, EmulatorError> {
+        self.emulator.call_far(proc, arguments)
+    }
+
+    /// Makes room for a structure on the stack, so a procedure that gets called can be given a
+    /// pointer to it. Returns the far pointer, the way a lParam holds it.
+    pub fn reserve_stack_space(&mut self, size: u16) -> u32 {
+        self.regs_mut().dec_sp(size);
+        encode_u16_u16_to_u32(
+            self.regs().read_gpr_16(Registers::REG_SP),
+            self.regs().read_segment(Registers::REG_SS),
+        )
     }
 
-    pub fn far_call_into_proc_execute(
-        &mut self,
-        segment: u16,
-        offset: u16,
-    ) -> Result<(), EmulatorError> {
-        // Setup return far into the system call trampoline
-        self.push_16(self.regs.read_segment(Registers::REG_CS))?;
-        self.push_16(0)?;
-        // Move into proc
-        self.regs.ip = offset;
-        self.regs.write_segment(Registers::REG_CS, segment);
-        Ok(())
+    pub fn release_stack_space(&mut self, size: u16) {
+        self.regs_mut().inc_sp(size);
     }
 
     pub fn dword_argument(&self, nr: u32) -> Result<u32, EmulatorError> {
-        let address = self.regs.flat_sp() + 4 + nr * 2;
-        self.memory.read_32(address)
+        let address = self.regs().flat_sp() + 4 + nr * 2;
+        self.memory().read_32(address)
     }
 
     pub fn pointer_argument(&self, nr: u32) -> Result<u32, EmulatorError> {
@@ -80,7 +83,7 @@ impl<'a> EmulatorAccessor<'a> {
     pub fn strlen(&self, mut ptr: u32) -> Result<u16, EmulatorError> {
         let mut length = 0u16;
         loop {
-            let data = self.memory.read_8(ptr)?;
+            let data = self.memory().read_8(ptr)?;
             if data == 0 {
                 break;
             }
@@ -97,8 +100,8 @@ impl<'a> EmulatorAccessor<'a> {
     ) -> Result<u16, EmulatorError> {
         let mut number_of_bytes_copied = 0u16;
         loop {
-            let data = self.memory.read_8(src_ptr)?;
-            self.memory.write_8(dst_ptr, data)?;
+            let data = self.memory().read_8(src_ptr)?;
+            self.memory_mut().write_8(dst_ptr, data)?;
             if data == 0 {
                 break;
             }
@@ -116,7 +119,7 @@ impl<'a> EmulatorAccessor<'a> {
     ) -> Result<HeapByteString, EmulatorError> {
         let mut output = Vec::new();
         loop {
-            let data = self.memory.read_8(src_ptr)?;
+            let data = self.memory().read_8(src_ptr)?;
             if data == 0 {
                 break;
             }
@@ -130,26 +133,29 @@ impl<'a> EmulatorAccessor<'a> {
         Ok(HeapByteString::from(output.into()))
     }
 
-    pub fn static_string(&self, src_ptr: u32) -> Result<ByteString, EmulatorError> {
-        let mut length = 0;
-        loop {
-            let current = src_ptr.saturating_add(length);
-            let data = self.memory.read_8(current)?;
-            if data == 0 {
-                return self
-                    .memory
-                    .slice(src_ptr, current)
-                    .map(ByteString::from_slice);
-            }
-            length += 1;
+    /// Reads the name of a resource. Numbers made with MAKEINTRESOURCE have a segment of 0,
+    /// and strings of the form "#123" stand for numbers too.
+    pub fn resource_name(&self, far_pointer: u32) -> Result<ResourceName, EmulatorError> {
+        if far_pointer >> 16 == 0 {
+            return Ok(ResourceName::Integer(far_pointer as u16));
         }
+        let name = self.clone_string(Pointer::from_far(far_pointer).0, true)?;
+        let number = name
+            .as_slice()
+            .strip_prefix(b"#")
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| digits.parse().ok());
+        Ok(match number {
+            Some(number) => ResourceName::Integer(number),
+            None => ResourceName::String(name),
+        })
     }
 
     pub fn read_rect(&self, src_ptr: u32) -> Result<Rect, EmulatorError> {
-        let rect_left = self.memory.read_i16(src_ptr)?;
-        let rect_top = self.memory.read_i16(src_ptr + 2)?;
-        let rect_right = self.memory.read_i16(src_ptr + 4)?;
-        let rect_bottom = self.memory.read_i16(src_ptr + 6)?;
+        let rect_left = self.memory().read_i16(src_ptr)?;
+        let rect_top = self.memory().read_i16(src_ptr + 2)?;
+        let rect_right = self.memory().read_i16(src_ptr + 4)?;
+        let rect_bottom = self.memory().read_i16(src_ptr + 6)?;
         Ok(Rect {
             left: rect_left,
             top: rect_top,
@@ -158,10 +164,22 @@ impl<'a> EmulatorAccessor<'a> {
         })
     }
 
+    pub fn read_point(&self, src_ptr: u32) -> Result<Point, EmulatorError> {
+        Ok(Point::new(
+            self.memory().read_i16(src_ptr)?,
+            self.memory().read_i16(src_ptr + 2)?,
+        ))
+    }
+
+    pub fn write_point(&mut self, dst_ptr: u32, point: Point) -> Result<(), EmulatorError> {
+        self.memory_mut().write_i16(dst_ptr, point.x)?;
+        self.memory_mut().write_i16(dst_ptr + 2, point.y)
+    }
+
     pub fn write_rect(&mut self, dst_ptr: u32, rect: &Rect) -> Result<(), EmulatorError> {
-        self.memory.write_i16(dst_ptr, rect.left)?;
-        self.memory.write_i16(dst_ptr + 2, rect.top)?;
-        self.memory.write_i16(dst_ptr + 4, rect.right)?;
-        self.memory.write_i16(dst_ptr + 6, rect.bottom)
+        self.memory_mut().write_i16(dst_ptr, rect.left)?;
+        self.memory_mut().write_i16(dst_ptr + 2, rect.top)?;
+        self.memory_mut().write_i16(dst_ptr + 4, rect.right)?;
+        self.memory_mut().write_i16(dst_ptr + 6, rect.bottom)
     }
 }
@@ -4,4 +4,6 @@ pub enum EmulatorError {
     Exit,
     InvalidOpcode,
     DivideError,
+    /// An interrupt that nothing handles, with its number.
+    UnsupportedInterrupt(u8),
 }
@@ -11,6 +11,18 @@ pub enum ExecutableFormatError {
     Memory,
 }
 
+impl ExecutableFormatError {
+    /// The error code LoadModule and WinExec report when loading fails with this error.
+    pub fn load_error_code(&self) -> u16 {
+        match self {
+            // Insufficient memory to start the application
+            ExecutableFormatError::Memory => 8,
+            // Invalid executable file
+            _ => 11,
+        }
+    }
+}
+
 pub struct Executable<'a> {
     internal_data: &'a mut [u8],
     cursor: usize,
@@ -43,6 +43,18 @@ impl<K: GenericHandle, V> GenericHandleTable<K, V> {
     pub fn get_mut(&mut self, handle: K) -> Option<&mut V> {
         self.internal_table.get_mut(&handle)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, &V)> {
+        self.internal_table
+            .iter()
+            .map(|(&handle, value)| (handle, value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (K, &mut V)> {
+        self.internal_table
+            .iter_mut()
+            .map(|(&handle, value)| (handle, value))
+    }
 }
 
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
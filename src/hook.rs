@@ -0,0 +1,206 @@
+use crate::constants::{
+    HitTest, MessageType, WH_CALLWNDPROC, WH_CBT, WH_GETMESSAGE, WH_KEYBOARD, WH_MOUSE,
+    WH_MSGFILTER, WH_SYSMSGFILTER,
+};
+use crate::memory::SegmentAndOffset;
+use crate::window_manager::ProcessId;
+
+#[derive(Copy, Clone)]
+pub struct Hook {
+    pub handle: u32,
+    pub hook_type: i16,
+    /// The task that installed the hook. The hook procedure is in its memory, so it gets called
+    /// in that task.
+    pub process_id: ProcessId,
+    /// The task whose messages the hook gets, or None for the hooks of the whole system.
+    pub task: Option<ProcessId>,
+    pub proc: SegmentAndOffset,
+}
+
+/// The memory that lParam of a hook call points to. A hook of another task gets a copy of it.
+#[derive(Copy, Clone)]
+pub struct HookParameter {
+    pub size: u16,
+    /// Where the far pointers into the memory itself are, which get pointed into the copy.
+    pub pointers: &'static [u16],
+}
+
+impl HookParameter {
+    /// Memory without pointers in it.
+    pub const fn of_size(size: u16) -> Self {
+        Self {
+            size,
+            pointers: &[],
+        }
+    }
+}
+
+/// A call of a hook procedure that another task installed, for the messages of the task that
+/// makes the call.
+#[derive(Copy, Clone)]
+pub struct HookCall {
+    pub hook_type: i16,
+    pub proc: SegmentAndOffset,
+    pub code: u16,
+    pub w_param: u16,
+    pub l_param: u32,
+    pub parameter: Option<HookParameter>,
+}
+
+/// The hooks of every task. A task calls the newest hook of a chain, which passes the call on
+/// to the next one with CallNextHookEx. The hooks of the task come before the hooks of the
+/// whole system.
+pub struct HookTable {
+    /// The hooks from the newest one to the oldest one.
+    hooks: Vec<Hook>,
+    next_handle: u32,
+}
+
+/// Whether hooks of a kind can be installed. Journal hooks and the others can't yet.
+pub fn is_supported(hook_type: i16) -> bool {
+    matches!(
+        hook_type,
+        WH_MSGFILTER
+            | WH_KEYBOARD
+            | WH_GETMESSAGE
+            | WH_CALLWNDPROC
+            | WH_CBT
+            | WH_SYSMSGFILTER
+            | WH_MOUSE
+    )
+}
+
+/// Whether WH_KEYBOARD hooks get to see a message.
+pub fn is_keyboard_message(message: u16) -> bool {
+    [
+        MessageType::KeyDown,
+        MessageType::KeyUp,
+        MessageType::SysKeyDown,
+        MessageType::SysKeyUp,
+    ]
+    .into_iter()
+    .any(|key_message| message == u16::from(key_message))
+}
+
+/// The hit test code that WH_MOUSE hooks get with a mouse message, or None if it isn't one.
+pub fn mouse_hit_test(message: u16, w_param: u16) -> Option<u16> {
+    let client = u16::from(MessageType::MouseMove)..=u16::from(MessageType::MButtonDblClk);
+    let non_client = u16::from(MessageType::NcMouseMove)..=u16::from(MessageType::NcMButtonDblClk);
+    if client.contains(&message) {
+        Some(HitTest::Client.into())
+    } else if non_client.contains(&message) {
+        // The non-client mouse messages have it in wParam
+        Some(w_param)
+    } else {
+        None
+    }
+}
+
+impl HookTable {
+    pub fn new() -> Self {
+        Self {
+            hooks: Vec::new(),
+            next_handle: 1,
+        }
+    }
+
+    /// Installs a hook in front of the chain of its kind. Returns its handle.
+    pub fn install(
+        &mut self,
+        hook_type: i16,
+        process_id: ProcessId,
+        task: Option<ProcessId>,
+        proc: SegmentAndOffset,
+    ) -> u32 {
+        let handle = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1).max(1);
+        self.hooks.insert(
+            0,
+            Hook {
+                handle,
+                hook_type,
+                process_id,
+                task,
+                proc,
+            },
+        );
+        handle
+    }
+
+    /// Returns whether there was such a hook.
+    pub fn remove(&mut self, handle: u32) -> bool {
+        let count = self.hooks.len();
+        self.hooks.retain(|hook| hook.handle != handle);
+        self.hooks.len() != count
+    }
+
+    /// Removes the newest hook of a kind that a task installed with the given procedure, for
+    /// UnhookWindowsHook. Returns whether there was such a hook.
+    pub fn remove_proc(
+        &mut self,
+        hook_type: i16,
+        process_id: ProcessId,
+        proc: SegmentAndOffset,
+    ) -> bool {
+        match self.hooks.iter().position(|hook| {
+            hook.hook_type == hook_type
+                && hook.process_id == process_id
+                && hook.proc.segment == proc.segment
+                && hook.proc.offset == proc.offset
+        }) {
+            Some(index) => {
+                self.hooks.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the hooks of a task that ended.
+    pub fn remove_hooks_of_process(&mut self, process_id: ProcessId) {
+        self.hooks.retain(|hook| hook.process_id != process_id);
+    }
+
+    /// The hooks of a kind that get called for the messages of a task, in the order they get
+    /// called.
+    fn chain(&self, hook_type: i16, process_id: ProcessId) -> impl Iterator<Item = Hook> + '_ {
+        let of_task = self
+            .hooks
+            .iter()
+            .filter(move |hook| hook.task == Some(process_id));
+        let of_system = self.hooks.iter().filter(|hook| hook.task.is_none());
+        of_task
+            .chain(of_system)
+            .filter(move |hook| hook.hook_type == hook_type)
+            .copied()
+    }
+
+    /// The hook with the given handle.
+    pub fn get(&self, handle: u32) -> Option<Hook> {
+        self.hooks
+            .iter()
+            .find(|hook| hook.handle == handle)
+            .copied()
+    }
+
+    /// The newest hook with the given procedure, for DefHookProc, which only knows the procedure.
+    pub fn find_proc(&self, proc: SegmentAndOffset) -> Option<Hook> {
+        self.hooks
+            .iter()
+            .find(|hook| hook.proc.segment == proc.segment && hook.proc.offset == proc.offset)
+            .copied()
+    }
+
+    /// The first hook of a kind for the messages of a task.
+    pub fn first(&self, hook_type: i16, process_id: ProcessId) -> Option<Hook> {
+        self.chain(hook_type, process_id).next()
+    }
+
+    /// The hook after the given one in its chain, for the messages of a task.
+    pub fn next(&self, handle: u32, process_id: ProcessId) -> Option<Hook> {
+        let hook_type = self.get(handle)?.hook_type;
+        self.chain(hook_type, process_id)
+            .skip_while(|hook| hook.handle != handle)
+            .nth(1)
+    }
+}
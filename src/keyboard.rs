@@ -0,0 +1,382 @@
+//! Virtual-key codes, the US keyboard layout and the state of the keys.
+
+pub const VK_LBUTTON: u8 = 0x01;
+pub const VK_RBUTTON: u8 = 0x02;
+pub const VK_MBUTTON: u8 = 0x04;
+pub const VK_BACK: u8 = 0x08;
+pub const VK_TAB: u8 = 0x09;
+pub const VK_RETURN: u8 = 0x0d;
+pub const VK_SHIFT: u8 = 0x10;
+pub const VK_CONTROL: u8 = 0x11;
+pub const VK_MENU: u8 = 0x12;
+pub const VK_PAUSE: u8 = 0x13;
+pub const VK_CAPITAL: u8 = 0x14;
+pub const VK_ESCAPE: u8 = 0x1b;
+pub const VK_SPACE: u8 = 0x20;
+pub const VK_PRIOR: u8 = 0x21;
+pub const VK_NEXT: u8 = 0x22;
+pub const VK_END: u8 = 0x23;
+pub const VK_HOME: u8 = 0x24;
+pub const VK_LEFT: u8 = 0x25;
+pub const VK_UP: u8 = 0x26;
+pub const VK_RIGHT: u8 = 0x27;
+pub const VK_DOWN: u8 = 0x28;
+pub const VK_SNAPSHOT: u8 = 0x2c;
+pub const VK_INSERT: u8 = 0x2d;
+pub const VK_DELETE: u8 = 0x2e;
+pub const VK_0: u8 = 0x30;
+pub const VK_A: u8 = 0x41;
+pub const VK_NUMPAD0: u8 = 0x60;
+pub const VK_MULTIPLY: u8 = 0x6a;
+pub const VK_ADD: u8 = 0x6b;
+pub const VK_SUBTRACT: u8 = 0x6d;
+pub const VK_DECIMAL: u8 = 0x6e;
+pub const VK_DIVIDE: u8 = 0x6f;
+pub const VK_F1: u8 = 0x70;
+pub const VK_F4: u8 = 0x73;
+pub const VK_F10: u8 = 0x79;
+pub const VK_F11: u8 = 0x7a;
+pub const VK_F12: u8 = 0x7b;
+pub const VK_NUMLOCK: u8 = 0x90;
+pub const VK_SCROLL: u8 = 0x91;
+pub const VK_OEM_1: u8 = 0xba;
+pub const VK_OEM_PLUS: u8 = 0xbb;
+pub const VK_OEM_COMMA: u8 = 0xbc;
+pub const VK_OEM_MINUS: u8 = 0xbd;
+pub const VK_OEM_PERIOD: u8 = 0xbe;
+pub const VK_OEM_2: u8 = 0xbf;
+pub const VK_OEM_3: u8 = 0xc0;
+pub const VK_OEM_4: u8 = 0xdb;
+pub const VK_OEM_5: u8 = 0xdc;
+pub const VK_OEM_6: u8 = 0xdd;
+pub const VK_OEM_7: u8 = 0xde;
+
+/// The shift state that VkKeyScan and OemKeyScan report in their high byte.
+pub const SHIFT_STATE_SHIFT: u8 = 1;
+pub const SHIFT_STATE_CONTROL: u8 = 2;
+
+/// Characters typed with the OEM keys, without and with shift.
+const OEM_CHARACTERS: [(u8, u8, u8); 11] = [
+    (VK_OEM_1, b';', b':'),
+    (VK_OEM_PLUS, b'=', b'+'),
+    (VK_OEM_COMMA, b',', b'<'),
+    (VK_OEM_MINUS, b'-', b'_'),
+    (VK_OEM_PERIOD, b'.', b'>'),
+    (VK_OEM_2, b'/', b'?'),
+    (VK_OEM_3, b'`', b'~'),
+    (VK_OEM_4, b'[', b'{'),
+    (VK_OEM_5, b'\\', b'|'),
+    (VK_OEM_6, b']', b'}'),
+    (VK_OEM_7, b'\'', b'"'),
+];
+
+/// Characters typed with the digit keys while shift is down.
+const SHIFTED_DIGITS: &[u8; 10] = b")!@#$%^&*(";
+
+/// Scan codes of the keys on an enhanced 101/102-key keyboard. The keys that share their scan code
+/// with a key on the numeric keypad come first, and are the extended keys.
+const SCAN_CODES: [(u8, u8); 72] = [
+    (VK_HOME, 0x47),
+    (VK_UP, 0x48),
+    (VK_PRIOR, 0x49),
+    (VK_LEFT, 0x4b),
+    (VK_RIGHT, 0x4d),
+    (VK_END, 0x4f),
+    (VK_DOWN, 0x50),
+    (VK_NEXT, 0x51),
+    (VK_INSERT, 0x52),
+    (VK_DELETE, 0x53),
+    (VK_ESCAPE, 0x01),
+    (VK_0 + 1, 0x02),
+    (VK_0 + 2, 0x03),
+    (VK_0 + 3, 0x04),
+    (VK_0 + 4, 0x05),
+    (VK_0 + 5, 0x06),
+    (VK_0 + 6, 0x07),
+    (VK_0 + 7, 0x08),
+    (VK_0 + 8, 0x09),
+    (VK_0 + 9, 0x0a),
+    (VK_0, 0x0b),
+    (VK_OEM_MINUS, 0x0c),
+    (VK_OEM_PLUS, 0x0d),
+    (VK_BACK, 0x0e),
+    (VK_TAB, 0x0f),
+    (VK_OEM_4, 0x1a),
+    (VK_OEM_6, 0x1b),
+    (VK_RETURN, 0x1c),
+    (VK_CONTROL, 0x1d),
+    (VK_OEM_1, 0x27),
+    (VK_OEM_7, 0x28),
+    (VK_OEM_3, 0x29),
+    (VK_SHIFT, 0x2a),
+    (VK_OEM_5, 0x2b),
+    (VK_OEM_COMMA, 0x33),
+    (VK_OEM_PERIOD, 0x34),
+    (VK_OEM_2, 0x35),
+    (VK_MULTIPLY, 0x37),
+    (VK_MENU, 0x38),
+    (VK_SPACE, 0x39),
+    (VK_CAPITAL, 0x3a),
+    (VK_F1, 0x3b),
+    (VK_F1 + 1, 0x3c),
+    (VK_F1 + 2, 0x3d),
+    (VK_F1 + 3, 0x3e),
+    (VK_F1 + 4, 0x3f),
+    (VK_F1 + 5, 0x40),
+    (VK_F1 + 6, 0x41),
+    (VK_F1 + 7, 0x42),
+    (VK_F1 + 8, 0x43),
+    (VK_F10, 0x44),
+    (VK_NUMLOCK, 0x45),
+    (VK_SCROLL, 0x46),
+    (VK_NUMPAD0 + 7, 0x47),
+    (VK_NUMPAD0 + 8, 0x48),
+    (VK_NUMPAD0 + 9, 0x49),
+    (VK_SUBTRACT, 0x4a),
+    (VK_NUMPAD0 + 4, 0x4b),
+    (VK_NUMPAD0 + 5, 0x4c),
+    (VK_NUMPAD0 + 6, 0x4d),
+    (VK_ADD, 0x4e),
+    (VK_NUMPAD0 + 1, 0x4f),
+    (VK_NUMPAD0 + 2, 0x50),
+    (VK_NUMPAD0 + 3, 0x51),
+    (VK_NUMPAD0, 0x52),
+    (VK_DECIMAL, 0x53),
+    (VK_F11, 0x57),
+    (VK_F12, 0x58),
+    (VK_DIVIDE, 0x35),
+    (VK_SNAPSHOT, 0x37),
+    (VK_PAUSE, 0x45),
+    // The right shift key has a scan code of its own
+    (VK_SHIFT, 0x36),
+];
+
+/// Scan codes of the letters, from A to Z.
+const LETTER_SCAN_CODES: [u8; 26] = [
+    0x1e, 0x30, 0x2e, 0x20, 0x12, 0x21, 0x22, 0x23, 0x17, 0x24, 0x25, 0x26, 0x32, 0x31, 0x18, 0x19,
+    0x10, 0x13, 0x1f, 0x14, 0x16, 0x2f, 0x11, 0x2d, 0x15, 0x2c,
+];
+
+/// Code page 437 characters in the Windows character set, from 0x80 onwards.
+/// Characters that don't exist there get their closest look-alike.
+const OEM_TO_ANSI: [u8; 128] = [
+    0xc7, 0xfc, 0xe9, 0xe2, 0xe4, 0xe0, 0xe5, 0xe7, 0xea, 0xeb, 0xe8, 0xef, 0xee, 0xec, 0xc4, 0xc5,
+    0xc9, 0xe6, 0xc6, 0xf4, 0xf6, 0xf2, 0xfb, 0xf9, 0xff, 0xd6, 0xdc, 0xa2, 0xa3, 0xa5, 0x5f, 0x83,
+    0xe1, 0xed, 0xf3, 0xfa, 0xf1, 0xd1, 0xaa, 0xba, 0xbf, 0x5f, 0xac, 0xbd, 0xbc, 0xa1, 0xab, 0xbb,
+    0x23, 0x23, 0x23, 0x7c, 0x2b, 0x7c, 0x7c, 0x2b, 0x2b, 0x2b, 0x7c, 0x2b, 0x2b, 0x2b, 0x2b, 0x2b,
+    0x2b, 0x2b, 0x2b, 0x2b, 0x2d, 0x2b, 0x7c, 0x7c, 0x2b, 0x2b, 0x2b, 0x2b, 0x2b, 0x2d, 0x2b, 0x2d,
+    0x2d, 0x2d, 0x2d, 0x2b, 0x2b, 0x2b, 0x2b, 0x2d, 0x2d, 0x2b, 0x2b, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f,
+    0x5f, 0xdf, 0x5f, 0x5f, 0x5f, 0x5f, 0xb5, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f,
+    0x5f, 0xb1, 0x5f, 0x5f, 0x5f, 0x5f, 0xf7, 0x5f, 0xb0, 0x5f, 0xb7, 0x5f, 0x6e, 0xb2, 0x5f, 0xa0,
+];
+
+/// Windows characters in code page 437, from 0x80 onwards.
+/// Accented letters that don't exist there lose their accent.
+const ANSI_TO_OEM: [u8; 128] = [
+    0x5f, 0x5f, 0x5f, 0x9f, 0x5f, 0x2e, 0x5f, 0x5f, 0x5f, 0x5f, 0x53, 0x5f, 0x5f, 0x5f, 0x5a, 0x5f,
+    0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x5f, 0x20, 0x54, 0x73, 0x5f, 0x5f, 0x5f, 0x7a, 0x59,
+    0xff, 0xad, 0x9b, 0x9c, 0x5f, 0x9d, 0x5f, 0x5f, 0x20, 0x5f, 0xa6, 0xae, 0xaa, 0x5f, 0x5f, 0x20,
+    0xf8, 0xf1, 0xfd, 0x33, 0x20, 0xe6, 0x5f, 0xfa, 0x20, 0x31, 0xa7, 0xaf, 0xac, 0xab, 0x33, 0xa8,
+    0x41, 0x41, 0x41, 0x41, 0x8e, 0x8f, 0x92, 0x80, 0x45, 0x90, 0x45, 0x45, 0x49, 0x49, 0x49, 0x49,
+    0x5f, 0xa5, 0x4f, 0x4f, 0x4f, 0x4f, 0x99, 0x5f, 0x5f, 0x55, 0x55, 0x55, 0x9a, 0x59, 0x5f, 0xe1,
+    0x85, 0xa0, 0x83, 0x61, 0x84, 0x86, 0x91, 0x87, 0x8a, 0x82, 0x88, 0x89, 0x8d, 0xa1, 0x8c, 0x8b,
+    0x5f, 0xa4, 0x95, 0xa2, 0x93, 0x6f, 0x94, 0xf6, 0x5f, 0x97, 0xa3, 0x96, 0x81, 0x79, 0x5f, 0x98,
+];
+
+pub fn oem_to_ansi(character: u8) -> u8 {
+    if character < 0x80 {
+        character
+    } else {
+        OEM_TO_ANSI[(character - 0x80) as usize]
+    }
+}
+
+pub fn ansi_to_oem(character: u8) -> u8 {
+    if character < 0x80 {
+        character
+    } else {
+        ANSI_TO_OEM[(character - 0x80) as usize]
+    }
+}
+
+/// The scan code of a virtual key, or 0 if no key generates it.
+pub fn scan_code_of(vk: u8) -> u8 {
+    if (VK_A..VK_A + 26).contains(&vk) {
+        LETTER_SCAN_CODES[(vk - VK_A) as usize]
+    } else {
+        SCAN_CODES
+            .iter()
+            .find(|&&(key, _)| key == vk)
+            .map_or(0, |&(_, scan_code)| scan_code)
+    }
+}
+
+/// The virtual key of a scan code, or 0 if there is none.
+/// Keys that share a scan code with the numeric keypad map to the extended key.
+pub fn virtual_key_of(scan_code: u8) -> u8 {
+    if let Some(index) = LETTER_SCAN_CODES.iter().position(|&s| s == scan_code) {
+        VK_A + index as u8
+    } else {
+        SCAN_CODES
+            .iter()
+            .find(|&&(_, s)| s == scan_code)
+            .map_or(0, |&(vk, _)| vk)
+    }
+}
+
+/// Whether the key is one of the extra keys of the enhanced keyboard,
+/// which sets the extended bit in the lParam of key messages.
+pub fn is_extended_key(vk: u8) -> bool {
+    matches!(
+        vk,
+        VK_PRIOR
+            | VK_NEXT
+            | VK_END
+            | VK_HOME
+            | VK_LEFT
+            | VK_UP
+            | VK_RIGHT
+            | VK_DOWN
+            | VK_INSERT
+            | VK_DELETE
+            | VK_DIVIDE
+            | VK_SNAPSHOT
+    )
+}
+
+/// The character a virtual key types on the US keyboard layout, given the shift state.
+/// Returns None for keys that don't type a character, e.g. the arrow keys.
+pub fn character_of(vk: u8, shift: bool, control: bool, caps_lock: bool, alt: bool) -> Option<u8> {
+    if control && alt {
+        return None;
+    }
+    if control {
+        return match vk {
+            _ if (VK_A..VK_A + 26).contains(&vk) => Some(vk - VK_A + 1),
+            VK_OEM_4 => Some(0x1b),
+            VK_OEM_5 => Some(0x1c),
+            VK_OEM_6 => Some(0x1d),
+            VK_BACK => Some(0x7f),
+            VK_RETURN => Some(b'\n'),
+            VK_ESCAPE | VK_SPACE => Some(vk),
+            _ => None,
+        };
+    }
+    match vk {
+        VK_BACK | VK_TAB | VK_RETURN | VK_ESCAPE | VK_SPACE => Some(vk),
+        _ if (VK_A..VK_A + 26).contains(&vk) => {
+            let letter = b'a' + (vk - VK_A);
+            Some(if shift != caps_lock {
+                letter.to_ascii_uppercase()
+            } else {
+                letter
+            })
+        }
+        _ if (VK_0..VK_0 + 10).contains(&vk) => Some(if shift {
+            SHIFTED_DIGITS[(vk - VK_0) as usize]
+        } else {
+            vk
+        }),
+        _ if (VK_NUMPAD0..VK_NUMPAD0 + 10).contains(&vk) => Some(b'0' + (vk - VK_NUMPAD0)),
+        VK_MULTIPLY => Some(b'*'),
+        VK_ADD => Some(b'+'),
+        VK_SUBTRACT => Some(b'-'),
+        VK_DECIMAL => Some(b'.'),
+        VK_DIVIDE => Some(b'/'),
+        _ => OEM_CHARACTERS
+            .iter()
+            .find(|&&(key, _, _)| key == vk)
+            .map(|&(_, unshifted, shifted)| if shift { shifted } else { unshifted }),
+    }
+}
+
+/// The virtual key and shift state that type a character on the US keyboard layout.
+pub fn key_of_character(character: u8) -> Option<(u8, u8)> {
+    // The main keys come before the numeric keypad, so digits map to the digit row
+    let keys = (VK_0..VK_0 + 10)
+        .chain(VK_A..VK_A + 26)
+        .chain(OEM_CHARACTERS.iter().map(|&(vk, _, _)| vk))
+        .chain([VK_BACK, VK_TAB, VK_RETURN, VK_ESCAPE, VK_SPACE]);
+    for vk in keys {
+        if character_of(vk, false, false, false, false) == Some(character) {
+            return Some((vk, 0));
+        }
+        if character_of(vk, true, false, false, false) == Some(character) {
+            return Some((vk, SHIFT_STATE_SHIFT));
+        }
+    }
+    (VK_A..VK_A + 26)
+        .chain([VK_OEM_4, VK_OEM_5, VK_OEM_6, VK_RETURN])
+        .find(|&vk| character_of(vk, false, true, false, false) == Some(character))
+        .map(|vk| (vk, SHIFT_STATE_CONTROL))
+}
+
+/// The state of every virtual key, in the format of GetKeyboardState: the high bit is set while
+/// the key is down, and the low bit toggles every time the key is pressed.
+pub struct KeyStates {
+    keys: [u8; 256],
+    pressed_since_query: [bool; 256],
+}
+
+impl KeyStates {
+    pub fn new() -> Self {
+        Self {
+            keys: [0; 256],
+            pressed_since_query: [false; 256],
+        }
+    }
+
+    /// Records a key going down or up.
+    pub fn set_key(&mut self, vk: u8, down: bool) {
+        let index = vk as usize;
+        if down {
+            if self.keys[index] & 0x80 == 0 {
+                self.keys[index] ^= 0x01;
+            }
+            self.keys[index] |= 0x80;
+            self.pressed_since_query[index] = true;
+        } else {
+            self.keys[index] &= !0x80;
+        }
+    }
+
+    pub fn is_down(&self, vk: u8) -> bool {
+        self.keys[vk as usize] & 0x80 != 0
+    }
+
+    pub fn is_toggled(&self, vk: u8) -> bool {
+        self.keys[vk as usize] & 0x01 != 0
+    }
+
+    /// The key state as GetKeyState returns it.
+    pub fn key_state(&self, vk: u8) -> u16 {
+        let key = self.keys[vk as usize];
+        (if key & 0x80 != 0 { 0xff80 } else { 0 }) | (key & 0x01) as u16
+    }
+
+    /// The key state as GetAsyncKeyState returns it: the low bit tells whether the key was
+    /// pressed since the previous call.
+    pub fn query_async_key_state(&mut self, vk: u8) -> u16 {
+        let pressed = std::mem::replace(&mut self.pressed_since_query[vk as usize], false);
+        (if self.is_down(vk) { 0x8000 } else { 0 }) | pressed as u16
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 256] {
+        &self.keys
+    }
+
+    pub fn set_bytes(&mut self, keys: [u8; 256]) {
+        self.keys = keys;
+    }
+
+    /// The character a key types with the shift keys and caps lock in this state.
+    pub fn character_of(&self, vk: u8) -> Option<u8> {
+        character_of(
+            vk,
+            self.is_down(VK_SHIFT),
+            self.is_down(VK_CONTROL),
+            self.is_toggled(VK_CAPITAL),
+            self.is_down(VK_MENU),
+        )
+    }
+}
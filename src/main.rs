@@ -2,36 +2,50 @@
 #![allow(clippy::manual_range_contains)]
 
 use crate::byte_string::HeapByteString;
+use crate::constants::{ShowWindowCommand, CONVENTIONAL_MEMORY_TOP};
 use crate::emulated_gdi::EmulatedGdi;
 use crate::emulated_kernel::EmulatedKernel;
 use crate::emulated_keyboard::EmulatedKeyboard;
-use crate::emulated_user::EmulatedUser;
+use crate::emulated_user::{BuiltinProcedures, EmulatedUser};
 use crate::emulator::Emulator;
+use crate::emulator_accessor::EmulatorAccessor;
 use crate::emulator_error::EmulatorError;
 use crate::executable::{Executable, ExecutableFormatError};
+use crate::handle_table::{GenericHandle, Handle};
 use crate::heap::Heap;
-use crate::memory::Memory;
+use crate::memory::{Memory, SegmentAndOffset};
+use crate::message_box::AutomaticAnswer;
 use crate::message_queue::MessageQueue;
-use crate::module::{DummyModule, GdiModule, KernelModule, KeyboardModule, Module, UserModule};
+use crate::module::{BuiltinModules, DummyModule, Module};
 use crate::object_environment::ObjectEnvironment;
 use crate::registers::Registers;
+use crate::scheduler::Scheduler;
 use crate::screen::Screen;
 use crate::segment_bump_allocator::SegmentBumpAllocator;
+use crate::task::{TaskDatabase, TaskRegistry};
 use crate::util::{
-    bool_to_result, debug_print_null_terminated_string, expect_magic, u16_from_slice,
+    bool_to_result, debug_print_null_terminated_string, encode_u16_u16_to_u32, expect_magic,
+    u16_from_slice,
 };
-use crate::window_manager::WindowManager;
+use crate::window_manager::{ProcessId, WindowManager};
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, RwLock};
 use std::thread;
 
 extern crate num_derive;
+mod accelerator;
 mod api_helpers;
 mod atom_table;
 mod bitmap;
 mod bitvector_allocator;
 mod byte_string;
+mod caret;
+mod clipboard;
 mod constants;
+mod controls;
+mod dialog;
 mod emulated_gdi;
 mod emulated_kernel;
 mod emulated_keyboard;
@@ -40,58 +54,433 @@ mod emulator;
 mod emulator_accessor;
 mod emulator_error;
 mod executable;
+mod font;
 mod handle_table;
 mod heap;
+mod hook;
+mod keyboard;
 mod memory;
+mod menu;
+mod message_box;
 mod message_queue;
 mod mod_rm;
 mod module;
+mod non_client;
 mod object_environment;
 mod registers;
+mod scheduler;
 mod screen;
 mod segment_bump_allocator;
+mod task;
+mod timer;
 mod two_d;
 mod util;
 mod window_manager;
 
 struct MZResult {
-    pub ne_header_offset: usize,
+    pub ne_header_offset: Option<usize>,
+    pub bytes_in_last_page: u16,
+    pub page_count: u16,
+    pub relocation_count: u16,
+    pub header_paragraphs: u16,
+    pub minimum_extra_paragraphs: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub ip: u16,
+    pub cs: u16,
+    pub relocation_table_offset: usize,
+}
+
+struct LaunchParameters {
+    pub path: String,
+    pub command_tail: Vec<u8>,
+    pub cmd_show: u16,
+    pub force_dos: bool,
+}
+
+/// State shared by every task running in the emulator.
+pub struct Session {
+    pub objects: RwLock<ObjectEnvironment>,
+    pub scheduler: Scheduler,
+    pub task_registry: RwLock<TaskRegistry>,
+    pub message_queues: RwLock<HashMap<ProcessId, Arc<MessageQueue>>>,
+    /// How message boxes get answered, if they shouldn't show.
+    pub message_box_answer: Option<AutomaticAnswer>,
 }
 
 fn main() -> Result<(), String> {
-    let window_manager = Arc::new(RwLock::new(WindowManager::new()));
+    // Usage: snowman_emulator [--dos] [--message-box-answer answer] [--run other_path]...
+    //                         [path] [arguments...]
+    // Every --run option starts an additional task next to the main program.
+    // --message-box-answer answers message boxes without showing them, for runs without anyone
+    // to click them. The answer is "default" for the default button, or a button such as "ok",
+    // "cancel", "yes" or "no".
+    let mut arguments = std::env::args().skip(1).peekable();
+    let force_dos = arguments.next_if(|argument| argument == "--dos").is_some();
+    let message_box_answer = match arguments.next_if(|argument| argument == "--message-box-answer")
+    {
+        Some(_) => {
+            let answer = arguments.next().unwrap_or_default();
+            Some(
+                AutomaticAnswer::parse(&answer)
+                    .ok_or_else(|| format!("unknown message box answer {:?}", answer))?,
+            )
+        }
+        None => None,
+    };
+    let mut other_paths = Vec::new();
+    while arguments.next_if(|argument| argument == "--run").is_some() {
+        other_paths.extend(arguments.next());
+    }
 
-    // Start one executable
-    let window_manager_clone = window_manager.clone();
-    let _exe = thread::spawn(move || {
-        //let path = "../vms/WINVER.EXE";
-        let path = "../vms/CLOCK.EXE";
-        //let path = "../vms/GENERIC.EXE";
-        //let path = "../Win16asm/hw.exe";
-        start_executable(path, &window_manager_clone);
+    let window_manager = Arc::new(RwLock::new(WindowManager::new()));
+    let session = Arc::new(Session {
+        objects: RwLock::new(ObjectEnvironment::new(window_manager.clone())),
+        scheduler: Scheduler::new(),
+        task_registry: RwLock::new(TaskRegistry::new()),
+        message_queues: RwLock::new(HashMap::new()),
+        message_box_answer,
     });
 
-    let mut screen = Screen::new(window_manager)?;
+    //let path = "../vms/WINVER.EXE";
+    //let path = "../vms/GENERIC.EXE";
+    //let path = "../Win16asm/hw.exe";
+    let path = arguments
+        .next()
+        .unwrap_or_else(|| "../vms/CLOCK.EXE".to_string());
+    let command_tail = arguments.collect::<Vec<_>>().join(" ");
+    spawn_task(
+        &session,
+        LaunchParameters {
+            path,
+            command_tail: command_tail.into_bytes(),
+            cmd_show: ShowWindowCommand::ShowNormal.into(),
+            force_dos,
+        },
+    );
+    for path in other_paths {
+        spawn_task(
+            &session,
+            LaunchParameters {
+                path,
+                command_tail: Vec::new(),
+                cmd_show: ShowWindowCommand::ShowNormal.into(),
+                force_dos,
+            },
+        );
+    }
+
+    let mut screen = Screen::new(window_manager, session)?;
     screen.window_loop();
     Ok(())
 }
 
-fn start_executable(path: &str, window_manager: &RwLock<WindowManager>) {
-    let mut bytes = std::fs::read(path).expect("test file should exist");
+/// Starts a new task on its own thread. It runs as soon as the scheduler gives it a turn.
+/// Once the program is loaded, the receiver gets its instance handle,
+/// or an error code below 32 if it could not be loaded.
+fn spawn_task(session: &Arc<Session>, launch_parameters: LaunchParameters) -> Receiver<u16> {
+    let process_id = session.scheduler.spawn();
+    let session = session.clone();
+    let (load_result_sender, load_result_receiver) = channel();
+    thread::spawn(move || {
+        let exit_guard = TaskExitGuard {
+            session: session.clone(),
+            process_id,
+        };
+        session.scheduler.run(process_id);
+        start_executable(
+            &launch_parameters,
+            &session,
+            process_id,
+            &load_result_sender,
+        );
+        drop(exit_guard);
+    });
+    load_result_receiver
+}
+
+/// Ends a task once its thread is done, even if it panicked, so that the other tasks don't wait
+/// for it forever: its message queue goes away, and the scheduler hands its turn on.
+struct TaskExitGuard {
+    session: Arc<Session>,
+    process_id: ProcessId,
+}
+
+impl Drop for TaskExitGuard {
+    fn drop(&mut self) {
+        remove_message_queue(&self.session, self.process_id);
+        self.session.scheduler.exit(self.process_id);
+    }
+}
+
+/// Removes the message queue of a task that ended. The tasks that still wait for a reply to a
+/// message they sent to it get 0 instead.
+fn remove_message_queue(session: &Session, process_id: ProcessId) {
+    let message_queue = session.message_queues.write().unwrap().remove(&process_id);
+    if let Some(message_queue) = message_queue {
+        for sender in message_queue.abandon_sent_messages() {
+            session.scheduler.wake(sender);
+        }
+    }
+}
+
+fn start_executable(
+    launch_parameters: &LaunchParameters,
+    session: &Arc<Session>,
+    process_id: ProcessId,
+    load_result: &Sender<u16>,
+) {
+    let mut bytes = match std::fs::read(&launch_parameters.path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            println!("{}: {}", launch_parameters.path, error);
+            // File not found
+            let _ = load_result.send(2);
+            return;
+        }
+    };
     let mut executable = Executable::new(bytes.as_mut_slice());
-    println!("{:?}", process_file(&mut executable, window_manager));
+    let result = process_file(
+        &mut executable,
+        launch_parameters,
+        session,
+        process_id,
+        load_result,
+    );
+    println!("{:?}", result);
+    if let Err(error) = result {
+        let _ = load_result.send(error.load_error_code());
+    }
+}
+
+/// The name of a module, as derived from the file name of a program without a module name.
+fn module_name_from_path(path: &str) -> &[u8] {
+    Path::new(path)
+        .file_stem()
+        .and_then(|file_stem| file_stem.to_str())
+        .unwrap_or(path)
+        .as_bytes()
 }
 
 fn process_file_mz(executable: &Executable) -> Result<MZResult, ExecutableFormatError> {
     executable.validate_magic_id(0, b"MZ")?;
     // TODO: check MZ checksum
-    let ne_header_offset = executable.read_u16(0x3C)? as usize;
-    Ok(MZResult { ne_header_offset })
+    let relocation_table_offset = executable.read_u16(0x18)? as usize;
+
+    // DOS-only programs don't have room for the new executable header offset, and if they do,
+    // it doesn't point to an NE header.
+    let ne_header_offset = if relocation_table_offset >= 0x40 {
+        let offset = executable.read_u16(0x3C)? as usize;
+        executable
+            .validate_magic_id(offset, b"NE")
+            .ok()
+            .map(|_| offset)
+    } else {
+        None
+    };
+
+    Ok(MZResult {
+        ne_header_offset,
+        bytes_in_last_page: executable.read_u16(0x02)?,
+        page_count: executable.read_u16(0x04)?,
+        relocation_count: executable.read_u16(0x06)?,
+        header_paragraphs: executable.read_u16(0x08)?,
+        minimum_extra_paragraphs: executable.read_u16(0x0A)?,
+        ss: executable.read_u16(0x0E)?,
+        sp: executable.read_u16(0x10)?,
+        ip: executable.read_u16(0x14)?,
+        cs: executable.read_u16(0x16)?,
+        relocation_table_offset,
+    })
+}
+
+fn write_program_segment_prefix(
+    memory: &mut Memory,
+    psp_segment: u16,
+    environment_segment: u16,
+    command_tail: &[u8],
+) -> Result<(), EmulatorError> {
+    let flat_address = (psp_segment as u32) << 4;
+    memory.zero(flat_address, flat_address + 0x100)?;
+    // INT 20h, for programs that exit by jumping to offset 0
+    memory.write_8(flat_address, 0xCD)?;
+    memory.write_8(flat_address + 1, 0x20)?;
+    // Segment of the first byte beyond the memory allocated to the program
+    memory.write_u16(flat_address + 0x02, CONVENTIONAL_MEMORY_TOP)?;
+    memory.write_u16(flat_address + 0x2C, environment_segment)?;
+    // Command tail: length byte, the characters, and a terminating carriage return
+    let command_tail = &command_tail[0..command_tail.len().min(126)];
+    memory.write_8(flat_address + 0x80, command_tail.len() as u8)?;
+    memory.copy_from(command_tail, flat_address as usize + 0x81)?;
+    memory.write_8(flat_address + 0x81 + command_tail.len() as u32, 0x0D)
+}
+
+fn write_environment_block(
+    memory: &mut Memory,
+    environment_segment: u16,
+    program_path: &[u8],
+) -> Result<(), EmulatorError> {
+    let flat_address = (environment_segment as u32) << 4;
+    // No variables, followed by a count of one string: the full program path
+    memory.write_8(flat_address, 0)?;
+    memory.write_8(flat_address + 1, 0)?;
+    memory.write_u16(flat_address + 2, 1)?;
+    let program_path = &program_path[0..program_path.len().min(0xFF)];
+    memory.copy_from(program_path, flat_address as usize + 4)?;
+    memory.write_8(flat_address + 4 + program_path.len() as u32, 0)
+}
+
+fn process_file_dos(
+    executable: &Executable,
+    mz_result: &MZResult,
+    launch_parameters: &LaunchParameters,
+    session: &Arc<Session>,
+    process_id: ProcessId,
+    load_result: &Sender<u16>,
+) -> Result<(), ExecutableFormatError> {
+    let header_size = mz_result.header_paragraphs as usize * 16;
+    let file_size = if mz_result.bytes_in_last_page == 0 {
+        mz_result.page_count as usize * 512
+    } else {
+        (mz_result.page_count as usize).saturating_sub(1) * 512
+            + mz_result.bytes_in_last_page as usize
+    };
+    let image = executable.slice(header_size, file_size.saturating_sub(header_size))?;
+
+    let mut memory = Memory::new();
+    let mut segment_bump_allocator = SegmentBumpAllocator::new();
+    // Keep the interrupt vector table and BIOS data area out of the program's way
+    segment_bump_allocator.allocate(0x500);
+    let environment_segment = segment_bump_allocator
+        .allocate(0x100)
+        .ok_or(ExecutableFormatError::Memory)?;
+    let psp_segment = segment_bump_allocator
+        .allocate(0x100 + image.len() + mz_result.minimum_extra_paragraphs as usize * 16)
+        .ok_or(ExecutableFormatError::Memory)?;
+    let load_segment = psp_segment + 0x10;
+
+    write_environment_block(
+        &mut memory,
+        environment_segment,
+        launch_parameters.path.as_bytes(),
+    )
+    .map_err(|_| ExecutableFormatError::Memory)?;
+    write_program_segment_prefix(
+        &mut memory,
+        psp_segment,
+        environment_segment,
+        &launch_parameters.command_tail,
+    )
+    .map_err(|_| ExecutableFormatError::Memory)?;
+    memory
+        .copy_from(image, (load_segment as usize) << 4)
+        .map_err(|_| ExecutableFormatError::Memory)?;
+
+    // Every relocation entry points to a segment word that has to be rebased to the load segment
+    for relocation_index in 0..mz_result.relocation_count as usize {
+        let entry_offset = mz_result.relocation_table_offset + relocation_index * 4;
+        let offset = executable.read_u16(entry_offset)?;
+        let segment = executable.read_u16(entry_offset + 2)?;
+        let flat_address = ((load_segment.wrapping_add(segment) as u32) << 4) + offset as u32;
+        let value = memory
+            .read_u16(flat_address)
+            .map_err(|_| ExecutableFormatError::Memory)?;
+        memory
+            .write_u16(flat_address, value.wrapping_add(load_segment))
+            .map_err(|_| ExecutableFormatError::Memory)?;
+    }
+
+    println!(
+        "DOS program: PSP {:x}, CS:IP {:x}:{:x}, SS:SP {:x}:{:x}",
+        psp_segment,
+        load_segment.wrapping_add(mz_result.cs),
+        mz_result.ip,
+        load_segment.wrapping_add(mz_result.ss),
+        mz_result.sp
+    );
+
+    // DOS programs never call into the Windows modules, but the emulator still needs them.
+    let resource_table = ResourceTable::new();
+    session
+        .message_queues
+        .write()
+        .unwrap()
+        .insert(process_id, Arc::new(MessageQueue::new()));
+    let h_instance: Handle = psp_segment.into();
+    let (h_module, _) = session
+        .task_registry
+        .write()
+        .unwrap()
+        .register_instance(
+            module_name_from_path(&launch_parameters.path),
+            launch_parameters.path.as_bytes(),
+            h_instance,
+        )
+        .ok_or(ExecutableFormatError::Memory)?;
+    let task = TaskDatabase {
+        process_id,
+        h_module,
+        h_instance,
+        h_prev_instance: Handle::null(),
+        cmd_show: launch_parameters.cmd_show,
+        psp_segment,
+        environment_segment,
+        stack_top: 0,
+        stack_bottom: mz_result.sp,
+    };
+    let emulated_kernel = EmulatedKernel::new(
+        session,
+        &resource_table,
+        &task,
+        Heap::new(0, 0),
+        None,
+        SegmentBumpAllocator::new(),
+    );
+    let emulated_user = EmulatedUser::new(
+        &session.objects,
+        &session.scheduler,
+        &task,
+        &session.message_queues,
+        &resource_table,
+        BuiltinProcedures::default(),
+        session.message_box_answer,
+    );
+    let emulated_gdi = EmulatedGdi::new(&session.objects);
+    let emulated_keyboard = EmulatedKeyboard::new();
+
+    // DS and ES point to the PSP at program start
+    let mut registers = Registers::new(
+        psp_segment,
+        load_segment.wrapping_add(mz_result.cs),
+        mz_result.ip,
+        mz_result.sp,
+    );
+    registers.write_segment(Registers::REG_SS, load_segment.wrapping_add(mz_result.ss));
+    let mut emulator = Emulator::new(
+        registers,
+        memory,
+        &emulated_kernel,
+        &emulated_user,
+        &emulated_gdi,
+        &emulated_keyboard,
+    );
+    let _ = load_result.send(h_instance.as_u16());
+    emulator.run();
+
+    remove_message_queue(session, process_id);
+    session
+        .task_registry
+        .write()
+        .unwrap()
+        .deregister_instance(task.h_module, task.h_instance);
+
+    Ok(())
 }
 
 fn validate_application_flags(executable: &Executable) -> Result<(), ExecutableFormatError> {
+    // The highest bit marks libraries, which is checked by the caller
     bool_to_result(
-        (executable.read_u8(0x0D)? & 0b11101000) == 0,
+        (executable.read_u8(0x0D)? & 0b01101000) == 0,
         ExecutableFormatError::ApplicationFlags,
     )
 }
@@ -291,77 +680,126 @@ fn validate_segment_index_and_offset(
     Ok(())
 }
 
-struct ModuleReferenceTable {
-    modules: Vec<Box<dyn Module>>,
-    user_module_index: Option<usize>,
+/// The entry points of a module by ordinal
+type EntryPoints = HashMap<u16, SegmentAndOffset>;
+
+enum ModuleReference<'a> {
+    Builtin(&'a dyn Module),
+    /// A library loaded into the task
+    Library(EntryPoints),
+    // TODO: modules we can't provide yet
+    Dummy(DummyModule),
 }
 
-impl ModuleReferenceTable {
-    pub fn module(&self, index: u16) -> Result<&dyn Module, EmulatorError> {
-        if index >= 1 && (index as usize) <= self.modules.len() {
-            Ok(&*self.modules[index as usize - 1])
-        } else {
-            Err(EmulatorError::OutOfBounds)
+struct ModuleReferenceTable<'a> {
+    modules: Vec<ModuleReference<'a>>,
+}
+
+impl<'a> ModuleReferenceTable<'a> {
+    /// Builds the table from the names of the imported modules. `library_entry_points` gives
+    /// the entry points of the libraries that are loaded in the task.
+    pub fn new(
+        module_names: &[HeapByteString],
+        builtin_modules: &'a BuiltinModules,
+        segment_bump_allocator: &mut SegmentBumpAllocator,
+        library_entry_points: &dyn Fn(&[u8]) -> Option<EntryPoints>,
+    ) -> Result<Self, ExecutableFormatError> {
+        let mut modules = Vec::with_capacity(module_names.len());
+        for module_name in module_names {
+            let module_name = module_name.as_slice();
+            if let Some(module) = builtin_modules.by_name(module_name) {
+                modules.push(ModuleReference::Builtin(module));
+            } else if let Some(entry_points) = library_entry_points(module_name) {
+                modules.push(ModuleReference::Library(entry_points));
+            } else {
+                let flat_address = (segment_bump_allocator
+                    .allocate(0x10000)
+                    .ok_or(ExecutableFormatError::Memory)?
+                    as u32)
+                    * 0x10;
+                modules.push(ModuleReference::Dummy(DummyModule::new(flat_address)));
+            }
+        }
+        Ok(Self { modules })
+    }
+
+    /// Resolves the address of an imported procedure.
+    pub fn procedure(
+        &self,
+        memory: &mut Memory,
+        index: u16,
+        ordinal: u16,
+    ) -> Result<SegmentAndOffset, EmulatorError> {
+        if index < 1 || (index as usize) > self.modules.len() {
+            return Err(EmulatorError::OutOfBounds);
         }
+        let module: &dyn Module = match &self.modules[index as usize - 1] {
+            ModuleReference::Builtin(module) => *module,
+            ModuleReference::Library(entry_points) => {
+                return entry_points
+                    .get(&ordinal)
+                    .copied()
+                    .ok_or(EmulatorError::OutOfBounds)
+            }
+            ModuleReference::Dummy(module) => module,
+        };
+        // A procedure that we don't emulate only fails once it gets called
+        let argument_bytes = module.argument_bytes_of_procedure(ordinal).unwrap_or(0);
+        module
+            .base_module()
+            .procedure(memory, ordinal, argument_bytes)
     }
 }
 
-fn process_module_reference_table(
+fn process_imported_module_names(
     executable: &Executable,
     offset_to_module_reference_table: usize,
     module_reference_count: u16,
-    segment_bump_allocator: &mut SegmentBumpAllocator,
-) -> Result<ModuleReferenceTable, ExecutableFormatError> {
+) -> Result<Vec<HeapByteString>, ExecutableFormatError> {
     let offset_to_imported_name_table = executable.read_u16(0x2A)? as usize;
 
-    let mut module_reference_table = ModuleReferenceTable {
-        modules: Vec::with_capacity(module_reference_count as usize),
-        user_module_index: None,
-    };
-
+    let mut module_names = Vec::with_capacity(module_reference_count as usize);
     for module_index in 0..module_reference_count {
         let module_name_offset_in_imported_name_table =
             executable.read_u16(offset_to_module_reference_table + (module_index * 2) as usize)?;
-        let start_offset =
-            offset_to_imported_name_table + module_name_offset_in_imported_name_table as usize;
-        let module_name_length = executable.read_u8(start_offset)?;
-        let module_name = executable.slice(start_offset + 1, module_name_length as usize)?;
-        println!(
-            "module {} = {}",
-            module_index + 1,
-            String::from_utf8_lossy(module_name)
-        );
+        let module_name = executable
+            .read_string(
+                offset_to_imported_name_table + module_name_offset_in_imported_name_table as usize,
+            )?
+            .ok_or(ExecutableFormatError::HeaderSize)?;
+        println!("module {} = {:?}", module_index + 1, module_name);
+        module_names.push(module_name);
+    }
 
-        let flat_address = (segment_bump_allocator
-            .allocate(0x10000)
-            .ok_or(ExecutableFormatError::Memory)? as u32)
-            * 0x10;
-        if module_name == b"KERNEL" {
-            module_reference_table
-                .modules
-                .push(Box::new(KernelModule::new(flat_address)));
-        } else if module_name == b"USER" {
-            module_reference_table.user_module_index = Some(module_reference_table.modules.len());
-            module_reference_table
-                .modules
-                .push(Box::new(UserModule::new(flat_address)));
-        } else if module_name == b"GDI" {
-            module_reference_table
-                .modules
-                .push(Box::new(GdiModule::new(flat_address)));
-        } else if module_name == b"KEYBOARD" {
-            module_reference_table
-                .modules
-                .push(Box::new(KeyboardModule::new(flat_address)));
-        } else {
-            // TODO
-            module_reference_table
-                .modules
-                .push(Box::new(DummyModule::new(flat_address)));
+    Ok(module_names)
+}
+
+/// Reads a resident or non-resident name table into a map from uppercase names to ordinals.
+/// The first entry is the module name or description, and is not a procedure.
+fn process_name_table(
+    executable: &Executable,
+    offset_to_name_table: usize,
+    names: &mut HashMap<HeapByteString, u16>,
+) -> Result<(), ExecutableFormatError> {
+    let mut offset = offset_to_name_table;
+    let mut is_first_entry = true;
+    loop {
+        let length = executable.read_u8(offset)? as usize;
+        if length == 0 {
+            break;
+        }
+        let name = executable.slice(offset + 1, length)?;
+        let ordinal = executable.read_u16(offset + 1 + length)?;
+        if !is_first_entry {
+            names.insert(
+                HeapByteString::from(name.to_ascii_uppercase().into()),
+                ordinal,
+            );
         }
+        is_first_entry = false;
+        offset += 3 + length;
     }
-
-    Ok(module_reference_table)
+    Ok(())
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -428,9 +866,24 @@ fn process_entry_table(
 
                 offset += 6;
             } else {
-                // TODO: fixed segment
+                // Fixed segment, the segment indicator is the segment number.
+                // 0xFE marks constants instead, which aren't procedures.
                 let offset_within_segment_to_entry_point = executable.read_u16(offset + 1)?;
-                println!("fixed segment {:x}", offset_within_segment_to_entry_point);
+                println!(
+                    "fixed segment {} {:x}",
+                    segment_indicator, offset_within_segment_to_entry_point
+                );
+
+                if segment_indicator != 0xfe {
+                    entry_table.entries.insert(
+                        ordinal_index,
+                        EntryTableEntry {
+                            segment_number: segment_indicator,
+                            offset: offset_within_segment_to_entry_point,
+                        },
+                    );
+                }
+
                 offset += 3;
             }
 
@@ -454,16 +907,11 @@ fn perform_relocations(
         for relocation in relocations {
             match &relocation.relocation_type {
                 RelocationType::ImportOrdinal(import) => {
-                    // Relocate kernel system call
-                    let module = module_reference_table
-                        .module(import.index_into_module_reference_table)
-                        .map_err(|_| ExecutableFormatError::Memory)?;
-                    let segment_and_offset = module
-                        .base_module()
+                    let segment_and_offset = module_reference_table
                         .procedure(
                             memory,
+                            import.index_into_module_reference_table,
                             import.procedure_ordinal_number,
-                            module.argument_bytes_of_procedure(import.procedure_ordinal_number),
                         )
                         .map_err(|_| ExecutableFormatError::Memory)?;
 
@@ -529,8 +977,17 @@ fn perform_relocations(
     Ok(())
 }
 
+/// The name of a resource, which is either a number or a lowercase string.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub enum ResourceName {
+    Integer(u16),
+    String(HeapByteString),
+}
+
 pub struct ResourceTable {
     strings_resources: HashMap<u16, HeapByteString>,
+    /// The resources of the predefined types, such as RT_MENU, by their type id.
+    predefined_resources: HashMap<u16, HashMap<ResourceName, Box<[u8]>>>,
     other_resources: HashMap<HeapByteString, HashMap<HeapByteString, Box<[u8]>>>,
 }
 
@@ -538,9 +995,17 @@ impl ResourceTable {
     pub fn new() -> Self {
         Self {
             strings_resources: HashMap::new(),
+            predefined_resources: HashMap::new(),
             other_resources: HashMap::new(),
         }
     }
+
+    pub fn predefined_resource(&self, type_id: u16, name: &ResourceName) -> Option<&[u8]> {
+        self.predefined_resources
+            .get(&type_id)
+            .and_then(|table| table.get(name))
+            .map(|data| data.as_ref())
+    }
 }
 
 fn process_resource_table(
@@ -571,10 +1036,10 @@ fn process_resource_table(
             let resource_offset_in_file =
                 (executable.read_u16(offset)? as usize) << alignment_shift_count;
             let length = (executable.read_u16(offset + 2)? as usize) << alignment_shift_count;
-            let id = executable.read_u16(offset + 6)?;
-            println!("entry index {}, id {}, length {}", index, id, length);
+            let raw_id = executable.read_u16(offset + 6)?;
+            println!("entry index {}, id {}, length {}", index, raw_id, length);
             // TODO: support non-integer ids
-            let id = id & !0x8000;
+            let id = raw_id & !0x8000;
 
             // String table
             if type_id == 0x8006 {
@@ -597,7 +1062,26 @@ fn process_resource_table(
                 }
 
                 executable.restore_cursor(old_cursor);
-            } else if (type_id & 0x8000) == 0 {
+            } else if (type_id & 0x8000) != 0 {
+                // Predefined type, whose resources can have a number or a string for a name
+                let name = if (raw_id & 0x8000) != 0 {
+                    Some(ResourceName::Integer(id))
+                } else {
+                    executable
+                        .read_string_to_lowercase(raw_id as usize)?
+                        .map(ResourceName::String)
+                };
+                if let Some(name) = name {
+                    let old_cursor = executable.seek_from_start(resource_offset_in_file)?;
+                    let data = executable.slice(0, length)?;
+                    resource_table
+                        .predefined_resources
+                        .entry(type_id & !0x8000)
+                        .or_default()
+                        .insert(name, data.into());
+                    executable.restore_cursor(old_cursor);
+                }
+            } else {
                 // Custom type
                 if let (Some(type_string), Some(name_string)) = (
                     executable.read_string_to_lowercase(type_id as usize)?,
@@ -626,23 +1110,44 @@ fn process_resource_table(
     Ok(resource_table)
 }
 
-fn process_file_ne(
+/// The parts of an NE module that are needed to load it.
+struct NeModule {
+    module_name: HeapByteString,
+    is_library: bool,
+    imported_module_names: Vec<HeapByteString>,
+    entry_table: EntryTable,
+    /// The names of the exported procedures in uppercase, and their ordinals
+    exported_names: HashMap<HeapByteString, u16>,
+    segment_table: SegmentTable,
+    resource_table: ResourceTable,
+    cs: u16,
+    ip: u16,
+    ds: u16,
+    ss: u16,
+    sp: u16,
+    stack_initial_size: u32,
+    heap_initial_size: u16,
+}
+
+fn process_ne_header(
     executable: &mut Executable,
     ne_header_offset: usize,
-    window_manager: &RwLock<WindowManager>,
-) -> Result<(), ExecutableFormatError> {
+) -> Result<NeModule, ExecutableFormatError> {
     let old_cursor = executable.seek_from_start(ne_header_offset)?;
     executable.validate_magic_id(0, b"NE")?;
     validate_application_flags(executable)?;
     validate_target_operating_system(executable)?;
 
-    let mut segment_bump_allocator = SegmentBumpAllocator::new();
-
     let offset_to_entry_table = executable.read_u16(0x04)? as usize;
     let entry_table_bytes = executable.read_u16(0x06)? as usize;
+    let is_library = (executable.read_u16(0x0C)? & 0x8000) != 0;
+    let heap_initial_size = executable.read_u16(0x10)?;
     let stack_initial_size = executable.read_u16(0x12)? as u32;
+    let non_resident_name_table_bytes = executable.read_u16(0x20)?;
     let offset_to_resource_table = executable.read_u16(0x24)? as usize;
     let offset_to_resident_name_table = executable.read_u16(0x26)? as usize;
+    let offset_to_non_resident_name_table =
+        encode_u16_u16_to_u32(executable.read_u16(0x2C)?, executable.read_u16(0x2E)?) as usize;
     let segment_table_segment_count = executable.read_u16(0x1C)? as usize;
     let module_reference_count = executable.read_u16(0x1E)?;
     let offset_to_segment_table = executable.read_u16(0x22)? as usize;
@@ -656,13 +1161,27 @@ fn process_file_ne(
         }
     };
 
-    let module_reference_table = process_module_reference_table(
+    let imported_module_names = process_imported_module_names(
         executable,
         offset_to_module_reference_table,
         module_reference_count,
-        &mut segment_bump_allocator,
     )?;
     let entry_table = process_entry_table(executable, offset_to_entry_table, entry_table_bytes)?;
+    // The first entry of the resident name table is the module name
+    let module_name = executable
+        .read_string(offset_to_resident_name_table)?
+        .ok_or(ExecutableFormatError::HeaderSize)?;
+    let mut exported_names = HashMap::new();
+    process_name_table(
+        executable,
+        offset_to_resident_name_table,
+        &mut exported_names,
+    )?;
+    if non_resident_name_table_bytes > 0 {
+        let name_table_cursor = executable.seek_from_start(offset_to_non_resident_name_table)?;
+        process_name_table(executable, 0, &mut exported_names)?;
+        executable.restore_cursor(name_table_cursor);
+    }
     let resource_table = if offset_to_resource_table == offset_to_resident_name_table {
         ResourceTable::new()
     } else {
@@ -694,31 +1213,51 @@ fn process_file_ne(
     )?;
     println!("{:#?}", segment_table);
 
-    validate_segment_index_and_offset(&segment_table, cs, ip)?;
-    validate_segment_index_and_offset(&segment_table, ss, sp)?;
-
     executable.restore_cursor(old_cursor);
 
-    let mut memory = Memory::new();
-
-    // Setup default trampolines
-    for module in &module_reference_table.modules {
-        module
-            .base_module()
-            .write_syscall_proc_return_trampoline(&mut memory)
-            .map_err(|_| ExecutableFormatError::Memory)?;
-    }
+    Ok(NeModule {
+        module_name,
+        is_library,
+        imported_module_names,
+        entry_table,
+        exported_names,
+        segment_table,
+        resource_table,
+        cs,
+        ip,
+        ds,
+        ss,
+        sp,
+        stack_initial_size,
+        heap_initial_size,
+    })
+}
 
+/// Copies the segments of a module into memory and relocates them.
+/// Returns the segment that was chosen for each of the segments of the module.
+fn load_segments(
+    executable: &Executable,
+    ne_module: &NeModule,
+    memory: &mut Memory,
+    segment_bump_allocator: &mut SegmentBumpAllocator,
+    module_reference_table: &ModuleReferenceTable,
+) -> Result<Vec<u16>, ExecutableFormatError> {
     // TODO: handle 0 segment rules
     // This allocates a place in memory for each segment, and copies their memory.
-    let mut chosen_segments = Vec::with_capacity(segment_table.len());
-    for segment in &segment_table {
+    let mut chosen_segments = Vec::with_capacity(ne_module.segment_table.len());
+    for (index, segment) in ne_module.segment_table.iter().enumerate() {
         let segment_bytes = executable.slice(
             segment.logical_sector_offset as usize,
             segment.length_of_segment_in_file as usize,
         )?;
+        // The stack and local heap live in the automatic data segment, after its data
+        let allocation_size = if index + 1 == ne_module.ds as usize {
+            0x10000
+        } else {
+            segment.minimum_allocation_size as usize
+        };
         let segment_selector = segment_bump_allocator
-            .allocate(segment.minimum_allocation_size as usize)
+            .allocate(allocation_size)
             .ok_or(ExecutableFormatError::Memory)?;
         let flat_address = segment_selector as u32 * 0x10;
         memory
@@ -727,66 +1266,363 @@ fn process_file_ne(
         chosen_segments.push(segment_selector);
     }
 
-    for (segment, &selector) in segment_table.iter().zip(&chosen_segments) {
+    for (segment, &selector) in ne_module.segment_table.iter().zip(&chosen_segments) {
         perform_relocations(
-            &mut memory,
+            memory,
             selector as u32 * 0x10,
-            &module_reference_table,
-            &entry_table,
+            module_reference_table,
+            &ne_module.entry_table,
             segment,
             &chosen_segments,
         )?;
     }
 
+    Ok(chosen_segments)
+}
+
+/// A library that was mapped into the address space of a task, but not yet initialized.
+pub struct MappedLibrary {
+    pub h_instance: Handle,
+    pub entry_points: EntryPoints,
+    pub lib_entry: Option<SegmentAndOffset>,
+    pub data_segment: u16,
+    pub heap_initial_size: u16,
+    /// The local heap in the automatic data segment, after the data
+    pub local_heap: Option<Heap>,
+}
+
+/// Reads the headers of an NE library.
+fn process_library_header(executable: &mut Executable) -> Result<NeModule, ExecutableFormatError> {
+    let ne_header_offset = process_file_mz(executable)?
+        .ne_header_offset
+        .ok_or(ExecutableFormatError::HeaderMagic)?;
+    let ne_module = process_ne_header(executable, ne_header_offset)?;
+    bool_to_result(
+        ne_module.is_library,
+        ExecutableFormatError::ApplicationFlags,
+    )?;
+    if ne_module.cs != 0 {
+        validate_segment_index_and_offset(&ne_module.segment_table, ne_module.cs, ne_module.ip)?;
+    }
+    Ok(ne_module)
+}
+
+/// Maps a library into memory. The libraries it imports must be loaded already.
+fn map_library(
+    executable: &Executable,
+    ne_module: &NeModule,
+    memory: &mut Memory,
+    segment_bump_allocator: &mut SegmentBumpAllocator,
+    module_reference_table: &ModuleReferenceTable,
+) -> Result<MappedLibrary, ExecutableFormatError> {
+    bool_to_result(
+        !ne_module.segment_table.is_empty(),
+        ExecutableFormatError::SegmentIndex,
+    )?;
+    let chosen_segments = load_segments(
+        executable,
+        ne_module,
+        memory,
+        segment_bump_allocator,
+        module_reference_table,
+    )?;
+    let segment = |segment_number: u8| {
+        chosen_segments
+            .get((segment_number as usize).wrapping_sub(1))
+            .copied()
+            .ok_or(ExecutableFormatError::SegmentIndex)
+    };
+
+    // Libraries without an automatic data segment are identified by their first segment
+    let data_segment = if ne_module.ds == 0 {
+        0
+    } else {
+        segment(ne_module.ds as u8)?
+    };
+    let h_instance: Handle = if data_segment == 0 {
+        chosen_segments[0].into()
+    } else {
+        data_segment.into()
+    };
+
+    let mut entry_points = HashMap::new();
+    for (&ordinal, entry) in &ne_module.entry_table.entries {
+        let entry_point = SegmentAndOffset {
+            segment: segment(entry.segment_number)?,
+            offset: entry.offset,
+        };
+        // Exported procedures start by loading DS from AX. Like the Windows loader does, patch
+        // "push ds; pop ax; nop" or "mov ax, ds; nop" into loading the library data segment.
+        if data_segment != 0 {
+            let flat_address = ((entry_point.segment as u32) << 4) + entry_point.offset as u32;
+            let prolog = memory
+                .slice(flat_address, flat_address + 3)
+                .map_err(|_| ExecutableFormatError::Memory)?;
+            if prolog == [0x1E, 0x58, 0x90] || prolog == [0x8C, 0xD8, 0x90] {
+                memory
+                    .write_8(flat_address, 0xB8)
+                    .and_then(|_| memory.write_u16(flat_address + 1, data_segment))
+                    .map_err(|_| ExecutableFormatError::Memory)?;
+            }
+        }
+        entry_points.insert(ordinal, entry_point);
+    }
+
+    let lib_entry = if ne_module.cs == 0 {
+        None
+    } else {
+        Some(SegmentAndOffset {
+            segment: segment(ne_module.cs as u8)?,
+            offset: ne_module.ip,
+        })
+    };
+
+    let local_heap = if data_segment == 0 {
+        None
+    } else {
+        let ds_segment_end =
+            (ne_module.segment_table[ne_module.ds as usize - 1].minimum_allocation_size + 1) & !1;
+        Some(Heap::new(
+            0xFFFEu32.saturating_sub(ds_segment_end) as u16,
+            ds_segment_end as u16,
+        ))
+    };
+
+    Ok(MappedLibrary {
+        h_instance,
+        entry_points,
+        lib_entry,
+        data_segment,
+        heap_initial_size: ne_module.heap_initial_size,
+        local_heap,
+    })
+}
+
+fn process_file_ne(
+    executable: &mut Executable,
+    ne_header_offset: usize,
+    launch_parameters: &LaunchParameters,
+    session: &Arc<Session>,
+    process_id: ProcessId,
+    load_result: &Sender<u16>,
+) -> Result<(), ExecutableFormatError> {
+    let ne_module = process_ne_header(executable, ne_header_offset)?;
+    // TODO: load the libraries the program imports
+    bool_to_result(
+        !ne_module.is_library,
+        ExecutableFormatError::ApplicationFlags,
+    )?;
+    let NeModule {
+        cs,
+        ip,
+        ds,
+        ss,
+        sp,
+        stack_initial_size,
+        ..
+    } = ne_module;
+    let segment_table = &ne_module.segment_table;
+
+    validate_segment_index_and_offset(segment_table, cs, ip)?;
+    validate_segment_index_and_offset(segment_table, ss, sp)?;
+
+    let mut segment_bump_allocator = SegmentBumpAllocator::new();
+    // Every task has an address space of its own. Shift the segments of each task by a few
+    // paragraphs, so that the instance handles of different tasks don't collide.
+    segment_bump_allocator.allocate(process_id.as_u16() as usize * 16);
+
+    let builtin_modules =
+        BuiltinModules::new(&mut segment_bump_allocator).ok_or(ExecutableFormatError::Memory)?;
+    let module_reference_table = ModuleReferenceTable::new(
+        &ne_module.imported_module_names,
+        &builtin_modules,
+        &mut segment_bump_allocator,
+        &|_| None,
+    )?;
+
+    let mut memory = Memory::new();
+
+    let environment_segment = segment_bump_allocator
+        .allocate(0x100)
+        .ok_or(ExecutableFormatError::Memory)?;
+    let psp_segment = segment_bump_allocator
+        .allocate(0x100)
+        .ok_or(ExecutableFormatError::Memory)?;
+    write_environment_block(
+        &mut memory,
+        environment_segment,
+        launch_parameters.path.as_bytes(),
+    )
+    .map_err(|_| ExecutableFormatError::Memory)?;
+    write_program_segment_prefix(
+        &mut memory,
+        psp_segment,
+        environment_segment,
+        &launch_parameters.command_tail,
+    )
+    .and_then(|_| {
+        // Windows programs get a null-terminated command line instead of a carriage return
+        let command_tail_length = memory.read_8(((psp_segment as u32) << 4) + 0x80)?;
+        memory.write_8(
+            ((psp_segment as u32) << 4) + 0x81 + command_tail_length as u32,
+            0,
+        )
+    })
+    .map_err(|_| ExecutableFormatError::Memory)?;
+
+    let chosen_segments = load_segments(
+        executable,
+        &ne_module,
+        &mut memory,
+        &mut segment_bump_allocator,
+        &module_reference_table,
+    )?;
+
     // Setup SP and heap pointer now that every offset and segment is known.
     let ds_segment_end = (segment_table[ds as usize - 1].minimum_allocation_size + 1) & !1;
     let ds_stack_end = ds_segment_end + stack_initial_size;
     let heap_size_left = 0xFFFEu32.saturating_sub(ds_stack_end);
     let sp = if sp == 0 { ds_stack_end as u16 } else { sp };
 
-    let button_wnd_proc = module_reference_table
-        .user_module_index
-        .ok_or(ExecutableFormatError::Memory)
-        .and_then(|user_module_index| {
-            module_reference_table.modules[user_module_index]
-                .base_module()
-                .procedure(&mut memory, 0xFFFF, 10)
-                .map_err(|_| ExecutableFormatError::Memory)
-        })?;
+    let user_module = builtin_modules.user();
+    let mut procedure = |ordinal| {
+        let argument_bytes = user_module
+            .argument_bytes_of_procedure(ordinal)
+            .expect("the procedures of the windows of USER exist");
+        user_module
+            .base_module()
+            .procedure(&mut memory, ordinal, argument_bytes)
+            .map_err(|_| ExecutableFormatError::Memory)
+    };
+    // The procedures of the windows of USER have no ordinals of their own
+    let builtin_procedures = BuiltinProcedures {
+        button: procedure(0xFFFF)?,
+        static_control: procedure(0xFFFE)?,
+        edit: procedure(0xFFFD)?,
+        list_box: procedure(0xFFFC)?,
+        combo_box: procedure(0xFFFB)?,
+        scroll_bar: procedure(0xFFFA)?,
+        def_dlg_proc: procedure(308)?,
+        message_box_dialog_proc: procedure(0xFFF9)?,
+    };
+
+    // The instance handle of a task is its DGROUP selector
+    let h_instance: Handle = chosen_segments[ds as usize - 1].into();
+    let (h_module, h_prev_instance) = session
+        .task_registry
+        .write()
+        .unwrap()
+        .register_instance(
+            ne_module.module_name.as_slice(),
+            launch_parameters.path.as_bytes(),
+            h_instance,
+        )
+        .ok_or(ExecutableFormatError::Memory)?;
+    let task = TaskDatabase {
+        process_id,
+        h_module,
+        h_instance,
+        h_prev_instance,
+        cmd_show: launch_parameters.cmd_show,
+        psp_segment,
+        environment_segment,
+        stack_top: ds_segment_end as u16,
+        stack_bottom: sp,
+    };
 
     // TODO: move the actual execution somewhere else
     let local_heap = Heap::new(heap_size_left as u16, ds_stack_end as u16);
-    let message_queue = MessageQueue::new();
-    let objects = RwLock::new(ObjectEnvironment::new(window_manager, local_heap));
-    let emulated_kernel = EmulatedKernel::new(&objects, &resource_table);
-    let emulated_user =
-        EmulatedUser::new(&objects, &message_queue, &resource_table, button_wnd_proc);
-    let emulated_gdi = EmulatedGdi::new(&objects);
+    session
+        .message_queues
+        .write()
+        .unwrap()
+        .insert(process_id, Arc::new(MessageQueue::new()));
+    let emulated_kernel = EmulatedKernel::new(
+        session,
+        &ne_module.resource_table,
+        &task,
+        local_heap,
+        Some(&builtin_modules),
+        segment_bump_allocator,
+    );
+    let emulated_user = EmulatedUser::new(
+        &session.objects,
+        &session.scheduler,
+        &task,
+        &session.message_queues,
+        &ne_module.resource_table,
+        builtin_procedures,
+        session.message_box_answer,
+    );
+    let emulated_gdi = EmulatedGdi::new(&session.objects);
     let emulated_keyboard = EmulatedKeyboard::new();
     println!("{:?}", chosen_segments);
+    // Registers at task entry, as set up by the Windows loader
+    let mut registers = Registers::new(
+        chosen_segments[ds as usize - 1],
+        chosen_segments[cs as usize - 1],
+        ip,
+        sp,
+    );
+    registers.write_gpr_16(Registers::REG_BX, stack_initial_size as u16);
+    registers.write_gpr_16(Registers::REG_CX, heap_size_left as u16);
+    registers.write_gpr_16(Registers::REG_DI, h_instance.as_u16());
+    registers.write_gpr_16(Registers::REG_SI, h_prev_instance.as_u16());
+    registers.write_segment(Registers::REG_ES, psp_segment);
     let mut emulator = Emulator::new(
-        Registers::new(
-            chosen_segments[ds as usize - 1],
-            chosen_segments[cs as usize - 1],
-            ip,
-            sp,
-        ),
+        registers,
         memory,
-        emulated_kernel,
-        emulated_user,
-        emulated_gdi,
-        emulated_keyboard,
+        &emulated_kernel,
+        &emulated_user,
+        &emulated_gdi,
+        &emulated_keyboard,
     );
+    let _ = load_result.send(h_instance.as_u16());
     emulator.run();
 
+    emulated_user.free_task_windows();
+    emulated_kernel.free_all_libraries(EmulatorAccessor::new(&mut emulator));
+    {
+        let mut objects = session.objects.write().unwrap();
+        objects.timers.kill_timers_of_process(process_id);
+        objects.hooks.remove_hooks_of_process(process_id);
+    }
+    remove_message_queue(session, process_id);
+    session
+        .task_registry
+        .write()
+        .unwrap()
+        .deregister_instance(task.h_module, task.h_instance);
+
     // TODO: validate CRC32
     Ok(())
 }
 
 fn process_file(
     executable: &mut Executable,
-    window_manager: &RwLock<WindowManager>,
+    launch_parameters: &LaunchParameters,
+    session: &Arc<Session>,
+    process_id: ProcessId,
+    load_result: &Sender<u16>,
 ) -> Result<(), ExecutableFormatError> {
     let mz_result = process_file_mz(executable)?;
-    process_file_ne(executable, mz_result.ne_header_offset, window_manager)
+    match mz_result.ne_header_offset {
+        Some(ne_header_offset) if !launch_parameters.force_dos => process_file_ne(
+            executable,
+            ne_header_offset,
+            launch_parameters,
+            session,
+            process_id,
+            load_result,
+        ),
+        // Either a DOS-only program, or the DOS stub of a Windows program was requested
+        _ => process_file_dos(
+            executable,
+            &mz_result,
+            launch_parameters,
+            session,
+            process_id,
+            load_result,
+        ),
+    }
 }
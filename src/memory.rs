@@ -9,7 +9,7 @@ pub struct Memory {
     bytes: Box<[u8; MEMORY_SIZE]>,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub struct SegmentAndOffset {
     pub segment: u16,
     pub offset: u16,
@@ -36,12 +36,6 @@ impl Memory {
             .ok_or(EmulatorError::OutOfBounds)
     }
 
-    pub fn segment_and_offset(&self, address: u32) -> SegmentAndOffset {
-        let offset = address as u16;
-        let segment = ((address - (address & 0xffff)) >> 4) as u16;
-        SegmentAndOffset { segment, offset }
-    }
-
     pub fn write_u16(&mut self, address: u32, data: u16) -> Result<(), EmulatorError> {
         if ((address + 1) as usize) < MEMORY_SIZE {
             self.bytes[address as usize] = data as u8;
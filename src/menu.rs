@@ -0,0 +1,1007 @@
+//! Menus: their items, the templates they are loaded from, how menu bars and popup menus are
+//! laid out and drawn, and how the user chooses from them.
+
+use crate::bitmap::{Bitmap, Color};
+use crate::byte_string::HeapByteString;
+use crate::constants::{
+    MenuFlags, MessageFilterCode, MessageType, SystemColors, SystemCommand, WindowStyles,
+};
+use crate::emulated_user::{EmulatedUser, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::emulator_accessor::EmulatorAccessor;
+use crate::emulator_error::EmulatorError;
+use crate::font;
+use crate::handle_table::{GenericHandle, Handle};
+use crate::keyboard::{VK_DOWN, VK_ESCAPE, VK_F10, VK_LEFT, VK_MENU, VK_RETURN, VK_RIGHT, VK_UP};
+use crate::memory::SegmentAndOffset;
+use crate::message_queue::{PostedMessage, WindowMessage};
+use crate::non_client;
+use crate::object_environment::UserObject;
+use crate::two_d::{Point, Rect};
+use crate::util::{encode_u16_u16_to_u32, u16_from_slice};
+
+/// SM_CYMENU, the height of a row of the menu bar, including the line below it.
+pub const MENU_BAR_HEIGHT: i16 = font::GLYPH_HEIGHT + 10;
+/// The space on either side of the text of an item in the menu bar.
+const BAR_ITEM_PADDING: i16 = 6;
+const POPUP_ITEM_HEIGHT: i16 = font::GLYPH_HEIGHT + 8;
+const SEPARATOR_HEIGHT: i16 = 8;
+/// The column on the left of a popup menu that holds the check marks.
+const CHECK_COLUMN_WIDTH: i16 = 14;
+/// The column on the right of a popup menu that holds the arrows of items that open a popup.
+const ARROW_COLUMN_WIDTH: i16 = 14;
+/// The space between the text of an item and its accelerator text.
+const ACCELERATOR_GAP: i16 = 12;
+
+/// A check mark of seven by seven pixels, with the leftmost pixel in the highest bit.
+const CHECK_MARK: [u8; 7] = [0x01, 0x03, 0x46, 0x6c, 0x38, 0x10, 0x00];
+
+pub struct MenuItem {
+    /// The state of the item, and whether it is a separator or opens a popup menu.
+    pub flags: MenuFlags,
+    /// The command the item sends, or the handle of the popup menu it opens.
+    pub id: u16,
+    pub text: HeapByteString,
+}
+
+impl MenuItem {
+    pub fn popup(&self) -> Option<Handle> {
+        self.flags
+            .contains(MenuFlags::POPUP)
+            .then(|| self.id.into())
+    }
+
+    /// Whether the item can be chosen. Separators can't, and neither can grayed or disabled items.
+    pub fn is_enabled(&self) -> bool {
+        !self
+            .flags
+            .intersects(MenuFlags::SEPARATOR | MenuFlags::GRAYED | MenuFlags::DISABLED)
+    }
+}
+
+pub struct Menu {
+    pub items: Vec<MenuItem>,
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    /// The default system menu, whose items send WM_SYSCOMMAND.
+    pub fn system_menu() -> Self {
+        let item = |command: SystemCommand, text: &[u8]| MenuItem {
+            flags: MenuFlags::empty(),
+            id: command.into(),
+            text: HeapByteString::from(text.into()),
+        };
+        Self {
+            items: vec![
+                item(SystemCommand::Restore, b"&Restore"),
+                item(SystemCommand::Move, b"&Move"),
+                item(SystemCommand::Size, b"&Size"),
+                item(SystemCommand::Minimize, b"Mi&nimize"),
+                item(SystemCommand::Maximize, b"Ma&ximize"),
+                MenuItem {
+                    flags: MenuFlags::SEPARATOR,
+                    id: 0,
+                    text: HeapByteString::from([].into()),
+                },
+                item(SystemCommand::Close, b"&Close\tAlt+F4"),
+            ],
+        }
+    }
+
+    /// Finds the item at a position, or the item with a command.
+    /// This doesn't look in the popup menus the items open.
+    pub fn find_item(&self, item: u16, flags: MenuFlags) -> Option<usize> {
+        if flags.contains(MenuFlags::BYPOSITION) {
+            (usize::from(item) < self.items.len()).then_some(usize::from(item))
+        } else {
+            self.items
+                .iter()
+                .position(|menu_item| menu_item.popup().is_none() && menu_item.id == item)
+        }
+    }
+
+    /// The item whose mnemonic is the given character, which is compared case-insensitively.
+    pub fn item_with_mnemonic(&self, character: u8) -> Option<usize> {
+        let character = character.to_ascii_lowercase();
+        self.items
+            .iter()
+            .position(|item| mnemonic_of(item.text.as_slice()) == Some(character))
+    }
+}
+
+/// An item of a menu template, with the items of the popup menu it opens.
+pub struct TemplateItem {
+    pub flags: MenuFlags,
+    pub id: u16,
+    pub text: HeapByteString,
+    pub children: Vec<TemplateItem>,
+}
+
+/// Reads the items of a menu template, as found in an RT_MENU resource.
+pub fn parse_template(data: &[u8]) -> Option<Vec<TemplateItem>> {
+    // The header holds a version number and the size of the rest of the header
+    let header_size = u16_from_slice(data, 2)? as usize;
+    let mut offset = 4 + header_size;
+    parse_template_items(data, &mut offset)
+}
+
+fn parse_template_items(data: &[u8], offset: &mut usize) -> Option<Vec<TemplateItem>> {
+    let mut items = Vec::new();
+    loop {
+        let mut flags = MenuFlags::from_bits_truncate(u16_from_slice(data, *offset)?);
+        *offset += 2;
+        // Items that open a popup menu have no command
+        let id = if flags.contains(MenuFlags::POPUP) {
+            0
+        } else {
+            *offset += 2;
+            u16_from_slice(data, *offset - 2)?
+        };
+        let length = data.get(*offset..)?.iter().position(|&byte| byte == 0)?;
+        let text = &data[*offset..*offset + length];
+        *offset += length + 1;
+
+        let children = if flags.contains(MenuFlags::POPUP) {
+            parse_template_items(data, offset)?
+        } else {
+            Vec::new()
+        };
+        let last = flags.contains(MenuFlags::END);
+        flags.remove(MenuFlags::END);
+        // A separator is an item without a command and without text
+        if !flags.contains(MenuFlags::POPUP) && id == 0 && text.is_empty() {
+            flags.insert(MenuFlags::SEPARATOR);
+        }
+        items.push(TemplateItem {
+            flags,
+            id,
+            text: HeapByteString::from(text.into()),
+            children,
+        });
+        if last {
+            return Some(items);
+        }
+    }
+}
+
+/// Splits the text of an item into the label as it is shown, without the ampersands, and the
+/// accelerator text that follows a tab. Also returns where the mnemonic is in the label.
+pub fn split_text(text: &[u8]) -> (Vec<u8>, Option<usize>, &[u8]) {
+    let (text, accelerator) = match text.iter().position(|&byte| byte == b'\t') {
+        Some(tab) => (&text[..tab], &text[tab + 1..]),
+        None => (text, &[][..]),
+    };
+    let mut label = Vec::with_capacity(text.len());
+    let mut mnemonic = None;
+    let mut characters = text.iter();
+    while let Some(&character) = characters.next() {
+        if character == b'&' {
+            // Two ampersands stand for an ampersand
+            match characters.next() {
+                Some(b'&') => label.push(b'&'),
+                Some(&next) => {
+                    mnemonic.get_or_insert(label.len());
+                    label.push(next);
+                }
+                None => {}
+            }
+        } else {
+            label.push(character);
+        }
+    }
+    (label, mnemonic, accelerator)
+}
+
+/// The lowercase character that comes after the ampersand in the text of an item.
+pub fn mnemonic_of(text: &[u8]) -> Option<u8> {
+    let (label, mnemonic, _) = split_text(text);
+    mnemonic.map(|index| label[index].to_ascii_lowercase())
+}
+
+/// Where the items of a menu bar go, relative to the top left corner of the menu bar.
+/// Items that don't fit on a row anymore go on the next one.
+pub fn bar_layout(menu: &Menu, width: i16) -> Vec<Rect> {
+    let mut rects = Vec::with_capacity(menu.items.len());
+    let mut position = Point::origin();
+    for item in &menu.items {
+        let (label, _, _) = split_text(item.text.as_slice());
+        let item_width = font::text_width(&label) + 2 * BAR_ITEM_PADDING;
+        let new_row = item
+            .flags
+            .intersects(MenuFlags::MENUBREAK | MenuFlags::MENUBARBREAK)
+            || position.x + item_width > width;
+        if new_row && position.x > 0 {
+            position = Point::new(0, position.y + MENU_BAR_HEIGHT);
+        }
+        rects.push(Rect {
+            left: position.x,
+            top: position.y,
+            right: position.x + item_width,
+            bottom: position.y + MENU_BAR_HEIGHT,
+        });
+        position.x += item_width;
+    }
+    rects
+}
+
+/// The height of a menu bar of the given width, which has at least one row.
+pub fn bar_height(menu: &Menu, width: i16) -> i16 {
+    bar_layout(menu, width)
+        .last()
+        .map_or(MENU_BAR_HEIGHT, |rect| rect.bottom)
+}
+
+/// Where the items of a popup menu go, relative to its top left corner, and the size of the
+/// popup menu.
+pub fn popup_layout(menu: &Menu) -> (Vec<Rect>, (i16, i16)) {
+    let mut label_width = 0;
+    let mut accelerator_width = 0;
+    for item in &menu.items {
+        let (label, _, accelerator) = split_text(item.text.as_slice());
+        label_width = label_width.max(font::text_width(&label));
+        accelerator_width = accelerator_width.max(font::text_width(accelerator));
+    }
+    if accelerator_width > 0 {
+        accelerator_width += ACCELERATOR_GAP;
+    }
+    let width = CHECK_COLUMN_WIDTH + label_width + accelerator_width + ARROW_COLUMN_WIDTH;
+
+    // There is a border of a pixel around the items
+    let mut top = 1;
+    let rects = menu
+        .items
+        .iter()
+        .map(|item| {
+            let height = if item.flags.contains(MenuFlags::SEPARATOR) {
+                SEPARATOR_HEIGHT
+            } else {
+                POPUP_ITEM_HEIGHT
+            };
+            top += height;
+            Rect {
+                left: 1,
+                top: top - height,
+                right: 1 + width,
+                bottom: top,
+            }
+        })
+        .collect();
+    (rects, (width + 2, top + 1))
+}
+
+/// The colours of the text and the background of an item.
+fn item_colors(
+    item: &MenuItem,
+    selected: bool,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) -> (Color, Color) {
+    if selected {
+        (
+            system_color(SystemColors::HighlightText),
+            system_color(SystemColors::Highlight),
+        )
+    } else if item.flags.intersects(MenuFlags::GRAYED) {
+        (
+            system_color(SystemColors::GrayText),
+            system_color(SystemColors::Menu),
+        )
+    } else {
+        (
+            system_color(SystemColors::MenuText),
+            system_color(SystemColors::Menu),
+        )
+    }
+}
+
+/// Draws a label with its mnemonic underlined.
+pub fn draw_label(bitmap: &mut Bitmap, position: Point, text: &[u8], color: Color) {
+    let (label, mnemonic, _) = split_text(text);
+    font::draw_text(bitmap, position, &label, color);
+    if let Some(index) = mnemonic {
+        let left = position.x + font::character_offset(index);
+        let y = position.y + font::GLYPH_HEIGHT;
+        bitmap.draw_horizontal_line(left, y, left + font::GLYPH_WIDTH, color);
+    }
+}
+
+/// Draws a menu bar in the given rectangle, which includes the line below it.
+pub fn paint_bar(
+    bitmap: &mut Bitmap,
+    menu: &Menu,
+    bar: Rect,
+    selected: Option<usize>,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    bitmap.fill_rectangle(bar, system_color(SystemColors::Menu));
+    let rects = bar_layout(menu, bar.right - bar.left);
+    for (index, (item, rect)) in menu.items.iter().zip(rects).enumerate() {
+        let rect = rect.offset(bar.left, bar.top);
+        let (text_color, background) = item_colors(item, selected == Some(index), system_color);
+        bitmap.fill_rectangle(
+            Rect {
+                bottom: rect.bottom - 1,
+                ..rect
+            },
+            background,
+        );
+        let text_top = rect.top + (MENU_BAR_HEIGHT - 1 - font::GLYPH_HEIGHT) / 2;
+        draw_label(
+            bitmap,
+            Point::new(rect.left + BAR_ITEM_PADDING, text_top),
+            item.text.as_slice(),
+            text_color,
+        );
+    }
+    // The line between the menu bar and the client area
+    bitmap.draw_horizontal_line(
+        bar.left,
+        bar.bottom - 1,
+        bar.right,
+        system_color(SystemColors::WindowFrame),
+    );
+}
+
+/// Draws a popup menu onto a bitmap of the size that popup_layout gives.
+pub fn paint_popup(
+    bitmap: &mut Bitmap,
+    menu: &Menu,
+    selected: Option<usize>,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    let (rects, (width, height)) = popup_layout(menu);
+    let bounds = Rect {
+        left: 0,
+        top: 0,
+        right: width,
+        bottom: height,
+    };
+    let frame = system_color(SystemColors::WindowFrame);
+    bitmap.fill_rectangle(bounds, system_color(SystemColors::Menu));
+    bitmap.draw_horizontal_line(0, 0, width, frame);
+    bitmap.draw_horizontal_line(0, height - 1, width, frame);
+    bitmap.draw_vertical_line(0, 0, height, frame);
+    bitmap.draw_vertical_line(width - 1, 0, height, frame);
+
+    for (index, (item, rect)) in menu.items.iter().zip(rects).enumerate() {
+        if item.flags.contains(MenuFlags::SEPARATOR) {
+            let y = (rect.top + rect.bottom) / 2;
+            bitmap.draw_horizontal_line(rect.left, y, rect.right, frame);
+            continue;
+        }
+        let (text_color, background) = item_colors(item, selected == Some(index), system_color);
+        bitmap.fill_rectangle(rect, background);
+        let text_top = rect.top + (POPUP_ITEM_HEIGHT - font::GLYPH_HEIGHT) / 2;
+
+        if item.flags.contains(MenuFlags::CHECKED) {
+            let top = rect.top + (POPUP_ITEM_HEIGHT - CHECK_MARK.len() as i16) / 2;
+            let left = rect.left + (CHECK_COLUMN_WIDTH - 7) / 2;
+            for (row, &bits) in CHECK_MARK.iter().enumerate() {
+                for column in 0..7 {
+                    if bits & (0x40 >> column) != 0 {
+                        bitmap.set_pixel(left + column, top + row as i16, text_color);
+                    }
+                }
+            }
+        }
+        draw_label(
+            bitmap,
+            Point::new(rect.left + CHECK_COLUMN_WIDTH, text_top),
+            item.text.as_slice(),
+            text_color,
+        );
+        let (_, _, accelerator) = split_text(item.text.as_slice());
+        if !accelerator.is_empty() {
+            let left = rect.right - ARROW_COLUMN_WIDTH - font::text_width(accelerator);
+            font::draw_text(bitmap, Point::new(left, text_top), accelerator, text_color);
+        }
+        if item.popup().is_some() {
+            // A triangle pointing to the right
+            let center = Point::new(
+                rect.right - ARROW_COLUMN_WIDTH / 2 - 2,
+                (rect.top + rect.bottom) / 2,
+            );
+            for column in 0..4 {
+                bitmap.draw_vertical_line(
+                    center.x + column,
+                    center.y - 3 + column,
+                    center.y + 4 - column,
+                    text_color,
+                );
+            }
+        }
+    }
+}
+
+/// A menu that is open while the user chooses from it: the menu bar or a popup menu.
+pub struct OpenMenu {
+    h_menu: Handle,
+    /// Where the menu is on the screen.
+    rect: Rect,
+    /// Where the items are on the screen.
+    item_rects: Vec<Rect>,
+    selected: Option<usize>,
+}
+
+/// The menus that are open while the user chooses a command from the menu bar, from the system
+/// menu, or from a popup menu of TrackPopupMenu. Each menu opened from the one before it.
+pub struct MenuTracking {
+    pub h_wnd: Handle,
+    pub proc: SegmentAndOffset,
+    /// Whether the first menu is the menu bar, which is drawn in the window instead of over it.
+    pub bar: bool,
+    pub system: bool,
+    /// Whether the mouse button that opened the menus is still down. Releasing it outside
+    /// the menus doesn't cancel them.
+    pub button_down: bool,
+    pub menus: Vec<OpenMenu>,
+}
+
+/// How choosing from the menus starts: with the mouse at a point on the screen, or with a
+/// key. The key is the mnemonic of an item, or 0 to select the first item.
+#[derive(Copy, Clone)]
+pub enum MenuStart {
+    Mouse(Point),
+    Key(u8),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum MenuOutcome {
+    Continue,
+    Cancel,
+    Choose(u16),
+}
+
+impl EmulatedUser<'_> {
+    /// Opens the menu bar of a window, or its system menu, for the user to choose a command.
+    pub fn enter_menu(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        h_wnd: Handle,
+        system: bool,
+        start: MenuStart,
+    ) -> Result<(), EmulatorError> {
+        let proc = match self.window_proc_of(h_wnd) {
+            Some(proc) => proc,
+            None => return Ok(()),
+        };
+        let h_menu = if system {
+            self.system_menu_of(h_wnd)
+        } else {
+            self.menu_bar_of(&self.read_objects(), h_wnd)
+                .map(|(h_menu, _)| h_menu)
+        };
+        let h_menu = match h_menu {
+            Some(h_menu) => h_menu,
+            None => return Ok(()),
+        };
+        // The window gets to update the menu before it is shown
+        self.call_wndproc_sync(
+            accessor,
+            proc,
+            h_wnd,
+            MessageType::InitMenu.into(),
+            h_menu.as_u16(),
+            0,
+        )?;
+
+        let mut tracking = MenuTracking {
+            h_wnd,
+            proc,
+            bar: !system,
+            system,
+            button_down: matches!(start, MenuStart::Mouse(_)),
+            menus: Vec::new(),
+        };
+        if system {
+            self.update_system_menu(h_wnd, h_menu);
+            if let Some(position) = self.system_menu_position(h_wnd) {
+                self.open_popup_menu(accessor, &mut tracking, h_menu, 0, position)?;
+            }
+        } else if let Some(bar) = self.open_menu_bar(h_wnd) {
+            tracking.menus.push(bar);
+        }
+        self.run_menu(accessor, tracking, start)
+    }
+
+    /// The menu bar of a window as an open menu, with its items on the screen.
+    fn open_menu_bar(&self, h_wnd: Handle) -> Option<OpenMenu> {
+        let objects = self.read_objects();
+        let (h_menu, bar) = self.menu_bar_of(&objects, h_wnd)?;
+        let position = self.screen_position_of(&objects, h_wnd)?;
+        let menu = match objects.user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu,
+            _ => return None,
+        };
+        let rect = bar.offset(position.x, position.y);
+        let item_rects = bar_layout(menu, rect.right - rect.left)
+            .into_iter()
+            .map(|item_rect| item_rect.offset(rect.left, rect.top))
+            .collect();
+        Some(OpenMenu {
+            h_menu,
+            rect,
+            item_rects,
+            selected: None,
+        })
+    }
+
+    /// Where the system menu of a window opens: below the system menu box, or below the icon
+    /// of a minimised window.
+    fn system_menu_position(&self, h_wnd: Handle) -> Option<Point> {
+        let objects = self.read_objects();
+        let style = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.style,
+            _ => return None,
+        };
+        let position = self.screen_position_of(&objects, h_wnd)?;
+        if style.contains(WindowStyles::MINIMIZE) {
+            let (_, height) = self.window_size_of(&objects, h_wnd)?;
+            Some(position + Point::new(0, height))
+        } else {
+            let frame_width = non_client::frame_width(style);
+            Some(
+                position
+                    + Point::new(
+                        frame_width,
+                        frame_width + non_client::CAPTION_HEIGHT - non_client::BORDER_WIDTH,
+                    ),
+            )
+        }
+    }
+
+    /// Grays the items of the system menu that don't apply to the window in its current state.
+    fn update_system_menu(&self, h_wnd: Handle, h_menu: Handle) {
+        let mut objects = self.write_objects();
+        let style = match objects.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => user_window.style,
+            _ => return,
+        };
+        let iconic = style.contains(WindowStyles::MINIMIZE);
+        let zoomed = style.contains(WindowStyles::MAXIMIZE);
+        let enabled = [
+            (SystemCommand::Restore, iconic || zoomed),
+            (SystemCommand::Move, !zoomed),
+            (
+                SystemCommand::Size,
+                !iconic && !zoomed && style.contains(WindowStyles::THICKFRAME),
+            ),
+            (
+                SystemCommand::Minimize,
+                !iconic && style.contains(WindowStyles::MINIMIZEBOX),
+            ),
+            (
+                SystemCommand::Maximize,
+                !zoomed && style.contains(WindowStyles::MAXIMIZEBOX),
+            ),
+        ];
+        if let Some(UserObject::Menu(menu)) = objects.user.get_mut(h_menu) {
+            for (command, enabled) in enabled {
+                if let Some(index) = menu.find_item(command.into(), MenuFlags::empty()) {
+                    menu.items[index].flags.set(MenuFlags::GRAYED, !enabled);
+                }
+            }
+        }
+    }
+
+    /// Lets the user choose a command from the open menus with the mouse or with the keyboard,
+    /// until a command is chosen or the menus get cancelled. The menus capture the mouse
+    /// meanwhile, and the chosen command gets posted to the window.
+    pub fn run_menu(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        mut tracking: MenuTracking,
+        start: MenuStart,
+    ) -> Result<(), EmulatorError> {
+        if tracking.menus.is_empty() {
+            return Ok(());
+        }
+        {
+            let objects = self.read_objects();
+            let window_identifier = objects.window_identifier(tracking.h_wnd);
+            objects
+                .write_window_manager()
+                .set_capture(window_identifier);
+        }
+        let outcome = match start {
+            MenuStart::Mouse(point) => {
+                self.menu_mouse_input(accessor, &mut tracking, MessageType::MouseMove, point)?
+            }
+            MenuStart::Key(0) => {
+                self.select_next_menu_item(accessor, &mut tracking, 0, true)?;
+                MenuOutcome::Continue
+            }
+            MenuStart::Key(character) => self
+                .choose_by_mnemonic(accessor, &mut tracking, character)?
+                .unwrap_or(MenuOutcome::Cancel),
+        };
+        let outcome = match outcome {
+            MenuOutcome::Continue => loop {
+                let input = self.wait_for_input();
+                let message = PostedMessage {
+                    h_wnd: tracking.h_wnd,
+                    ..input.into()
+                };
+                if self.filter_modal_message(accessor, MessageFilterCode::Menu, &message)? {
+                    continue;
+                }
+                let outcome = match input.message {
+                    MessageType::MouseMove | MessageType::LButtonDown | MessageType::LButtonUp => {
+                        self.menu_mouse_input(accessor, &mut tracking, input.message, input.point)?
+                    }
+                    MessageType::KeyDown | MessageType::SysKeyDown => {
+                        self.menu_key_input(accessor, &mut tracking, input.w_param as u8)?
+                    }
+                    _ => MenuOutcome::Continue,
+                };
+                if outcome != MenuOutcome::Continue {
+                    break outcome;
+                }
+            },
+            outcome => outcome,
+        };
+
+        self.close_menus_above(&mut tracking, 0);
+        if tracking.bar {
+            self.paint_menu_bar(tracking.h_wnd, None);
+        } else {
+            self.read_objects().write_window_manager().close_popup();
+        }
+        self.read_objects().write_window_manager().set_capture(None);
+        // A menu of 0xFFFF without a menu handle tells the window that the menus closed
+        self.call_wndproc_sync(
+            accessor,
+            tracking.proc,
+            tracking.h_wnd,
+            MessageType::MenuSelect.into(),
+            0,
+            encode_u16_u16_to_u32(0xffff, 0),
+        )?;
+        if let MenuOutcome::Choose(id) = outcome {
+            self.message_queue.post(WindowMessage {
+                h_wnd: tracking.h_wnd,
+                message: if tracking.system {
+                    MessageType::SysCommand
+                } else {
+                    MessageType::Command
+                },
+                w_param: id,
+                l_param: 0,
+                time: 0,
+                point: Point::origin(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Selects the item under the mouse and opens its popup menu. Releasing the mouse button
+    /// on an item chooses it, and pressing the mouse button outside the menus cancels them.
+    fn menu_mouse_input(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        tracking: &mut MenuTracking,
+        message: MessageType,
+        point: Point,
+    ) -> Result<MenuOutcome, EmulatorError> {
+        let target = self.menu_item_at(tracking, point);
+        let button_was_down = message == MessageType::LButtonUp
+            && std::mem::replace(&mut tracking.button_down, false);
+        match (message, target) {
+            (MessageType::LButtonUp, Some((level, Some(index)))) => {
+                let command = self
+                    .with_menu_item(tracking.menus[level].h_menu, index, |item| {
+                        (item.popup().is_none() && item.is_enabled()).then_some(item.id)
+                    })
+                    .flatten();
+                Ok(command.map_or(MenuOutcome::Continue, MenuOutcome::Choose))
+            }
+            (MessageType::LButtonUp, None) if !button_was_down => Ok(MenuOutcome::Cancel),
+            (MessageType::LButtonDown, None) => Ok(MenuOutcome::Cancel),
+            (MessageType::MouseMove | MessageType::LButtonDown, Some((level, Some(index)))) => {
+                self.select_menu_item(accessor, tracking, level, Some(index))?;
+                self.open_submenu(accessor, tracking, level, false)?;
+                Ok(MenuOutcome::Continue)
+            }
+            _ => Ok(MenuOutcome::Continue),
+        }
+    }
+
+    /// Moves through the menus with the arrow keys, chooses with Enter, and closes a popup
+    /// menu with Escape. Other keys choose the item with that mnemonic.
+    fn menu_key_input(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        tracking: &mut MenuTracking,
+        vk: u8,
+    ) -> Result<MenuOutcome, EmulatorError> {
+        // The keys are about the menu that opened last
+        let level = tracking.menus.len() - 1;
+        let first_popup = usize::from(tracking.bar);
+        let selected = tracking.menus[level].selected.and_then(|index| {
+            self.with_menu_item(tracking.menus[level].h_menu, index, |item| {
+                (item.popup(), item.is_enabled().then_some(item.id))
+            })
+        });
+        match vk {
+            VK_ESCAPE if level == 0 => return Ok(MenuOutcome::Cancel),
+            VK_ESCAPE => self.close_menus_above(tracking, level - 1),
+            VK_MENU | VK_F10 => return Ok(MenuOutcome::Cancel),
+            VK_UP | VK_DOWN if level < first_popup => {
+                self.open_submenu(accessor, tracking, level, true)?
+            }
+            VK_UP | VK_DOWN => {
+                self.select_next_menu_item(accessor, tracking, level, vk == VK_DOWN)?
+            }
+            VK_RIGHT
+                if level >= first_popup && selected.is_some_and(|(popup, _)| popup.is_some()) =>
+            {
+                self.open_submenu(accessor, tracking, level, true)?
+            }
+            VK_LEFT if level > first_popup => self.close_menus_above(tracking, level - 1),
+            VK_LEFT | VK_RIGHT if tracking.bar => {
+                // Moving along the menu bar keeps a popup menu open
+                let popup_open = level > 0;
+                self.select_next_menu_item(accessor, tracking, 0, vk == VK_RIGHT)?;
+                if popup_open {
+                    self.open_submenu(accessor, tracking, 0, true)?;
+                }
+            }
+            VK_RETURN => match selected {
+                Some((Some(_), _)) => self.open_submenu(accessor, tracking, level, true)?,
+                Some((None, Some(id))) => return Ok(MenuOutcome::Choose(id)),
+                _ => {}
+            },
+            _ => {
+                let character = self.read_objects().key_states.character_of(vk);
+                if let Some(character) = character {
+                    return Ok(self
+                        .choose_by_mnemonic(accessor, tracking, character)?
+                        .unwrap_or(MenuOutcome::Continue));
+                }
+            }
+        }
+        Ok(MenuOutcome::Continue)
+    }
+
+    /// Selects the item with a mnemonic in the menu that opened last. An item that opens a
+    /// popup menu opens it, and other items get chosen. Returns None if no item has the
+    /// mnemonic.
+    fn choose_by_mnemonic(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        tracking: &mut MenuTracking,
+        character: u8,
+    ) -> Result<Option<MenuOutcome>, EmulatorError> {
+        let level = tracking.menus.len() - 1;
+        let h_menu = tracking.menus[level].h_menu;
+        let index = match self.read_objects().user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu.item_with_mnemonic(character),
+            _ => None,
+        };
+        let index = match index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        self.select_menu_item(accessor, tracking, level, Some(index))?;
+        let item = self.with_menu_item(h_menu, index, |item| {
+            (item.popup(), item.is_enabled().then_some(item.id))
+        });
+        match item {
+            Some((Some(_), _)) => {
+                self.open_submenu(accessor, tracking, level, true)?;
+                Ok(Some(MenuOutcome::Continue))
+            }
+            Some((None, Some(id))) => Ok(Some(MenuOutcome::Choose(id))),
+            _ => Ok(Some(MenuOutcome::Continue)),
+        }
+    }
+
+    /// The open menu at a point on the screen, and the item at that point if it can be
+    /// selected.
+    fn menu_item_at(
+        &self,
+        tracking: &MenuTracking,
+        point: Point,
+    ) -> Option<(usize, Option<usize>)> {
+        // Popup menus cover the menus they opened from
+        let level = (0..tracking.menus.len())
+            .rev()
+            .find(|&level| tracking.menus[level].rect.contains(point))?;
+        let open_menu = &tracking.menus[level];
+        let index = open_menu
+            .item_rects
+            .iter()
+            .position(|rect| rect.contains(point))
+            .filter(|&index| {
+                self.with_menu_item(open_menu.h_menu, index, |item| {
+                    !item.flags.contains(MenuFlags::SEPARATOR)
+                }) == Some(true)
+            });
+        Some((level, index))
+    }
+
+    /// Selects an item of an open menu, which closes the popup menus that opened from it.
+    /// The window hears about the selection with WM_MENUSELECT.
+    fn select_menu_item(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        tracking: &mut MenuTracking,
+        level: usize,
+        index: Option<usize>,
+    ) -> Result<(), EmulatorError> {
+        if tracking.menus[level].selected == index {
+            return Ok(());
+        }
+        self.close_menus_above(tracking, level);
+        tracking.menus[level].selected = index;
+        self.paint_open_menu(tracking, level);
+
+        let h_menu = tracking.menus[level].h_menu;
+        let item = index
+            .and_then(|index| self.with_menu_item(h_menu, index, |item| (item.id, item.flags)));
+        if let Some((id, mut flags)) = item {
+            if tracking.system {
+                flags |= MenuFlags::SYSMENU;
+            }
+            self.call_wndproc_sync(
+                accessor,
+                tracking.proc,
+                tracking.h_wnd,
+                MessageType::MenuSelect.into(),
+                id,
+                encode_u16_u16_to_u32(flags.bits(), h_menu.as_u16()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Selects the item after the selected one, or the one before it, skipping separators.
+    /// The selection wraps around.
+    fn select_next_menu_item(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        tracking: &mut MenuTracking,
+        level: usize,
+        forward: bool,
+    ) -> Result<(), EmulatorError> {
+        let open_menu = &tracking.menus[level];
+        let count = open_menu.item_rects.len();
+        let next = (1..=count)
+            .map(|step| match open_menu.selected {
+                Some(selected) if forward => (selected + step) % count,
+                Some(selected) => (selected + count - step) % count,
+                None if forward => step - 1,
+                None => count - step,
+            })
+            .find(|&index| {
+                self.with_menu_item(open_menu.h_menu, index, |item| {
+                    !item.flags.contains(MenuFlags::SEPARATOR)
+                }) == Some(true)
+            });
+        if next.is_some() {
+            self.select_menu_item(accessor, tracking, level, next)?;
+        }
+        Ok(())
+    }
+
+    /// Opens the popup menu of the selected item of an open menu, unless it is open already.
+    /// Popup menus of the menu bar open below it, other popup menus open next to the item.
+    fn open_submenu(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        tracking: &mut MenuTracking,
+        level: usize,
+        select_first: bool,
+    ) -> Result<(), EmulatorError> {
+        if tracking.menus.len() > level + 1 {
+            return Ok(());
+        }
+        let open_menu = &tracking.menus[level];
+        let index = match open_menu.selected {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let popup = self
+            .with_menu_item(open_menu.h_menu, index, |item| {
+                item.popup().filter(|_| item.is_enabled())
+            })
+            .flatten();
+        let popup = match popup {
+            Some(popup) => popup,
+            None => return Ok(()),
+        };
+        let item_rect = open_menu.item_rects[index];
+        let position = if tracking.bar && level == 0 {
+            Point::new(item_rect.left, item_rect.bottom)
+        } else {
+            Point::new(item_rect.right, item_rect.top - 1)
+        };
+        self.open_popup_menu(accessor, tracking, popup, index, position)?;
+        if select_first && tracking.menus.len() > level + 1 {
+            self.select_next_menu_item(accessor, tracking, level + 1, true)?;
+        }
+        Ok(())
+    }
+
+    /// Opens a popup menu at a position on the screen, after WM_INITMENUPOPUP lets the window
+    /// update it. The popup menu is moved to stay on the screen.
+    pub fn open_popup_menu(
+        &self,
+        accessor: &mut EmulatorAccessor,
+        tracking: &mut MenuTracking,
+        h_menu: Handle,
+        index: usize,
+        position: Point,
+    ) -> Result<(), EmulatorError> {
+        self.call_wndproc_sync(
+            accessor,
+            tracking.proc,
+            tracking.h_wnd,
+            MessageType::InitMenuPopup.into(),
+            h_menu.as_u16(),
+            encode_u16_u16_to_u32(index as u16, tracking.system.into()),
+        )?;
+        let objects = self.read_objects();
+        let menu = match objects.user.get(h_menu) {
+            Some(UserObject::Menu(menu)) => menu,
+            _ => return Ok(()),
+        };
+        let (item_rects, (width, height)) = popup_layout(menu);
+        let position = Point::new(
+            position.x.min(SCREEN_WIDTH - width).max(0),
+            position.y.min(SCREEN_HEIGHT - height).max(0),
+        );
+        let mut bitmap = Bitmap::new(width, height);
+        paint_popup(&mut bitmap, menu, None, &|color| {
+            self.get_system_color(color)
+        });
+        objects.write_window_manager().open_popup(position, bitmap);
+        tracking.menus.push(OpenMenu {
+            h_menu,
+            rect: Rect {
+                left: position.x,
+                top: position.y,
+                right: position.x + width,
+                bottom: position.y + height,
+            },
+            item_rects: item_rects
+                .into_iter()
+                .map(|rect| rect.offset(position.x, position.y))
+                .collect(),
+            selected: None,
+        });
+        Ok(())
+    }
+
+    /// Closes the popup menus that opened from an open menu.
+    fn close_menus_above(&self, tracking: &mut MenuTracking, level: usize) {
+        let objects = self.read_objects();
+        let mut window_manager = objects.write_window_manager();
+        while tracking.menus.len() > level + 1 {
+            tracking.menus.pop();
+            window_manager.close_popup();
+        }
+    }
+
+    /// Draws an open menu again, after its selection changed.
+    fn paint_open_menu(&self, tracking: &MenuTracking, level: usize) {
+        let open_menu = &tracking.menus[level];
+        if tracking.bar && level == 0 {
+            self.paint_menu_bar(tracking.h_wnd, open_menu.selected);
+            return;
+        }
+        let objects = self.read_objects();
+        if let Some(UserObject::Menu(menu)) = objects.user.get(open_menu.h_menu) {
+            let mut window_manager = objects.write_window_manager();
+            // The menu bar is not one of the popup menus
+            let popup = level - usize::from(tracking.bar);
+            if let Some(bitmap) = window_manager.popup_bitmap(popup) {
+                paint_popup(bitmap, menu, open_menu.selected, &|color| {
+                    self.get_system_color(color)
+                });
+            }
+        }
+    }
+}
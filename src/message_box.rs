@@ -0,0 +1,239 @@
+//! Message boxes: the buttons and icon that the style of a message box asks for, the dialog box
+//! template it is laid out in, and how it gets answered without showing it.
+
+use crate::byte_string::HeapByteString;
+use crate::constants::{
+    ButtonType, DialogStyles, MessageBoxIcon, MessageBoxType, StaticStyles, StaticType, SystemIcon,
+    WindowStyles, IDABORT, IDCANCEL, IDIGNORE, IDNO, IDOK, IDRETRY, IDYES,
+};
+use crate::controls::{self, LINE_HEIGHT};
+use crate::dialog::{DialogItem, DialogTemplate};
+use crate::font;
+use crate::non_client::ICON_SIZE;
+use crate::two_d::Rect;
+
+/// The caption of a message box that has none.
+const DEFAULT_CAPTION: &[u8] = b"Error";
+/// The widest the text of a message box gets before it wraps, in characters.
+const MAX_TEXT_COLUMNS: i16 = 60;
+/// The space around and between the parts of a message box, in dialog units.
+const MARGIN: i16 = 8;
+const BUTTON_WIDTH: i16 = 40;
+const BUTTON_HEIGHT: i16 = 14;
+const BUTTON_GAP: i16 = 6;
+
+/// How message boxes get answered without being shown, such as when the emulator runs
+/// without anyone to click them.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AutomaticAnswer {
+    /// Chooses the default button.
+    DefaultButton,
+    /// Chooses the button with this command, or the default button if the message box has
+    /// no such button.
+    Button(u16),
+}
+
+impl AutomaticAnswer {
+    /// Reads an answer as given on the command line: "default", or the name of a button such
+    /// as "ok", "cancel" or "yes".
+    pub fn parse(answer: &str) -> Option<Self> {
+        let id = match answer.to_ascii_lowercase().as_str() {
+            "default" => return Some(Self::DefaultButton),
+            "ok" => IDOK,
+            "cancel" => IDCANCEL,
+            "abort" => IDABORT,
+            "retry" => IDRETRY,
+            "ignore" => IDIGNORE,
+            "yes" => IDYES,
+            "no" => IDNO,
+            _ => return None,
+        };
+        Some(Self::Button(id))
+    }
+
+    /// The command that a message box with the given style returns for this answer.
+    pub fn answer(self, style: u16) -> u16 {
+        let buttons = buttons(message_box_type(style));
+        match self {
+            Self::Button(id) if buttons.iter().any(|&(button_id, _)| button_id == id) => id,
+            _ => default_button(style),
+        }
+    }
+}
+
+/// The kind of message box that a message box style asks for. Unknown kinds only have OK.
+pub fn message_box_type(style: u16) -> MessageBoxType {
+    match style & 0xf {
+        0x1 => MessageBoxType::OkCancel,
+        0x2 => MessageBoxType::AbortRetryIgnore,
+        0x3 => MessageBoxType::YesNoCancel,
+        0x4 => MessageBoxType::YesNo,
+        0x5 => MessageBoxType::RetryCancel,
+        _ => MessageBoxType::Ok,
+    }
+}
+
+fn message_box_icon(style: u16) -> Option<MessageBoxIcon> {
+    match style & 0xf0 {
+        0x10 => Some(MessageBoxIcon::Hand),
+        0x20 => Some(MessageBoxIcon::Question),
+        0x30 => Some(MessageBoxIcon::Exclamation),
+        0x40 => Some(MessageBoxIcon::Asterisk),
+        _ => None,
+    }
+}
+
+/// The commands and labels of the buttons of a kind of message box, from left to right.
+pub fn buttons(message_box_type: MessageBoxType) -> &'static [(u16, &'static [u8])] {
+    match message_box_type {
+        MessageBoxType::Ok => &[(IDOK, b"OK")],
+        MessageBoxType::OkCancel => &[(IDOK, b"OK"), (IDCANCEL, b"Cancel")],
+        MessageBoxType::AbortRetryIgnore => &[
+            (IDABORT, b"&Abort"),
+            (IDRETRY, b"&Retry"),
+            (IDIGNORE, b"&Ignore"),
+        ],
+        MessageBoxType::YesNoCancel => &[(IDYES, b"&Yes"), (IDNO, b"&No"), (IDCANCEL, b"Cancel")],
+        MessageBoxType::YesNo => &[(IDYES, b"&Yes"), (IDNO, b"&No")],
+        MessageBoxType::RetryCancel => &[(IDRETRY, b"&Retry"), (IDCANCEL, b"Cancel")],
+    }
+}
+
+/// The command of the default button: MB_DEFBUTTON2 and MB_DEFBUTTON3 pick the second and
+/// third button, and otherwise it is the first.
+pub fn default_button(style: u16) -> u16 {
+    let buttons = buttons(message_box_type(style));
+    let index = ((style >> 8) & 0xf) as usize;
+    buttons.get(index).unwrap_or(&buttons[0]).0
+}
+
+/// Lays out a message box as a dialog box template: the icon on the left, the text wrapped
+/// next to it, and the buttons in a row below. The message box is centered on a screen of the
+/// given size in pixels.
+pub fn template(
+    text: &[u8],
+    caption: Option<&[u8]>,
+    style: u16,
+    (screen_width, screen_height): (i16, i16),
+) -> DialogTemplate {
+    let lines = controls::wrap_text(text, MAX_TEXT_COLUMNS * font::GLYPH_ADVANCE);
+    let columns = lines
+        .iter()
+        .map(|&(start, end)| (end - start) as i16)
+        .max()
+        .unwrap_or(0);
+    let text_width = horizontal_units(columns * font::GLYPH_ADVANCE);
+    let text_height = vertical_units(lines.len() as i16 * LINE_HEIGHT);
+
+    let mut items = Vec::new();
+    let mut text_left = MARGIN;
+    let mut content_height = text_height;
+    if let Some(icon) = message_box_icon(style) {
+        let system_icon = match icon {
+            MessageBoxIcon::Hand => SystemIcon::Hand,
+            MessageBoxIcon::Question => SystemIcon::Question,
+            MessageBoxIcon::Exclamation => SystemIcon::Exclamation,
+            MessageBoxIcon::Asterisk => SystemIcon::Asterisk,
+        };
+        let (icon_width, icon_height) = (horizontal_units(ICON_SIZE), vertical_units(ICON_SIZE));
+        items.push(item(
+            Rect {
+                left: MARGIN,
+                top: MARGIN,
+                right: MARGIN + icon_width,
+                bottom: MARGIN + icon_height,
+            },
+            0,
+            u16::from(StaticType::Icon).into(),
+            b"STATIC",
+            format!("#{}", u16::from(system_icon)).as_bytes(),
+        ));
+        text_left += icon_width + MARGIN;
+        content_height = content_height.max(icon_height);
+    }
+    // The text is vertically centered next to the icon
+    let text_top = MARGIN + (content_height - text_height) / 2;
+    items.push(item(
+        Rect {
+            left: text_left,
+            top: text_top,
+            right: text_left + text_width,
+            bottom: text_top + text_height,
+        },
+        0xffff,
+        (u16::from(StaticType::Left) | StaticStyles::NOPREFIX.bits()).into(),
+        b"STATIC",
+        text,
+    ));
+
+    let buttons = buttons(message_box_type(style));
+    let default_id = default_button(style);
+    let count = buttons.len() as i16;
+    let row_width = count * BUTTON_WIDTH + (count - 1) * BUTTON_GAP;
+    let width = (text_left + text_width + MARGIN).max(row_width + 2 * MARGIN);
+    let buttons_top = MARGIN + content_height + MARGIN;
+    let height = buttons_top + BUTTON_HEIGHT + MARGIN;
+    let mut left = (width - row_width) / 2;
+    for (index, &(id, label)) in buttons.iter().enumerate() {
+        let button_type = if id == default_id {
+            ButtonType::DefPushButton
+        } else {
+            ButtonType::PushButton
+        };
+        // The buttons are a group that Tab goes through
+        let mut button_style = WindowStyles::TABSTOP.bits() | u16::from(button_type) as u32;
+        if index == 0 {
+            button_style |= WindowStyles::GROUP.bits();
+        }
+        items.push(item(
+            Rect {
+                left,
+                top: buttons_top,
+                right: left + BUTTON_WIDTH,
+                bottom: buttons_top + BUTTON_HEIGHT,
+            },
+            id,
+            button_style,
+            b"BUTTON",
+            label,
+        ));
+        left += BUTTON_WIDTH + BUTTON_GAP;
+    }
+
+    let left = (horizontal_units(screen_width) - width) / 2;
+    let top = (vertical_units(screen_height) - height) / 2;
+    DialogTemplate {
+        style: (WindowStyles::POPUP | WindowStyles::CAPTION | WindowStyles::SYSMENU).bits()
+            | (DialogStyles::MODALFRAME | DialogStyles::ABSALIGN).bits(),
+        rect: Rect {
+            left,
+            top,
+            right: left + width,
+            bottom: top + height,
+        },
+        menu: None,
+        class: None,
+        caption: HeapByteString::from(caption.unwrap_or(DEFAULT_CAPTION).into()),
+        items,
+    }
+}
+
+fn item(rect: Rect, id: u16, style: u32, class: &[u8], text: &[u8]) -> DialogItem {
+    DialogItem {
+        rect,
+        id,
+        style: style | (WindowStyles::CHILD | WindowStyles::VISIBLE).bits(),
+        class: HeapByteString::from(class.into()),
+        text: HeapByteString::from(text.into()),
+    }
+}
+
+/// The horizontal dialog units that cover at least the given number of pixels.
+fn horizontal_units(pixels: i16) -> i16 {
+    (pixels * 4 + font::GLYPH_ADVANCE - 1) / font::GLYPH_ADVANCE
+}
+
+/// The vertical dialog units that cover at least the given number of pixels.
+fn vertical_units(pixels: i16) -> i16 {
+    (pixels * 8 + font::GLYPH_HEIGHT - 1) / font::GLYPH_HEIGHT
+}
@@ -1,8 +1,12 @@
 use crate::constants::MessageType;
 use crate::handle_table::Handle;
+use crate::hook::HookCall;
 use crate::two_d::Point;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use crate::window_manager::ProcessId;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 
+#[derive(Copy, Clone)]
 pub struct WindowMessage {
     pub h_wnd: Handle,
     pub message: MessageType,
@@ -12,35 +16,199 @@ pub struct WindowMessage {
     pub point: Point,
 }
 
+/// A message the way an application gets it in a MSG structure. Unlike the input, it can be
+/// any message, including the ones that the application defines itself.
+#[derive(Copy, Clone)]
+pub struct PostedMessage {
+    pub h_wnd: Handle,
+    pub message: u16,
+    pub w_param: u16,
+    pub l_param: u32,
+    pub time: u32,
+    pub point: Point,
+}
+
+impl From<WindowMessage> for PostedMessage {
+    fn from(message: WindowMessage) -> Self {
+        Self {
+            h_wnd: message.h_wnd,
+            message: message.message.into(),
+            w_param: message.w_param,
+            l_param: message.l_param,
+            time: message.time,
+            point: message.point,
+        }
+    }
+}
+
+/// Which messages GetMessage and PeekMessage take: only those for a window, unless the window
+/// is null, and only those in a range of message numbers, unless both ends of it are zero.
+#[derive(Copy, Clone)]
+pub struct MessageFilter {
+    pub h_wnd: Handle,
+    pub min: u16,
+    pub max: u16,
+}
+
+impl MessageFilter {
+    /// Takes every message for a window, or every message at all for the null handle.
+    pub fn window(h_wnd: Handle) -> Self {
+        Self {
+            h_wnd,
+            min: 0,
+            max: 0,
+        }
+    }
+
+    pub fn accepts_message(&self, message: u16) -> bool {
+        (self.min == 0 && self.max == 0) || (self.min..=self.max).contains(&message)
+    }
+
+    pub fn accepts(&self, message: &PostedMessage) -> bool {
+        // WM_QUIT is for no window in particular
+        let for_window = self.h_wnd == Handle::null()
+            || message.h_wnd == self.h_wnd
+            || message.message == MessageType::Quit.into();
+        for_window && self.accepts_message(message.message)
+    }
+}
+
+/// The result of a window procedure or a hook, and the memory that l_param pointed to once it
+/// returned.
+type Answer = (u32, Vec<u8>);
+
+/// Where a task that sent a message to a window of another task, or called a hook of another
+/// task, gets the answer.
+#[derive(Clone, Default)]
+pub struct Reply(Arc<Mutex<Option<Answer>>>);
+
+impl Reply {
+    pub fn set(&self, result: u32, data: Vec<u8>) {
+        *self.0.lock().unwrap() = Some((result, data));
+    }
+
+    pub fn take(&self) -> Option<Answer> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// What another task wants this task to do for it.
+pub enum SentCall {
+    /// A message for a window of this task.
+    Message(PostedMessage),
+    /// A call of a hook that this task installed, for the messages of the other task.
+    Hook(HookCall),
+}
+
+/// A message or a hook call that another task sent to this task, and waits for the reply to.
+pub struct SentMessage {
+    pub call: SentCall,
+    /// A copy of the memory that l_param points to in the sender, if it is a pointer.
+    pub data: Option<Vec<u8>>,
+    pub sender: ProcessId,
+    pub reply: Reply,
+}
+
+/// The messages posted to a task, the messages other tasks sent to it, and the input from the
+/// screen that still needs to be turned into messages. Input is only taken once the posted
+/// messages are handled.
 pub struct MessageQueue {
-    tx: Sender<WindowMessage>,
-    rx: Receiver<WindowMessage>,
+    messages: Mutex<VecDeque<PostedMessage>>,
+    sent: Mutex<VecDeque<SentMessage>>,
+    input: Mutex<VecDeque<WindowMessage>>,
 }
 
 impl MessageQueue {
     pub fn new() -> Self {
-        let (tx, rx) = channel::<WindowMessage>();
-        Self { tx, rx }
+        Self {
+            messages: Mutex::new(VecDeque::new()),
+            sent: Mutex::new(VecDeque::new()),
+            input: Mutex::new(VecDeque::new()),
+        }
     }
 
-    pub fn send(&self, msg: WindowMessage) -> bool {
-        self.tx.send(msg).is_ok()
+    /// Queues mouse input for the top-level window under the cursor.
+    /// The point is in screen coordinates, and consecutive mouse moves are combined.
+    pub fn post_input(&self, input: WindowMessage) {
+        let mut queued_input = self.input.lock().unwrap();
+        match queued_input.back_mut() {
+            Some(last)
+                if last.message == MessageType::MouseMove
+                    && input.message == MessageType::MouseMove
+                    && last.h_wnd == input.h_wnd =>
+            {
+                *last = input
+            }
+            _ => queued_input.push_back(input),
+        }
     }
 
-    pub fn receive(&self, h_wnd: Handle) -> Option<WindowMessage> {
-        loop {
-            return if let Ok(message) = self.rx.recv() {
-                // h_wnd acts as a filter, if the handle does not match, the message must be discarded
-                // Null handle means that no filtering should take place.
-                // Note: thread messages did not seem to exist back then...
-                if h_wnd != Handle::null() && message.h_wnd != h_wnd {
-                    continue;
-                }
+    pub fn receive_input(&self) -> Option<WindowMessage> {
+        self.input.lock().unwrap().pop_front()
+    }
 
-                Some(message)
-            } else {
-                None
-            };
-        }
+    pub fn post(&self, message: impl Into<PostedMessage>) {
+        self.messages.lock().unwrap().push_back(message.into());
+    }
+
+    /// Takes the first message that the filter accepts out of the queue, without waiting.
+    /// The messages that it doesn't accept stay in the queue.
+    pub fn receive(&self, filter: MessageFilter) -> Option<PostedMessage> {
+        let mut messages = self.messages.lock().unwrap();
+        let index = messages
+            .iter()
+            .position(|message| filter.accepts(message))?;
+        messages.remove(index)
+    }
+
+    /// Like `receive`, but leaves the message in the queue.
+    pub fn peek(&self, filter: MessageFilter) -> Option<PostedMessage> {
+        let messages = self.messages.lock().unwrap();
+        messages
+            .iter()
+            .copied()
+            .find(|message| filter.accepts(message))
+    }
+
+    /// Queues a message that another task sent, which gets the result through the reply.
+    pub fn send_from(&self, sender: ProcessId, call: SentCall, data: Option<Vec<u8>>) -> Reply {
+        let reply = Reply::default();
+        self.sent.lock().unwrap().push_back(SentMessage {
+            call,
+            data,
+            sender,
+            reply: reply.clone(),
+        });
+        reply
+    }
+
+    pub fn receive_sent(&self) -> Option<SentMessage> {
+        self.sent.lock().unwrap().pop_front()
+    }
+
+    /// Answers the messages that other tasks still wait for with 0, once the task has ended.
+    /// Returns the tasks that have to be woken up.
+    pub fn abandon_sent_messages(&self) -> Vec<ProcessId> {
+        self.sent
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|sent| {
+                sent.reply.set(0, Vec::new());
+                sent.sender
+            })
+            .collect()
+    }
+
+    /// Drops the messages and the input for a window that got destroyed.
+    pub fn discard_messages_of(&self, h_wnd: Handle) {
+        self.messages
+            .lock()
+            .unwrap()
+            .retain(|message| message.h_wnd != h_wnd);
+        self.input
+            .lock()
+            .unwrap()
+            .retain(|input| input.h_wnd != h_wnd);
     }
 }
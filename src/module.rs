@@ -1,18 +1,34 @@
 use crate::constants::{GDI_INT_VECTOR, KERNEL_INT_VECTOR, KEYBOARD_INT_VECTOR, USER_INT_VECTOR};
 use crate::emulator_error::EmulatorError;
+use crate::handle_table::{GenericHandle, Handle};
 use crate::memory::SegmentAndOffset;
+use crate::segment_bump_allocator::SegmentBumpAllocator;
 use crate::Memory;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 
 pub struct BaseModule {
     flat_address: Cell<u32>,
     last_write_offset: Cell<u32>,
     int_vector: u8,
+    procedures: RefCell<HashMap<u16, SegmentAndOffset>>,
 }
 
 pub trait Module {
-    fn argument_bytes_of_procedure(&self, procedure: u16) -> u16;
+    /// How many bytes of arguments a procedure pops off the stack, or None if the module
+    /// doesn't have the procedure.
+    fn argument_bytes_of_procedure(&self, procedure: u16) -> Option<u16>;
+    /// The names of the procedures that can be looked up by name, with their ordinals.
+    fn exported_names(&self) -> &'static [(&'static str, u16)];
     fn base_module(&self) -> &BaseModule;
+
+    /// The ordinal of a procedure that the module exports by name, ignoring case.
+    fn ordinal_of_procedure(&self, name: &[u8]) -> Option<u16> {
+        self.exported_names()
+            .iter()
+            .find(|(exported_name, _)| exported_name.as_bytes().eq_ignore_ascii_case(name))
+            .map(|&(_, ordinal)| ordinal)
+    }
 }
 
 impl BaseModule {
@@ -22,9 +38,15 @@ impl BaseModule {
             flat_address: Cell::new(flat_address),
             last_write_offset: Cell::new(0),
             int_vector,
+            procedures: RefCell::new(HashMap::new()),
         }
     }
 
+    /// The segment holding the procedures, which doubles as the instance handle of the module.
+    pub fn segment(&self) -> u16 {
+        (self.flat_address.get() >> 4) as u16
+    }
+
     fn write_syscall_dispatch_byte(
         &self,
         memory: &mut Memory,
@@ -35,16 +57,6 @@ impl BaseModule {
         memory.write_8(index, data)
     }
 
-    pub fn write_syscall_proc_return_trampoline(
-        &self,
-        memory: &mut Memory,
-    ) -> Result<(), EmulatorError> {
-        // pop ax
-        self.write_syscall_dispatch_byte(memory, 0x58)?;
-        // ret
-        self.write_syscall_dispatch_byte(memory, 0xC3)
-    }
-
     fn write_syscall_dispatch(
         &self,
         memory: &mut Memory,
@@ -78,12 +90,107 @@ impl BaseModule {
         procedure: u16,
         argument_bytes: u16,
     ) -> Result<SegmentAndOffset, EmulatorError> {
-        // TODO: deduplicate them?
+        if let Some(&segment_and_offset) = self.procedures.borrow().get(&procedure) {
+            return Ok(segment_and_offset);
+        }
         let flat_address = self.write_syscall_dispatch(memory, procedure, argument_bytes)?;
-        Ok(memory.segment_and_offset(flat_address))
+        let segment_and_offset = SegmentAndOffset {
+            segment: self.segment(),
+            offset: (flat_address - self.flat_address.get()) as u16,
+        };
+        self.procedures
+            .borrow_mut()
+            .insert(procedure, segment_and_offset);
+        Ok(segment_and_offset)
+    }
+}
+
+/// The modules we emulate ourselves. Every task gets one copy of them, shared between the
+/// program and the libraries it loads.
+pub struct BuiltinModules {
+    kernel: KernelModule,
+    user: UserModule,
+    gdi: GdiModule,
+    keyboard: KeyboardModule,
+}
+
+impl BuiltinModules {
+    pub fn new(segment_bump_allocator: &mut SegmentBumpAllocator) -> Option<Self> {
+        let mut allocate = || Some((segment_bump_allocator.allocate(0x10000)? as u32) * 0x10);
+        Some(Self {
+            kernel: KernelModule::new(allocate()?),
+            user: UserModule::new(allocate()?),
+            gdi: GdiModule::new(allocate()?),
+            keyboard: KeyboardModule::new(allocate()?),
+        })
+    }
+
+    fn modules(&self) -> [(&[u8], &dyn Module); 4] {
+        [
+            (b"KERNEL", &self.kernel),
+            (b"USER", &self.user),
+            (b"GDI", &self.gdi),
+            (b"KEYBOARD", &self.keyboard),
+        ]
+    }
+
+    /// Finds a built-in module by its name, ignoring case.
+    pub fn by_name(&self, module_name: &[u8]) -> Option<&dyn Module> {
+        self.modules()
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(module_name))
+            .map(|(_, module)| module)
+    }
+
+    pub fn by_instance(&self, h_instance: Handle) -> Option<&dyn Module> {
+        self.modules()
+            .into_iter()
+            .find(|(_, module)| module.base_module().segment() == h_instance.as_u16())
+            .map(|(_, module)| module)
+    }
+
+    pub fn user(&self) -> &dyn Module {
+        &self.user
     }
 }
 
+/// The procedures of KERNEL that GetProcAddress can find by name.
+const KERNEL_EXPORTED_NAMES: &[(&str, u16)] = &[
+    ("GETVERSION", 3),
+    ("LOCALINIT", 4),
+    ("LOCALALLOC", 5),
+    ("LOCALFREE", 7),
+    ("GLOBALALLOC", 15),
+    ("GLOBALFREE", 17),
+    ("GLOBALLOCK", 18),
+    ("GLOBALUNLOCK", 19),
+    ("GLOBALSIZE", 20),
+    ("LOCKSEGMENT", 23),
+    ("UNLOCKSEGMENT", 24),
+    ("YIELD", 29),
+    ("WAITEVENT", 30),
+    ("LOADMODULE", 45),
+    ("GETMODULEHANDLE", 47),
+    ("GETMODULEUSAGE", 48),
+    ("GETMODULEFILENAME", 49),
+    ("GETPROCADDRESS", 50),
+    ("MAKEPROCINSTANCE", 51),
+    ("GETPROFILEINT", 57),
+    ("GETPROFILESTRING", 58),
+    ("FINDRESOURCE", 60),
+    ("LOADRESOURCE", 61),
+    ("LSTRCAT", 89),
+    ("LSTRLEN", 90),
+    ("INITTASK", 91),
+    ("LOADLIBRARY", 95),
+    ("FREELIBRARY", 96),
+    ("GETPRIVATEPROFILEINT", 127),
+    ("GETPRIVATEPROFILESTRING", 128),
+    ("GETDOSENVIRONMENT", 131),
+    ("GETWINFLAGS", 132),
+    ("WINEXEC", 166),
+];
+
 pub struct KernelModule {
     base_module: BaseModule,
 }
@@ -97,21 +204,25 @@ impl KernelModule {
 }
 
 impl Module for KernelModule {
-    fn argument_bytes_of_procedure(&self, procedure: u16) -> u16 {
-        match procedure {
-            91 | 102 => 0,
-            7 | 18 | 19 | 23 | 24 | 30 => 2,
-            5 | 61 | 90 => 4,
-            50 | 51 => 6,
-            54 | 89 => 8,
+    fn argument_bytes_of_procedure(&self, procedure: u16) -> Option<u16> {
+        Some(match procedure {
+            3 | 29 | 91 | 102 | 131 | 132 => 0,
+            7 | 17 | 18 | 19 | 20 | 23 | 24 | 30 | 48 | 96 => 2,
+            5 | 47 | 61 | 90 | 95 => 4,
+            4 | 15 | 50 | 51 | 166 => 6,
+            45 | 49 | 54 | 89 => 8,
             57 | 60 => 10,
             127 => 14,
             129 => 16,
             58 => 18,
             128 => 22,
             178 => 0, /* TODO: don't know! */
-            _ => unimplemented!("procedure {}", procedure),
-        }
+            _ => return None,
+        })
+    }
+
+    fn exported_names(&self) -> &'static [(&'static str, u16)] {
+        KERNEL_EXPORTED_NAMES
     }
 
     fn base_module(&self) -> &BaseModule {
@@ -119,6 +230,161 @@ impl Module for KernelModule {
     }
 }
 
+/// The procedures of USER that GetProcAddress can find by name.
+const USER_EXPORTED_NAMES: &[(&str, u16)] = &[
+    ("MESSAGEBOX", 1),
+    ("INITAPP", 5),
+    ("POSTQUITMESSAGE", 6),
+    ("SETTIMER", 10),
+    ("KILLTIMER", 12),
+    ("SETFOCUS", 22),
+    ("GETFOCUS", 23),
+    ("CLIENTTOSCREEN", 28),
+    ("SCREENTOCLIENT", 29),
+    ("WINDOWFROMPOINT", 30),
+    ("ISICONIC", 31),
+    ("GETWINDOWRECT", 32),
+    ("GETCLIENTRECT", 33),
+    ("ENABLEWINDOW", 34),
+    ("ISWINDOWENABLED", 35),
+    ("SETWINDOWTEXT", 37),
+    ("BEGINPAINT", 39),
+    ("ENDPAINT", 40),
+    ("CREATEWINDOW", 41),
+    ("SHOWWINDOW", 42),
+    ("GETPARENT", 46),
+    ("ISWINDOW", 47),
+    ("ISCHILD", 48),
+    ("ISWINDOWVISIBLE", 49),
+    ("FINDWINDOW", 50),
+    ("DESTROYWINDOW", 53),
+    ("ENUMWINDOWS", 54),
+    ("ENUMCHILDWINDOWS", 55),
+    ("MOVEWINDOW", 56),
+    ("REGISTERCLASS", 57),
+    ("SETACTIVEWINDOW", 59),
+    ("GETACTIVEWINDOW", 60),
+    ("SETSCROLLPOS", 62),
+    ("GETSCROLLPOS", 63),
+    ("SETSCROLLRANGE", 64),
+    ("GETSCROLLRANGE", 65),
+    ("GETDC", 66),
+    ("RELEASEDC", 68),
+    ("SETCURSOR", 69),
+    ("SETRECT", 72),
+    ("OFFSETRECT", 77),
+    ("INFLATERECT", 78),
+    ("FILLRECT", 81),
+    ("DIALOGBOX", 87),
+    ("ENDDIALOG", 88),
+    ("CREATEDIALOG", 89),
+    ("ISDIALOGMESSAGE", 90),
+    ("GETDLGITEM", 91),
+    ("SETDLGITEMTEXT", 92),
+    ("GETDLGITEMTEXT", 93),
+    ("SETDLGITEMINT", 94),
+    ("GETDLGITEMINT", 95),
+    ("CHECKRADIOBUTTON", 96),
+    ("CHECKDLGBUTTON", 97),
+    ("ISDLGBUTTONCHECKED", 98),
+    ("SENDDLGITEMMESSAGE", 101),
+    ("GETKEYSTATE", 106),
+    ("DEFWINDOWPROC", 107),
+    ("GETMESSAGE", 108),
+    ("PEEKMESSAGE", 109),
+    ("POSTMESSAGE", 110),
+    ("SENDMESSAGE", 111),
+    ("TRANSLATEMESSAGE", 113),
+    ("DISPATCHMESSAGE", 114),
+    ("GETMESSAGEPOS", 119),
+    ("GETMESSAGETIME", 120),
+    ("SETWINDOWSHOOK", 121),
+    ("CALLWINDOWPROC", 122),
+    ("CALLMSGFILTER", 123),
+    ("UPDATEWINDOW", 124),
+    ("INVALIDATERECT", 125),
+    ("INVALIDATERGN", 126),
+    ("VALIDATERECT", 127),
+    ("VALIDATERGN", 128),
+    ("GETCLASSWORD", 129),
+    ("SETCLASSWORD", 130),
+    ("GETCLASSLONG", 131),
+    ("SETCLASSLONG", 132),
+    ("GETWINDOWWORD", 133),
+    ("SETWINDOWWORD", 134),
+    ("GETWINDOWLONG", 135),
+    ("SETWINDOWLONG", 136),
+    ("OPENCLIPBOARD", 137),
+    ("CLOSECLIPBOARD", 138),
+    ("EMPTYCLIPBOARD", 139),
+    ("GETCLIPBOARDOWNER", 140),
+    ("SETCLIPBOARDDATA", 141),
+    ("GETCLIPBOARDDATA", 142),
+    ("COUNTCLIPBOARDFORMATS", 143),
+    ("ENUMCLIPBOARDFORMATS", 144),
+    ("REGISTERCLIPBOARDFORMAT", 145),
+    ("GETCLIPBOARDFORMATNAME", 146),
+    ("LOADMENU", 150),
+    ("CREATEMENU", 151),
+    ("DESTROYMENU", 152),
+    ("CHECKMENUITEM", 154),
+    ("ENABLEMENUITEM", 155),
+    ("GETSYSTEMMENU", 156),
+    ("GETMENU", 157),
+    ("SETMENU", 158),
+    ("GETSUBMENU", 159),
+    ("DRAWMENUBAR", 160),
+    ("GETMENUSTRING", 161),
+    ("CREATECARET", 163),
+    ("DESTROYCARET", 164),
+    ("SETCARETPOS", 165),
+    ("HIDECARET", 166),
+    ("SHOWCARET", 167),
+    ("SETCARETBLINKTIME", 168),
+    ("GETCARETBLINKTIME", 169),
+    ("LOADCURSOR", 173),
+    ("LOADSTRING", 176),
+    ("LOADACCELERATORS", 177),
+    ("TRANSLATEACCELERATOR", 178),
+    ("GETSYSTEMMETRICS", 179),
+    ("GETSYSCOLOR", 180),
+    ("GETCARETPOS", 183),
+    ("GETUPDATERECT", 190),
+    ("CHILDWINDOWFROMPOINT", 191),
+    ("INSENDMESSAGE", 192),
+    ("ISCLIPBOARDFORMATAVAILABLE", 193),
+    ("GETKEYBOARDSTATE", 222),
+    ("SETKEYBOARDSTATE", 223),
+    ("GETTOPWINDOW", 229),
+    ("GETNEXTWINDOW", 230),
+    ("SETWINDOWPOS", 232),
+    ("SETPARENT", 233),
+    ("UNHOOKWINDOWSHOOK", 234),
+    ("DEFHOOKPROC", 235),
+    ("DIALOGBOXPARAM", 239),
+    ("CREATEDIALOGPARAM", 241),
+    ("GETASYNCKEYSTATE", 249),
+    ("GETMENUSTATE", 250),
+    ("MAPWINDOWPOINTS", 258),
+    ("GETWINDOW", 262),
+    ("GETMENUITEMCOUNT", 263),
+    ("GETMENUITEMID", 264),
+    ("ISZOOMED", 272),
+    ("GETDESKTOPWINDOW", 286),
+    ("SETWINDOWSHOOKEX", 291),
+    ("UNHOOKWINDOWSHOOKEX", 292),
+    ("CALLNEXTHOOKEX", 293),
+    ("DEFDLGPROC", 308),
+    ("INSERTMENU", 410),
+    ("APPENDMENU", 411),
+    ("REMOVEMENU", 412),
+    ("DELETEMENU", 413),
+    ("MODIFYMENU", 414),
+    ("CREATEPOPUPMENU", 415),
+    ("TRACKPOPUPMENU", 416),
+    ("_WSPRINTF", 420),
+];
+
 pub struct UserModule {
     base_module: BaseModule,
 }
@@ -132,20 +398,46 @@ impl UserModule {
 }
 
 impl Module for UserModule {
-    fn argument_bytes_of_procedure(&self, procedure: u16) -> u16 {
-        match procedure {
-            19 | 243 => 0,
-            5 | 6 | 18 | 31 | 59 | 66 | 69 | 124 | 157 | 179 | 180 | 249 | 272 | 287 => 2,
-            12 | 42 | 57 | 68 | 113 | 114 | 135 | 156 => 4,
-            32 | 33 | 37 | 39 | 40 | 134 | 154 | 155 | 173 | 174 => 6,
-            77 | 78 | 81 | 125 | 136 => 8,
-            0xFFFF | 10 | 107 | 108 | 110 | 176 | 411 => 10,
-            1 | 72 | 87 => 12,
-            232 => 14,
+    fn argument_bytes_of_procedure(&self, procedure: u16) -> Option<u16> {
+        Some(match procedure {
+            19 | 23 | 60 | 119 | 120 | 138 | 139 | 140 | 143 | 151 | 164 | 169 | 192 | 243
+            | 286 | 415 => 0,
+            5 | 6 | 18 | 22 | 31 | 35 | 46 | 47 | 49 | 53 | 59 | 66 | 69 | 106 | 124 | 137
+            | 142 | 144 | 152 | 157 | 160 | 166 | 167 | 168 | 179 | 180 | 193 | 229 | 249 | 263
+            | 272 | 287 => 2,
+            12 | 30 | 34 | 42 | 48 | 57 | 63 | 68 | 88 | 91 | 98 | 113 | 114 | 128 | 129 | 131
+            | 133 | 135 | 141 | 145 | 156 | 158 | 159 | 165 | 183 | 222 | 223 | 230 | 233 | 262
+            | 264 | 292 => 4,
+            28 | 29 | 32 | 33 | 37 | 39 | 40 | 90 | 97 | 121 | 123 | 126 | 127 | 130 | 134
+            | 150 | 154 | 155 | 173 | 174 | 177 | 191 | 234 | 250 | 412 | 413 => 6,
+            50 | 54 | 62 | 77 | 78 | 81 | 92 | 94 | 96 | 125 | 132 | 136 | 146 | 163 | 178
+            | 190 => 8,
+            0xFFF9..=0xFFFF
+            | 10
+            | 55
+            | 64
+            | 93
+            | 95
+            | 107
+            | 108
+            | 110
+            | 111
+            | 176
+            | 258
+            | 291
+            | 308
+            | 411 => 10,
+            1 | 56 | 65 | 72 | 87 | 89 | 101 | 109 | 161 | 235 | 293 | 410 | 414 => 12,
+            122 | 232 => 14,
+            239 | 241 | 416 => 16,
             41 => 30,
             420 => 0, // WSPRINTF's caller cleans up the arguments
-            _ => unimplemented!("procedure {}", procedure),
-        }
+            _ => return None,
+        })
+    }
+
+    fn exported_names(&self) -> &'static [(&'static str, u16)] {
+        USER_EXPORTED_NAMES
     }
 
     fn base_module(&self) -> &BaseModule {
@@ -153,6 +445,27 @@ impl Module for UserModule {
     }
 }
 
+/// The procedures of GDI that GetProcAddress can find by name.
+const GDI_EXPORTED_NAMES: &[(&str, u16)] = &[
+    ("SETBKMODE", 2),
+    ("SETROP2", 4),
+    ("LINETO", 19),
+    ("MOVETO", 20),
+    ("RECTANGLE", 27),
+    ("SELECTOBJECT", 45),
+    ("CREATEDC", 53),
+    ("CREATEPEN", 61),
+    ("CREATERECTRGN", 64),
+    ("CREATERECTRGNINDIRECT", 65),
+    ("CREATESOLIDBRUSH", 66),
+    ("DELETEDC", 68),
+    ("DELETEOBJECT", 69),
+    ("GETDEVICECAPS", 80),
+    ("GETSTOCKOBJECT", 87),
+    ("ADDFONTRESOURCE", 119),
+    ("MULDIV", 128),
+];
+
 pub struct GdiModule {
     base_module: BaseModule,
 }
@@ -166,20 +479,24 @@ impl GdiModule {
 }
 
 impl Module for GdiModule {
-    fn argument_bytes_of_procedure(&self, procedure: u16) -> u16 {
-        match procedure {
+    fn argument_bytes_of_procedure(&self, procedure: u16) -> Option<u16> {
+        Some(match procedure {
             52 | 68 | 69 | 87 => 2,
-            2 | 4 | 45 | 57 | 66 | 80 | 119 | 346 => 4,
+            2 | 4 | 45 | 57 | 65 | 66 | 80 | 119 | 346 => 4,
             1 | 9 | 19 | 20 | 128 | 154 => 6,
-            61 | 91 => 8,
+            61 | 64 | 91 => 8,
             27 => 10,
             156 => 12,
             29 => 14,
             53 => 16,
             34 => 20,
             351 => 22,
-            _ => unimplemented!("procedure {}", procedure),
-        }
+            _ => return None,
+        })
+    }
+
+    fn exported_names(&self) -> &'static [(&'static str, u16)] {
+        GDI_EXPORTED_NAMES
     }
 
     fn base_module(&self) -> &BaseModule {
@@ -187,6 +504,20 @@ impl Module for GdiModule {
     }
 }
 
+/// The procedures of KEYBOARD that GetProcAddress can find by name.
+const KEYBOARD_EXPORTED_NAMES: &[(&str, u16)] = &[
+    ("TOASCII", 4),
+    ("ANSITOOEM", 5),
+    ("OEMTOANSI", 6),
+    ("OEMKEYSCAN", 128),
+    ("VKKEYSCAN", 129),
+    ("GETKEYBOARDTYPE", 130),
+    ("MAPVIRTUALKEY", 131),
+    ("GETKBCODEPAGE", 132),
+    ("ANSITOOEMBUFF", 134),
+    ("OEMTOANSIBUFF", 135),
+];
+
 pub struct KeyboardModule {
     base_module: BaseModule,
 }
@@ -200,10 +531,20 @@ impl KeyboardModule {
 }
 
 impl Module for KeyboardModule {
-    fn argument_bytes_of_procedure(&self, procedure: u16) -> u16 {
-        match procedure {
-            _ => 0,
-        }
+    fn argument_bytes_of_procedure(&self, procedure: u16) -> Option<u16> {
+        Some(match procedure {
+            132 => 0,
+            128..=130 => 2,
+            131 => 4,
+            5 | 6 => 8,
+            134 | 135 => 10,
+            4 => 14,
+            _ => return None,
+        })
+    }
+
+    fn exported_names(&self) -> &'static [(&'static str, u16)] {
+        KEYBOARD_EXPORTED_NAMES
     }
 
     fn base_module(&self) -> &BaseModule {
@@ -224,10 +565,12 @@ impl DummyModule {
 }
 
 impl Module for DummyModule {
-    fn argument_bytes_of_procedure(&self, procedure: u16) -> u16 {
-        match procedure {
-            _ => 0,
-        }
+    fn argument_bytes_of_procedure(&self, _procedure: u16) -> Option<u16> {
+        Some(0)
+    }
+
+    fn exported_names(&self) -> &'static [(&'static str, u16)] {
+        &[]
     }
 
     fn base_module(&self) -> &BaseModule {
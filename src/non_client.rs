@@ -0,0 +1,448 @@
+//! The non-client area of windows: the frame, the caption bar and its buttons.
+
+use crate::bitmap::{Bitmap, Color};
+use crate::constants::{HitTest, SystemColors, WindowStyles};
+use crate::font;
+use crate::two_d::{Point, Rect};
+
+/// SM_CXBORDER and SM_CYBORDER.
+pub const BORDER_WIDTH: i16 = 1;
+/// SM_CXFRAME and SM_CYFRAME, the sizing border including its outer line.
+pub const FRAME_WIDTH: i16 = 4;
+/// SM_CXDLGFRAME and SM_CYDLGFRAME.
+pub const DIALOG_FRAME_WIDTH: i16 = 4;
+/// SM_CYCAPTION, which includes the line below the caption bar.
+pub const CAPTION_HEIGHT: i16 = 20;
+/// SM_CXSIZE and SM_CYSIZE, the size of the system menu box and of the caption buttons.
+pub const BUTTON_SIZE: i16 = 18;
+/// SM_CXMINTRACK and SM_CYMINTRACK, the smallest size a window can be sized to.
+pub const MIN_TRACK_WIDTH: i16 = 100;
+pub const MIN_TRACK_HEIGHT: i16 = CAPTION_HEIGHT + 2 * FRAME_WIDTH;
+/// SM_CXICON and SM_CYICON.
+pub const ICON_SIZE: i16 = 32;
+/// The size of a minimised window: its icon with the title below it.
+pub const ICON_SPACING: i16 = 64;
+pub const ICON_CELL_HEIGHT: i16 = ICON_SIZE + font::GLYPH_HEIGHT + 6;
+
+/// The width of the frame around a window, on each side.
+pub fn frame_width(style: WindowStyles) -> i16 {
+    if style.contains(WindowStyles::THICKFRAME) {
+        FRAME_WIDTH
+    } else if style.contains(WindowStyles::DLGFRAME) && !style.contains(WindowStyles::BORDER) {
+        DIALOG_FRAME_WIDTH
+    } else if style.intersects(WindowStyles::BORDER | WindowStyles::DLGFRAME) {
+        // This includes windows with a caption, which is both a border and a dialog frame
+        BORDER_WIDTH
+    } else {
+        0
+    }
+}
+
+fn has_caption(style: WindowStyles) -> bool {
+    style.contains(WindowStyles::CAPTION)
+}
+
+/// Shrinks a window rectangle to the client rectangle, the way WM_NCCALCSIZE does.
+pub fn calculate_client_rect(style: WindowStyles, window_rect: Rect) -> Rect {
+    // Minimised windows have no client area
+    if style.contains(WindowStyles::MINIMIZE) {
+        return Rect {
+            right: window_rect.left,
+            bottom: window_rect.top,
+            ..window_rect
+        };
+    }
+    let mut rect = window_rect.shrink(frame_width(style));
+    if has_caption(style) {
+        rect.top = rect.top.saturating_add(CAPTION_HEIGHT - BORDER_WIDTH);
+    }
+    rect.right = rect.right.max(rect.left);
+    rect.bottom = rect.bottom.max(rect.top);
+    rect
+}
+
+/// Grows a client rectangle to the window rectangle around it, the way AdjustWindowRect does.
+pub fn calculate_window_rect(style: WindowStyles, client_rect: Rect) -> Rect {
+    let frame_width = frame_width(style);
+    let mut rect = client_rect.inflate(frame_width, frame_width);
+    if has_caption(style) {
+        rect.top = rect.top.saturating_sub(CAPTION_HEIGHT - BORDER_WIDTH);
+    }
+    rect
+}
+
+/// The caption bar of a window of the given size, without the line below it.
+fn caption_rect(style: WindowStyles, width: i16) -> Rect {
+    let frame_width = frame_width(style);
+    Rect {
+        left: frame_width,
+        top: frame_width,
+        right: width - frame_width,
+        bottom: frame_width + CAPTION_HEIGHT - 2 * BORDER_WIDTH,
+    }
+}
+
+/// The caption buttons on the right, from right to left, each with the line on its left side.
+fn caption_buttons(style: WindowStyles, caption: Rect) -> Vec<(HitTest, Rect)> {
+    let mut buttons = Vec::new();
+    let mut right = caption.right;
+    for (button_style, hit_test) in [
+        (WindowStyles::MAXIMIZEBOX, HitTest::MaxButton),
+        (WindowStyles::MINIMIZEBOX, HitTest::MinButton),
+    ] {
+        if style.contains(button_style) {
+            buttons.push((
+                hit_test,
+                Rect {
+                    left: right - BUTTON_SIZE,
+                    top: caption.top,
+                    right,
+                    bottom: caption.bottom,
+                },
+            ));
+            right -= BUTTON_SIZE;
+        }
+    }
+    buttons
+}
+
+/// Finds the part of a window a point is in, relative to the top left corner of the window.
+pub fn hit_test(style: WindowStyles, width: i16, height: i16, point: Point) -> HitTest {
+    let window_rect = Rect {
+        left: 0,
+        top: 0,
+        right: width,
+        bottom: height,
+    };
+    if !window_rect.contains(point) {
+        return HitTest::Nowhere;
+    }
+    // Icons are moved around like a caption
+    if style.contains(WindowStyles::MINIMIZE) {
+        return HitTest::Caption;
+    }
+    let client_rect = calculate_client_rect(style, window_rect);
+    if client_rect.contains(point) {
+        return HitTest::Client;
+    }
+
+    let frame_width = frame_width(style);
+    if !window_rect.shrink(frame_width).contains(point) {
+        if !style.contains(WindowStyles::THICKFRAME) {
+            return HitTest::Border;
+        }
+        // The sizing border sizes in two directions near the corners
+        let corner = frame_width + CAPTION_HEIGHT - BORDER_WIDTH;
+        let left = point.x < corner;
+        let right = point.x >= width - corner;
+        return if point.y < corner {
+            match (left, right) {
+                (true, _) => HitTest::TopLeft,
+                (_, true) => HitTest::TopRight,
+                _ => HitTest::Top,
+            }
+        } else if point.y >= height - corner {
+            match (left, right) {
+                (true, _) => HitTest::BottomLeft,
+                (_, true) => HitTest::BottomRight,
+                _ => HitTest::Bottom,
+            }
+        } else if left {
+            HitTest::Left
+        } else {
+            HitTest::Right
+        };
+    }
+
+    if has_caption(style) {
+        let caption = caption_rect(style, width);
+        // The line below the caption bar still belongs to the caption
+        if point.y <= caption.bottom {
+            if style.contains(WindowStyles::SYSMENU) && point.x < caption.left + BUTTON_SIZE {
+                return HitTest::SysMenu;
+            }
+            return caption_buttons(style, caption)
+                .into_iter()
+                .find(|(_, rect)| rect.contains(point))
+                .map_or(HitTest::Caption, |(hit_test, _)| hit_test);
+        }
+    }
+    HitTest::Nowhere
+}
+
+/// Draws the non-client area of a window onto a bitmap whose origin is the top left corner
+/// of the window. The client area is left alone. A caption button can be drawn pressed.
+pub fn paint(
+    bitmap: &mut Bitmap,
+    style: WindowStyles,
+    (width, height): (i16, i16),
+    title: &[u8],
+    active: bool,
+    pressed: Option<HitTest>,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    if style.contains(WindowStyles::MINIMIZE) {
+        paint_icon(bitmap, width, title, active, system_color);
+        return;
+    }
+    let frame_width = frame_width(style);
+    if frame_width == 0 {
+        return;
+    }
+    let window_frame = system_color(SystemColors::WindowFrame);
+    let (caption_color, caption_text_color, border_color) = if active {
+        (
+            system_color(SystemColors::ActiveCaption),
+            system_color(SystemColors::CaptionText),
+            system_color(SystemColors::ActiveBorder),
+        )
+    } else {
+        (
+            system_color(SystemColors::InactiveCaption),
+            system_color(SystemColors::InactiveCaptionText),
+            system_color(SystemColors::InactiveBorder),
+        )
+    };
+
+    let window_rect = Rect {
+        left: 0,
+        top: 0,
+        right: width,
+        bottom: height,
+    };
+    draw_outline(bitmap, window_rect, window_frame);
+    if frame_width > BORDER_WIDTH {
+        // Dialog frames take the colour of the caption bar
+        let (band_color, inner_line) = if style.contains(WindowStyles::THICKFRAME) {
+            (border_color, true)
+        } else {
+            (caption_color, false)
+        };
+        let inner = window_rect.shrink(frame_width);
+        let outer = window_rect.shrink(BORDER_WIDTH);
+        for band in [
+            Rect {
+                bottom: inner.top,
+                ..outer
+            },
+            Rect {
+                top: inner.bottom,
+                ..outer
+            },
+            Rect {
+                right: inner.left,
+                ..outer
+            },
+            Rect {
+                left: inner.right,
+                ..outer
+            },
+        ] {
+            bitmap.fill_rectangle(band, band_color);
+        }
+        if inner_line {
+            draw_outline(
+                bitmap,
+                inner.inflate(BORDER_WIDTH, BORDER_WIDTH),
+                window_frame,
+            );
+            // The notches that mark where sizing in two directions starts
+            let corner = frame_width + CAPTION_HEIGHT - BORDER_WIDTH;
+            for x in [corner, width - 1 - corner] {
+                bitmap.draw_vertical_line(x, outer.top, inner.top, window_frame);
+                bitmap.draw_vertical_line(x, inner.bottom, outer.bottom, window_frame);
+            }
+            for y in [corner, height - 1 - corner] {
+                bitmap.draw_horizontal_line(outer.left, y, inner.left, window_frame);
+                bitmap.draw_horizontal_line(inner.right, y, outer.right, window_frame);
+            }
+        }
+    }
+
+    if !has_caption(style) {
+        return;
+    }
+    let caption = caption_rect(style, width);
+    bitmap.fill_rectangle(caption, caption_color);
+    bitmap.draw_horizontal_line(caption.left, caption.bottom, caption.right, window_frame);
+
+    let mut title_left = caption.left;
+    if style.contains(WindowStyles::SYSMENU) {
+        let right = caption.left + BUTTON_SIZE - BORDER_WIDTH;
+        let box_rect = Rect { right, ..caption };
+        bitmap.fill_rectangle(box_rect, system_color(SystemColors::ButtonFace));
+        bitmap.draw_vertical_line(right, caption.top, caption.bottom, window_frame);
+        // The bar that looks like the space bar, as Alt+Space opens the system menu
+        let middle = (caption.top + caption.bottom) / 2;
+        let bar = Rect {
+            left: box_rect.left + 3,
+            top: middle - 2,
+            right: box_rect.right - 3,
+            bottom: middle + 1,
+        };
+        bitmap.fill_rectangle(bar.offset(1, 1), system_color(SystemColors::ButtonShadow));
+        bitmap.fill_rectangle(bar, system_color(SystemColors::ButtonHighlight));
+        draw_outline(bitmap, bar, window_frame);
+        title_left = right + BORDER_WIDTH;
+    }
+
+    let mut title_right = caption.right;
+    for (hit_test, rect) in caption_buttons(style, caption) {
+        bitmap.draw_vertical_line(rect.left, rect.top, rect.bottom, window_frame);
+        let face = Rect {
+            left: rect.left + BORDER_WIDTH,
+            ..rect
+        };
+        let is_pressed = pressed == Some(hit_test);
+        draw_button(bitmap, face, is_pressed, system_color);
+        let arrow_color = system_color(SystemColors::ButtonText);
+        // The face moves down and to the right while the button is pressed
+        let center = Point::new(
+            (face.left + face.right) / 2 - 1 + is_pressed as i16,
+            (face.top + face.bottom) / 2 - 1 + is_pressed as i16,
+        );
+        if hit_test == HitTest::MinButton {
+            draw_arrow(bitmap, center + Point::new(0, -2), false, arrow_color);
+        } else if style.contains(WindowStyles::MAXIMIZE) {
+            // The restore button
+            draw_arrow(bitmap, center + Point::new(0, -5), true, arrow_color);
+            draw_arrow(bitmap, center + Point::new(0, 1), false, arrow_color);
+        } else {
+            draw_arrow(bitmap, center + Point::new(0, -2), true, arrow_color);
+        }
+        title_right = rect.left;
+    }
+
+    // Long titles get cut off where they would cover the buttons
+    let title = fit_text(title, title_right - title_left - 2);
+    let text_position = Point::new(
+        (title_left + title_right - font::text_width(title)) / 2,
+        (caption.top + caption.bottom - font::GLYPH_HEIGHT) / 2 + 1,
+    );
+    font::draw_text(bitmap, text_position, title, caption_text_color);
+}
+
+/// Minimised windows show as their icon, with the title below it.
+fn paint_icon(
+    bitmap: &mut Bitmap,
+    width: i16,
+    title: &[u8],
+    active: bool,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    bitmap.fill_rectangle(
+        Rect {
+            left: 0,
+            top: 0,
+            right: width,
+            bottom: ICON_CELL_HEIGHT,
+        },
+        system_color(SystemColors::Background),
+    );
+
+    // TODO: draw the icon of the window class, this is a little window instead
+    let left = (width - ICON_SIZE) / 2;
+    let icon = Rect {
+        left,
+        top: 0,
+        right: left + ICON_SIZE,
+        bottom: ICON_SIZE,
+    }
+    .shrink(2);
+    bitmap.fill_rectangle(icon, system_color(SystemColors::Window));
+    draw_outline(bitmap, icon, system_color(SystemColors::WindowFrame));
+    bitmap.fill_rectangle(
+        Rect {
+            bottom: icon.top + 7,
+            ..icon
+        }
+        .shrink(1),
+        system_color(SystemColors::ActiveCaption),
+    );
+
+    let title = fit_text(title, width - 4);
+    let text_width = font::text_width(title);
+    let label = Rect {
+        left: (width - text_width) / 2 - 2,
+        top: ICON_SIZE + 2,
+        right: (width + text_width) / 2 + 2,
+        bottom: ICON_SIZE + 4 + font::GLYPH_HEIGHT,
+    };
+    let (label_color, text_color) = if active {
+        (
+            system_color(SystemColors::ActiveCaption),
+            system_color(SystemColors::CaptionText),
+        )
+    } else {
+        (
+            system_color(SystemColors::Background),
+            system_color(SystemColors::WindowText),
+        )
+    };
+    bitmap.fill_rectangle(label, label_color);
+    font::draw_text(
+        bitmap,
+        Point::new(label.left + 2, label.top + 1),
+        title,
+        text_color,
+    );
+}
+
+/// Cuts off the end of a text that doesn't fit in the given width.
+fn fit_text(text: &[u8], width: i16) -> &[u8] {
+    let mut text = text;
+    while !text.is_empty() && font::text_width(text) > width {
+        text = &text[..text.len() - 1];
+    }
+    text
+}
+
+/// Draws a one pixel wide rectangle along the inside of the given rectangle.
+fn draw_outline(bitmap: &mut Bitmap, rect: Rect, color: Color) {
+    bitmap.draw_horizontal_line(rect.left, rect.top, rect.right, color);
+    bitmap.draw_horizontal_line(rect.left, rect.bottom - 1, rect.right, color);
+    bitmap.draw_vertical_line(rect.left, rect.top, rect.bottom, color);
+    bitmap.draw_vertical_line(rect.right - 1, rect.top, rect.bottom, color);
+}
+
+fn draw_button(
+    bitmap: &mut Bitmap,
+    rect: Rect,
+    pressed: bool,
+    system_color: &dyn Fn(SystemColors) -> Color,
+) {
+    bitmap.fill_rectangle(rect, system_color(SystemColors::ButtonFace));
+    if pressed {
+        let shadow = system_color(SystemColors::ButtonShadow);
+        bitmap.draw_horizontal_line(rect.left, rect.top, rect.right, shadow);
+        bitmap.draw_vertical_line(rect.left, rect.top, rect.bottom, shadow);
+        return;
+    }
+    let highlight = system_color(SystemColors::ButtonHighlight);
+    bitmap.draw_horizontal_line(rect.left, rect.top, rect.right - 1, highlight);
+    bitmap.draw_vertical_line(rect.left, rect.top, rect.bottom - 1, highlight);
+    let shadow = system_color(SystemColors::ButtonShadow);
+    for inset in 1..=2 {
+        bitmap.draw_horizontal_line(
+            rect.left + inset - 1,
+            rect.bottom - inset,
+            rect.right,
+            shadow,
+        );
+        bitmap.draw_vertical_line(
+            rect.right - inset,
+            rect.top + inset - 1,
+            rect.bottom,
+            shadow,
+        );
+    }
+}
+
+/// Draws a triangle pointing up or down, four pixels high, below the given point and
+/// centred on it.
+fn draw_arrow(bitmap: &mut Bitmap, top: Point, up: bool, color: Color) {
+    for row in 0..4 {
+        let y = if up { top.y + row } else { top.y + 3 - row };
+        bitmap.draw_horizontal_line(top.x - row, y, top.x + row + 1, color);
+    }
+}
@@ -1,32 +1,83 @@
+use crate::atom_table::AtomTable;
 use crate::bitmap::{BitmapView, Color};
-use crate::constants::RasterOp;
+use crate::byte_string::HeapByteString;
+use crate::clipboard::Clipboard;
+use crate::constants::{ClassStyles, RasterOp, WindowStyles};
+use crate::controls::Control;
+use crate::dialog::Dialog;
 use crate::handle_table::{Handle, HandleTable};
-use crate::heap::Heap;
+use crate::hook::HookTable;
+use crate::keyboard::KeyStates;
 use crate::memory::SegmentAndOffset;
-use crate::two_d::Point;
-use crate::window_manager::WindowIdentifier;
+use crate::menu::Menu;
+use crate::timer::TimerTable;
+use crate::two_d::{Point, Rect};
+use crate::window_manager::{ProcessId, WindowIdentifier};
 use crate::WindowManager;
-use std::cell::Cell;
-use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 pub struct UserWindow {
+    pub process_id: ProcessId,
     pub proc: SegmentAndOffset,
-    pub parent_dc: bool,
+    /// The class the window was created from.
+    pub class_name: HeapByteString,
+    pub class_style: ClassStyles,
+    pub style: WindowStyles,
+    /// The low word of the style, which every class gives its own meaning, such as the kind of
+    /// button for a button.
+    pub class_specific_style: u16,
+    /// The extended styles, which only SetWindowLong changes.
+    pub ex_style: u32,
+    /// The instance that created the window.
+    pub h_instance: Handle,
+    pub title: HeapByteString,
+    /// Where a minimised or maximised window goes back to once it is restored.
+    pub restored_rect: Option<Rect>,
+    /// The menu bar of a top-level window, or the id of a child window.
+    pub menu: Handle,
+    /// The copy of the system menu that the window got to change, if any.
+    pub system_menu: Handle,
+    /// What a dialog box keeps about itself, if the window is one.
+    pub dialog: Option<Dialog>,
+    /// What a window of one of the predefined control classes keeps about itself.
+    pub control: Option<Box<Control>>,
     pub parent_handle: Handle,
+    /// The window that a top-level window belongs to, such as the window of a dialog box.
+    pub owner_handle: Handle,
     pub children: Vec<Handle>,
+    /// The background brush of the class, or a system color index plus one.
+    pub background: Handle,
+    /// The part of the client area that needs to be painted, if any.
+    pub update_region: Option<Rect>,
+    /// Whether the background of the update region must be erased before it is painted.
+    pub erase_background: bool,
+    /// Whether the frame must be drawn again before the client area is painted.
+    pub redraw_frame: bool,
+    /// Whether the caption is drawn like the one of the active window, as WM_NCACTIVATE said.
+    pub active_frame: bool,
+    /// The extra bytes of the window, as many as its class asks for.
+    pub extra: Vec<u8>,
 }
 
 pub enum UserObject {
     Window(UserWindow),
+    Menu(Menu),
 }
 
 pub struct DeviceContext {
+    /// The window that the device context was gotten for.
+    pub h_wnd: Handle,
     pub bitmap_window_identifier: WindowIdentifier,
     pub bitmap_translation: Point,
-    pub position: Cell<Point>,
+    pub position: Point,
     pub selected_brush: Handle,
     pub selected_pen: Handle,
     pub raster_op: RasterOp,
+    /// Drawing outside of this rectangle, in the coordinates of the device context, is
+    /// discarded.
+    pub clip: Option<Rect>,
+    /// Whether the caret got hidden while the device context draws on its bitmap.
+    pub hides_caret: bool,
 }
 
 pub struct Pen {
@@ -45,37 +96,77 @@ pub enum GdiObject {
     DC(DeviceContext),
     SolidBrush(Color),
     Pen(Pen),
+    /// A region, which can only be a rectangle so far.
+    Region(Rect),
     // TODO: remove me once we have all types
     Placeholder,
 }
 
-pub struct ObjectEnvironment<'a> {
+/// The USER and GDI objects, atoms, timers and keyboard state, which are shared by every task
+/// in the system.
+pub struct ObjectEnvironment {
     pub user: HandleTable<UserObject>,
     pub gdi: HandleTable<GdiObject>,
-    pub window_manager: &'a RwLock<WindowManager>,
-    pub local_heap: Heap,
+    pub atoms: AtomTable<'static>,
+    pub timers: TimerTable,
+    pub hooks: HookTable,
+    /// The keys as of the last input message that was read, for GetKeyState.
+    pub key_states: KeyStates,
+    /// The keys as they are right now, for GetAsyncKeyState.
+    pub async_key_states: KeyStates,
+    /// Where the mouse cursor is right now, in screen coordinates.
+    pub cursor_position: Point,
+    pub window_manager: Arc<RwLock<WindowManager>>,
+    pub clipboard: Clipboard,
 }
 
 impl UserWindow {
-    pub fn new(proc: SegmentAndOffset, parent_dc: bool, parent_handle: Handle) -> Self {
+    pub fn new(
+        process_id: ProcessId,
+        proc: SegmentAndOffset,
+        class_name: HeapByteString,
+        class_style: ClassStyles,
+        style: WindowStyles,
+        title: HeapByteString,
+        parent_handle: Handle,
+    ) -> Self {
         Self {
+            process_id,
             proc,
+            class_name,
             children: Vec::new(),
-            parent_dc,
+            class_style,
+            style,
+            class_specific_style: 0,
+            ex_style: 0,
+            h_instance: Handle::null(),
+            title,
+            restored_rect: None,
+            menu: Handle::null(),
+            system_menu: Handle::null(),
+            dialog: None,
+            control: None,
             parent_handle,
+            owner_handle: Handle::null(),
+            background: Handle::null(),
+            update_region: None,
+            erase_background: false,
+            redraw_frame: false,
+            active_frame: false,
+            extra: Vec::new(),
         }
     }
 }
 
-impl<'a> ObjectEnvironment<'a> {
-    pub fn new(window_manager: &'a RwLock<WindowManager>, local_heap: Heap) -> Self {
+impl ObjectEnvironment {
+    pub fn new(window_manager: Arc<RwLock<WindowManager>>) -> Self {
         let mut gdi = HandleTable::new();
 
         // Stock objects
         gdi.register(GdiObject::SolidBrush(Color(255, 255, 255)));
-        gdi.register(GdiObject::Placeholder);
-        gdi.register(GdiObject::Placeholder);
-        gdi.register(GdiObject::Placeholder);
+        gdi.register(GdiObject::SolidBrush(Color(192, 192, 192)));
+        gdi.register(GdiObject::SolidBrush(Color(128, 128, 128)));
+        gdi.register(GdiObject::SolidBrush(Color(64, 64, 64)));
         gdi.register(GdiObject::SolidBrush(Color(0, 0, 0)));
         gdi.register(GdiObject::Placeholder);
         gdi.register(GdiObject::Pen(Pen {
@@ -99,8 +190,14 @@ impl<'a> ObjectEnvironment<'a> {
         Self {
             user: HandleTable::new(),
             gdi,
+            atoms: AtomTable::new(),
+            timers: TimerTable::new(),
+            hooks: HookTable::new(),
+            key_states: KeyStates::new(),
+            async_key_states: KeyStates::new(),
+            cursor_position: Point::origin(),
             window_manager,
-            local_heap,
+            clipboard: Clipboard::new(),
         }
     }
 
@@ -112,6 +209,17 @@ impl<'a> ObjectEnvironment<'a> {
         self.window_manager.write().unwrap()
     }
 
+    /// Identifies a window in the window manager, which knows windows by their owning task.
+    pub fn window_identifier(&self, h_wnd: Handle) -> Option<WindowIdentifier> {
+        match self.user.get(h_wnd) {
+            Some(UserObject::Window(user_window)) => Some(WindowIdentifier {
+                process_id: user_window.process_id,
+                window_handle: h_wnd,
+            }),
+            _ => None,
+        }
+    }
+
     pub fn with_paint_bitmap_for(&self, h_dc: Handle, f: &dyn Fn(BitmapView, &DeviceContext)) {
         if let Some(GdiObject::DC(device_context)) = self.gdi.get(h_dc) {
             if let Some(bitmap) = self
@@ -142,6 +250,6 @@ impl DeviceContext {
     }
 
     pub fn move_to(&mut self, position: Point) {
-        self.position.set(position);
+        self.position = position;
     }
 }
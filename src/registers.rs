@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct Registers {
     pub ip: u16,
     gpr: [u16; 8],
@@ -87,6 +88,15 @@ impl Registers {
         }
     }
 
+    #[inline]
+    pub fn set_carry_flag(&mut self, flag: bool) {
+        if flag {
+            self.flags |= Self::FLAG_CF;
+        } else {
+            self.flags &= !Self::FLAG_CF;
+        }
+    }
+
     pub fn dec_sp(&mut self, amount: u16) {
         self.gpr[Self::REG_SP as usize] = self.gpr[Self::REG_SP as usize].wrapping_sub(amount);
     }
@@ -0,0 +1,146 @@
+use crate::window_manager::ProcessId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Instant;
+
+struct SchedulerState {
+    next_process_id: u16,
+    running: Option<ProcessId>,
+    ready: VecDeque<ProcessId>,
+    events: HashMap<ProcessId, u16>,
+}
+
+/// Cooperative scheduler for the tasks in the session.
+/// Every task runs on its own thread, but only the task holding the baton may execute,
+/// just like only one task ran at a time on Windows 3.x. A task gives up the baton when it
+/// yields or waits for an event, e.g. in GetMessage with an empty queue.
+pub struct Scheduler {
+    state: Mutex<SchedulerState>,
+    condvar: Condvar,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(SchedulerState {
+                next_process_id: 1,
+                running: None,
+                ready: VecDeque::new(),
+                events: HashMap::new(),
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SchedulerState> {
+        self.state.lock().unwrap()
+    }
+
+    fn acquire<'s>(
+        &'s self,
+        mut state: MutexGuard<'s, SchedulerState>,
+        process_id: ProcessId,
+    ) -> MutexGuard<'s, SchedulerState> {
+        while state.running.is_some() || state.ready.front() != Some(&process_id) {
+            state = self.condvar.wait(state).unwrap();
+        }
+        state.ready.pop_front();
+        state.running = Some(process_id);
+        state
+    }
+
+    fn release(&self, state: &mut MutexGuard<SchedulerState>) {
+        state.running = None;
+        self.condvar.notify_all();
+    }
+
+    /// Creates a new task, which is ready to run once it calls `run`.
+    pub fn spawn(&self) -> ProcessId {
+        let mut state = self.lock();
+        let process_id = ProcessId::new(state.next_process_id);
+        state.next_process_id = state.next_process_id.wrapping_add(1);
+        state.ready.push_back(process_id);
+        // A new task starts with an event pending
+        state.events.insert(process_id, 1);
+        process_id
+    }
+
+    /// Blocks until the given task holds the baton.
+    pub fn run(&self, process_id: ProcessId) {
+        let state = self.lock();
+        drop(self.acquire(state, process_id));
+    }
+
+    /// Lets every other ready task run before continuing.
+    pub fn yield_now(&self, process_id: ProcessId) {
+        let mut state = self.lock();
+        if state.ready.is_empty() {
+            return;
+        }
+        self.release(&mut state);
+        state.ready.push_back(process_id);
+        drop(self.acquire(state, process_id));
+    }
+
+    /// Waits until an event is posted to the given task, letting other tasks run meanwhile.
+    /// Returns true if the task actually had to wait.
+    pub fn wait(&self, process_id: ProcessId) -> bool {
+        let mut state = self.lock();
+        let had_to_wait = state.events.get(&process_id).copied().unwrap_or(0) == 0;
+        if had_to_wait {
+            self.release(&mut state);
+            while state.events.get(&process_id).copied().unwrap_or(0) == 0 {
+                state = self.condvar.wait(state).unwrap();
+            }
+            state.ready.push_back(process_id);
+            state = self.acquire(state, process_id);
+        }
+        if let Some(events) = state.events.get_mut(&process_id) {
+            *events -= 1;
+        }
+        had_to_wait
+    }
+
+    /// Like `wait`, but stops waiting once the deadline has passed.
+    /// Returns true if an event was posted to the task.
+    pub fn wait_until(&self, process_id: ProcessId, deadline: Instant) -> bool {
+        let mut state = self.lock();
+        if state.events.get(&process_id).copied().unwrap_or(0) == 0 {
+            self.release(&mut state);
+            while state.events.get(&process_id).copied().unwrap_or(0) == 0 {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+            }
+            state.ready.push_back(process_id);
+            state = self.acquire(state, process_id);
+        }
+        match state.events.get_mut(&process_id) {
+            Some(events) if *events > 0 => {
+                *events -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Posts an event to the given task, waking it up if it is waiting.
+    pub fn wake(&self, process_id: ProcessId) {
+        let mut state = self.lock();
+        if let Some(events) = state.events.get_mut(&process_id) {
+            *events = events.saturating_add(1);
+            self.condvar.notify_all();
+        }
+    }
+
+    /// Removes a task that has finished running, and passes the baton on.
+    pub fn exit(&self, process_id: ProcessId) {
+        let mut state = self.lock();
+        state.events.remove(&process_id);
+        if state.running == Some(process_id) {
+            self.release(&mut state);
+        }
+    }
+}
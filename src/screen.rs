@@ -1,7 +1,21 @@
 use crate::bitmap::Bitmap;
+use crate::constants::{MessageType, MouseKeyState};
+use crate::keyboard::{
+    is_extended_key, scan_code_of, VK_0, VK_A, VK_ADD, VK_BACK, VK_CAPITAL, VK_CONTROL, VK_DECIMAL,
+    VK_DELETE, VK_DIVIDE, VK_DOWN, VK_END, VK_ESCAPE, VK_F1, VK_F10, VK_HOME, VK_INSERT,
+    VK_LBUTTON, VK_LEFT, VK_MBUTTON, VK_MENU, VK_MULTIPLY, VK_NEXT, VK_NUMLOCK, VK_NUMPAD0,
+    VK_OEM_1, VK_OEM_2, VK_OEM_3, VK_OEM_4, VK_OEM_5, VK_OEM_6, VK_OEM_7, VK_OEM_COMMA,
+    VK_OEM_MINUS, VK_OEM_PERIOD, VK_OEM_PLUS, VK_PAUSE, VK_PRIOR, VK_RBUTTON, VK_RETURN, VK_RIGHT,
+    VK_SCROLL, VK_SHIFT, VK_SNAPSHOT, VK_SPACE, VK_SUBTRACT, VK_TAB, VK_UP,
+};
+use crate::message_queue::WindowMessage;
+use crate::util::encode_u16_u16_to_u32;
 use crate::window_manager::WindowManager;
+use crate::Session;
+use sdl2::clipboard::ClipboardUtil;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::{MouseButton, MouseState};
 use sdl2::pixels::Color;
 use sdl2::rect::Point;
 use sdl2::render::WindowCanvas;
@@ -16,12 +30,17 @@ pub struct ScreenCanvas {
 
 pub struct Screen {
     sdl_context: Sdl,
+    clipboard: ClipboardUtil,
     canvas: ScreenCanvas,
     window_manager: Arc<RwLock<WindowManager>>,
+    session: Arc<Session>,
 }
 
 impl Screen {
-    pub fn new(window_manager: Arc<RwLock<WindowManager>>) -> Result<Self, String> {
+    pub fn new(
+        window_manager: Arc<RwLock<WindowManager>>,
+        session: Arc<Session>,
+    ) -> Result<Self, String> {
         // Setup window
         let sdl_context = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
@@ -36,28 +55,285 @@ impl Screen {
         canvas.present();
 
         Ok(Self {
+            clipboard: video_subsystem.clipboard(),
             sdl_context,
             canvas: ScreenCanvas { canvas },
             window_manager,
+            session,
         })
     }
 
+    /// Sends mouse input to the task owning the topmost window under the cursor, or the window
+    /// that captured the mouse. That task finds out which of its windows is hit once it reads
+    /// its message queue.
+    fn post_mouse_input(&self, message: MessageType, x: i32, y: i32, key_state: MouseKeyState) {
+        let point = crate::two_d::Point::new(x as i16, y as i16);
+        self.session.objects.write().unwrap().cursor_position = point;
+        let window_identifier = match self.window_manager.read().unwrap().mouse_target(point) {
+            Some(window_identifier) => window_identifier,
+            None => return,
+        };
+        let process_id = window_identifier.process_id;
+        if let Some(message_queue) = self.session.message_queues.read().unwrap().get(&process_id) {
+            message_queue.post_input(WindowMessage {
+                h_wnd: window_identifier.window_handle,
+                message,
+                w_param: key_state.bits(),
+                l_param: 0,
+                time: self.session.objects.read().unwrap().timers.tick_count(),
+                point,
+            });
+            self.session.scheduler.wake(process_id);
+        }
+    }
+
+    /// Sends keyboard input to the task owning the window with the focus, or the active window.
+    fn post_key_input(&self, keycode: Keycode, down: bool, repeat: bool) {
+        let vk = match Self::virtual_key(keycode) {
+            Some(vk) => vk,
+            None => return,
+        };
+        let (scan_code, extended) = match keycode {
+            Keycode::RShift => (0x36, false),
+            Keycode::RCtrl | Keycode::RAlt => (scan_code_of(vk), true),
+            Keycode::KpEnter => (scan_code_of(vk), true),
+            _ => (scan_code_of(vk), is_extended_key(vk)),
+        };
+
+        self.set_async_key_state(vk, down);
+        let (alt, control, time) = {
+            let objects = self.session.objects.read().unwrap();
+            (
+                objects.async_key_states.is_down(VK_MENU),
+                objects.async_key_states.is_down(VK_CONTROL),
+                objects.timers.tick_count(),
+            )
+        };
+        // Keys pressed together with Alt, and F10, are meant for the menu
+        let system_key = !control && (alt || vk == VK_MENU || vk == VK_F10);
+        let message = match (down, system_key) {
+            (true, false) => MessageType::KeyDown,
+            (false, false) => MessageType::KeyUp,
+            (true, true) => MessageType::SysKeyDown,
+            (false, true) => MessageType::SysKeyUp,
+        };
+        // Repeat count, scan code, extended key, Alt down, key was down before, key goes up
+        let flags = scan_code as u16
+            | (extended as u16) << 8
+            | (alt as u16) << 13
+            | ((repeat || !down) as u16) << 14
+            | (!down as u16) << 15;
+
+        let window_identifier = match self.window_manager.read().unwrap().keyboard_target() {
+            Some(window_identifier) => window_identifier,
+            None => return,
+        };
+        let process_id = window_identifier.process_id;
+        if let Some(message_queue) = self.session.message_queues.read().unwrap().get(&process_id) {
+            message_queue.post_input(WindowMessage {
+                h_wnd: window_identifier.window_handle,
+                message,
+                w_param: vk as u16,
+                l_param: encode_u16_u16_to_u32(1, flags),
+                time,
+                point: crate::two_d::Point::origin(),
+            });
+            self.session.scheduler.wake(process_id);
+        }
+    }
+
+    fn virtual_key(keycode: Keycode) -> Option<u8> {
+        let code = keycode as i32;
+        if (Keycode::A as i32..=Keycode::Z as i32).contains(&code) {
+            return Some(VK_A + (code - Keycode::A as i32) as u8);
+        }
+        if (Keycode::Num0 as i32..=Keycode::Num9 as i32).contains(&code) {
+            return Some(VK_0 + (code - Keycode::Num0 as i32) as u8);
+        }
+        if (Keycode::F1 as i32..=Keycode::F12 as i32).contains(&code) {
+            return Some(VK_F1 + (code - Keycode::F1 as i32) as u8);
+        }
+        if (Keycode::Kp1 as i32..=Keycode::Kp9 as i32).contains(&code) {
+            return Some(VK_NUMPAD0 + 1 + (code - Keycode::Kp1 as i32) as u8);
+        }
+        let vk = match keycode {
+            Keycode::Backspace => VK_BACK,
+            Keycode::Tab => VK_TAB,
+            Keycode::Return | Keycode::KpEnter => VK_RETURN,
+            Keycode::LShift | Keycode::RShift => VK_SHIFT,
+            Keycode::LCtrl | Keycode::RCtrl => VK_CONTROL,
+            Keycode::LAlt | Keycode::RAlt => VK_MENU,
+            Keycode::Pause => VK_PAUSE,
+            Keycode::CapsLock => VK_CAPITAL,
+            Keycode::Escape => VK_ESCAPE,
+            Keycode::Space => VK_SPACE,
+            Keycode::PageUp => VK_PRIOR,
+            Keycode::PageDown => VK_NEXT,
+            Keycode::End => VK_END,
+            Keycode::Home => VK_HOME,
+            Keycode::Left => VK_LEFT,
+            Keycode::Up => VK_UP,
+            Keycode::Right => VK_RIGHT,
+            Keycode::Down => VK_DOWN,
+            Keycode::PrintScreen => VK_SNAPSHOT,
+            Keycode::Insert => VK_INSERT,
+            Keycode::Delete => VK_DELETE,
+            Keycode::Kp0 => VK_NUMPAD0,
+            Keycode::KpMultiply => VK_MULTIPLY,
+            Keycode::KpPlus => VK_ADD,
+            Keycode::KpMinus => VK_SUBTRACT,
+            Keycode::KpPeriod => VK_DECIMAL,
+            Keycode::KpDivide => VK_DIVIDE,
+            Keycode::NumLockClear => VK_NUMLOCK,
+            Keycode::ScrollLock => VK_SCROLL,
+            Keycode::Semicolon => VK_OEM_1,
+            Keycode::Equals => VK_OEM_PLUS,
+            Keycode::Comma => VK_OEM_COMMA,
+            Keycode::Minus => VK_OEM_MINUS,
+            Keycode::Period => VK_OEM_PERIOD,
+            Keycode::Slash => VK_OEM_2,
+            Keycode::Backquote => VK_OEM_3,
+            Keycode::LeftBracket => VK_OEM_4,
+            Keycode::Backslash => VK_OEM_5,
+            Keycode::RightBracket => VK_OEM_6,
+            Keycode::Quote => VK_OEM_7,
+            _ => return None,
+        };
+        Some(vk)
+    }
+
+    fn mouse_buttons(mouse_state: MouseState) -> MouseKeyState {
+        let mut buttons = MouseKeyState::empty();
+        buttons.set(MouseKeyState::LBUTTON, mouse_state.left());
+        buttons.set(MouseKeyState::RBUTTON, mouse_state.right());
+        buttons.set(MouseKeyState::MBUTTON, mouse_state.middle());
+        buttons
+    }
+
+    /// Gives the text that a task put on the clipboard to the host, or takes the text that got
+    /// copied on the host if it changed.
+    fn exchange_clipboard_text(&self, host_clipboard_changed: bool) {
+        let text_for_host = self
+            .session
+            .objects
+            .write()
+            .unwrap()
+            .clipboard
+            .take_text_for_host();
+        if let Some(text) = text_for_host {
+            // Failing to reach the host clipboard isn't worth stopping for
+            let _ = self.clipboard.set_clipboard_text(&text);
+        } else if host_clipboard_changed && self.clipboard.has_clipboard_text() {
+            let text = match self.clipboard.clipboard_text() {
+                Ok(text) => text,
+                Err(_) => return,
+            };
+            let mut objects = self.session.objects.write().unwrap();
+            for handle in objects.clipboard.receive_host_text(text) {
+                objects.gdi.deregister(handle);
+            }
+        }
+    }
+
+    fn set_async_key_state(&self, vk: u8, down: bool) {
+        self.session
+            .objects
+            .write()
+            .unwrap()
+            .async_key_states
+            .set_key(vk, down);
+    }
+
+    /// Combines the pressed mouse buttons with the state of the shift and control keys.
+    fn mouse_key_state(&self, buttons: MouseKeyState) -> MouseKeyState {
+        let mut key_state = buttons;
+        let modifiers = self.sdl_context.keyboard().mod_state();
+        key_state.set(
+            MouseKeyState::SHIFT,
+            modifiers.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+        );
+        key_state.set(
+            MouseKeyState::CONTROL,
+            modifiers.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+        );
+        key_state
+    }
+
+    /// The message, key state flag and virtual key of a mouse button going down or up.
+    fn button_message(button: MouseButton, down: bool) -> Option<(MessageType, MouseKeyState, u8)> {
+        let (down_message, up_message, key_state, vk) = match button {
+            MouseButton::Left => (
+                MessageType::LButtonDown,
+                MessageType::LButtonUp,
+                MouseKeyState::LBUTTON,
+                VK_LBUTTON,
+            ),
+            MouseButton::Right => (
+                MessageType::RButtonDown,
+                MessageType::RButtonUp,
+                MouseKeyState::RBUTTON,
+                VK_RBUTTON,
+            ),
+            MouseButton::Middle => (
+                MessageType::MButtonDown,
+                MessageType::MButtonUp,
+                MouseKeyState::MBUTTON,
+                VK_MBUTTON,
+            ),
+            _ => return None,
+        };
+        Some((if down { down_message } else { up_message }, key_state, vk))
+    }
+
     pub fn window_loop(&mut self) {
         let mut event_pump = self.sdl_context.event_pump().unwrap();
+        let mut mouse_buttons = MouseKeyState::empty();
+        // The host clipboard may already have text when we start
+        let mut host_clipboard_changed = true;
         // TODO: be more efficient than always redrawing everything
         'running: loop {
             self.canvas.canvas.set_draw_color(Color::RGB(0, 0, 0));
             self.canvas.canvas.clear();
             for event in event_pump.poll_iter() {
                 match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
+                    Event::Quit { .. } => break 'running,
+                    Event::ClipboardUpdate { .. } => host_clipboard_changed = true,
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        repeat,
                         ..
-                    } => break 'running,
+                    } => self.post_key_input(keycode, true, repeat),
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => self.post_key_input(keycode, false, false),
+                    Event::MouseMotion {
+                        mousestate, x, y, ..
+                    } => {
+                        mouse_buttons = Self::mouse_buttons(mousestate);
+                        let key_state = self.mouse_key_state(mouse_buttons);
+                        self.post_mouse_input(MessageType::MouseMove, x, y, key_state);
+                    }
+                    Event::MouseButtonDown {
+                        mouse_btn, x, y, ..
+                    }
+                    | Event::MouseButtonUp {
+                        mouse_btn, x, y, ..
+                    } => {
+                        let down = matches!(event, Event::MouseButtonDown { .. });
+                        // Double clicks are recognized by USER, as only it knows the window classes
+                        if let Some((message, button, vk)) = Self::button_message(mouse_btn, down) {
+                            mouse_buttons.set(button, down);
+                            self.set_async_key_state(vk, down);
+                            let key_state = self.mouse_key_state(mouse_buttons);
+                            self.post_mouse_input(message, x, y, key_state);
+                        }
+                    }
                     _ => {}
                 }
             }
+            self.exchange_clipboard_text(host_clipboard_changed);
+            host_clipboard_changed = false;
             {
                 self.window_manager.write().unwrap().paint(&mut self.canvas);
             }
@@ -68,6 +344,26 @@ impl Screen {
 }
 
 impl ScreenCanvas {
+    /// Draws the outline of a window that is being moved or sized, as thick as a sizing border.
+    pub fn draw_tracking_rect(&mut self, rect: crate::two_d::Rect) {
+        self.canvas.set_draw_color(Color::RGB(128, 128, 128));
+        for inset in 0..3 {
+            let width = rect.right as i32 - rect.left as i32 - 2 * inset;
+            let height = rect.bottom as i32 - rect.top as i32 - 2 * inset;
+            if width <= 0 || height <= 0 {
+                break;
+            }
+            self.canvas
+                .draw_rect(sdl2::rect::Rect::new(
+                    rect.left as i32 + inset,
+                    rect.top as i32 + inset,
+                    width as u32,
+                    height as u32,
+                ))
+                .unwrap();
+        }
+    }
+
     pub fn blit_bitmap(&mut self, position: crate::two_d::Point, bitmap: &Bitmap) {
         let top_left = Point::new(position.x as i32, position.y as i32);
 
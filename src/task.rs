@@ -0,0 +1,112 @@
+use crate::byte_string::HeapByteString;
+use crate::handle_table::{Handle, HandleTable};
+use crate::window_manager::ProcessId;
+use std::collections::HashMap;
+
+/// The information Windows keeps in the task database of every running task.
+pub struct TaskDatabase {
+    pub process_id: ProcessId,
+    pub h_module: Handle,
+    pub h_instance: Handle,
+    pub h_prev_instance: Handle,
+    pub cmd_show: u16,
+    pub psp_segment: u16,
+    pub environment_segment: u16,
+    /// Lowest offset the stack may grow to in the stack segment.
+    pub stack_top: u16,
+    /// Offset of the bottom of the stack, i.e. the initial SP.
+    pub stack_bottom: u16,
+}
+
+struct ModuleEntry {
+    name: HeapByteString,
+    path: HeapByteString,
+    instances: Vec<Handle>,
+}
+
+/// Keeps track of the loaded modules and the running instances of each of them, so that second
+/// copies of a program can be told about their previous instance.
+pub struct TaskRegistry {
+    modules: HandleTable<ModuleEntry>,
+    module_names: HashMap<HeapByteString, Handle>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self {
+            modules: HandleTable::new(),
+            module_names: HashMap::new(),
+        }
+    }
+
+    /// Registers a new instance of a module, loading the module if this is its first instance.
+    /// Returns the module handle and the handle of the previous instance of the same module,
+    /// which is a null handle for the first one.
+    pub fn register_instance(
+        &mut self,
+        module_name: &[u8],
+        path: &[u8],
+        h_instance: Handle,
+    ) -> Option<(Handle, Handle)> {
+        let module_name = HeapByteString::from(module_name.to_ascii_uppercase().into());
+        let h_module = match self.module_names.get(&module_name) {
+            Some(&h_module) => h_module,
+            None => {
+                let h_module = self.modules.register(ModuleEntry {
+                    name: module_name.clone(),
+                    path: HeapByteString::from(path.into()),
+                    instances: Vec::new(),
+                })?;
+                self.module_names.insert(module_name, h_module);
+                h_module
+            }
+        };
+        let instances = &mut self.modules.get_mut(h_module)?.instances;
+        let h_prev_instance = instances.last().copied().unwrap_or(Handle::null());
+        instances.push(h_instance);
+        Some((h_module, h_prev_instance))
+    }
+
+    /// Removes an instance, and unloads the module once its last instance is gone.
+    pub fn deregister_instance(&mut self, h_module: Handle, h_instance: Handle) {
+        if let Some(module) = self.modules.get_mut(h_module) {
+            if let Some(index) = module.instances.iter().rposition(|&h| h == h_instance) {
+                module.instances.remove(index);
+            }
+            if module.instances.is_empty() {
+                self.module_names.remove(&module.name);
+                self.modules.deregister(h_module);
+            }
+        }
+    }
+
+    /// Finds a loaded module by its name, ignoring case.
+    pub fn module_handle(&self, module_name: &[u8]) -> Option<Handle> {
+        let module_name = HeapByteString::from(module_name.to_ascii_uppercase().into());
+        self.module_names.get(&module_name).copied()
+    }
+
+    /// Many module functions accept both a module handle and an instance handle.
+    pub fn resolve_module_handle(&self, h_module_or_instance: Handle) -> Option<Handle> {
+        if self.modules.get(h_module_or_instance).is_some() {
+            return Some(h_module_or_instance);
+        }
+        self.module_names.values().copied().find(|&h_module| {
+            self.modules
+                .get(h_module)
+                .map(|module| module.instances.contains(&h_module_or_instance))
+                .unwrap_or(false)
+        })
+    }
+
+    pub fn module_usage(&self, h_module: Handle) -> u16 {
+        self.modules
+            .get(h_module)
+            .map(|module| module.instances.len() as u16)
+            .unwrap_or(0)
+    }
+
+    pub fn module_path(&self, h_module: Handle) -> Option<HeapByteString> {
+        self.modules.get(h_module).map(|module| module.path.clone())
+    }
+}
@@ -0,0 +1,146 @@
+use crate::constants::MessageType;
+use crate::handle_table::Handle;
+use crate::memory::SegmentAndOffset;
+use crate::message_queue::WindowMessage;
+use crate::two_d::Point;
+use crate::util::encode_u16_u16_to_u32;
+use crate::window_manager::ProcessId;
+use std::time::{Duration, Instant};
+
+/// Windows 3.1 has room for 32 timers in the whole system.
+const MAX_TIMERS: usize = 32;
+
+struct Timer {
+    process_id: ProcessId,
+    h_wnd: Handle,
+    id: u16,
+    interval: Duration,
+    next_due: Instant,
+    proc: Option<SegmentAndOffset>,
+}
+
+/// The timers of every task. Timers don't post messages themselves: a task gets a WM_TIMER
+/// message from GetMessage or PeekMessage once one of its timers elapsed and its queue is empty.
+/// Elapsing multiple times before that still results in a single message.
+pub struct TimerTable {
+    timers: Vec<Timer>,
+    start_time: Instant,
+}
+
+impl TimerTable {
+    pub fn new() -> Self {
+        Self {
+            timers: Vec::new(),
+            start_time: Instant::now(),
+        }
+    }
+
+    /// Milliseconds since the system started.
+    pub fn tick_count(&self) -> u32 {
+        self.start_time.elapsed().as_millis() as u32
+    }
+
+    /// Creates a timer, or replaces the existing timer with the same window and id.
+    /// Timers without a window get an id assigned, as they don't have a window to tell them
+    /// apart. Returns the id, or None if the system has run out of timers.
+    pub fn set_timer(
+        &mut self,
+        process_id: ProcessId,
+        h_wnd: Handle,
+        id: u16,
+        interval_ms: u16,
+        proc: Option<SegmentAndOffset>,
+    ) -> Option<u16> {
+        let id = if h_wnd == Handle::null() {
+            (1..=u16::MAX).find(|&id| self.position(process_id, h_wnd, id).is_none())?
+        } else {
+            id
+        };
+        let interval = Duration::from_millis(interval_ms.max(1) as u64);
+        let timer = Timer {
+            process_id,
+            h_wnd,
+            id,
+            interval,
+            next_due: Instant::now() + interval,
+            proc,
+        };
+        match self.position(process_id, h_wnd, id) {
+            Some(index) => self.timers[index] = timer,
+            None if self.timers.len() < MAX_TIMERS => self.timers.push(timer),
+            None => return None,
+        }
+        Some(id)
+    }
+
+    /// Returns whether the timer existed.
+    pub fn kill_timer(&mut self, process_id: ProcessId, h_wnd: Handle, id: u16) -> bool {
+        match self.position(process_id, h_wnd, id) {
+            Some(index) => {
+                self.timers.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the timers of a window that got destroyed.
+    pub fn kill_timers_of_window(&mut self, h_wnd: Handle) {
+        self.timers.retain(|timer| timer.h_wnd != h_wnd);
+    }
+
+    /// Removes the timers of a task that ended.
+    pub fn kill_timers_of_process(&mut self, process_id: ProcessId) {
+        self.timers.retain(|timer| timer.process_id != process_id);
+    }
+
+    fn position(&self, process_id: ProcessId, h_wnd: Handle, id: u16) -> Option<usize> {
+        // Timers without a window belong to the task that created them
+        self.timers.iter().position(|timer| {
+            timer.h_wnd == h_wnd
+                && timer.id == id
+                && (h_wnd != Handle::null() || timer.process_id == process_id)
+        })
+    }
+
+    /// Gets the message for the first timer of the task that elapsed, if any.
+    /// A null window handle matches every timer. If `remove` is set, the timer starts
+    /// counting again, so the message won't be reported twice.
+    pub fn elapsed_timer_message(
+        &mut self,
+        process_id: ProcessId,
+        h_wnd: Handle,
+        remove: bool,
+    ) -> Option<WindowMessage> {
+        let now = Instant::now();
+        let time = self.tick_count();
+        let timer = self.timers.iter_mut().find(|timer| {
+            timer.process_id == process_id
+                && (h_wnd == Handle::null() || timer.h_wnd == h_wnd)
+                && timer.next_due <= now
+        })?;
+        if remove {
+            timer.next_due = now + timer.interval;
+        }
+        Some(WindowMessage {
+            h_wnd: timer.h_wnd,
+            message: MessageType::Timer,
+            w_param: timer.id,
+            // lParam holds the TIMERPROC, which DispatchMessage calls instead of the window proc
+            l_param: timer
+                .proc
+                .map_or(0, |proc| encode_u16_u16_to_u32(proc.offset, proc.segment)),
+            time,
+            point: Point::origin(),
+        })
+    }
+
+    /// The moment the next timer of the task elapses.
+    pub fn next_due(&self, process_id: ProcessId) -> Option<Instant> {
+        self.timers
+            .iter()
+            .filter(|timer| timer.process_id == process_id)
+            .map(|timer| timer.next_due)
+            .min()
+    }
+}
@@ -48,6 +48,20 @@ pub fn u32_from_array<const N: usize>(bytes: &[u8; N], offset: usize) -> Option<
     ))
 }
 
+/// Reads a little-endian word or long, such as from the extra bytes of a window.
+pub fn read_le_value(bytes: &[u8], offset: usize, size: usize) -> Option<u32> {
+    let mut value = [0; 4];
+    value[..size].copy_from_slice(bytes.get(offset..offset.checked_add(size)?)?);
+    Some(u32::from_le_bytes(value))
+}
+
+/// Writes a little-endian word or long. Returns the value that was there before.
+pub fn replace_le_value(bytes: &mut [u8], offset: usize, size: usize, value: u32) -> Option<u32> {
+    let previous = read_le_value(bytes, offset, size)?;
+    bytes[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    Some(previous)
+}
+
 #[inline]
 pub fn add_with_flags_16(a: u16, b: u16) -> (u16, bool, bool) {
     let (result, carry) = a.overflowing_add(b);
@@ -1,24 +1,37 @@
 use crate::bitmap::{Bitmap, BitmapView};
+use crate::caret::{self, Caret};
 use crate::handle_table::Handle;
 use crate::object_environment::DeviceContext;
 use crate::screen::ScreenCanvas;
 use crate::two_d::{Point, Rect};
 use std::collections::HashMap;
+use std::time::Instant;
 
 struct Window {
     position: Point,
     width: i16,
     height: i16,
+    /// The client area, relative to the top left corner of the window.
+    client_rect: Rect,
+    /// Covers the whole window, including the non-client area.
     front_bitmap: Option<Bitmap>,
 }
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 pub struct ProcessId(u16);
 
 impl ProcessId {
+    pub const fn new(id: u16) -> Self {
+        Self(id)
+    }
+
     pub const fn null() -> Self {
         Self(0)
     }
+
+    pub const fn as_u16(self) -> u16 {
+        self.0
+    }
 }
 
 #[derive(Eq, PartialEq, Hash, Copy, Clone)]
@@ -30,6 +43,20 @@ pub struct WindowIdentifier {
 pub struct WindowManager {
     window_stack: Vec<WindowIdentifier>,
     windows: HashMap<WindowIdentifier, Window>,
+    /// The window that gets all mouse input, wherever the cursor is.
+    capture: Option<WindowIdentifier>,
+    /// The top-level window that the user works with.
+    active: Option<WindowIdentifier>,
+    /// The window that gets the keyboard input.
+    focus: Option<WindowIdentifier>,
+    /// The outline that shows where a window goes while it is being moved or sized.
+    tracking_rect: Option<Rect>,
+    /// The popup menus that are open, from the first one that opened to the last one.
+    /// They cover all windows.
+    popups: Vec<(Point, Bitmap)>,
+    caret: Option<Caret>,
+    /// How long the caret stays on and off, in milliseconds.
+    caret_blink_time: u16,
 }
 
 impl WindowIdentifier {
@@ -46,6 +73,13 @@ impl WindowManager {
         Self {
             window_stack: Vec::new(),
             windows: HashMap::new(),
+            capture: None,
+            active: None,
+            focus: None,
+            tracking_rect: None,
+            popups: Vec::new(),
+            caret: None,
+            caret_blink_time: caret::DEFAULT_BLINK_TIME,
         }
     }
 
@@ -57,27 +91,20 @@ impl WindowManager {
         width: i16,
         height: i16,
         use_parent_bitmap: bool,
-    ) -> (i16, i16) {
+    ) {
         // TODO: set sane limits for arguments?
-        let number_or_default = |number: i16, default: i16| {
-            if number == -32768 {
-                default
-            } else {
-                number
-            }
-        };
-
-        let width = number_or_default(width, 400);
-        let height = number_or_default(height, 300);
         self.windows.insert(
             identifier,
             Window {
-                position: Point {
-                    x: number_or_default(x, 0),
-                    y: number_or_default(y, 0),
-                },
+                position: Point { x, y },
                 width,
                 height,
+                client_rect: Rect {
+                    left: 0,
+                    top: 0,
+                    right: width,
+                    bottom: height,
+                },
                 front_bitmap: if use_parent_bitmap {
                     None
                 } else {
@@ -85,18 +112,159 @@ impl WindowManager {
                 },
             },
         );
-
-        (width, height)
     }
 
+    /// Shows a window on top of the other windows.
     pub fn show_window(&mut self, identifier: WindowIdentifier) {
+        self.hide_window(identifier);
+        self.window_stack.push(identifier);
+    }
+
+    pub fn hide_window(&mut self, identifier: WindowIdentifier) {
         if let Some(index) = self.window_stack.iter().position(|&w| w == identifier) {
             self.window_stack.remove(index);
         }
-        self.window_stack.push(identifier);
+    }
+
+    /// Forgets a window that got destroyed, along with its bitmap.
+    pub fn destroy_window(&mut self, identifier: WindowIdentifier) {
+        if self.caret.as_ref().is_some_and(|caret| {
+            caret.owner == identifier || caret.bitmap_window_identifier() == identifier
+        }) {
+            self.destroy_caret();
+        }
+        self.hide_window(identifier);
+        self.windows.remove(&identifier);
+        if self.capture == Some(identifier) {
+            self.capture = None;
+        }
+        if self.active == Some(identifier) {
+            self.active = None;
+        }
+        if self.focus == Some(identifier) {
+            self.focus = None;
+        }
+    }
+
+    pub fn is_shown(&self, identifier: WindowIdentifier) -> bool {
+        self.window_stack.contains(&identifier)
+    }
+
+    /// Puts a shown window right below another one, or at the bottom if there is no other one.
+    pub fn place_below(&mut self, identifier: WindowIdentifier, above: Option<WindowIdentifier>) {
+        if !self.is_shown(identifier) {
+            return;
+        }
+        self.hide_window(identifier);
+        let index = above
+            .and_then(|above| self.window_stack.iter().position(|&w| w == above))
+            .unwrap_or(0);
+        self.window_stack.insert(index, identifier);
+    }
+
+    /// Moves and sizes a window. The contents of a window that changes size are lost.
+    pub fn set_window_rect(
+        &mut self,
+        identifier: WindowIdentifier,
+        position: Point,
+        width: i16,
+        height: i16,
+    ) {
+        if let Some(window) = self.windows.get_mut(&identifier) {
+            window.position = position;
+            if window.width != width || window.height != height {
+                window.width = width;
+                window.height = height;
+                if window.front_bitmap.is_some() {
+                    window.front_bitmap = Some(Bitmap::new(width.max(1), height.max(1)));
+                    if let Some(caret) = self.caret.as_mut() {
+                        if caret.bitmap_window_identifier() == identifier {
+                            caret.forget_drawing();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gives a window that drew on the bitmap of its parent a bitmap of its own, for when it
+    /// becomes a top-level window.
+    pub fn give_own_bitmap(&mut self, identifier: WindowIdentifier) {
+        if let Some(window) = self.windows.get_mut(&identifier) {
+            if window.front_bitmap.is_none() {
+                window.front_bitmap = Some(Bitmap::new(window.width.max(1), window.height.max(1)));
+            }
+        }
+    }
+
+    pub fn set_capture(&mut self, identifier: Option<WindowIdentifier>) {
+        self.capture = identifier;
+    }
+
+    /// The window that should get mouse input at the given point on the screen.
+    pub fn mouse_target(&self, point: Point) -> Option<WindowIdentifier> {
+        self.capture.or_else(|| self.window_at(point))
+    }
+
+    pub fn set_tracking_rect(&mut self, rect: Option<Rect>) {
+        self.tracking_rect = rect;
+    }
+
+    pub fn open_popup(&mut self, position: Point, bitmap: Bitmap) {
+        self.popups.push((position, bitmap));
+    }
+
+    /// Closes the popup menu that opened last.
+    pub fn close_popup(&mut self) {
+        self.popups.pop();
+    }
+
+    pub fn popup_bitmap(&mut self, index: usize) -> Option<&mut Bitmap> {
+        self.popups.get_mut(index).map(|(_, bitmap)| bitmap)
+    }
+
+    /// The shown windows, from the topmost one down.
+    pub fn shown_windows(&self) -> impl Iterator<Item = WindowIdentifier> + '_ {
+        self.window_stack.iter().rev().copied()
+    }
+
+    pub fn active_window(&self) -> Option<WindowIdentifier> {
+        self.active
+    }
+
+    pub fn set_active_window(&mut self, identifier: Option<WindowIdentifier>) {
+        self.active = identifier;
+    }
+
+    pub fn focus(&self) -> Option<WindowIdentifier> {
+        self.focus
+    }
+
+    pub fn set_focus(&mut self, identifier: Option<WindowIdentifier>) {
+        self.focus = identifier;
+    }
+
+    /// The window that should get keyboard input: the one with the focus, or else the active
+    /// window.
+    pub fn keyboard_target(&self) -> Option<WindowIdentifier> {
+        self.focus.or(self.active)
+    }
+
+    /// The topmost top-level window that contains the given point on the screen. Child windows
+    /// are left to the window they are in.
+    pub fn window_at(&self, point: Point) -> Option<WindowIdentifier> {
+        self.window_stack.iter().rev().copied().find(|&identifier| {
+            self.windows
+                .get(&identifier)
+                .is_some_and(|window| window.front_bitmap.is_some())
+                && self
+                    .window_rect_of(identifier)
+                    .is_some_and(|rect| rect.contains(point))
+        })
     }
 
     pub fn paint(&mut self, screen: &mut ScreenCanvas) {
+        self.blink_caret();
         // TODO: be more efficient than always redrawing everything
         for identifier in &self.window_stack {
             if let Some(window) = self.windows.get(identifier) {
@@ -105,6 +273,102 @@ impl WindowManager {
                 }
             }
         }
+        for (position, bitmap) in &self.popups {
+            screen.blit_bitmap(*position, bitmap);
+        }
+        if let Some(rect) = self.tracking_rect {
+            screen.draw_tracking_rect(rect);
+        }
+    }
+
+    /// Replaces the caret with a new one, which is hidden until it gets shown.
+    pub fn create_caret(&mut self, caret: Caret) {
+        self.destroy_caret();
+        self.caret = Some(caret);
+    }
+
+    pub fn destroy_caret(&mut self) {
+        self.erase_caret();
+        self.caret = None;
+    }
+
+    pub fn caret(&self) -> Option<&Caret> {
+        self.caret.as_ref()
+    }
+
+    pub fn hide_caret(&mut self) {
+        self.erase_caret();
+        if let Some(caret) = self.caret.as_mut() {
+            caret.hide();
+        }
+    }
+
+    pub fn show_caret(&mut self) {
+        if self.caret.as_mut().is_some_and(|caret| caret.show()) {
+            self.invert_caret();
+        }
+    }
+
+    /// Moves the caret to a position in the client area of its owner, which lies at the given
+    /// translation on the bitmap of the given window.
+    pub fn place_caret(
+        &mut self,
+        position: Point,
+        bitmap_window_identifier: WindowIdentifier,
+        bitmap_translation: Point,
+        client_rect: Rect,
+    ) {
+        let drawn = self.caret.as_ref().is_some_and(|caret| caret.is_drawn());
+        self.erase_caret();
+        if let Some(caret) = self.caret.as_mut() {
+            caret.place(
+                position,
+                bitmap_window_identifier,
+                bitmap_translation,
+                client_rect,
+            );
+        }
+        if drawn {
+            self.invert_caret();
+        }
+    }
+
+    pub fn caret_blink_time(&self) -> u16 {
+        self.caret_blink_time
+    }
+
+    pub fn set_caret_blink_time(&mut self, blink_time: u16) {
+        self.caret_blink_time = blink_time;
+    }
+
+    /// Turns the caret on or off once it has been on or off for long enough.
+    fn blink_caret(&mut self) {
+        if self
+            .caret
+            .as_ref()
+            .is_some_and(|caret| caret.should_blink(Instant::now(), self.caret_blink_time))
+        {
+            self.invert_caret();
+        }
+    }
+
+    fn erase_caret(&mut self) {
+        if self.caret.as_ref().is_some_and(|caret| caret.is_drawn()) {
+            self.invert_caret();
+        }
+    }
+
+    fn invert_caret(&mut self) {
+        if let Some(caret) = self.caret.as_mut() {
+            match self
+                .windows
+                .get_mut(&caret.bitmap_window_identifier())
+                .and_then(|window| window.front_bitmap.as_mut())
+            {
+                Some(bitmap) => caret.invert(bitmap),
+                None => caret.forget_drawing(),
+            }
+        }
     }
 
     pub fn paint_bitmap_for(&mut self, identifier: WindowIdentifier) -> Option<&mut Bitmap> {
@@ -116,9 +380,13 @@ impl WindowManager {
     pub fn paint_bitmap_for_dc(&mut self, dc: &DeviceContext) -> Option<BitmapView> {
         self.paint_bitmap_for(dc.bitmap_window_identifier)
             .map(|bitmap| {
-                bitmap.move_to(dc.position.get());
+                bitmap.move_to(dc.position);
                 bitmap.set_raster_op(dc.raster_op);
-                BitmapView::new(bitmap, dc.bitmap_translation)
+                let mut view = BitmapView::new(bitmap, dc.bitmap_translation);
+                if let Some(clip) = dc.clip {
+                    view.clip_to(clip);
+                }
+                view
             })
     }
 
@@ -126,17 +394,32 @@ impl WindowManager {
         self.windows.get(&identifier).map(|window| window.position)
     }
 
+    /// Sets the client area, relative to the top left corner of the window.
+    pub fn set_client_rect(&mut self, identifier: WindowIdentifier, rect: Rect) {
+        if let Some(window) = self.windows.get_mut(&identifier) {
+            window.client_rect = rect;
+        }
+    }
+
+    /// The top left corner of the client area, relative to the top left corner of the window.
+    pub fn client_origin_of(&self, identifier: WindowIdentifier) -> Option<Point> {
+        self.windows
+            .get(&identifier)
+            .map(|window| Point::new(window.client_rect.left, window.client_rect.top))
+    }
+
     pub fn client_rect_of(&self, identifier: WindowIdentifier) -> Option<Rect> {
         self.windows.get(&identifier).map(|window| Rect {
             top: 0,
             left: 0,
-            right: window.width,
-            bottom: window.height,
+            right: window.client_rect.right - window.client_rect.left,
+            bottom: window.client_rect.bottom - window.client_rect.top,
         })
     }
 
+    /// The rectangle of a window, relative to the client area of its parent for child windows
+    /// and on the screen for top-level windows.
     pub fn window_rect_of(&self, identifier: WindowIdentifier) -> Option<Rect> {
-        // TODO: what about nested windows?
         self.windows.get(&identifier).map(|window| Rect {
             top: window.position.y,
             left: window.position.x,